@@ -10,6 +10,9 @@ pub struct BatchingStoreWriterConfig {
     pub batch_flush_interval_millis: u64,
     pub batch_flush_record_count_threshold: u64,
     pub batch_flush_size_threshold: u64,
+    pub batch_flush_interval_overrides: Vec<(String, u64)>,
+    pub bulk_mode: bool,
+    pub wal_path: Option<String>,
 }
 impl BatchingStoreWriterConfig {
     pub fn new() -> Self {
@@ -20,6 +23,9 @@ impl BatchingStoreWriterConfig {
             batch_flush_interval_millis: 1000,          // 1 second
             batch_flush_record_count_threshold: u64::MAX, // unbound
             batch_flush_size_threshold: 1024 * 1024,    // 1MB
+            batch_flush_interval_overrides: Vec::new(),
+            bulk_mode: false,
+            wal_path: None,
         }
     }
     /// set the number of writer threads. defaults to 1.
@@ -47,6 +53,14 @@ impl BatchingStoreWriterConfig {
         self.batch_flush_interval_millis = v;
         self
     }
+    /// override batch_flush_interval_millis for keys matching a prefix of "{keyspace}/{key}". defaults to no overrides.
+    /// when multiple prefixes match a key, the longest one is used. an interval of 0 writes appends for matching keys immediately.
+    pub fn set_batch_flush_interval_override(mut self, prefix: String, v: u64) -> Self {
+        self.batch_flush_interval_overrides
+            .retain(|(existing, _)| existing != &prefix);
+        self.batch_flush_interval_overrides.push((prefix, v));
+        self
+    }
     /// flush a batch upon reaching a specific record count. defaults to u64::MAX records.
     pub fn set_batch_flush_record_count_threshold(mut self, v: u64) -> Self {
         self.batch_flush_record_count_threshold = v;
@@ -58,6 +72,20 @@ impl BatchingStoreWriterConfig {
         self.batch_flush_size_threshold = v;
         self
     }
+    /// ignore flush intervals and flush batches strictly on record count or size thresholds. defaults to false.
+    /// intended for bulk loads, which must call flush_all at the end to write remaining partial batches.
+    pub fn set_bulk_mode(mut self, v: bool) -> Self {
+        self.bulk_mode = v;
+        self
+    }
+    /// set the path of a local write-ahead log, defaults to None.
+    /// when set, appends are persisted to the log before being queued and replayed to the underlying writer on startup,
+    /// so a crash does not lose appends that were accepted but not yet flushed. the log is truncated on flush_all,
+    /// except for entries of keys with a failed append, which are kept until they are replayed on restart.
+    pub fn set_wal_path(mut self, v: Option<String>) -> Self {
+        self.wal_path = v;
+        self
+    }
     pub fn load(ini: &Ini) -> Result<Self, StoreError> {
         let s3 = match ini.section(Some("s3")) {
             None => {
@@ -136,6 +164,28 @@ impl BatchingStoreWriterConfig {
                 }
             }
         }
+        if let Some(overrides) = ini.section(Some("batch_flush_interval_overrides")) {
+            for (prefix, v) in overrides.iter() {
+                match u64::from_str(v) {
+                    Ok(v) => cfg = cfg.set_batch_flush_interval_override(prefix.to_string(), v),
+                    Err(_) => {
+                        return Err(StoreError::BadConfiguration(format!(
+                            "batch_flush_interval_overrides {}",
+                            prefix
+                        )))
+                    }
+                }
+            }
+        }
+        if let Some(v) = s3.get("bulk_mode") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_bulk_mode(v),
+                Err(_) => return Err(StoreError::BadConfiguration("s3 bulk_mode".to_string())),
+            }
+        }
+        if let Some(v) = s3.get("wal_path") {
+            cfg = cfg.set_wal_path(Some(v.to_string()));
+        }
 
         return Ok(cfg);
     }
@@ -1,5 +1,13 @@
 mod config;
+mod stats;
+#[cfg(feature = "sync-batching")]
+mod sync;
+mod wal;
 mod writer;
 
 pub type BatchingStoreWriterConfig = config::BatchingStoreWriterConfig;
 pub type BatchingStoreWriter<W> = writer::BatchingStoreWriter<W>;
+#[cfg(feature = "sync-batching")]
+pub type SyncBatchingStoreWriter<W> = sync::SyncBatchingStoreWriter<W>;
+pub type BatchStats = stats::BatchStats;
+pub type FlushReason = stats::FlushReason;
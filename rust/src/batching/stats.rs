@@ -0,0 +1,79 @@
+/// The reason a batch was written to the underlying writer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlushReason {
+    /// batch_flush_interval_millis elapsed, or is 0
+    Interval,
+    /// batch_flush_record_count_threshold was reached
+    RecordCount,
+    /// batch_flush_size_threshold was reached
+    Size,
+    /// flush_key or flush_all was called
+    Requested,
+}
+
+/// Statistics of batches written by a BatchingStoreWriter, used to tune batch_flush_* thresholds
+#[derive(Debug, Clone)]
+pub struct BatchStats {
+    pub interval_flush_count: u64,
+    pub record_count_flush_count: u64,
+    pub size_flush_count: u64,
+    pub requested_flush_count: u64,
+    pub flushed_record_count: u64,
+    pub flushed_size: u64,
+    pub max_batch_record_count: u64,
+    pub max_batch_size: u64,
+    /// tasks queued and not yet executed by each writer thread, at the time the stats were taken
+    pub lane_queue_depths: Vec<u64>,
+}
+impl BatchStats {
+    pub fn new() -> Self {
+        Self {
+            interval_flush_count: 0,
+            record_count_flush_count: 0,
+            size_flush_count: 0,
+            requested_flush_count: 0,
+            flushed_record_count: 0,
+            flushed_size: 0,
+            max_batch_record_count: 0,
+            max_batch_size: 0,
+            lane_queue_depths: Vec::new(),
+        }
+    }
+    pub fn record_flush(&mut self, reason: FlushReason, record_count: u64, size: u64) {
+        match reason {
+            FlushReason::Interval => self.interval_flush_count += 1,
+            FlushReason::RecordCount => self.record_count_flush_count += 1,
+            FlushReason::Size => self.size_flush_count += 1,
+            FlushReason::Requested => self.requested_flush_count += 1,
+        }
+        self.flushed_record_count += record_count;
+        self.flushed_size += size;
+        if record_count > self.max_batch_record_count {
+            self.max_batch_record_count = record_count;
+        }
+        if size > self.max_batch_size {
+            self.max_batch_size = size;
+        }
+    }
+    /// total number of batches written
+    pub fn flush_count(&self) -> u64 {
+        self.interval_flush_count
+            + self.record_count_flush_count
+            + self.size_flush_count
+            + self.requested_flush_count
+    }
+    /// average number of records per written batch
+    pub fn average_batch_record_count(&self) -> f64 {
+        match self.flush_count() {
+            0 => 0.0,
+            n => self.flushed_record_count as f64 / n as f64,
+        }
+    }
+    /// average size in bytes of record values per written batch
+    pub fn average_batch_size(&self) -> f64 {
+        match self.flush_count() {
+            0 => 0.0,
+            n => self.flushed_size as f64 / n as f64,
+        }
+    }
+}
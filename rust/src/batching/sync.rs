@@ -0,0 +1,154 @@
+use super::writer::{drain_flush_report, FailedKeys, Task, TaskExecutor, WrittenKeys};
+use crate::common::time::*;
+use crate::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use threadlanes::LaneExecutor;
+
+/// A batching writer that accumulates and writes batches in the calling thread, without any background threads.
+/// Batches are written by the same thresholds as a BatchingStoreWriter, but only from calls to append, duty_cycle, or flush_*.
+/// writer_thread_count, writer_thread_queue_capacity, and wal_path are ignored.
+pub struct SyncBatchingStoreWriter<W: StoreWriter> {
+    executor: RefCell<TaskExecutor<W>>,
+    batch_check_interval_millis: u64,
+    bulk_mode: bool,
+    next_batch_check: RefCell<u64>,
+    failed_keys: FailedKeys,
+    written_keys: WrittenKeys,
+    stats: Arc<Mutex<BatchStats>>,
+    queue_depth: Arc<AtomicU64>,
+    clock: Arc<dyn Clock>,
+    record_transform: Option<RecordTransform>,
+}
+impl<W: StoreWriter> SyncBatchingStoreWriter<W> {
+    pub fn new(config: BatchingStoreWriterConfig, writer: W) -> Self {
+        let failed_keys: FailedKeys = Arc::new(Mutex::new(HashMap::new()));
+        let written_keys: WrittenKeys = Arc::new(Mutex::new(HashMap::new()));
+        let stats = Arc::new(Mutex::new(BatchStats::new()));
+        let queue_depth = Arc::new(AtomicU64::new(0));
+        Self {
+            executor: RefCell::new(TaskExecutor::new(
+                writer,
+                &config,
+                failed_keys.clone(),
+                written_keys.clone(),
+                // there is no write-ahead log to keep after a failed flush of all keys
+                Arc::new(AtomicBool::new(false)),
+                stats.clone(),
+                queue_depth.clone(),
+            )),
+            batch_check_interval_millis: config.batch_check_interval_millis,
+            bulk_mode: config.bulk_mode,
+            next_batch_check: RefCell::new(
+                time_now_as_millis() + config.batch_check_interval_millis,
+            ),
+            failed_keys,
+            written_keys,
+            stats,
+            queue_depth,
+            clock: Arc::new(SystemClock),
+            record_transform: None,
+        }
+    }
+    /// read the time of batch flush intervals from the given clock instead of the system clock, such as a MockClock in tests
+    pub fn set_clock(mut self, v: Arc<dyn Clock>) -> Self {
+        self.next_batch_check
+            .replace(v.now_millis() + self.batch_check_interval_millis);
+        self.clock = v;
+        self
+    }
+    /// invoke the transform on every appended record before it is batched,
+    /// so it can redact or normalize values, assign timestamps, or reject an append by returning an error from append.
+    pub fn set_record_transform(mut self, v: RecordTransform) -> Self {
+        self.record_transform = Some(v);
+        self
+    }
+    /// snapshot of batch flush statistics since the writer was created
+    pub fn stats(&self) -> BatchStats {
+        self.stats.lock().unwrap().clone()
+    }
+    fn execute(&self, task: Task) {
+        // the executor counts down a queue depth for every task, as if it was sent to a lane
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+        self.executor.borrow_mut().execute(task);
+    }
+}
+impl<W: StoreWriter> StoreWriter for SyncBatchingStoreWriter<W> {
+    fn create_keyspace(&self, keyspace: &str) -> Result<CreatedKeyspace, StoreError> {
+        self.executor.borrow().writer.create_keyspace(keyspace)
+    }
+    fn append(
+        &self,
+        keyspace: &str,
+        key: &str,
+        mut inserts: Vec<Insertion>,
+    ) -> Result<(), StoreError> {
+        // transformed before batching, so a rejected record only fails its own append
+        if let Some(transform) = &self.record_transform {
+            for insert in inserts.iter_mut() {
+                transform(insert)?;
+            }
+        }
+        self.execute(Task::Append(
+            keyspace.to_string(),
+            key.to_string(),
+            inserts,
+            self.clock.now_millis(),
+        ));
+        self.duty_cycle()
+    }
+    fn flush_key(&self, keyspace: &str, key: &str) -> Result<(), StoreError> {
+        self.execute(Task::FlushKey(keyspace.to_string(), key.to_string()));
+        // report a failed append of the key since its last flush
+        let failed = self
+            .failed_keys
+            .lock()
+            .unwrap()
+            .remove(&(keyspace.to_string(), key.to_string()));
+        match failed {
+            None => Ok(()),
+            Some(err) => Err(err),
+        }
+    }
+    fn flush_all(&self) -> Result<(), StoreError> {
+        self.execute(Task::FlushAll);
+        self.written_keys.lock().unwrap().clear();
+        // report the first failed append since the last flush, all failures were logged by the executor
+        let failed: Vec<StoreError> = self
+            .failed_keys
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, err)| err)
+            .collect();
+        match failed.into_iter().next() {
+            None => Ok(()),
+            Some(err) => Err(err),
+        }
+    }
+    /// flush all pending batches like flush_all, reporting every key written since it was last flushed, with its next offset once
+    /// flushed by the underlying writer, and every key with a failed write, so a caller can acknowledge the keys that are durable.
+    /// keys already flushed by flush_key are not reported again.
+    fn flush_all_with_report(&self) -> Result<FlushReport, StoreError> {
+        self.execute(Task::FlushAll);
+        Ok(drain_flush_report(&self.written_keys, &self.failed_keys))
+    }
+    fn duty_cycle(&self) -> Result<(), StoreError> {
+        if self.bulk_mode {
+            // no interval checks in bulk mode
+            return Ok(());
+        }
+        let now = self.clock.now_millis();
+        if now >= *self.next_batch_check.borrow() {
+            self.execute(Task::CheckWrite(now));
+            self.next_batch_check
+                .replace(now + self.batch_check_interval_millis);
+        }
+        Ok(())
+    }
+    fn timestamp_now(&self, keyspace: &str) -> Result<i64, StoreError> {
+        self.executor.borrow().writer.timestamp_now(keyspace)
+    }
+}
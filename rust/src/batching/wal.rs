@@ -0,0 +1,260 @@
+use crate::common::buffer::*;
+use crate::*;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+/// Local write-ahead log of appends accepted by the batching writer but not yet flushed.
+/// Each entry is length-prefixed, so a partially written entry at the tail from a crash is ignored on replay.
+pub struct WriteAheadLog {
+    path: String,
+    file: File,
+    /// the key of each entry in the log, in log order
+    entries: Vec<WalEntry>,
+}
+
+struct WalEntry {
+    keyspace: String,
+    key: String,
+    /// the entry belongs to a failed append, so it is kept by truncate until it is replayed on restart
+    retained: bool,
+}
+impl WriteAheadLog {
+    /// open the log at the given path, returning the log and any appends that were never truncated
+    pub fn open(path: &str) -> Result<(Self, Vec<Append>), StoreError> {
+        let mut contents: Vec<u8> = Vec::new();
+        match File::open(path) {
+            Ok(mut f) => {
+                if let Err(err) = f.read_to_end(&mut contents) {
+                    return Err(wal_error(path, err));
+                }
+            }
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(wal_error(path, err));
+                }
+            }
+        }
+        let pending = deserialize_entries(&contents)?;
+        let entries = pending
+            .iter()
+            .map(|append| WalEntry {
+                keyspace: append.keyspace.clone(),
+                key: append.key.clone(),
+                retained: false,
+            })
+            .collect();
+        Ok((
+            Self {
+                path: path.to_string(),
+                file: open_append(path)?,
+                entries,
+            },
+            pending,
+        ))
+    }
+    /// durably persist an append before it is queued
+    pub fn write(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        inserts: &Vec<Insertion>,
+    ) -> Result<(), StoreError> {
+        let entry = serialize_entry(keyspace, key, inserts)?;
+        if let Err(err) = self.file.write_all(&entry) {
+            return Err(wal_error(&self.path, err));
+        }
+        if let Err(err) = self.file.sync_data() {
+            return Err(wal_error(&self.path, err));
+        }
+        self.entries.push(WalEntry {
+            keyspace: keyspace.to_string(),
+            key: key.to_string(),
+            retained: false,
+        });
+        Ok(())
+    }
+    /// keep the entries of a key written so far on truncate, since the writer dropped the records of its failed append.
+    /// entries the key writes later are only kept if they fail as well.
+    pub fn retain_key(&mut self, keyspace: &str, key: &str) {
+        for entry in self.entries.iter_mut() {
+            if entry.keyspace == keyspace && entry.key == key {
+                entry.retained = true;
+            }
+        }
+    }
+    /// discard the entries that were not retained, called once everything else written to the log has been flushed to the store
+    pub fn truncate(&mut self) -> Result<(), StoreError> {
+        if self.entries.iter().any(|entry| entry.retained) {
+            return self.rewrite_retained();
+        }
+        if let Err(err) = self.file.set_len(0) {
+            return Err(wal_error(&self.path, err));
+        }
+        if let Err(err) = self.file.sync_data() {
+            return Err(wal_error(&self.path, err));
+        }
+        self.entries.clear();
+        Ok(())
+    }
+    /// replace the log with only the retained entries.
+    /// the entries are written to a temporary file that is renamed over the log, so a crash keeps either the old or the new log.
+    fn rewrite_retained(&mut self) -> Result<(), StoreError> {
+        let mut contents: Vec<u8> = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut f) => {
+                if let Err(err) = f.read_to_end(&mut contents) {
+                    return Err(wal_error(&self.path, err));
+                }
+            }
+            Err(err) => return Err(wal_error(&self.path, err)),
+        }
+        let mut retained: Vec<u8> = Vec::new();
+        for (append, entry) in deserialize_entries(&contents)?
+            .iter()
+            .zip(self.entries.iter())
+        {
+            if entry.retained {
+                retained.append(&mut serialize_entry(
+                    &append.keyspace,
+                    &append.key,
+                    &append.records,
+                )?);
+            }
+        }
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut tmp = match File::create(&tmp_path) {
+            Ok(v) => v,
+            Err(err) => return Err(wal_error(&tmp_path, err)),
+        };
+        if let Err(err) = tmp.write_all(&retained) {
+            return Err(wal_error(&tmp_path, err));
+        }
+        if let Err(err) = tmp.sync_data() {
+            return Err(wal_error(&tmp_path, err));
+        }
+        if let Err(err) = std::fs::rename(&tmp_path, &self.path) {
+            return Err(wal_error(&self.path, err));
+        }
+        self.file = open_append(&self.path)?;
+        self.entries.retain(|entry| entry.retained);
+        Ok(())
+    }
+}
+
+fn open_append(path: &str) -> Result<File, StoreError> {
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(v) => Ok(v),
+        Err(err) => Err(wal_error(path, err)),
+    }
+}
+
+fn serialize_entry(
+    keyspace: &str,
+    key: &str,
+    inserts: &Vec<Insertion>,
+) -> Result<Vec<u8>, StoreError> {
+    let mut body: Vec<u8> = Vec::new();
+    append_u32(&mut body, keyspace.len() as u32);
+    append_buffer(&mut body, keyspace.as_bytes());
+    append_u32(&mut body, key.len() as u32);
+    append_buffer(&mut body, key.as_bytes());
+    append_u32(&mut body, inserts.len() as u32);
+    for insert in inserts.iter() {
+        // timestamps are assigned before appends are written to the log, so replays keep them
+        match insert.timestamp {
+            Some(v) => append_i64(&mut body, v),
+            None => {
+                return Err(StoreError::BadData(
+                    "write-ahead log insert without timestamp".to_string(),
+                ))
+            }
+        }
+        append_u128(
+            &mut body,
+            match insert.nonce {
+                None => u128::MAX,
+                Some(v) => v,
+            },
+        );
+        append_u32(&mut body, insert.record.len() as u32);
+        append_buffer(&mut body, &insert.record);
+    }
+    let mut entry: Vec<u8> = Vec::new();
+    append_u32(&mut entry, body.len() as u32);
+    entry.append(&mut body);
+    Ok(entry)
+}
+
+fn deserialize_entries(buffer: &Vec<u8>) -> Result<Vec<Append>, StoreError> {
+    let mut appends: Vec<Append> = Vec::new();
+    let mut pos: usize = 0;
+    while pos + 4 <= buffer.len() {
+        let length = read_u32(buffer, pos)? as usize;
+        if pos + 4 + length > buffer.len() {
+            // entry was not fully written before a crash, it was never acknowledged
+            log::warn!("ignoring partial write-ahead log entry at position {}", pos);
+            break;
+        }
+        appends.push(deserialize_entry(&buffer[pos + 4..pos + 4 + length])?);
+        pos += 4 + length;
+    }
+    Ok(appends)
+}
+
+fn deserialize_entry(body: &[u8]) -> Result<Append, StoreError> {
+    let mut pos: usize = 0;
+    let keyspace = read_string(body, &mut pos)?;
+    let key = read_string(body, &mut pos)?;
+    check_remaining(body, pos, 4)?;
+    let count = read_u32(body, pos)?;
+    pos += 4;
+    let mut records: Vec<Insertion> = Vec::new();
+    for _ in 0..count {
+        check_remaining(body, pos, 28)?;
+        let timestamp = read_i64(body, pos)?;
+        let nonce = match read_u128(body, pos + 8)? {
+            u128::MAX => None,
+            v => Some(v),
+        };
+        let length = read_u32(body, pos + 24)? as usize;
+        pos += 28;
+        check_remaining(body, pos, length)?;
+        records.push(Insertion {
+            record: read_bytes_copy(body, pos, length),
+            nonce,
+            timestamp: Some(timestamp),
+        });
+        pos += length;
+    }
+    Ok(Append {
+        keyspace,
+        key,
+        records,
+    })
+}
+
+fn read_string(body: &[u8], pos: &mut usize) -> Result<String, StoreError> {
+    check_remaining(body, *pos, 4)?;
+    let length = read_u32(body, *pos)? as usize;
+    *pos += 4;
+    check_remaining(body, *pos, length)?;
+    let value = match String::from_utf8(read_bytes_copy(body, *pos, length)) {
+        Ok(v) => v,
+        Err(_) => return Err(StoreError::BadData("write-ahead log string".to_string())),
+    };
+    *pos += length;
+    Ok(value)
+}
+
+fn check_remaining(body: &[u8], pos: usize, length: usize) -> Result<(), StoreError> {
+    if pos + length > body.len() {
+        return Err(StoreError::BadData(
+            "write-ahead log entry truncated".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn wal_error(path: &str, err: std::io::Error) -> StoreError {
+    StoreError::IOError(format!("write-ahead log {}: {}", path, err.to_string()))
+}
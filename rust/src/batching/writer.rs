@@ -1,9 +1,13 @@
+use super::wal::WriteAheadLog;
 use crate::common::time::*;
 use crate::*;
 use linked_hash_map::LinkedHashMap;
 use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use threadlanes::{LaneExecutor, ThreadLanes};
 
 struct Batch {
@@ -11,40 +15,221 @@ struct Batch {
     size: u64,
     flush_timestamp: u64,
 }
-enum Task {
-    Append(String, String, Vec<Insertion>),
+/// Append and CheckWrite carry the time they were sent, so the clock is only read by the sending thread
+pub enum Task {
+    Append(String, String, Vec<Insertion>, u64),
     FlushKey(String, String),
     FlushAll,
-    CheckWrite,
+    CheckWrite(u64),
 }
-struct TaskExecutor<W: StoreWriter> {
-    writer: W,
+/// failed batched appends by key, reported by the next flush of the key
+pub type FailedKeys = Arc<Mutex<HashMap<(String, String), StoreError>>>;
+/// next offset of keys flushed by the last flush of all keys, read after the flush of the underlying writer and drained by flush_all_with_report
+pub type WrittenKeys = Arc<Mutex<HashMap<(String, String), Option<u64>>>>;
+
+/// drain written and failed keys into a report, leaving out keys that also failed since their records may be lost
+pub fn drain_flush_report(written_keys: &WrittenKeys, failed_keys: &FailedKeys) -> FlushReport {
+    let failed: Vec<FailedKey> = failed_keys
+        .lock()
+        .unwrap()
+        .drain()
+        .map(|((keyspace, key), error)| FailedKey {
+            keyspace,
+            key,
+            error,
+        })
+        .collect();
+    let flushed: Vec<FlushedKey> = written_keys
+        .lock()
+        .unwrap()
+        .drain()
+        .filter(|((keyspace, key), _)| {
+            !failed
+                .iter()
+                .any(|f| &f.keyspace == keyspace && &f.key == key)
+        })
+        .map(|((keyspace, key), next_offset)| FlushedKey {
+            keyspace,
+            key,
+            next_offset,
+        })
+        .collect();
+    FlushReport { flushed, failed }
+}
+
+/// accumulates batches for the keys of one lane, writing them when a threshold, interval, or flush is reached
+pub struct TaskExecutor<W: StoreWriter> {
+    pub writer: W,
+    failed_keys: FailedKeys,
+    written_keys: WrittenKeys,
+    /// set when a flush of every key of the writer failed without naming the failed keys
+    flush_all_failed: Arc<AtomicBool>,
+    /// keys appended to the writer of this lane since they were last flushed, by flush_key or a flush of every key
+    unflushed_keys: HashSet<(String, String)>,
+    stats: Arc<Mutex<BatchStats>>,
+    queue_depth: Arc<AtomicU64>,
     batches: LinkedHashMap<(String, String), Batch>,
     batch_flush_interval_millis: u64,
+    batch_flush_interval_overrides: Vec<(String, u64)>,
     batch_flush_record_count_threshold: u64,
     batch_flush_size_threshold: u64,
+    bulk_mode: bool,
 }
 impl<W: StoreWriter> TaskExecutor<W> {
-    fn new(writer: W, config: &BatchingStoreWriterConfig) -> Self {
+    pub fn new(
+        writer: W,
+        config: &BatchingStoreWriterConfig,
+        failed_keys: FailedKeys,
+        written_keys: WrittenKeys,
+        flush_all_failed: Arc<AtomicBool>,
+        stats: Arc<Mutex<BatchStats>>,
+        queue_depth: Arc<AtomicU64>,
+    ) -> Self {
         Self {
             writer,
+            failed_keys,
+            written_keys,
+            flush_all_failed,
+            unflushed_keys: HashSet::new(),
+            stats,
+            queue_depth,
             batches: LinkedHashMap::new(),
             batch_flush_interval_millis: config.batch_flush_interval_millis,
+            batch_flush_interval_overrides: config.batch_flush_interval_overrides.clone(),
             batch_flush_record_count_threshold: config.batch_flush_record_count_threshold,
             batch_flush_size_threshold: config.batch_flush_size_threshold,
+            bulk_mode: config.bulk_mode,
+        }
+    }
+    /// flush interval of the longest matching override prefix, or the configured default
+    fn flush_interval_millis(&self, keyspace: &str, key: &str) -> u64 {
+        if self.batch_flush_interval_overrides.is_empty() {
+            return self.batch_flush_interval_millis;
+        }
+        let path = format!("{}/{}", keyspace, key);
+        let mut result: Option<(usize, u64)> = None;
+        for (prefix, interval) in self.batch_flush_interval_overrides.iter() {
+            if path.starts_with(prefix.as_str()) {
+                match result {
+                    Some((len, _)) if len >= prefix.len() => {}
+                    _ => result = Some((prefix.len(), *interval)),
+                }
+            }
+        }
+        match result {
+            None => self.batch_flush_interval_millis,
+            Some((_, interval)) => interval,
+        }
+    }
+    fn write(
+        &mut self,
+        keyspace: &str,
+        key: &str,
+        inserts: Vec<Insertion>,
+        size: u64,
+        reason: FlushReason,
+    ) {
+        self.stats
+            .lock()
+            .unwrap()
+            .record_flush(reason, inserts.len() as u64, size);
+        match self.writer.append(keyspace, key, inserts) {
+            Ok(_) => {
+                self.unflushed_keys
+                    .insert((keyspace.to_string(), key.to_string()));
+            }
+            Err(err) => {
+                log::error!("append to {}/{} failed: {}", keyspace, key, err.to_string());
+                self.fail(keyspace.to_string(), key.to_string(), err);
+            }
+        }
+    }
+    fn fail(&self, keyspace: String, key: String, err: StoreError) {
+        self.failed_keys
+            .lock()
+            .unwrap()
+            .insert((keyspace, key), err);
+    }
+    /// flush a key in the writer, which may hold written batches in its own buffers
+    fn flush_writer_key(&mut self, keyspace: String, key: String) {
+        let batch_key = (keyspace, key);
+        self.unflushed_keys.remove(&batch_key);
+        if let Err(err) = self.writer.flush_key(&batch_key.0, &batch_key.1) {
+            log::error!(
+                "flush of {}/{} failed: {}",
+                batch_key.0,
+                batch_key.1,
+                err.to_string()
+            );
+            self.fail(batch_key.0, batch_key.1, err);
+        }
+    }
+    /// flush every key in the writer, failing every key appended since its last flush when the writer can not tell which keys failed.
+    /// the other keys appended since their last flush are reported as written with their next offset once the writer flushed them,
+    /// so the offsets include records that were held in the buffers of the writer.
+    fn flush_writer(&mut self) {
+        let unflushed = std::mem::replace(&mut self.unflushed_keys, HashSet::new());
+        match self.writer.flush_all_with_report() {
+            Ok(report) => {
+                for (keyspace, key) in unflushed {
+                    if report
+                        .failed
+                        .iter()
+                        .any(|f| f.keyspace == keyspace && f.key == key)
+                    {
+                        continue;
+                    }
+                    match self.writer.next_offset(&keyspace, &key) {
+                        Ok(next_offset) => {
+                            self.written_keys
+                                .lock()
+                                .unwrap()
+                                .insert((keyspace, key), next_offset);
+                        }
+                        Err(err) => {
+                            log::error!(
+                                "next offset of {}/{} failed: {}",
+                                keyspace,
+                                key,
+                                err.to_string()
+                            );
+                            self.fail(keyspace, key, err);
+                        }
+                    }
+                }
+                for failed in report.failed {
+                    self.fail(failed.keyspace, failed.key, failed.error);
+                }
+            }
+            Err(err) => {
+                log::error!("flush of all keys failed: {}", err.to_string());
+                self.flush_all_failed.store(true, Ordering::SeqCst);
+                for (keyspace, key) in unflushed {
+                    let error = StoreError::IOError(format!(
+                        "flush of all keys failed: {}",
+                        err.to_string()
+                    ));
+                    self.fail(keyspace, key, error);
+                }
+            }
         }
     }
 }
 impl<W: StoreWriter> LaneExecutor<Task> for TaskExecutor<W> {
     fn execute(&mut self, task: Task) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
         match task {
-            Task::Append(keyspace, key, mut inserts) => {
-                // set None timestamps now
-                let now = time_now_as_millis();
-                for insert in &mut inserts {
-                    if let None = insert.timestamp {
-                        insert.timestamp = Some(now as i64);
-                    }
+            Task::Append(keyspace, key, mut inserts, now) => {
+                // set None timestamps now, in the timestamp unit of the keyspace
+                let writer = &self.writer;
+                let filled = fill_timestamps(&mut inserts, || writer.timestamp_now(&keyspace));
+                if let Err(err) = filled {
+                    log::error!("append to {}/{} failed: {}", keyspace, key, err.to_string());
+                    self.failed_keys
+                        .lock()
+                        .unwrap()
+                        .insert((keyspace, key), err);
+                    return;
                 }
                 // handle batch
                 let batch_key = (keyspace, key);
@@ -55,31 +240,48 @@ impl<W: StoreWriter> LaneExecutor<Task> for TaskExecutor<W> {
                         batch.inserts.append(&mut inserts);
                         batch.size += batch_size;
                         // check if batch should be written now due to count threshold
-                        if batch.inserts.len() as u64 >= self.batch_flush_record_count_threshold
-                            || batch.size >= self.batch_flush_size_threshold
+                        let reason = if batch.inserts.len() as u64
+                            >= self.batch_flush_record_count_threshold
                         {
+                            Some(FlushReason::RecordCount)
+                        } else if batch.size >= self.batch_flush_size_threshold {
+                            Some(FlushReason::Size)
+                        } else {
+                            None
+                        };
+                        if let Some(reason) = reason {
                             // write now
-                            let entry = self.batches.pop_front().unwrap();
-                            self.writer
-                                .append(&entry.0 .0, &entry.0 .1, entry.1.inserts)
-                                .expect("append failed");
+                            let batch = self.batches.remove(&batch_key).unwrap();
+                            self.write(
+                                &batch_key.0,
+                                &batch_key.1,
+                                batch.inserts,
+                                batch.size,
+                                reason,
+                            );
                         }
                     }
                     None => {
-                        if self.batch_flush_interval_millis == 0
-                            || inserts.len() as u64 >= self.batch_flush_record_count_threshold
-                            || batch_size >= self.batch_flush_size_threshold
-                        {
+                        let flush_interval_millis =
+                            self.flush_interval_millis(&batch_key.0, &batch_key.1);
+                        let reason = if flush_interval_millis == 0 && !self.bulk_mode {
+                            Some(FlushReason::Interval)
+                        } else if inserts.len() as u64 >= self.batch_flush_record_count_threshold {
+                            Some(FlushReason::RecordCount)
+                        } else if batch_size >= self.batch_flush_size_threshold {
+                            Some(FlushReason::Size)
+                        } else {
+                            None
+                        };
+                        if let Some(reason) = reason {
                             // write now
-                            self.writer
-                                .append(&batch_key.0, &batch_key.1, inserts)
-                                .expect("append failed");
+                            self.write(&batch_key.0, &batch_key.1, inserts, batch_size, reason);
                         } else {
                             // start new batch
                             self.batches.insert(
                                 batch_key,
                                 Batch {
-                                    flush_timestamp: now + self.batch_flush_interval_millis,
+                                    flush_timestamp: now + flush_interval_millis,
                                     size: batch_size,
                                     inserts: inserts,
                                 },
@@ -91,29 +293,65 @@ impl<W: StoreWriter> LaneExecutor<Task> for TaskExecutor<W> {
             Task::FlushKey(keyspace, key) => {
                 let batch_key = (keyspace, key);
                 if let Some(batch) = self.batches.remove(&batch_key) {
-                    self.writer
-                        .append(&batch_key.0, &batch_key.1, batch.inserts)
-                        .expect("append failed");
+                    self.write(
+                        &batch_key.0,
+                        &batch_key.1,
+                        batch.inserts,
+                        batch.size,
+                        FlushReason::Requested,
+                    );
                 }
+                self.flush_writer_key(batch_key.0, batch_key.1);
             }
             Task::FlushAll => {
                 while !self.batches.is_empty() {
                     let entry = self.batches.pop_front().unwrap();
-                    self.writer
-                        .append(&entry.0 .0, &entry.0 .1, entry.1.inserts)
-                        .expect("append failed");
+                    self.write(
+                        &entry.0 .0,
+                        &entry.0 .1,
+                        entry.1.inserts,
+                        entry.1.size,
+                        FlushReason::Requested,
+                    );
                 }
+                self.flush_writer();
             }
-            Task::CheckWrite => {
+            Task::CheckWrite(now) => {
                 // check if batches should be written due to time threshold
-                let now = time_now_as_millis();
-                while !self.batches.is_empty()
-                    && now > self.batches.front().unwrap().1.flush_timestamp
-                {
-                    let entry = self.batches.pop_front().unwrap();
-                    self.writer
-                        .append(&entry.0 .0, &entry.0 .1, entry.1.inserts)
-                        .expect("append failed");
+                if self.bulk_mode {
+                    // intervals are ignored, batches are only written on thresholds or flush
+                } else if self.batch_flush_interval_overrides.is_empty() {
+                    // all batches share an interval, so they are ordered by flush time
+                    while !self.batches.is_empty()
+                        && now > self.batches.front().unwrap().1.flush_timestamp
+                    {
+                        let entry = self.batches.pop_front().unwrap();
+                        self.write(
+                            &entry.0 .0,
+                            &entry.0 .1,
+                            entry.1.inserts,
+                            entry.1.size,
+                            FlushReason::Interval,
+                        );
+                    }
+                } else {
+                    // overridden intervals break flush time ordering, check every batch
+                    let expired: Vec<(String, String)> = self
+                        .batches
+                        .iter()
+                        .filter(|(_, batch)| now > batch.flush_timestamp)
+                        .map(|(batch_key, _)| batch_key.clone())
+                        .collect();
+                    for batch_key in expired {
+                        let batch = self.batches.remove(&batch_key).unwrap();
+                        self.write(
+                            &batch_key.0,
+                            &batch_key.1,
+                            batch.inserts,
+                            batch.size,
+                            FlushReason::Interval,
+                        );
+                    }
                 }
             }
         }
@@ -124,25 +362,128 @@ pub struct BatchingStoreWriter<W: StoreWriter> {
     writer: W,
     writer_thread_count: u64,
     batch_check_interval_millis: u64,
+    bulk_mode: bool,
     next_batch_check: RefCell<u64>,
     thread_lanes: ThreadLanes<Task>,
+    failed_keys: FailedKeys,
+    written_keys: WrittenKeys,
+    flush_all_failed: Arc<AtomicBool>,
+    stats: Arc<Mutex<BatchStats>>,
+    queue_depths: Vec<Arc<AtomicU64>>,
+    wal: RefCell<Option<WriteAheadLog>>,
+    clock: Arc<dyn Clock>,
+    record_transform: Option<RecordTransform>,
+    shut_down: bool,
 }
 impl<W: StoreWriter + Clone + Send + 'static> BatchingStoreWriter<W> {
     pub fn new(config: BatchingStoreWriterConfig, writer: W) -> Result<Self, StoreError> {
+        let wal = match &config.wal_path {
+            None => None,
+            Some(path) => {
+                // replay appends that were accepted but never flushed before the last shutdown
+                let (mut wal, pending) = WriteAheadLog::open(path)?;
+                if !pending.is_empty() {
+                    log::info!("replaying {} appends from write-ahead log", pending.len());
+                    for append in pending {
+                        writer.append(&append.keyspace, &append.key, append.records)?;
+                    }
+                    writer.flush_all()?;
+                }
+                wal.truncate()?;
+                Some(wal)
+            }
+        };
+        let failed_keys: FailedKeys = Arc::new(Mutex::new(HashMap::new()));
+        let written_keys: WrittenKeys = Arc::new(Mutex::new(HashMap::new()));
+        let flush_all_failed = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(Mutex::new(BatchStats::new()));
+        let mut queue_depths: Vec<Arc<AtomicU64>> = Vec::new();
         let mut executors: Vec<TaskExecutor<W>> = Vec::new();
         for _ in 0..config.writer_thread_count {
-            executors.push(TaskExecutor::new(writer.clone(), &config));
+            let queue_depth = Arc::new(AtomicU64::new(0));
+            executors.push(TaskExecutor::new(
+                writer.clone(),
+                &config,
+                failed_keys.clone(),
+                written_keys.clone(),
+                flush_all_failed.clone(),
+                stats.clone(),
+                queue_depth.clone(),
+            ));
+            queue_depths.push(queue_depth);
         }
         Ok(Self {
             writer,
             writer_thread_count: config.writer_thread_count as u64,
             batch_check_interval_millis: config.batch_check_interval_millis,
+            bulk_mode: config.bulk_mode,
             next_batch_check: RefCell::new(
                 time_now_as_millis() + config.batch_check_interval_millis,
             ),
             thread_lanes: ThreadLanes::new(executors, config.writer_thread_queue_capacity),
+            failed_keys,
+            written_keys,
+            flush_all_failed,
+            stats,
+            queue_depths,
+            wal: RefCell::new(wal),
+            clock: Arc::new(SystemClock),
+            record_transform: None,
+            shut_down: false,
         })
     }
+    /// read the time of batch flush intervals from the given clock instead of the system clock, such as a MockClock in tests
+    pub fn set_clock(mut self, v: Arc<dyn Clock>) -> Self {
+        self.next_batch_check
+            .replace(v.now_millis() + self.batch_check_interval_millis);
+        self.clock = v;
+        self
+    }
+    /// invoke the transform on every appended record before it is written to the write-ahead log and queued,
+    /// so it can redact or normalize values, assign timestamps, or reject an append by returning an error from append.
+    pub fn set_record_transform(mut self, v: RecordTransform) -> Self {
+        self.record_transform = Some(v);
+        self
+    }
+    /// snapshot of batch flush statistics since the writer was created, including the current queue depth of each writer thread
+    pub fn stats(&self) -> BatchStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.lane_queue_depths = self
+            .queue_depths
+            .iter()
+            .map(|d| d.load(Ordering::Relaxed))
+            .collect();
+        stats
+    }
+    /// flush all pending batches and queued appends, then stop the writer threads.
+    /// returns the first failed append since the last flush, so buffered data is never silently dropped on a clean stop.
+    pub fn shutdown(mut self) -> Result<(), StoreError> {
+        let result = self.flush_all();
+        // already flushed, so dropping only stops the writer threads once their queues are drained
+        self.shut_down = true;
+        result
+    }
+}
+impl<W: StoreWriter> Drop for BatchingStoreWriter<W> {
+    /// best-effort flush of batches when the writer is dropped without shutdown, so they are not silently lost.
+    /// drop cannot return an error, so a failed flush is only logged.
+    fn drop(&mut self) {
+        if self.shut_down {
+            return;
+        }
+        if let Err(err) = self.flush_all() {
+            log::error!(
+                "flush on drop of batching writer failed: {}",
+                err.to_string()
+            );
+        }
+    }
+}
+impl<W: StoreWriter> BatchingStoreWriter<W> {
+    fn send(&self, lane: usize, task: Task) {
+        self.queue_depths[lane].fetch_add(1, Ordering::Relaxed);
+        self.thread_lanes.send(lane, task);
+    }
 }
 impl<W: StoreWriter> StoreWriter for BatchingStoreWriter<W> {
     fn create_keyspace(&self, keyspace: &str) -> Result<CreatedKeyspace, StoreError> {
@@ -152,42 +493,109 @@ impl<W: StoreWriter> StoreWriter for BatchingStoreWriter<W> {
         &self,
         keyspace: &str,
         key: &str,
-        inserts: Vec<Insertion>,
+        mut inserts: Vec<Insertion>,
     ) -> Result<(), StoreError> {
-        self.thread_lanes.send(
+        // transformed and checked before anything is logged or queued, so a rejected record only fails its own append
+        // instead of the merged batch of its lane at flush
+        if let Some(transform) = &self.record_transform {
+            for insert in inserts.iter_mut() {
+                transform(insert)?;
+            }
+        }
+        for insert in inserts.iter() {
+            check_insertion_timestamp(insert)?;
+        }
+        if let Some(wal) = self.wal.borrow_mut().as_mut() {
+            // set None timestamps now, so replayed records keep their original timestamps
+            fill_timestamps(&mut inserts, || self.writer.timestamp_now(keyspace))?;
+            wal.write(keyspace, key, &inserts)?;
+        }
+        self.send(
             lane(keyspace, key, self.writer_thread_count),
-            Task::Append(keyspace.to_string(), key.to_string(), inserts),
+            Task::Append(
+                keyspace.to_string(),
+                key.to_string(),
+                inserts,
+                self.clock.now_millis(),
+            ),
         );
         self.duty_cycle()?;
         Ok(())
     }
     fn flush_key(&self, keyspace: &str, key: &str) -> Result<(), StoreError> {
-        self.thread_lanes.send(
+        self.send(
             lane(keyspace, key, self.writer_thread_count),
             Task::FlushKey(keyspace.to_string(), key.to_string()),
         );
         self.thread_lanes
             .flush_lane(lane(keyspace, key, self.writer_thread_count));
-        Ok(())
+        // report a failed append of the key since its last flush
+        let failed = self
+            .failed_keys
+            .lock()
+            .unwrap()
+            .remove(&(keyspace.to_string(), key.to_string()));
+        match failed {
+            None => Ok(()),
+            Some(err) => {
+                if let Some(wal) = self.wal.borrow_mut().as_mut() {
+                    // keep the entries of the failed append when the log is truncated, so they are replayed on restart
+                    wal.retain_key(keyspace, key);
+                }
+                Err(err)
+            }
+        }
     }
     fn flush_all(&self) -> Result<(), StoreError> {
+        // report the first failed append since the last flush, all failures were logged by the writer threads
+        match self.flush_all_with_report()?.failed.into_iter().next() {
+            None => Ok(()),
+            Some(failed) => Err(failed.error),
+        }
+    }
+    /// flush all pending batches like flush_all, reporting every key written since it was last flushed, with its next offset once
+    /// flushed by the underlying writer, and every key with a failed write, so a caller can acknowledge the keys that are durable.
+    /// keys already flushed by flush_key are not reported again.
+    fn flush_all_with_report(&self) -> Result<FlushReport, StoreError> {
         for lane in 0..self.writer_thread_count {
-            self.thread_lanes.send(lane as usize, Task::FlushAll);
+            self.send(lane as usize, Task::FlushAll);
         }
         self.thread_lanes.flush();
-        Ok(())
+        let report = drain_flush_report(&self.written_keys, &self.failed_keys);
+        let flush_all_failed = self.flush_all_failed.swap(false, Ordering::SeqCst);
+        if let Some(wal) = self.wal.borrow_mut().as_mut() {
+            // keep the entries of failed appends, so they are replayed on restart
+            for failed in report.failed.iter() {
+                wal.retain_key(&failed.keyspace, &failed.key);
+            }
+            if flush_all_failed {
+                // the writer of a lane could not tell which keys it failed to flush, so keep the whole log
+                log::warn!("keeping write-ahead log after a failed flush of all keys");
+            } else {
+                // everything else written to the log has now been flushed by the writer of every lane
+                wal.truncate()?;
+            }
+        }
+        Ok(report)
     }
     fn duty_cycle(&self) -> Result<(), StoreError> {
-        let now = time_now_as_millis();
+        if self.bulk_mode {
+            // no interval checks in bulk mode
+            return Ok(());
+        }
+        let now = self.clock.now_millis();
         if now >= *self.next_batch_check.borrow() {
             for lane in 0..self.writer_thread_count {
-                self.thread_lanes.send(lane as usize, Task::CheckWrite);
+                self.send(lane as usize, Task::CheckWrite(now));
             }
             self.next_batch_check
                 .replace(now + self.batch_check_interval_millis);
         }
         Ok(())
     }
+    fn timestamp_now(&self, keyspace: &str) -> Result<i64, StoreError> {
+        self.writer.timestamp_now(keyspace)
+    }
 }
 
 fn lane(keyspace: &str, key: &str, thread_count: u64) -> usize {
@@ -0,0 +1,254 @@
+extern crate exitcode;
+extern crate ini;
+extern crate klstore;
+extern crate log;
+
+use ini::Ini;
+use klstore::*;
+use std::env;
+
+const USAGE: &str = "usage:
+  klstore read <CONFIG_PATH> <KEYSPACE> <KEY> [--from offset:N|ts:N|nonce:N] [--direction fwd|bwd] [--limit N] [--format utf8|hex]
+  klstore meta <CONFIG_PATH> <KEYSPACE> [KEY]
+  klstore layout <CONFIG_PATH> <KEYSPACE> <KEY>";
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        exit_usage();
+    }
+    match args[1].as_str() {
+        "read" => read(&args[2..]),
+        "meta" => meta(&args[2..]),
+        "layout" => layout(&args[2..]),
+        _ => exit_usage(),
+    }
+}
+
+fn exit_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(exitcode::USAGE);
+}
+
+fn load_reader(cfg_path: &str) -> S3StoreReader {
+    let cfg = match Ini::load_from_file(cfg_path) {
+        Ok(v) => v,
+        Err(err) => {
+            log::error!("could not load {}: {}", cfg_path, err.to_string());
+            std::process::exit(exitcode::CONFIG);
+        }
+    };
+    let s3_config = match S3StoreConfig::load(&cfg) {
+        Ok(v) => v,
+        Err(err) => {
+            log::error!("could not load s3 config: {}", err.to_string());
+            std::process::exit(exitcode::CONFIG);
+        }
+    };
+    match S3StoreReader::new(s3_config) {
+        Ok(v) => v,
+        Err(err) => {
+            log::error!("could not instantiate s3 reader: {}", err.to_string());
+            std::process::exit(exitcode::SOFTWARE);
+        }
+    }
+}
+
+enum OutputFormat {
+    Utf8,
+    Hex,
+}
+
+fn read(args: &[String]) {
+    if args.len() < 3 {
+        exit_usage();
+    }
+    let reader = load_reader(&args[0]);
+    let keyspace = &args[1];
+    let key = &args[2];
+
+    // parse options
+    let mut start = StartPosition::First;
+    let mut direction = Direction::Forwards;
+    let mut limit: Option<u64> = None;
+    let mut format = OutputFormat::Utf8;
+    let mut i = 3;
+    while i < args.len() {
+        if i + 1 >= args.len() {
+            exit_usage();
+        }
+        let value = &args[i + 1];
+        match args[i].as_str() {
+            "--from" => start = parse_start_position(value),
+            "--direction" => {
+                direction = match value.as_str() {
+                    "fwd" => Direction::Forwards,
+                    "bwd" => Direction::Backwards,
+                    _ => exit_usage(),
+                }
+            }
+            "--limit" => match value.parse::<u64>() {
+                Ok(v) => limit = Some(v),
+                Err(_) => exit_usage(),
+            },
+            "--format" => {
+                format = match value.as_str() {
+                    "utf8" => OutputFormat::Utf8,
+                    "hex" => OutputFormat::Hex,
+                    _ => exit_usage(),
+                }
+            }
+            _ => exit_usage(),
+        }
+        i += 2;
+    }
+
+    // page through records, following continuation tokens
+    let mut remaining = limit.unwrap_or(u64::MAX);
+    let mut page = match reader.read_first_page(keyspace, key, direction, start, limit) {
+        Ok(v) => v,
+        Err(err) => exit_store_error(err),
+    };
+    loop {
+        for record in page.records.iter() {
+            if remaining == 0 {
+                return;
+            }
+            print_record(record, &format);
+            remaining -= 1;
+        }
+        if remaining == 0 {
+            return;
+        }
+        let continuation = match page.continuation {
+            None => return,
+            Some(v) => v,
+        };
+        page = match reader.read_next_page(keyspace, key, continuation, limit) {
+            Ok(v) => v,
+            Err(err) => exit_store_error(err),
+        };
+    }
+}
+
+fn meta(args: &[String]) {
+    if args.len() < 2 || args.len() > 3 {
+        exit_usage();
+    }
+    let reader = load_reader(&args[0]);
+    let keyspace = &args[1];
+    match reader.read_keyspace_metadata(keyspace) {
+        Ok(v) => println!(
+            "keyspace={} created_timestamp={} timestamp_unit={} format_version={} forward_only={} u64_nonces={}",
+            keyspace,
+            v.created_timestamp,
+            v.timestamp_unit.name(),
+            v.format_version,
+            v.forward_only,
+            v.u64_nonces
+        ),
+        Err(err) => exit_store_error(err),
+    }
+    if args.len() == 3 {
+        let key = &args[2];
+        match reader.read_key_metadata(keyspace, key) {
+            Ok(None) => {
+                println!("key={} not found", key);
+                return;
+            }
+            Ok(Some(v)) => println!(
+                "key={} next_offset={} next_nonce={}",
+                key, v.next_offset, v.next_nonce
+            ),
+            Err(err) => exit_store_error(err),
+        }
+        match reader.read_key_size(keyspace, key) {
+            Ok(v) => println!(
+                "key={} object_count={} total_size={}",
+                key, v.object_count, v.total_size
+            ),
+            Err(err) => exit_store_error(err),
+        }
+    }
+}
+
+fn layout(args: &[String]) {
+    if args.len() != 3 {
+        exit_usage();
+    }
+    let reader = load_reader(&args[0]);
+    let layout = match reader.describe_key(&args[1], &args[2]) {
+        Ok(v) => v,
+        Err(err) => exit_store_error(err),
+    };
+    match layout.watermark_offset {
+        Some(v) => println!("watermark={}", v),
+        None => println!("watermark=-"),
+    }
+    for obj in layout.objects.iter() {
+        println!(
+            "offsets={}-{} timestamps={}-{} nonces={}-{} size={} prior={} sealed={}",
+            obj.first_offset,
+            obj.last_offset,
+            obj.min_timestamp,
+            obj.max_timestamp,
+            obj.first_nonce,
+            obj.next_nonce,
+            obj.size,
+            obj.prior_start_offset,
+            obj.sealed
+        );
+    }
+}
+
+fn parse_start_position(v: &str) -> StartPosition {
+    let (kind, value) = match v.split_once(':') {
+        Some(v) => v,
+        None => exit_usage(),
+    };
+    match kind {
+        "offset" => match value.parse::<u64>() {
+            Ok(v) => StartPosition::Offset(v),
+            Err(_) => exit_usage(),
+        },
+        "ts" => match value.parse::<i64>() {
+            Ok(v) => StartPosition::Timestamp(v),
+            Err(_) => exit_usage(),
+        },
+        "nonce" => match value.parse::<u128>() {
+            Ok(v) => StartPosition::Nonce(v),
+            Err(_) => exit_usage(),
+        },
+        _ => exit_usage(),
+    }
+}
+
+fn print_record(record: &Record, format: &OutputFormat) {
+    let nonce = match record.nonce {
+        Some(v) => v.to_string(),
+        None => "-".to_string(),
+    };
+    let value = match format {
+        OutputFormat::Utf8 => String::from_utf8_lossy(&record.value).to_string(),
+        OutputFormat::Hex => record
+            .value
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(""),
+    };
+    println!(
+        "offset={} timestamp={} nonce={} value={}",
+        record.offset, record.timestamp, nonce, value
+    );
+}
+
+fn exit_store_error(err: StoreError) -> ! {
+    log::error!("{}", err.to_string());
+    if err.is_retryable() {
+        std::process::exit(exitcode::IOERR);
+    } else {
+        std::process::exit(exitcode::SOFTWARE);
+    }
+}
@@ -1,6 +1,10 @@
 use crate::*;
 use linked_hash_map::LinkedHashMap;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::mem::size_of;
+
+/// estimated bookkeeping of a LinkedHashMap entry beyond its key and value, for its hash slot and list links
+const ENTRY_OVERHEAD_BYTES: usize = 64;
 
 pub trait CacheFetcher<K> {
     fn load_key(&self, keyspace: &str, key: &str) -> Result<K, StoreError>;
@@ -9,6 +13,8 @@ pub trait CacheFetcher<K> {
 pub struct StoreCache<K, L: CacheFetcher<K>> {
     loader: L,
     max_cached_keys: usize,
+    max_cached_bytes: usize,
+    cached_bytes: Cell<usize>,
     keys: RefCell<LinkedHashMap<(String, String), K>>,
 }
 impl<K: Clone, L: CacheFetcher<K>> StoreCache<K, L> {
@@ -16,9 +22,16 @@ impl<K: Clone, L: CacheFetcher<K>> StoreCache<K, L> {
         Self {
             loader,
             max_cached_keys,
+            max_cached_bytes: 0,
+            cached_bytes: Cell::new(0),
             keys: RefCell::new(LinkedHashMap::new()),
         }
     }
+    /// also evict the oldest keys while the estimated memory of cached keys exceeds this many bytes, 0 is unbounded
+    pub fn with_max_cached_bytes(mut self, v: usize) -> Self {
+        self.max_cached_bytes = v;
+        self
+    }
     pub fn get_or_read_key(&self, keyspace: &str, key: &str) -> Result<K, StoreError> {
         let mapk = (keyspace.to_string(), key.to_string());
         let mut keys = self.keys.borrow_mut();
@@ -27,23 +40,70 @@ impl<K: Clone, L: CacheFetcher<K>> StoreCache<K, L> {
         }
         let result = match self.loader.load_key(keyspace, key) {
             Ok(k) => {
+                self.cached_bytes
+                    .set(self.cached_bytes.get() + entry_size::<K>(&mapk));
                 keys.insert(mapk.clone(), k.clone());
                 k
             }
             Err(err) => return Err(err),
         };
-        if keys.len() > self.max_cached_keys && keys.len() > 1 {
-            keys.pop_front();
-        }
+        self.evict(&mut keys);
         Ok(result)
     }
+    pub fn contains_key(&self, keyspace: &str, key: &str) -> bool {
+        let mapk = (keyspace.to_string(), key.to_string());
+        self.keys.borrow().contains_key(&mapk)
+    }
     pub fn set_key(&self, keyspace: &str, key: &str, value: K) {
         let mapk = (keyspace.to_string(), key.to_string());
+        let size = entry_size::<K>(&mapk);
         let mut keys = self.keys.borrow_mut();
         if let None = keys.insert(mapk, value) {
-            if keys.len() > self.max_cached_keys && keys.len() > 1 {
-                keys.pop_front();
+            self.cached_bytes.set(self.cached_bytes.get() + size);
+            self.evict(&mut keys);
+        }
+    }
+    /// forget a key so the next access reloads it from storage
+    pub fn remove_key(&self, keyspace: &str, key: &str) {
+        let mapk = (keyspace.to_string(), key.to_string());
+        if let Some(_) = self.keys.borrow_mut().remove(&mapk) {
+            self.cached_bytes
+                .set(self.cached_bytes.get() - entry_size::<K>(&mapk));
+        }
+    }
+    /// forget every key of a keyspace
+    pub fn remove_keyspace(&self, keyspace: &str) {
+        let mut keys = self.keys.borrow_mut();
+        let removed: Vec<(String, String)> = keys
+            .keys()
+            .filter(|mapk| mapk.0 == keyspace)
+            .cloned()
+            .collect();
+        for mapk in removed {
+            keys.remove(&mapk);
+            self.cached_bytes
+                .set(self.cached_bytes.get() - entry_size::<K>(&mapk));
+        }
+    }
+    /// pop the oldest keys until both bounds are met, always keeping the newest key
+    fn evict(&self, keys: &mut LinkedHashMap<(String, String), K>) {
+        while keys.len() > 1
+            && (keys.len() > self.max_cached_keys
+                || (self.max_cached_bytes > 0 && self.cached_bytes.get() > self.max_cached_bytes))
+        {
+            if let Some((mapk, _)) = keys.pop_front() {
+                self.cached_bytes
+                    .set(self.cached_bytes.get() - entry_size::<K>(&mapk));
             }
         }
     }
 }
+
+/// estimated memory of a cached key, counting the keyspace and key strings along with the value
+fn entry_size<K>(mapk: &(String, String)) -> usize {
+    size_of::<(String, String)>()
+        + mapk.0.len()
+        + mapk.1.len()
+        + size_of::<K>()
+        + ENTRY_OVERHEAD_BYTES
+}
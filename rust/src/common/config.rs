@@ -6,6 +6,20 @@ pub fn keyspace_metadata_from_ini(ini: &ini::Ini) -> Result<KeyspaceMetadata, St
             Some(created) => match created.parse::<i64>() {
                 Ok(created) => Ok(KeyspaceMetadata {
                     created_timestamp: created,
+                    timestamp_unit: match section.get("timestamp_unit") {
+                        // keyspaces created before units were configurable are in milliseconds
+                        None | Some("millis") => TimestampUnit::Millis,
+                        Some("nanos") => TimestampUnit::Nanos,
+                        Some(v) => {
+                            return Err(StoreError::BadData(format!(
+                                "invalid keyspace timestamp_unit {}",
+                                v
+                            )))
+                        }
+                    },
+                    format_version: parse_format_version(section.get("format_version"))?,
+                    forward_only: parse_layout_flag("forward_only", section.get("forward_only"))?,
+                    u64_nonces: parse_layout_flag("u64_nonces", section.get("u64_nonces"))?,
                 }),
                 Err(_) => Err(StoreError::BadData(
                     "invalid keyspace created_timestamp".to_string(),
@@ -20,3 +34,36 @@ pub fn keyspace_metadata_from_ini(ini: &ini::Ini) -> Result<KeyspaceMetadata, St
         )),
     }
 }
+
+/// format version of the data objects of a keyspace, rejecting versions newer than this build can read
+fn parse_format_version(v: Option<&str>) -> Result<u32, StoreError> {
+    let version = match v {
+        // keyspaces created before the format was recorded use the first format
+        None => 1,
+        Some(v) => match v.parse::<u32>() {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(StoreError::BadData(format!(
+                    "invalid keyspace format_version {}",
+                    v
+                )))
+            }
+        },
+    };
+    crate::format::check_format_version(version)?;
+    Ok(version)
+}
+
+/// a layout flag of the keyspace config, which is unset for keyspaces created before the flag existed
+fn parse_layout_flag(name: &str, v: Option<&str>) -> Result<bool, StoreError> {
+    match v {
+        None => Ok(false),
+        Some(v) => match v.parse::<bool>() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(StoreError::BadData(format!(
+                "invalid keyspace {} {}",
+                name, v
+            ))),
+        },
+    }
+}
@@ -3,6 +3,40 @@ use crate::common::records::RecordFilter;
 use crate::{Direction, KeyMetadata, StoreError};
 use regex::Regex;
 
+/// zero-padded width of the first offset in data object paths, which fits u64::MAX.
+/// listings rely on paths sorting by offset, so every path of a bucket must use the same width.
+pub const OFFSET_PAD_WIDTH: usize = 20;
+
+/// number of hash shards of sharded key paths, written as two hex digits.
+/// changing it would move every key of a sharded bucket, so it is fixed.
+const KEY_SHARD_COUNT: u32 = 256;
+
+/// the path segment of a key below its keyspace, passed as the key to every path function.
+/// when sharded, the key is prefixed by a short hash of keyspace/key, so keys of a hot keyspace are spread across
+/// S3 partitions instead of sharing one prefix. the hash is FNV-1a, which is deterministic across builds and platforms.
+pub fn key_path_segment(keyspace: &str, key: &str, sharded: bool) -> String {
+    if !sharded {
+        return key.to_string();
+    }
+    let mut hash: u32 = 0x811c9dc5;
+    for b in keyspace.bytes().chain("/".bytes()).chain(key.bytes()) {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    format!("{:02x}/{}", hash % KEY_SHARD_COUNT, key)
+}
+
+/// the key of a path segment from key_path_segment, removing the shard when sharded
+pub fn key_from_path_segment(segment: &str, sharded: bool) -> &str {
+    match sharded {
+        false => segment,
+        true => match segment.find('/') {
+            None => segment,
+            Some(i) => &segment[i + 1..],
+        },
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Watermark {
     pub offset: u64,
@@ -25,22 +59,232 @@ impl Watermark {
     }
     pub fn start_from(&self, root_prefix: &str, keyspace: &str, key: &str) -> String {
         format!(
-            "{}{}/{}/data_o{:0>20}-",
-            root_prefix, keyspace, key, &self.offset,
+            "{}{}/{}/data_o{:0>width$}-",
+            root_prefix,
+            keyspace,
+            key,
+            &self.offset,
+            width = OFFSET_PAD_WIDTH,
         )
     }
 }
 
+/// Sparse index of the objects at or before the watermark of a key.
+/// Compaction never rewrites those objects, so every indexed object stays valid once written.
+#[derive(Clone, Debug)]
+pub struct KeyIndex {
+    pub objects: Vec<KeyPath>,
+}
+impl KeyIndex {
+    const ENTRY_SIZE: usize = 80;
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+        }
+    }
+    pub fn path(root_prefix: &str, keyspace: &str, key: &str) -> String {
+        format!("{}{}/{}/index", root_prefix, keyspace, key)
+    }
+    pub fn from(buffer: &Vec<u8>) -> Result<KeyIndex, StoreError> {
+        if buffer.len() % KeyIndex::ENTRY_SIZE != 0 {
+            return Err(StoreError::BadData("key index length".to_string()));
+        }
+        let mut objects: Vec<KeyPath> = Vec::new();
+        let mut pos: usize = 0;
+        while pos < buffer.len() {
+            objects.push(KeyPath {
+                first_offset: read_u64(&buffer, pos)?,
+                last_offset: read_u64(&buffer, pos + 8)?,
+                min_timestamp: read_i64(&buffer, pos + 16)?,
+                max_timestamp: read_i64(&buffer, pos + 24)?,
+                first_nonce: read_u128(&buffer, pos + 32)?,
+                next_nonce: read_u128(&buffer, pos + 48)?,
+                size: read_u64(&buffer, pos + 64)?,
+                prior_start_offset: read_u64(&buffer, pos + 72)?,
+            });
+            pos += KeyIndex::ENTRY_SIZE;
+        }
+        return Ok(KeyIndex { objects });
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        for obj in self.objects.iter() {
+            append_u64(&mut buf, obj.first_offset);
+            append_u64(&mut buf, obj.last_offset);
+            append_i64(&mut buf, obj.min_timestamp);
+            append_i64(&mut buf, obj.max_timestamp);
+            append_u128(&mut buf, obj.first_nonce);
+            append_u128(&mut buf, obj.next_nonce);
+            append_u64(&mut buf, obj.size);
+            append_u64(&mut buf, obj.prior_start_offset);
+        }
+        return buf;
+    }
+    /// first offset of the last indexed object, or 0 when empty
+    pub fn last_start_offset(&self) -> u64 {
+        match self.objects.last() {
+            None => 0,
+            Some(v) => v.first_offset,
+        }
+    }
+}
+
+/// Offset range, record count, and size of every compacted object of a key, written by compaction when compaction_manifest is enabled.
+/// Compacted objects are only ever replaced by objects covering the same offsets, so any offset in the manifest
+/// that is not covered by a listed object was lost, such as by a compaction that failed partway through deleting.
+#[derive(Clone, Debug)]
+pub struct CompactionManifest {
+    pub segments: Vec<ManifestSegment>,
+}
+#[derive(Clone, Debug)]
+pub struct ManifestSegment {
+    pub first_offset: u64,
+    pub last_offset: u64,
+    pub record_count: u64,
+    pub size: u64,
+}
+impl CompactionManifest {
+    const ENTRY_SIZE: usize = 32;
+    pub fn new() -> Self {
+        Self {
+            segments: Vec::new(),
+        }
+    }
+    pub fn path(root_prefix: &str, keyspace: &str, key: &str) -> String {
+        format!("{}{}/{}/manifest", root_prefix, keyspace, key)
+    }
+    pub fn from(buffer: &Vec<u8>) -> Result<CompactionManifest, StoreError> {
+        if buffer.len() % CompactionManifest::ENTRY_SIZE != 0 {
+            return Err(StoreError::BadData(
+                "compaction manifest length".to_string(),
+            ));
+        }
+        let mut segments: Vec<ManifestSegment> = Vec::new();
+        let mut pos: usize = 0;
+        while pos < buffer.len() {
+            segments.push(ManifestSegment {
+                first_offset: read_u64(&buffer, pos)?,
+                last_offset: read_u64(&buffer, pos + 8)?,
+                record_count: read_u64(&buffer, pos + 16)?,
+                size: read_u64(&buffer, pos + 24)?,
+            });
+            pos += CompactionManifest::ENTRY_SIZE;
+        }
+        return Ok(CompactionManifest { segments });
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        for segment in self.segments.iter() {
+            append_u64(&mut buf, segment.first_offset);
+            append_u64(&mut buf, segment.last_offset);
+            append_u64(&mut buf, segment.record_count);
+            append_u64(&mut buf, segment.size);
+        }
+        return buf;
+    }
+    /// replace every segment at or after the first of the given segments, which a compaction rewrote
+    pub fn replace_from(&mut self, segments: Vec<ManifestSegment>) {
+        if let Some(first) = segments.first() {
+            let first_offset = first.first_offset;
+            self.segments.retain(|s| s.last_offset < first_offset);
+        }
+        self.segments.extend(segments);
+    }
+    /// remove every segment that ends before first_offset, whose objects were truncated
+    pub fn truncate_before(&mut self, first_offset: u64) {
+        self.segments.retain(|s| s.last_offset >= first_offset);
+    }
+    /// first and last offset covered by compacted objects, or None when empty
+    pub fn offset_range(&self) -> Option<(u64, u64)> {
+        match (self.segments.first(), self.segments.last()) {
+            (Some(first), Some(last)) => Some((first.first_offset, last.last_offset)),
+            _ => None,
+        }
+    }
+}
+
+/// Lease held by a writer while it compacts a key, created with a conditional PUT so only one compactor holds it.
+/// A lease is ignored once expired, so a compactor that crashed does not block compaction forever.
+/// The owner is a random token of the acquisition, so a compactor recognizes its own lease.
+#[derive(Clone, Debug)]
+pub struct CompactionLease {
+    pub expires_millis: u64,
+    pub owner: u64,
+}
+impl CompactionLease {
+    const SIZE: usize = 16;
+    /// size of leases written before they had an owner, which are read with owner 0
+    const UNOWNED_SIZE: usize = 8;
+    pub fn path(root_prefix: &str, keyspace: &str, key: &str) -> String {
+        format!("{}{}/{}/compacting", root_prefix, keyspace, key)
+    }
+    pub fn from(buffer: &Vec<u8>) -> Result<CompactionLease, StoreError> {
+        match buffer.len() {
+            CompactionLease::SIZE => Ok(CompactionLease {
+                expires_millis: read_u64(&buffer, 0)?,
+                owner: read_u64(&buffer, 8)?,
+            }),
+            CompactionLease::UNOWNED_SIZE => Ok(CompactionLease {
+                expires_millis: read_u64(&buffer, 0)?,
+                owner: 0,
+            }),
+            _ => Err(StoreError::BadData("compaction lease length".to_string())),
+        }
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        append_u64(&mut buf, self.expires_millis);
+        append_u64(&mut buf, self.owner);
+        buf
+    }
+}
+
+/// Next nonce of a key set by reset_nonce, which applies until an object is written at next_offset.
+/// Objects written after the reset carry the new nonce sequence in their paths, which makes the reset obsolete.
+#[derive(Clone, Debug)]
+pub struct NonceReset {
+    pub next_offset: u64,
+    pub next_nonce: u128,
+}
+impl NonceReset {
+    const SIZE: usize = 24;
+    pub fn path(root_prefix: &str, keyspace: &str, key: &str) -> String {
+        format!("{}{}/{}/nonce_reset", root_prefix, keyspace, key)
+    }
+    pub fn from(buffer: &Vec<u8>) -> Result<NonceReset, StoreError> {
+        if buffer.len() != NonceReset::SIZE {
+            return Err(StoreError::BadData("nonce reset length".to_string()));
+        }
+        Ok(NonceReset {
+            next_offset: read_u64(&buffer, 0)?,
+            next_nonce: read_u128(&buffer, 8)?,
+        })
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        append_u64(&mut buf, self.next_offset);
+        append_u128(&mut buf, self.next_nonce);
+        return buf;
+    }
+    /// override the next nonce of metadata from object paths, only when nothing was written since the reset
+    pub fn apply(&self, metadata: &mut KeyMetadata) {
+        if metadata.next_offset == self.next_offset {
+            metadata.next_nonce = self.next_nonce;
+        }
+    }
+}
+
 pub struct KeyspacePath {}
 impl KeyspacePath {
     pub fn config_path(root_prefix: &str, keyspace: &str) -> String {
         format!("{}{}_config.ini", root_prefix, keyspace)
     }
-    // pub fn prefix(root_prefix: &str, keyspace: &str) -> String {
-    //     format!("{}{}/", root_prefix, keyspace)
-    // }
+    pub fn prefix(root_prefix: &str, keyspace: &str) -> String {
+        format!("{}{}/", root_prefix, keyspace)
+    }
 }
 
+/// the ranges of a data object, decoded from its path
 #[derive(Debug, Clone)]
 pub struct KeyPath {
     pub first_offset: u64,
@@ -55,7 +299,7 @@ pub struct KeyPath {
 impl KeyPath {
     pub fn to_path(&self, root_prefix: &str, keyspace: &str, key: &str) -> String {
         format!(
-            "{}{}/{}/data_o{:0>20}-o{}_t{}-t{}_n{}-n{}_s{}_p{}.bin",
+            "{}{}/{}/data_o{:0>width$}-o{}_t{}-t{}_n{}-n{}_s{}_p{}.bin",
             root_prefix,
             keyspace,
             key,
@@ -67,11 +311,30 @@ impl KeyPath {
             &self.next_nonce,
             &self.size,
             &self.prior_start_offset,
+            width = OFFSET_PAD_WIDTH,
+        )
+    }
+    /// prefix of every object of a key, including its watermark, index, and markers
+    pub fn prefix(root_prefix: &str, keyspace: &str, key: &str) -> String {
+        format!("{}{}/{}/", root_prefix, keyspace, key)
+    }
+    /// path of the marker conditionally written before the data object appended at first_offset.
+    /// it is outside of the data prefix, so it is never listed as a data object.
+    pub fn offset_lock_path(
+        root_prefix: &str,
+        keyspace: &str,
+        key: &str,
+        first_offset: u64,
+    ) -> String {
+        format!(
+            "{}{}/{}/o{:0>width$}.lock",
+            root_prefix,
+            keyspace,
+            key,
+            first_offset,
+            width = OFFSET_PAD_WIDTH,
         )
     }
-    // pub fn prefix(root_prefix: &str, keyspace: &str, key: &str) -> String {
-    //     format!("{}{}/{}/", root_prefix, keyspace, key)
-    // }
     pub fn prefix_data_only(root_prefix: &str, keyspace: &str, key: &str) -> String {
         format!("{}{}/{}/data_", root_prefix, keyspace, key)
     }
@@ -82,8 +345,12 @@ impl KeyPath {
         watermark: &Watermark,
     ) -> String {
         format!(
-            "{}{}/{}/data_o{:0>20}",
-            root_prefix, keyspace, key, watermark.offset
+            "{}{}/{}/data_o{:0>width$}",
+            root_prefix,
+            keyspace,
+            key,
+            watermark.offset,
+            width = OFFSET_PAD_WIDTH,
         )
     }
     pub fn after_watermark_prefix(
@@ -104,11 +371,12 @@ impl KeyPath {
             format!("{}{}/{}/data_o", root_prefix, keyspace, key)
         } else {
             format!(
-                "{}{}/{}/data_o{:0>20}",
+                "{}{}/{}/data_o{:0>width$}",
                 root_prefix,
                 keyspace,
                 key,
-                offset + 1
+                offset + 1,
+                width = OFFSET_PAD_WIDTH,
             )
         }
     }
@@ -140,6 +408,7 @@ pub struct KeyPathParser {
 impl KeyPathParser {
     pub fn new() -> Self {
         Self {
+            // negative timestamps are rejected by appends, but still parsed from objects written before that
             rex: Regex::new(
                 r"/data_o(\d+)-o(\d+)_t(-?\d+)-t(-?\d+)_n(\d+)-n(\d+)_s(\d+)_p(\d+)\.bin$",
             )
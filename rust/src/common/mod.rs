@@ -1,3 +1,5 @@
+// the kafka number parsers are the only users of some widths and byte orders
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
 pub mod buffer;
 pub mod cache;
 pub mod config;
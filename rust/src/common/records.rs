@@ -2,44 +2,185 @@ use crate::common::buffer::*;
 use crate::*;
 use std::cmp::{max, min};
 
+/// version of the record layout within data objects, written to the config of each created keyspace.
+/// options of the layout, such as forward-only records and u64 nonces, are separate flags of the config.
+pub const RECORD_FORMAT_VERSION: u32 = 1;
+
+/// layout of the records of a data object, determined by the format version and layout flags of its keyspace
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordLayout {
+    /// each record ends with its total length, so records can be iterated backwards
+    pub trailer: bool,
+    /// nonces are u64 instead of u128, with u64::MAX for no nonce
+    pub u64_nonces: bool,
+}
+impl RecordLayout {
+    /// the default layout, which is also the layout of keyspaces created before layouts were recorded
+    pub fn new() -> Self {
+        Self {
+            trailer: true,
+            u64_nonces: false,
+        }
+    }
+    /// omit the trailing total length of every record, saving 4 bytes per record, but records can only be read forwards
+    pub fn set_forward_only(mut self, v: bool) -> Self {
+        self.trailer = !v;
+        self
+    }
+    /// store nonces as u64 instead of u128, saving 8 bytes per record
+    pub fn set_u64_nonces(mut self, v: bool) -> Self {
+        self.u64_nonces = v;
+        self
+    }
+    /// layout of the data objects of a keyspace, from the format version and layout flags of its config
+    pub fn for_keyspace(metadata: &KeyspaceMetadata) -> Result<Self, StoreError> {
+        crate::format::check_format_version(metadata.format_version)?;
+        Ok(Self::new()
+            .set_forward_only(metadata.forward_only)
+            .set_u64_nonces(metadata.u64_nonces))
+    }
+    /// return an error if a nonce does not fit the nonce width, whose max value means no nonce
+    pub fn check_nonce(&self, nonce: Option<u128>) -> Result<(), StoreError> {
+        match nonce {
+            Some(v) if self.u64_nonces && v >= u64::MAX as u128 => Err(StoreError::BadData(
+                format!("nonce {} exceeds the u64 nonces of the keyspace", v),
+            )),
+            _ => Ok(()),
+        }
+    }
+    fn header_size(&self) -> usize {
+        match self.u64_nonces {
+            true => 28,
+            false => 36,
+        }
+    }
+    fn trailer_size(&self) -> usize {
+        match self.trailer {
+            true => 4,
+            false => 0,
+        }
+    }
+}
+
 pub struct SerializedInsertion {
     pub first_insert_offset: u64,
     pub last_insert_offset: u64,
     pub next_offset: u64,
     pub min_timestamp: i64,
     pub max_timestamp: i64,
+    /// number of timestamps raised to keep timestamps from decreasing with offset
+    pub clamped_count: u64,
     pub buffer: Vec<u8>,
 }
 
-pub fn serialize_insertion(inserts: &Vec<&Insertion>, next_offset: u64) -> SerializedInsertion {
-    let mut buffer: Vec<u8> = Vec::new();
-    let mut min_timestamp = i64::MAX;
-    let mut max_timestamp = i64::MIN;
-    let first_insert_offset = next_offset;
-    let mut cur_offset = first_insert_offset;
+/// serialize inserts starting at next_offset, assigning now to inserts without a timestamp.
+/// when monotonic_from is given, timestamps lower than it or any prior insert are clamped so timestamps never decrease with offset.
+pub fn serialize_insertion(
+    inserts: &Vec<&Insertion>,
+    next_offset: u64,
+    now: i64,
+    monotonic_from: Option<i64>,
+    layout: RecordLayout,
+) -> SerializedInsertion {
+    let mut serializer = InsertionSerializer::new(next_offset, now, monotonic_from, layout);
     for insert in (&inserts).iter() {
-        let timestamp = super::time::insertion_timestamp(&insert);
-        let nonce = match insert.nonce {
-            None => u128::MAX,
-            Some(v) => v,
-        };
-        min_timestamp = min(min_timestamp, timestamp);
-        max_timestamp = max(max_timestamp, timestamp);
-        append_u64(&mut buffer, cur_offset);
-        append_i64(&mut buffer, timestamp);
-        append_u128(&mut buffer, nonce);
-        append_u32(&mut buffer, insert.record.len() as u32);
-        append_buffer(&mut buffer, &insert.record);
-        append_u32(&mut buffer, 36 + insert.record.len() as u32);
-        cur_offset += 1;
+        serializer.push(insert);
     }
-    SerializedInsertion {
-        first_insert_offset,
-        last_insert_offset: cur_offset - 1,
-        next_offset: cur_offset,
-        min_timestamp,
-        max_timestamp,
-        buffer,
+    serializer.finish()
+}
+
+/// size of an insert once serialized, including its header and any trailing total length
+pub fn serialized_size(insert: &Insertion, layout: RecordLayout) -> u64 {
+    (layout.header_size() + insert.record.len() + layout.trailer_size()) as u64
+}
+
+/// serializes inserts one at a time, so an append does not need every insert in memory at once
+pub struct InsertionSerializer {
+    buffer: Vec<u8>,
+    first_insert_offset: u64,
+    cur_offset: u64,
+    min_timestamp: i64,
+    max_timestamp: i64,
+    now: i64,
+    monotonic: bool,
+    clamped_count: u64,
+    layout: RecordLayout,
+}
+impl InsertionSerializer {
+    pub fn new(
+        next_offset: u64,
+        now: i64,
+        monotonic_from: Option<i64>,
+        layout: RecordLayout,
+    ) -> Self {
+        Self {
+            buffer: Vec::new(),
+            first_insert_offset: next_offset,
+            cur_offset: next_offset,
+            min_timestamp: i64::MAX,
+            max_timestamp: match monotonic_from {
+                None => i64::MIN,
+                Some(v) => v,
+            },
+            now,
+            monotonic: monotonic_from.is_some(),
+            clamped_count: 0,
+            layout,
+        }
+    }
+    pub fn push(&mut self, insert: &Insertion) {
+        let mut timestamp = super::time::insertion_timestamp(&insert, self.now);
+        if self.monotonic && timestamp < self.max_timestamp {
+            timestamp = self.max_timestamp;
+            self.clamped_count += 1;
+        }
+        self.min_timestamp = min(self.min_timestamp, timestamp);
+        self.max_timestamp = max(self.max_timestamp, timestamp);
+        append_u64(&mut self.buffer, self.cur_offset);
+        append_i64(&mut self.buffer, timestamp);
+        match self.layout.u64_nonces {
+            // nonces were checked to fit by the writer or format::serialize_records
+            true => append_u64(
+                &mut self.buffer,
+                match insert.nonce {
+                    None => u64::MAX,
+                    Some(v) => v as u64,
+                },
+            ),
+            false => append_u128(
+                &mut self.buffer,
+                match insert.nonce {
+                    None => u128::MAX,
+                    Some(v) => v,
+                },
+            ),
+        }
+        append_u32(&mut self.buffer, insert.record.len() as u32);
+        append_buffer(&mut self.buffer, &insert.record);
+        if self.layout.trailer {
+            let total_length = self.layout.header_size() + insert.record.len();
+            append_u32(&mut self.buffer, total_length as u32);
+        }
+        self.cur_offset += 1;
+    }
+    /// number of inserts pushed so far
+    pub fn count(&self) -> u64 {
+        self.cur_offset - self.first_insert_offset
+    }
+    /// size of the serialized inserts so far
+    pub fn size(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+    pub fn finish(self) -> SerializedInsertion {
+        SerializedInsertion {
+            first_insert_offset: self.first_insert_offset,
+            last_insert_offset: self.cur_offset - 1,
+            next_offset: self.cur_offset,
+            min_timestamp: self.min_timestamp,
+            max_timestamp: self.max_timestamp,
+            clamped_count: self.clamped_count,
+            buffer: self.buffer,
+        }
     }
 }
 
@@ -50,6 +191,9 @@ pub struct RecordFilter {
     pub start_timestamp: i64,
     pub start_nonce: u128,
     pub direction: Direction,
+    /// applied after the offset, timestamp, and nonce filtering, only matching records count towards max_size
+    pub predicate: Option<RecordPredicate>,
+    pub layout: RecordLayout,
 }
 impl RecordFilter {
     pub fn from(
@@ -66,6 +210,8 @@ impl RecordFilter {
                     start_timestamp: i64::MIN,
                     start_nonce: u128::MIN,
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
                 StartPosition::Nonce(v) => RecordFilter {
                     defined: true,
@@ -74,6 +220,8 @@ impl RecordFilter {
                     start_timestamp: i64::MIN,
                     start_nonce: v.clone(),
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
                 StartPosition::Timestamp(v) => RecordFilter {
                     defined: true,
@@ -82,14 +230,18 @@ impl RecordFilter {
                     start_timestamp: v.clone(),
                     start_nonce: u128::MIN,
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
-                StartPosition::First => RecordFilter {
+                StartPosition::Default | StartPosition::First => RecordFilter {
                     defined: false,
                     max_size,
                     start_offset: u64::MIN,
                     start_timestamp: i64::MIN,
                     start_nonce: u128::MIN,
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
             },
             Direction::Backwards => match position {
@@ -100,6 +252,8 @@ impl RecordFilter {
                     start_timestamp: i64::MAX,
                     start_nonce: u128::MAX,
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
                 StartPosition::Nonce(v) => RecordFilter {
                     defined: true,
@@ -108,6 +262,8 @@ impl RecordFilter {
                     start_timestamp: i64::MAX,
                     start_nonce: v.clone(),
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
                 StartPosition::Timestamp(v) => RecordFilter {
                     defined: true,
@@ -116,14 +272,18 @@ impl RecordFilter {
                     start_timestamp: v.clone(),
                     start_nonce: u128::MAX,
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
-                StartPosition::First => RecordFilter {
+                StartPosition::Default | StartPosition::First => RecordFilter {
                     defined: false,
                     max_size,
                     start_offset: u64::MAX,
                     start_timestamp: i64::MAX,
                     start_nonce: u128::MAX,
                     direction,
+                    predicate: None,
+                    layout: RecordLayout::new(),
                 },
             },
         }
@@ -141,6 +301,8 @@ impl RecordFilter {
                 start_timestamp: i64::MIN,
                 start_nonce: u128::MIN,
                 direction,
+                predicate: None,
+                layout: RecordLayout::new(),
             },
             Direction::Backwards => RecordFilter {
                 defined: true,
@@ -149,9 +311,32 @@ impl RecordFilter {
                 start_timestamp: i64::MAX,
                 start_nonce: u128::MAX,
                 direction,
+                predicate: None,
+                layout: RecordLayout::new(),
             },
         }
     }
+    pub fn with_predicate(mut self, predicate: &Option<RecordPredicate>) -> Self {
+        self.predicate = predicate.clone();
+        self
+    }
+    pub fn with_layout(mut self, layout: RecordLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+    /// the same filter for the rest of a page that already holds the given number of records
+    pub fn remaining(&self, collected: usize) -> RecordFilter {
+        RecordFilter {
+            defined: self.defined,
+            max_size: self.max_size.saturating_sub(collected as u64),
+            start_offset: self.start_offset,
+            start_timestamp: self.start_timestamp,
+            start_nonce: self.start_nonce,
+            direction: self.direction.clone(),
+            predicate: self.predicate.clone(),
+            layout: self.layout,
+        }
+    }
 }
 
 fn record_in_range(
@@ -206,7 +391,7 @@ fn record_in_range(
 }
 
 pub fn deserialize_and_filter_records(
-    buffer: &Vec<u8>,
+    buffer: &[u8],
     records: &mut Vec<Record>,
     filter: &RecordFilter,
     continuation_offset: u64,
@@ -214,48 +399,83 @@ pub fn deserialize_and_filter_records(
     match filter.direction {
         Direction::Forwards => {
             let mut pos: usize = 0;
+            let mut found_first_match = !records.is_empty();
             while pos < buffer.len() && (records.len() as u64) < filter.max_size {
                 // deserialize header and check if it's in range
-                let header = RecordHeader::deserialize(buffer, pos)?;
-                pos += RecordHeader::SIZE;
+                let header = RecordHeader::deserialize(buffer, pos, filter.layout)?;
+                verify_record_framing(buffer, pos, &header, filter.layout)?;
+                pos += filter.layout.header_size();
                 if header.offset >= continuation_offset
-                    && record_in_range(&header, filter, !records.is_empty())
+                    && record_in_range(&header, filter, found_first_match)
                 {
                     // matching, add to records
+                    found_first_match = true;
                     let value = read_bytes_copy(buffer, pos, header.length as usize);
-                    records.push(Record {
-                        offset: header.offset,
-                        timestamp: header.timestamp,
-                        nonce: header.nonce,
-                        value,
-                    });
+                    push_if_matches_predicate(
+                        records,
+                        filter,
+                        Record {
+                            offset: header.offset,
+                            timestamp: header.timestamp,
+                            nonce: header.nonce,
+                            value,
+                        },
+                    );
                 }
                 pos += header.length as usize;
-                pos += 4; // trailing total length field
+                pos += filter.layout.trailer_size();
             }
             // return if read fully
             return Ok(pos == buffer.len());
         }
         Direction::Backwards => {
+            if !filter.layout.trailer {
+                return Err(StoreError::BadConfiguration(
+                    "records without a trailing total length can not be read backwards".to_string(),
+                ));
+            }
             let mut pos: usize = buffer.len();
+            let mut found_first_match = !records.is_empty();
             while pos > 0 && (records.len() as u64) < filter.max_size {
                 // read total length of trailing record
+                if pos < 4 {
+                    return Err(StoreError::BadData(format!(
+                        "truncated record trailer at {}",
+                        pos
+                    )));
+                }
                 pos -= 4;
-                let total_length = read_u32(buffer, pos)?;
-                pos -= total_length as usize;
+                let total_length = read_u32(buffer, pos)? as usize;
+                if total_length < filter.layout.header_size() || total_length > pos {
+                    return Err(StoreError::BadData(format!(
+                        "invalid record total length {} at {}",
+                        total_length, pos
+                    )));
+                }
+                pos -= total_length;
                 // deserialize header and check if it's in range
-                let header = RecordHeader::deserialize(buffer, pos)?;
+                let header = RecordHeader::deserialize(buffer, pos, filter.layout)?;
+                verify_record_framing(buffer, pos, &header, filter.layout)?;
                 if header.offset <= continuation_offset
-                    && record_in_range(&header, filter, !records.is_empty())
+                    && record_in_range(&header, filter, found_first_match)
                 {
                     // matching, add to records
-                    let value = read_bytes_copy(buffer, pos, header.length as usize);
-                    records.push(Record {
-                        offset: header.offset,
-                        timestamp: header.timestamp,
-                        nonce: header.nonce,
-                        value,
-                    });
+                    found_first_match = true;
+                    let value = read_bytes_copy(
+                        buffer,
+                        pos + filter.layout.header_size(),
+                        header.length as usize,
+                    );
+                    push_if_matches_predicate(
+                        records,
+                        filter,
+                        Record {
+                            offset: header.offset,
+                            timestamp: header.timestamp,
+                            nonce: header.nonce,
+                            value,
+                        },
+                    );
                 }
             }
             // return if read fully
@@ -264,6 +484,136 @@ pub fn deserialize_and_filter_records(
     }
 }
 
+/// invoke f with every record of an object from start_offset through end_offset inclusive, in offset order.
+/// values borrow from buffer, so no record is copied.
+pub fn visit_records_in_range(
+    buffer: &[u8],
+    layout: RecordLayout,
+    start_offset: u64,
+    end_offset: u64,
+    f: &mut dyn FnMut(RecordRef),
+) -> Result<(), StoreError> {
+    let mut pos: usize = 0;
+    while pos < buffer.len() {
+        let header = RecordHeader::deserialize(buffer, pos, layout)?;
+        verify_record_framing(buffer, pos, &header, layout)?;
+        pos += layout.header_size();
+        if header.offset > end_offset {
+            break;
+        }
+        if header.offset >= start_offset {
+            f(RecordRef {
+                offset: header.offset,
+                timestamp: header.timestamp,
+                nonce: header.nonce,
+                value: &buffer[pos..pos + header.length as usize],
+            });
+        }
+        pos += header.length as usize;
+        pos += layout.trailer_size();
+    }
+    Ok(())
+}
+
+/// count the records of an object, verifying the framing of every record
+pub fn count_records(buffer: &[u8], layout: RecordLayout) -> Result<u64, StoreError> {
+    let mut pos: usize = 0;
+    let mut count: u64 = 0;
+    while pos < buffer.len() {
+        let header = RecordHeader::deserialize(buffer, pos, layout)?;
+        verify_record_framing(buffer, pos, &header, layout)?;
+        pos += layout.header_size() + header.length as usize + layout.trailer_size();
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// true when the layout frames every record of an object exactly, with consecutive offsets from first_offset to last_offset
+pub fn layout_matches(
+    buffer: &[u8],
+    layout: RecordLayout,
+    first_offset: u64,
+    last_offset: u64,
+) -> bool {
+    let mut pos: usize = 0;
+    let mut offset = first_offset;
+    while pos < buffer.len() {
+        let header = match RecordHeader::deserialize(buffer, pos, layout) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        if header.offset != offset || verify_record_framing(buffer, pos, &header, layout).is_err() {
+            return false;
+        }
+        pos += layout.header_size() + header.length as usize + layout.trailer_size();
+        offset += 1;
+    }
+    offset == last_offset + 1
+}
+
+/// length of the complete records at the start of a prefix of an object, excluding a trailing partial record
+pub fn complete_prefix_length(buffer: &[u8], layout: RecordLayout) -> Result<usize, StoreError> {
+    let mut pos: usize = 0;
+    while pos + layout.header_size() <= buffer.len() {
+        let header = RecordHeader::deserialize(buffer, pos, layout)?;
+        let next_pos = pos + layout.header_size() + header.length as usize + layout.trailer_size();
+        if next_pos > buffer.len() {
+            break;
+        }
+        pos = next_pos;
+    }
+    Ok(pos)
+}
+
+/// position of the first complete record of a suffix of an object, following trailing total lengths back from the end
+pub fn complete_suffix_start(buffer: &[u8]) -> Result<usize, StoreError> {
+    let mut pos: usize = buffer.len();
+    while pos >= 4 {
+        let total_length = read_u32(buffer, pos - 4)? as usize;
+        if total_length + 4 > pos {
+            break;
+        }
+        pos -= total_length + 4;
+    }
+    Ok(pos)
+}
+
+fn push_if_matches_predicate(records: &mut Vec<Record>, filter: &RecordFilter, record: Record) {
+    match &filter.predicate {
+        Some(predicate) if !predicate(&record) => {}
+        _ => records.push(record),
+    }
+}
+
+/// verify the leading value length agrees with the trailing total length, which is the header plus value size.
+/// a mismatch means corruption, so stop instead of parsing the next record from the wrong position.
+/// without a trailer, only verify the record fits in the buffer.
+fn verify_record_framing(
+    buffer: &[u8],
+    header_pos: usize,
+    header: &RecordHeader,
+    layout: RecordLayout,
+) -> Result<(), StoreError> {
+    let trailer_pos = header_pos + layout.header_size() + header.length as usize;
+    if trailer_pos + layout.trailer_size() > buffer.len() {
+        return Err(StoreError::BadData(format!(
+            "record at offset {} with length {} exceeds buffer",
+            header.offset, header.length
+        )));
+    }
+    if !layout.trailer {
+        return Ok(());
+    }
+    let total_length = read_u32(buffer, trailer_pos)? as usize;
+    if total_length != layout.header_size() + header.length as usize {
+        return Err(StoreError::BadData(format!(
+            "record at offset {} has length {} but total length {}",
+            header.offset, header.length, total_length
+        )));
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 struct RecordHeader {
     pub offset: u64,
@@ -272,17 +622,39 @@ struct RecordHeader {
     pub length: u32,
 }
 impl RecordHeader {
-    const SIZE: usize = 36;
-    fn deserialize(buffer: &[u8], mut pos: usize) -> Result<Self, StoreError> {
+    fn deserialize(
+        buffer: &[u8],
+        mut pos: usize,
+        layout: RecordLayout,
+    ) -> Result<Self, StoreError> {
+        if pos + layout.header_size() > buffer.len() {
+            return Err(StoreError::BadData(format!(
+                "truncated record header at {}",
+                pos
+            )));
+        }
         let offset = read_u64(buffer, pos)?;
         pos += 8;
         let timestamp = read_i64(buffer, pos)?;
         pos += 8;
-        let nonce = match read_u128(buffer, pos)? {
-            u128::MAX => None,
-            v => Some(v),
+        let nonce = match layout.u64_nonces {
+            true => {
+                let nonce = match read_u64(buffer, pos)? {
+                    u64::MAX => None,
+                    v => Some(v as u128),
+                };
+                pos += 8;
+                nonce
+            }
+            false => {
+                let nonce = match read_u128(buffer, pos)? {
+                    u128::MAX => None,
+                    v => Some(v),
+                };
+                pos += 16;
+                nonce
+            }
         };
-        pos += 16;
         let length = read_u32(buffer, pos)?;
         Ok(Self {
             offset,
@@ -295,23 +667,15 @@ impl RecordHeader {
 
 pub struct NonceFilterResult<'a> {
     pub records: Vec<&'a Insertion>,
-    pub first_nonce: Option<u128>,
-    pub first_potential_nonce: u128,
-    pub next_nonce: u128,
 }
 
 pub fn nonce_filter<'a>(records: &'a Vec<Insertion>, next_nonce: u128) -> NonceFilterResult<'a> {
-    let mut first_nonce: Option<u128> = None;
-    let first_potential_nonce = next_nonce;
     let mut next_nonce = next_nonce;
     let records: Vec<&Insertion> = (&records)
         .iter()
         .filter(|e| match e.nonce {
             Some(nonce) => {
                 if nonce >= next_nonce {
-                    if let None = first_nonce {
-                        first_nonce = Some(nonce);
-                    }
                     next_nonce = nonce + 1;
                     true
                 } else {
@@ -321,10 +685,12 @@ pub fn nonce_filter<'a>(records: &'a Vec<Insertion>, next_nonce: u128) -> NonceF
             None => true,
         })
         .collect();
+    NonceFilterResult { records }
+}
+
+/// keep every record regardless of nonces, the nonce range of each written object is derived from its records
+pub fn nonce_passthrough<'a>(records: &'a Vec<Insertion>) -> NonceFilterResult<'a> {
     NonceFilterResult {
-        records,
-        first_nonce,
-        first_potential_nonce,
-        next_nonce,
+        records: records.iter().collect(),
     }
 }
@@ -1,14 +1,71 @@
 use crate::*;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn insertion_timestamp(insert: &Insertion) -> i64 {
+/// source of the current time for flush and commit intervals, so tests can control time instead of sleeping
+pub trait Clock: Send + Sync {
+    /// milliseconds since the epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// the system clock, the default Clock everywhere
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        time_now_as_millis()
+    }
+}
+
+/// the timestamp of the insert, or now when it has none
+pub fn insertion_timestamp(insert: &Insertion, now: i64) -> i64 {
     match insert.timestamp {
         Some(v) => v,
-        None => SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("system time is negative")
-            .as_millis() as i64,
+        None => now,
+    }
+}
+
+/// set the timestamp of every insert without one to now, only reading the time when one is missing
+pub fn fill_timestamps<F: FnOnce() -> Result<i64, StoreError>>(
+    inserts: &mut Vec<Insertion>,
+    now: F,
+) -> Result<(), StoreError> {
+    if inserts.iter().all(|insert| insert.timestamp.is_some()) {
+        return Ok(());
+    }
+    let now = now()?;
+    for insert in inserts.iter_mut() {
+        if let None = insert.timestamp {
+            insert.timestamp = Some(now);
+        }
+    }
+    Ok(())
+}
+
+/// timestamps are written unpadded in object paths, where a negative timestamp would not sort before positive ones,
+/// so appends reject them rather than write paths that break timestamp-ordered listings.
+pub fn check_insertion_timestamp(insert: &Insertion) -> Result<(), StoreError> {
+    match insert.timestamp {
+        Some(v) if v < 0 => Err(StoreError::BadData(format!(
+            "negative timestamp {} is not supported",
+            v
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// return the timestamp of the insert, or BadData if it is lower than max_timestamp, for TimestampPolicy::Reject
+pub fn check_timestamp_not_before(
+    insert: &Insertion,
+    now: i64,
+    max_timestamp: i64,
+) -> Result<i64, StoreError> {
+    let timestamp = insertion_timestamp(insert, now);
+    if timestamp < max_timestamp {
+        return Err(StoreError::BadData(format!(
+            "timestamp {} is before the max timestamp {} of the key",
+            timestamp, max_timestamp
+        )));
     }
+    Ok(timestamp)
 }
 
 pub fn time_now_as_millis() -> u64 {
@@ -0,0 +1,3 @@
+mod parquet;
+
+pub use self::parquet::export_parquet;
@@ -0,0 +1,113 @@
+use crate::*;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+use std::io::Write;
+use std::sync::Arc;
+
+const SCHEMA: &str = "
+message record {
+    REQUIRED INT64 offset;
+    REQUIRED INT64 timestamp;
+    OPTIONAL BYTE_ARRAY nonce (UTF8);
+    REQUIRED BYTE_ARRAY value;
+}
+";
+
+/// export all records of a key to the given writer in parquet format, returning the number of rows written.
+/// each page of records is written as a separate row group, so the key is never fully buffered in memory.
+/// nonces are written as UTF-8 strings, since u128 values do not fit in a parquet INT64.
+pub fn export_parquet<R: StoreReader, W: Write + Send>(
+    reader: &R,
+    keyspace: &str,
+    key: &str,
+    writer: W,
+) -> Result<u64, StoreError> {
+    let schema = Arc::new(parse_message_type(SCHEMA).map_err(parquet_error)?);
+    let props = Arc::new(WriterProperties::builder().build());
+    let mut file_writer = SerializedFileWriter::new(writer, schema, props).map_err(parquet_error)?;
+
+    let mut row_count: u64 = 0;
+    let mut page =
+        reader.read_first_page(keyspace, key, Direction::Forwards, StartPosition::First, None)?;
+    loop {
+        if !page.records.is_empty() {
+            write_row_group(&mut file_writer, &page.records)?;
+            row_count += page.records.len() as u64;
+        }
+        let continuation = match page.continuation {
+            None => break,
+            Some(v) => v,
+        };
+        page = reader.read_next_page(keyspace, key, continuation, None)?;
+    }
+
+    file_writer.close().map_err(parquet_error)?;
+    Ok(row_count)
+}
+
+fn write_row_group<W: Write + Send>(
+    file_writer: &mut SerializedFileWriter<W>,
+    records: &Vec<Record>,
+) -> Result<(), StoreError> {
+    let offsets: Vec<i64> = records.iter().map(|r| r.offset as i64).collect();
+    let timestamps: Vec<i64> = records.iter().map(|r| r.timestamp).collect();
+    let nonces: Vec<ByteArray> = records
+        .iter()
+        .filter_map(|r| r.nonce)
+        .map(|n| ByteArray::from(n.to_string().as_str()))
+        .collect();
+    let nonce_def_levels: Vec<i16> = records
+        .iter()
+        .map(|r| match r.nonce {
+            Some(_) => 1,
+            None => 0,
+        })
+        .collect();
+    let values: Vec<ByteArray> = records
+        .iter()
+        .map(|r| ByteArray::from(r.value.clone()))
+        .collect();
+
+    let mut row_group = file_writer.next_row_group().map_err(parquet_error)?;
+    let mut column_index = 0;
+    while let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+        match column_index {
+            0 => write_i64_column(&mut column, &offsets)?,
+            1 => write_i64_column(&mut column, &timestamps)?,
+            2 => {
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&nonces, Some(&nonce_def_levels), None)
+                    .map_err(parquet_error)?;
+            }
+            _ => {
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)
+                    .map_err(parquet_error)?;
+            }
+        }
+        column.close().map_err(parquet_error)?;
+        column_index += 1;
+    }
+    row_group.close().map_err(parquet_error)?;
+    Ok(())
+}
+
+fn write_i64_column(
+    column: &mut SerializedColumnWriter<'_>,
+    values: &Vec<i64>,
+) -> Result<(), StoreError> {
+    column
+        .typed::<Int64Type>()
+        .write_batch(values, None, None)
+        .map_err(parquet_error)?;
+    Ok(())
+}
+
+fn parquet_error(err: ParquetError) -> StoreError {
+    StoreError::IOError(format!("parquet: {}", err.to_string()))
+}
@@ -0,0 +1,61 @@
+use crate::common::records::*;
+use crate::*;
+
+/// version of the record layout written by serialize_records and to the config of every created keyspace.
+/// version 1 is a sequence of records with no object header, each record being a little-endian
+/// offset u64, timestamp i64, nonce u128 (u128::MAX for no nonce), value length u32, the value,
+/// and a trailing total length u32 of the 36 byte header plus value, so records can be iterated backwards.
+/// a RecordLayout of the version may omit the trailing total length or store a nonce u64 (u64::MAX for no nonce) for a 28 byte header.
+/// the records of existing objects are never rewritten in a different version, so a layout change always comes with a new version.
+pub const FORMAT_VERSION: u32 = RECORD_FORMAT_VERSION;
+
+/// options of the record layout within FORMAT_VERSION, from the layout flags of a keyspace config
+pub type RecordLayout = crate::common::records::RecordLayout;
+
+/// return an error if records of the given format version can not be read by this build
+pub fn check_format_version(format_version: u32) -> Result<(), StoreError> {
+    if format_version == 0 || format_version > FORMAT_VERSION {
+        return Err(StoreError::BadData(format!(
+            "unsupported format_version {}, newest supported is {}",
+            format_version, FORMAT_VERSION
+        )));
+    }
+    Ok(())
+}
+
+/// serialize inserts with consecutive offsets from first_offset, in the given layout.
+/// inserts without a timestamp are assigned now, and a nonce that does not fit the nonces of the layout returns BadData.
+pub fn serialize_records(
+    layout: RecordLayout,
+    inserts: &[Insertion],
+    first_offset: u64,
+    now: i64,
+) -> Result<Vec<u8>, StoreError> {
+    for insert in inserts {
+        layout.check_nonce(insert.nonce)?;
+    }
+    let inserts: Vec<&Insertion> = inserts.iter().collect();
+    Ok(serialize_insertion(&inserts, first_offset, now, None, layout).buffer)
+}
+
+/// deserialize the records of a buffer written in the given layout, in the given direction from start.
+/// records failing the predicate are skipped, and a buffer that is truncated or corrupt returns BadData.
+/// reading a forward-only layout backwards returns BadConfiguration.
+pub fn deserialize_records(
+    layout: RecordLayout,
+    buffer: &[u8],
+    direction: Direction,
+    start: &StartPosition,
+    predicate: &Option<RecordPredicate>,
+) -> Result<Vec<Record>, StoreError> {
+    let continuation_offset = match direction {
+        Direction::Forwards => u64::MIN,
+        Direction::Backwards => u64::MAX,
+    };
+    let filter = RecordFilter::from(start, u64::MAX, direction)
+        .with_predicate(predicate)
+        .with_layout(layout);
+    let mut records: Vec<Record> = Vec::new();
+    deserialize_and_filter_records(buffer, &mut records, &filter, continuation_offset)?;
+    Ok(records)
+}
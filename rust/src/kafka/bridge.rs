@@ -1,3 +1,4 @@
+use super::config::KafkaConsumerCommitOn;
 use super::parse::*;
 use super::KafkaConsumerBridgeConfig;
 use super::{KafkaConsumerNumberParser, KafkaConsumerUtf8Parser};
@@ -6,22 +7,31 @@ use crate::*;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, Rebalance};
 use rdkafka::message::{BorrowedMessage, Message};
-use rdkafka::Offset;
+use rdkafka::{Offset, TopicPartitionList};
 use std::cell::RefCell;
+use std::cmp::min;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 pub struct KafkaConsumerBridge<W: StoreWriter> {
-    consumer: BaseConsumer<RebalanceLogger>,
+    consumer: BaseConsumer<BridgeConsumerContext>,
     topic: String,
     nonce_parser: KafkaConsumerNumberParser,
     timestamp_parser: KafkaConsumerNumberParser,
     keyspace_parser: KafkaConsumerUtf8Parser,
     key_parser: KafkaConsumerUtf8Parser,
+    dead_letter_keyspace: Option<String>,
     offset_commit_interval_millis: u64,
+    offset_commit_record_count: u64,
+    offset_commit_on: KafkaConsumerCommitOn,
+    offset_commit_mode: CommitMode,
     writer: W,
     internal_mut: RefCell<BridgeInternalMut>,
+    event_listener: Option<StoreEventListener>,
+    clock: Arc<dyn Clock>,
 }
 impl<W: StoreWriter> KafkaConsumerBridge<W> {
     pub fn new(config: KafkaConsumerBridgeConfig, writer: W) -> Result<Self, StoreError> {
@@ -56,7 +66,11 @@ impl<W: StoreWriter> KafkaConsumerBridge<W> {
         }
         consumer_config.set("enable.auto.commit", "false");
         consumer_config.set("enable.auto.offset.store", "true");
-        let consumer = consumer_config.create_with_context(RebalanceLogger {});
+        let consumer = consumer_config.create_with_context(BridgeConsumerContext {
+            start_timestamp: config.start_timestamp,
+            first_assignment_received: AtomicBool::new(false),
+            pending_seek: Mutex::new(None),
+        });
         if let Err(err) = consumer {
             return Err(StoreError::IOError(format!(
                 "could not create kafka consumer: {}",
@@ -71,14 +85,33 @@ impl<W: StoreWriter> KafkaConsumerBridge<W> {
             timestamp_parser: config.timestamp_parser,
             keyspace_parser: config.keyspace_parser,
             key_parser: config.key_parser,
+            dead_letter_keyspace: config.dead_letter_keyspace,
             writer: writer,
             internal_mut: RefCell::new(BridgeInternalMut {
                 next_commit_timestamp: time_now_as_millis() + offset_commit_interval_millis,
                 commit_stats: CommitStats::new(),
+                commit_offsets: CommitOffsets::new(),
             }),
             offset_commit_interval_millis,
+            offset_commit_record_count: config.offset_commit_record_count,
+            offset_commit_on: config.offset_commit_on,
+            offset_commit_mode: config.offset_commit_mode,
+            event_listener: None,
+            clock: Arc::new(SystemClock),
         })
     }
+    /// read the time of offset commit intervals from the given clock instead of the system clock, such as a MockClock in tests
+    pub fn set_clock(mut self, v: Arc<dyn Clock>) -> Self {
+        self.internal_mut.borrow_mut().next_commit_timestamp =
+            v.now_millis() + self.offset_commit_interval_millis;
+        self.clock = v;
+        self
+    }
+    /// receive a StoreEvent::PartitionCommitted for every partition with records committed by a scheduled commit
+    pub fn set_event_listener(mut self, v: StoreEventListener) -> Self {
+        self.event_listener = Some(v);
+        self
+    }
     pub fn subscribe(&self) -> Result<(), StoreError> {
         match self.consumer.subscribe(&[&self.topic]) {
             Ok(_) => Ok(()),
@@ -89,31 +122,226 @@ impl<W: StoreWriter> KafkaConsumerBridge<W> {
         }
     }
     pub fn poll(&self, timeout: Duration) -> Result<(), StoreError> {
-        let now = time_now_as_millis();
+        let now = self.clock.now_millis();
         let mut internal_mut = self.internal_mut.borrow_mut();
+        self.seek_to_start_timestamp(timeout)?;
         let result = self.poll_kafka_consumer(&mut internal_mut, timeout);
-        if now >= internal_mut.next_commit_timestamp {
+        let time_due = now >= internal_mut.next_commit_timestamp;
+        let count_due = internal_mut.commit_stats.record_count_since_commit as u64
+            >= self.offset_commit_record_count;
+        let commit_due = match self.offset_commit_on {
+            KafkaConsumerCommitOn::Time => time_due,
+            KafkaConsumerCommitOn::Count => count_due,
+            KafkaConsumerCommitOn::Both => time_due || count_due,
+        };
+        if commit_due {
             log::info!(
                 "scheduled commit: {}",
                 internal_mut.commit_stats.to_string()
             );
             internal_mut.next_commit_timestamp = now + self.offset_commit_interval_millis;
-            if internal_mut.commit_stats.record_count_since_commit > 0 {
-                log::info!("flushing writer");
-                self.writer.flush_all()?;
-                log::info!("commiting offsets");
-                if let Err(err) = self.consumer.commit_consumer_state(CommitMode::Async) {
-                    return Err(StoreError::IOError(format!(
-                        "consumer commit failed: {}",
-                        err.to_string()
-                    )));
-                }
-            }
+            let commit_result = match internal_mut.commit_stats.record_count_since_commit > 0 {
+                true => self.commit_pending(&mut internal_mut, timeout),
+                false => Ok(()),
+            };
+            // records that failed to commit were sought back, so they are counted again when they are consumed again
             internal_mut.commit_stats.reset();
+            commit_result?;
             log::info!("commit complete");
         }
         return result;
     }
+    /// flush the writer and commit offsets of records consumed since the last commit.
+    /// a failed flush is returned only after committing partitions that are safe to commit.
+    fn commit_pending(
+        &self,
+        internal_mut: &mut BridgeInternalMut,
+        timeout: Duration,
+    ) -> Result<(), StoreError> {
+        log::info!("flushing writer");
+        let flush_result = self.flush_pending_keys(&mut internal_mut.commit_offsets);
+        log::info!("commiting offsets");
+        self.commit_offsets(&mut internal_mut.commit_offsets, self.offset_commit_mode)?;
+        if let Err(_) = flush_result {
+            // records that were not flushed were dropped by the writer, consume them again
+            self.seek_to_failed_offsets(&internal_mut.commit_offsets, timeout)?;
+        }
+        flush_result?;
+        if let Some(listener) = &self.event_listener {
+            for (partition, stats) in internal_mut.commit_stats.partition_stats.iter() {
+                listener(&StoreEvent::PartitionCommitted {
+                    topic: self.topic.clone(),
+                    partition: *partition,
+                    record_count: stats.record_count as u64,
+                    last_timestamp: stats.last_timestamp,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// flush every key appended since the last commit and synchronously commit offsets,
+    /// returning the writer so it can be shut down.
+    pub fn shutdown(self) -> Result<W, StoreError> {
+        let mut internal_mut = self.internal_mut.borrow_mut();
+        log::info!("flushing writer");
+        let flush_result = self.flush_pending_keys(&mut internal_mut.commit_offsets);
+        log::info!("commiting offsets");
+        self.commit_offsets(&mut internal_mut.commit_offsets, CommitMode::Sync)?;
+        flush_result?;
+        drop(internal_mut);
+        Ok(self.writer)
+    }
+    /// flush the writer once for every key appended since the last commit.
+    /// partitions of keys that failed to flush are capped at the first record that was not flushed,
+    /// and the caps are cleared once a later flush of every key succeeds.
+    fn flush_pending_keys(&self, commit_offsets: &mut CommitOffsets) -> Result<(), StoreError> {
+        let pending_keys = std::mem::replace(&mut commit_offsets.pending_keys, HashMap::new());
+        let failed = match self.writer.flush_all_with_report() {
+            Ok(report) => report.failed,
+            Err(err) => {
+                // the writer can not tell which keys failed, so none of them are flushed
+                log::error!("flush failed: {}", err.to_string());
+                for (_, partitions) in pending_keys.iter() {
+                    commit_offsets.cap(partitions);
+                }
+                return Err(err);
+            }
+        };
+        if failed.is_empty() {
+            // partitions were sought back to their capped offsets, so those records were appended again and are now flushed
+            commit_offsets.failed_offsets.clear();
+            return Ok(());
+        }
+        for failed_key in failed.iter() {
+            log::error!(
+                "flush of {}/{} failed: {}",
+                failed_key.keyspace,
+                failed_key.key,
+                failed_key.error.to_string()
+            );
+            let pending_key = (failed_key.keyspace.clone(), failed_key.key.clone());
+            if let Some(partitions) = pending_keys.get(&pending_key) {
+                commit_offsets.cap(partitions);
+            }
+        }
+        Err(failed.into_iter().next().unwrap().error)
+    }
+    /// seek partitions with a failed flush back to their first record that was not flushed, so it is appended again
+    fn seek_to_failed_offsets(
+        &self,
+        commit_offsets: &CommitOffsets,
+        timeout: Duration,
+    ) -> Result<(), StoreError> {
+        for (partition, offset) in commit_offsets.failed_offsets.iter() {
+            log::info!(
+                "seeking {}:{} to {} after failed flush",
+                self.topic,
+                partition,
+                offset
+            );
+            self.seek(*partition, *offset, timeout)?;
+        }
+        Ok(())
+    }
+    fn seek(&self, partition: i32, offset: i64, timeout: Duration) -> Result<(), StoreError> {
+        if let Err(err) =
+            self.consumer
+                .seek(&self.topic, partition, Offset::Offset(offset), timeout)
+        {
+            return Err(StoreError::IOError(format!(
+                "seek of {}:{} to {} failed: {}",
+                self.topic,
+                partition,
+                offset,
+                err.to_string()
+            )));
+        }
+        Ok(())
+    }
+    /// commit the consumed offset of each partition consumed since the last commit,
+    /// never passing the first record of a failed flush
+    fn commit_offsets(
+        &self,
+        commit_offsets: &mut CommitOffsets,
+        mode: CommitMode,
+    ) -> Result<(), StoreError> {
+        let mut tpl = TopicPartitionList::new();
+        for (partition, offset) in commit_offsets.consumed_offsets.iter() {
+            let offset = match commit_offsets.failed_offsets.get(partition) {
+                None => *offset,
+                Some(v) => min(*v, *offset),
+            };
+            if let Err(err) =
+                tpl.add_partition_offset(&self.topic, *partition, Offset::Offset(offset))
+            {
+                return Err(StoreError::IOError(format!(
+                    "could not build commit offsets: {}",
+                    err.to_string()
+                )));
+            }
+        }
+        // only commit partitions consumed since the last commit, which may have been revoked since
+        commit_offsets.consumed_offsets.clear();
+        if tpl.count() == 0 {
+            return Ok(());
+        }
+        if let Err(err) = self.consumer.commit(&tpl, mode) {
+            return Err(StoreError::IOError(format!(
+                "consumer commit failed: {}",
+                err.to_string()
+            )));
+        }
+        Ok(())
+    }
+    /// seek partitions from the first assignment to the configured start_timestamp, if any are pending
+    fn seek_to_start_timestamp(&self, timeout: Duration) -> Result<(), StoreError> {
+        let assigned = match self.consumer.context().pending_seek.lock().unwrap().take() {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let start_timestamp = self.consumer.context().start_timestamp.unwrap();
+        let mut timestamps = TopicPartitionList::new();
+        for elem in assigned.elements() {
+            if let Err(err) = timestamps.add_partition_offset(
+                elem.topic(),
+                elem.partition(),
+                Offset::Offset(start_timestamp),
+            ) {
+                return Err(StoreError::IOError(format!(
+                    "could not build start_timestamp offsets: {}",
+                    err.to_string()
+                )));
+            }
+        }
+        let offsets = match self.consumer.offsets_for_times(timestamps, timeout) {
+            Ok(v) => v,
+            Err(err) => {
+                return Err(StoreError::IOError(format!(
+                    "offsets_for_times failed: {}",
+                    err.to_string()
+                )))
+            }
+        };
+        for elem in offsets.elements() {
+            log::info!(
+                "seeking {}:{} to {:?} for start_timestamp {}",
+                elem.topic(),
+                elem.partition(),
+                elem.offset(),
+                start_timestamp
+            );
+            if let Err(err) =
+                self.consumer
+                    .seek(elem.topic(), elem.partition(), elem.offset(), timeout)
+            {
+                return Err(StoreError::IOError(format!(
+                    "seek to start_timestamp failed: {}",
+                    err.to_string()
+                )));
+            }
+        }
+        Ok(())
+    }
     fn poll_kafka_consumer(
         &self,
         internal_mut: &mut BridgeInternalMut,
@@ -125,20 +353,68 @@ impl<W: StoreWriter> KafkaConsumerBridge<W> {
                     return Err(StoreError::IOError(err.to_string()));
                 }
                 let message = result.unwrap();
+                if self
+                    .consumer
+                    .context()
+                    .pending_seek
+                    .lock()
+                    .unwrap()
+                    .is_some()
+                {
+                    // the first assignment arrived during this poll, so the message was fetched from the committed offset.
+                    // seek now and drop it, the seek delivers it again if it is at/after start_timestamp
+                    self.seek_to_start_timestamp(timeout)?;
+                    return Ok(());
+                }
                 if let None = message.payload() {
+                    internal_mut
+                        .commit_offsets
+                        .consumed(message.partition(), message.offset());
                     return Ok(());
                 }
-                let timestamp = self.parse_timestamp(&message)?;
-                let inserts = vec![Insertion {
-                    record: message.payload().unwrap().to_vec(),
-                    nonce: self.parse_nonce(&message)?,
-                    timestamp: timestamp,
-                }];
-                self.writer.append(
-                    &self.parse_keyspace(&message)?,
-                    &self.parse_key(&message)?,
-                    inserts,
-                )?;
+                let (keyspace, key, inserts) = match self.parse_message(&message) {
+                    Ok(v) => v,
+                    Err(err) => match &self.dead_letter_keyspace {
+                        None => {
+                            // hold the partition at the message, so it is never committed past
+                            self.seek(message.partition(), message.offset(), timeout)?;
+                            return Err(err);
+                        }
+                        Some(dead_letter_keyspace) => {
+                            log::error!(
+                                "dead-lettering {}:{}@{}: {}",
+                                self.topic,
+                                message.partition(),
+                                message.offset(),
+                                err.to_string()
+                            );
+                            (
+                                dead_letter_keyspace.clone(),
+                                message.partition().to_string(),
+                                vec![Insertion {
+                                    record: message.payload().unwrap().to_vec(),
+                                    nonce: Some(message.offset() as u128),
+                                    timestamp: None,
+                                }],
+                            )
+                        }
+                    },
+                };
+                let timestamp = inserts[0].timestamp;
+                if let Err(err) = self.writer.append(&keyspace, &key, inserts) {
+                    // consume the message again, so it is never committed without being appended
+                    self.seek(message.partition(), message.offset(), timeout)?;
+                    return Err(err);
+                }
+                internal_mut
+                    .commit_offsets
+                    .consumed(message.partition(), message.offset());
+                internal_mut.commit_offsets.appended(
+                    keyspace,
+                    key,
+                    message.partition(),
+                    message.offset(),
+                );
                 internal_mut
                     .commit_stats
                     .increment(message.partition(), timestamp);
@@ -147,6 +423,20 @@ impl<W: StoreWriter> KafkaConsumerBridge<W> {
             None => return Ok(()),
         }
     }
+    /// the keyspace, key, and insertion of a message with a payload
+    fn parse_message(
+        &self,
+        message: &BorrowedMessage<'_>,
+    ) -> Result<(String, String, Vec<Insertion>), StoreError> {
+        let inserts = vec![Insertion {
+            record: message.payload().unwrap().to_vec(),
+            nonce: self.parse_nonce(message)?,
+            timestamp: self.parse_timestamp(message)?,
+        }];
+        let keyspace = self.parse_keyspace(message)?;
+        let key = self.parse_key(message)?;
+        Ok((keyspace, key, inserts))
+    }
     fn parse_nonce(&self, message: &BorrowedMessage<'_>) -> Result<Option<u128>, StoreError> {
         parse_u128_opt(&self.nonce_parser, message)
     }
@@ -161,16 +451,27 @@ impl<W: StoreWriter> KafkaConsumerBridge<W> {
     }
 }
 
-struct RebalanceLogger {}
-impl rdkafka::client::ClientContext for RebalanceLogger {}
-impl rdkafka::consumer::ConsumerContext for RebalanceLogger {
+struct BridgeConsumerContext {
+    start_timestamp: Option<i64>,
+    first_assignment_received: AtomicBool,
+    pending_seek: Mutex<Option<TopicPartitionList>>,
+}
+impl rdkafka::client::ClientContext for BridgeConsumerContext {}
+impl rdkafka::consumer::ConsumerContext for BridgeConsumerContext {
     fn post_rebalance(&self, rebalance: &Rebalance) {
         match rebalance {
             Rebalance::Assign(tpl) => {
                 log::info!(
                     "partitions assigned: {}",
                     topic_map_to_string(&tpl.to_topic_map())
-                )
+                );
+                // only the first assignment seeks to start_timestamp. the consumer thread performs the seek before
+                // handling any message, since the context can not seek from within the rebalance callback
+                if let Some(_) = self.start_timestamp {
+                    if !self.first_assignment_received.swap(true, Ordering::Relaxed) {
+                        *self.pending_seek.lock().unwrap() = Some((*tpl).clone());
+                    }
+                }
             }
             Rebalance::Revoke(tpl) => {
                 log::info!(
@@ -202,6 +503,48 @@ fn topic_map_to_string(map: &HashMap<(String, i32), Offset>) -> String {
 struct BridgeInternalMut {
     next_commit_timestamp: u64,
     commit_stats: CommitStats,
+    commit_offsets: CommitOffsets,
+}
+
+/// tracks which offsets of each partition are safe to commit
+struct CommitOffsets {
+    /// next offset to consume for each partition consumed since the last commit
+    consumed_offsets: BTreeMap<i32, i64>,
+    /// first offset of each partition appended to each key since the last commit
+    pending_keys: HashMap<(String, String), BTreeMap<i32, i64>>,
+    /// first offset of each partition that failed to flush, which is never committed past
+    failed_offsets: BTreeMap<i32, i64>,
+}
+impl CommitOffsets {
+    fn new() -> Self {
+        Self {
+            consumed_offsets: BTreeMap::new(),
+            pending_keys: HashMap::new(),
+            failed_offsets: BTreeMap::new(),
+        }
+    }
+    fn consumed(&mut self, partition: i32, offset: i64) {
+        self.consumed_offsets.insert(partition, offset + 1);
+    }
+    fn appended(&mut self, keyspace: String, key: String, partition: i32, offset: i64) {
+        let partitions = self
+            .pending_keys
+            .entry((keyspace, key))
+            .or_insert_with(BTreeMap::new);
+        if let None = partitions.get(&partition) {
+            partitions.insert(partition, offset);
+        }
+    }
+    /// never commit the given partitions past the given offsets, the first records of a key that failed to flush
+    fn cap(&mut self, partitions: &BTreeMap<i32, i64>) {
+        for (partition, offset) in partitions.iter() {
+            let ceiling = match self.failed_offsets.get(partition) {
+                None => *offset,
+                Some(v) => min(*v, *offset),
+            };
+            self.failed_offsets.insert(*partition, ceiling);
+        }
+    }
 }
 
 struct CommitStats {
@@ -216,6 +559,7 @@ impl CommitStats {
         }
     }
     fn reset(&mut self) {
+        self.record_count_since_commit = 0;
         self.partition_stats.clear();
     }
     fn increment(&mut self, partition: i32, timestamp: Option<i64>) {
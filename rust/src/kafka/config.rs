@@ -2,8 +2,20 @@ use super::parse::*;
 use super::{KafkaConsumerNumberParser, KafkaConsumerUtf8Parser};
 use crate::StoreError;
 use ini::Ini;
+use rdkafka::consumer::CommitMode;
 use std::collections::HashMap;
 
+/// What triggers flushing the writer and committing consumed offsets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KafkaConsumerCommitOn {
+    /// every offset_commit_interval_seconds
+    Time,
+    /// every offset_commit_record_count records
+    Count,
+    /// whichever of the interval or record count is reached first
+    Both,
+}
+
 #[derive(Clone, Debug)]
 pub struct KafkaConsumerBridgeConfig {
     pub consumer_config: HashMap<String, String>,
@@ -12,7 +24,12 @@ pub struct KafkaConsumerBridgeConfig {
     pub timestamp_parser: KafkaConsumerNumberParser,
     pub keyspace_parser: KafkaConsumerUtf8Parser,
     pub key_parser: KafkaConsumerUtf8Parser,
+    pub dead_letter_keyspace: Option<String>,
     pub offset_commit_interval_seconds: u64,
+    pub offset_commit_record_count: u64,
+    pub offset_commit_on: KafkaConsumerCommitOn,
+    pub offset_commit_mode: CommitMode,
+    pub start_timestamp: Option<i64>,
 }
 impl KafkaConsumerBridgeConfig {
     pub fn new() -> Self {
@@ -23,7 +40,12 @@ impl KafkaConsumerBridgeConfig {
             timestamp_parser: KafkaConsumerNumberParser::None,
             keyspace_parser: KafkaConsumerUtf8Parser::None,
             key_parser: KafkaConsumerUtf8Parser::None,
+            dead_letter_keyspace: None,
             offset_commit_interval_seconds: 60,
+            offset_commit_record_count: 10000,
+            offset_commit_on: KafkaConsumerCommitOn::Time,
+            offset_commit_mode: CommitMode::Async,
+            start_timestamp: None,
         }
     }
     pub fn set_consumer_config(mut self, v: HashMap<String, String>) -> Self {
@@ -54,10 +76,38 @@ impl KafkaConsumerBridgeConfig {
         self.key_parser = v;
         self
     }
+    /// append messages that fail to parse to this keyspace, under the key of their partition number, defaults to None.
+    /// when None, a message that fails to parse holds its partition, so its offset is never committed past.
+    pub fn set_dead_letter_keyspace(mut self, v: Option<String>) -> Self {
+        self.dead_letter_keyspace = v;
+        self
+    }
     pub fn set_offset_commit_interval_seconds(mut self, v: u64) -> Self {
         self.offset_commit_interval_seconds = v;
         self
     }
+    /// records appended since the last commit that trigger a commit when offset_commit_on is Count or Both, defaults to 10000
+    pub fn set_offset_commit_record_count(mut self, v: u64) -> Self {
+        self.offset_commit_record_count = v;
+        self
+    }
+    /// what triggers flushing the writer and committing offsets, defaults to Time
+    pub fn set_offset_commit_on(mut self, v: KafkaConsumerCommitOn) -> Self {
+        self.offset_commit_on = v;
+        self
+    }
+    /// mode of commits made while polling, defaults to Async. shutdown always commits synchronously.
+    /// an async commit may not complete before a crash, so use Sync for stronger at-least-once progress.
+    pub fn set_offset_commit_mode(mut self, v: CommitMode) -> Self {
+        self.offset_commit_mode = v;
+        self
+    }
+    /// seek each partition of the first assignment to the first offset at/after the given timestamp in millis.
+    /// partitions assigned by subsequent rebalances resume from their committed offsets.
+    pub fn set_start_timestamp(mut self, v: i64) -> Self {
+        self.start_timestamp = Some(v);
+        self
+    }
     pub fn load(ini: &Ini) -> Result<Self, StoreError> {
         let kafka = ini.section(Some("kafka"));
         let parser = ini.section(Some("parser"));
@@ -75,13 +125,21 @@ impl KafkaConsumerBridgeConfig {
         let parser = parser.unwrap();
 
         let mut topic: Option<String> = None;
+        let mut dead_letter_keyspace: Option<String> = None;
         let mut offset_commit_interval_seconds = 60u64;
+        let mut offset_commit_record_count = 10000u64;
+        let mut offset_commit_on = KafkaConsumerCommitOn::Time;
+        let mut offset_commit_mode = CommitMode::Async;
+        let mut start_timestamp: Option<i64> = None;
         let mut consumer_config: HashMap<String, String> = HashMap::new();
         for (k, v) in kafka.iter() {
             match k {
                 "topic" => {
                     topic = Some(v.to_string());
                 }
+                "dead_letter_keyspace" => {
+                    dead_letter_keyspace = Some(v.to_string());
+                }
                 "offset_commit_interval_seconds" => {
                     offset_commit_interval_seconds = match v.to_string().parse::<u64>() {
                         Ok(v) => v,
@@ -93,6 +151,53 @@ impl KafkaConsumerBridgeConfig {
                         }
                     }
                 }
+                "offset_commit_record_count" => {
+                    offset_commit_record_count = match v.to_string().parse::<u64>() {
+                        Ok(v) => v,
+                        Err(_) => {
+                            return Err(StoreError::BadConfiguration(format!(
+                                "offset_commit_record_count={}",
+                                v
+                            )))
+                        }
+                    }
+                }
+                "offset_commit_on" => {
+                    offset_commit_on = match v {
+                        "time" => KafkaConsumerCommitOn::Time,
+                        "count" => KafkaConsumerCommitOn::Count,
+                        "both" => KafkaConsumerCommitOn::Both,
+                        _ => {
+                            return Err(StoreError::BadConfiguration(format!(
+                                "offset_commit_on={}",
+                                v
+                            )))
+                        }
+                    }
+                }
+                "offset_commit_mode" => {
+                    offset_commit_mode = match v {
+                        "async" => CommitMode::Async,
+                        "sync" => CommitMode::Sync,
+                        _ => {
+                            return Err(StoreError::BadConfiguration(format!(
+                                "offset_commit_mode={}",
+                                v
+                            )))
+                        }
+                    }
+                }
+                "start_timestamp" => {
+                    start_timestamp = match v.to_string().parse::<i64>() {
+                        Ok(v) => Some(v),
+                        Err(_) => {
+                            return Err(StoreError::BadConfiguration(format!(
+                                "start_timestamp={}",
+                                v
+                            )))
+                        }
+                    }
+                }
                 _ => {
                     consumer_config.insert(k.to_string(), v.to_string());
                 }
@@ -111,7 +216,12 @@ impl KafkaConsumerBridgeConfig {
             timestamp_parser,
             keyspace_parser,
             key_parser,
+            dead_letter_keyspace,
             offset_commit_interval_seconds,
+            offset_commit_record_count,
+            offset_commit_on,
+            offset_commit_mode,
+            start_timestamp,
         })
     }
 }
@@ -26,58 +26,23 @@ fn main() {
         }
     };
 
-    // parse configurations from ini
-    let s3_config = match S3StoreConfig::load(&cfg) {
-        Ok(v) => {
-            log::info!("{:#?}", &v);
-            v
-        }
-        Err(err) => {
-            log::error!("could not load s3 config: {}", err.to_string());
-            std::process::exit(exitcode::CONFIG);
-        }
-    };
-    let batcher_config = match BatchingStoreWriterConfig::load(&cfg) {
-        Ok(v) => {
-            log::info!("{:#?}", &v);
-            v
-        }
-        Err(err) => {
-            log::error!("could not load batcher config: {}", err.to_string());
-            std::process::exit(exitcode::CONFIG);
-        }
-    };
-    let kafka_config = match KafkaConsumerBridgeConfig::load(&cfg) {
-        Ok(v) => {
-            log::info!("{:#?}", &v);
-            v
-        }
-        Err(err) => {
-            log::error!("could not load kafka config: {}", err.to_string());
-            std::process::exit(exitcode::CONFIG);
-        }
-    };
-
-    // instantiate objects
-    let s3 = match S3StoreWriter::new(s3_config) {
-        Ok(v) => v,
-        Err(err) => {
-            log::error!("could not instantiate s3 writer: {}", err.to_string());
-            std::process::exit(exitcode::SOFTWARE);
-        }
-    };
-    let batcher = match BatchingStoreWriter::new(batcher_config, s3) {
+    // load configurations from ini and instantiate the pipeline
+    let builder = match StorePipelineBuilder::load(&cfg) {
         Ok(v) => v,
         Err(err) => {
-            log::error!("could not instantiate batcher: {}", err.to_string());
-            std::process::exit(exitcode::SOFTWARE);
+            log::error!("could not load config: {}", err.to_string());
+            std::process::exit(exitcode::CONFIG);
         }
     };
-    let kafka = match KafkaConsumerBridge::new(kafka_config, batcher) {
+    let pipeline = match builder.build() {
         Ok(v) => v,
         Err(err) => {
-            log::error!("could not instantiate kafka bridge: {}", err.to_string());
-            std::process::exit(exitcode::SOFTWARE);
+            log::error!("could not instantiate pipeline: {}", err.to_string());
+            match err.error {
+                StoreError::BadConfiguration(_) => std::process::exit(exitcode::CONFIG),
+                _ if err.error.is_retryable() => std::process::exit(exitcode::UNAVAILABLE),
+                _ => std::process::exit(exitcode::SOFTWARE),
+            }
         }
     };
 
@@ -93,7 +58,7 @@ fn main() {
 
     // subscrive
     log::info!("subscribing");
-    match kafka.subscribe() {
+    match pipeline.subscribe() {
         Ok(_) => {}
         Err(err) => {
             log::error!("could not subscrive to kafka topic: {}", err.to_string());
@@ -105,10 +70,10 @@ fn main() {
     let poll_timeout = Duration::from_millis(100);
     log::info!("entering poll loop");
     while running.load(Ordering::Relaxed) {
-        match kafka.poll(poll_timeout) {
+        match pipeline.poll(poll_timeout) {
             Ok(_) => {}
-            Err(StoreError::IOError(s)) => {
-                log::error!("IOError, Exiting: {}", s.to_string());
+            Err(err) if err.is_retryable() => {
+                log::error!("retryable error, exiting: {}", err.to_string());
                 std::process::exit(exitcode::IOERR);
             }
             Err(err) => {
@@ -116,5 +81,12 @@ fn main() {
             }
         }
     }
+
+    // drain buffered data and commit offsets before exiting
+    log::info!("shutting down");
+    if let Err(err) = pipeline.shutdown() {
+        log::error!("could not shut down pipeline: {}", err.to_string());
+        std::process::exit(exitcode::IOERR);
+    }
     log::info!("exiting")
 }
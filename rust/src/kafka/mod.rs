@@ -1,8 +1,14 @@
 mod bridge;
 mod config;
 mod parse;
+mod pipeline;
 
 pub type KafkaConsumerBridgeConfig = config::KafkaConsumerBridgeConfig;
+pub type KafkaConsumerCommitOn = config::KafkaConsumerCommitOn;
 pub type KafkaConsumerBridge<W> = bridge::KafkaConsumerBridge<W>;
 pub type KafkaConsumerNumberParser = parse::KafkaConsumerNumberParser;
 pub type KafkaConsumerUtf8Parser = parse::KafkaConsumerUtf8Parser;
+pub type StorePipeline = pipeline::StorePipeline;
+pub type StorePipelineBuilder = pipeline::StorePipelineBuilder;
+pub type StorePipelineError = pipeline::StorePipelineError;
+pub type StorePipelineStage = pipeline::StorePipelineStage;
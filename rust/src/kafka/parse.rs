@@ -4,6 +4,7 @@ use rdkafka::message::Headers;
 use rdkafka::message::{BorrowedMessage, Message};
 use regex::Regex;
 use std::str;
+use std::sync::OnceLock;
 
 #[derive(Clone, Debug)]
 pub enum KafkaConsumerNumberParser {
@@ -16,6 +17,7 @@ pub enum KafkaConsumerNumberParser {
     RecordKeyUtf8,
     RecordOffset,
     RecordPartition,
+    RecordTimestamp,
 }
 
 #[derive(Clone, Debug)]
@@ -23,8 +25,22 @@ pub enum KafkaConsumerUtf8Parser {
     None,
     Static(String),
     RecordHeader(String),
+    RecordHeaderHex(String),
     RecordKey,
+    RecordKeyHex,
     RecordPartition,
+    RegexCapture {
+        source: Box<KafkaConsumerUtf8Parser>,
+        pattern: Regex,
+        group: usize,
+    },
+}
+
+/// matches parser configs with arguments, like `Name(args)`, compiled once for all parsers
+fn parser_args_regex() -> &'static Regex {
+    static PARSER_ARGS_REGEX: OnceLock<Regex> = OnceLock::new();
+    PARSER_ARGS_REGEX
+        .get_or_init(|| Regex::new(r"^(.+)\((.+)\)$").expect("parser regex compilation failure"))
 }
 
 pub fn create_number_parser(cfg: Option<&str>) -> Result<KafkaConsumerNumberParser, StoreError> {
@@ -39,11 +55,10 @@ pub fn create_number_parser(cfg: Option<&str>) -> Result<KafkaConsumerNumberPars
         "RecordKeyUtf8" => return Ok(KafkaConsumerNumberParser::RecordKeyUtf8),
         "RecordOffset" => return Ok(KafkaConsumerNumberParser::RecordOffset),
         "RecordPartition" => return Ok(KafkaConsumerNumberParser::RecordPartition),
+        "RecordTimestamp" => return Ok(KafkaConsumerNumberParser::RecordTimestamp),
         _ => {}
     }
-    let rex =
-        Regex::new(r"^(.+)\((.+)\)$").expect("create_number_parser regex compilation failure");
-    if let Some(cap) = rex.captures(cfg) {
+    if let Some(cap) = parser_args_regex().captures(cfg) {
         match &cap[1] {
             "RecordHeaderBigEndian" => {
                 return Ok(KafkaConsumerNumberParser::RecordHeaderBigEndian(
@@ -77,11 +92,15 @@ pub fn create_utf8_parser(cfg: Option<&str>) -> Result<KafkaConsumerUtf8Parser,
     match cfg {
         "None" => return Ok(KafkaConsumerUtf8Parser::None),
         "RecordKey" => return Ok(KafkaConsumerUtf8Parser::RecordKey),
+        "RecordKeyHex" => return Ok(KafkaConsumerUtf8Parser::RecordKeyHex),
         "RecordPartition" => return Ok(KafkaConsumerUtf8Parser::RecordPartition),
         _ => {}
     }
-    let rex = Regex::new(r"^(.+)\((.+)\)$").expect("create_utf8_parser regex compilation failure");
-    if let Some(cap) = rex.captures(cfg) {
+    // the capture pattern may contain parentheses, so it can't go through the generic regex
+    if cfg.starts_with("RegexCapture(") && cfg.ends_with(")") {
+        return create_regex_capture_parser(cfg, &cfg["RegexCapture(".len()..cfg.len() - 1]);
+    }
+    if let Some(cap) = parser_args_regex().captures(cfg) {
         match &cap[1] {
             "Static" => {
                 return Ok(KafkaConsumerUtf8Parser::Static(cap[2].to_string()));
@@ -89,15 +108,82 @@ pub fn create_utf8_parser(cfg: Option<&str>) -> Result<KafkaConsumerUtf8Parser,
             "RecordHeader" => {
                 return Ok(KafkaConsumerUtf8Parser::RecordHeader(cap[2].to_string()));
             }
+            "RecordHeaderHex" => {
+                return Ok(KafkaConsumerUtf8Parser::RecordHeaderHex(cap[2].to_string()));
+            }
             _ => {}
         }
     }
     return Err(StoreError::BadConfiguration(format!(
-        "invalid number parser: {}",
+        "invalid utf8 parser: {}",
         cfg
     )));
 }
 
+/// parse `{source}, {pattern}, {group}`, where the pattern itself may contain commas
+fn create_regex_capture_parser(
+    cfg: &str,
+    args: &str,
+) -> Result<KafkaConsumerUtf8Parser, StoreError> {
+    let (source, rest) = match args.split_once(',') {
+        Some(v) => v,
+        None => {
+            return Err(StoreError::BadConfiguration(format!(
+                "invalid regex capture parser: {}",
+                cfg
+            )))
+        }
+    };
+    let (pattern, group) = match rest.rsplit_once(',') {
+        Some(v) => v,
+        None => {
+            return Err(StoreError::BadConfiguration(format!(
+                "invalid regex capture parser: {}",
+                cfg
+            )))
+        }
+    };
+    let source = match create_utf8_parser(Some(source.trim()))? {
+        KafkaConsumerUtf8Parser::None => {
+            return Err(StoreError::BadConfiguration(format!(
+                "invalid regex capture source: {}",
+                cfg
+            )))
+        }
+        v => v,
+    };
+    let pattern = match Regex::new(pattern.trim()) {
+        Ok(v) => v,
+        Err(err) => {
+            return Err(StoreError::BadConfiguration(format!(
+                "invalid regex capture pattern: {}: {}",
+                cfg,
+                err.to_string()
+            )))
+        }
+    };
+    let group = match group.trim().parse::<usize>() {
+        Ok(v) => v,
+        Err(_) => {
+            return Err(StoreError::BadConfiguration(format!(
+                "invalid regex capture group: {}",
+                cfg
+            )))
+        }
+    };
+    if group >= pattern.captures_len() {
+        return Err(StoreError::BadConfiguration(format!(
+            "regex capture group out of range: {}",
+            cfg
+        )));
+    }
+    Ok(KafkaConsumerUtf8Parser::RegexCapture {
+        source: Box::new(source),
+        pattern,
+        group,
+    })
+}
+
 pub fn parse_utf8_req(
     parser: &KafkaConsumerUtf8Parser,
     message: &BorrowedMessage<'_>,
@@ -118,8 +204,21 @@ pub fn parse_utf8_opt(
         KafkaConsumerUtf8Parser::None => Ok(None),
         KafkaConsumerUtf8Parser::Static(v) => Ok(Some(v.to_string())),
         KafkaConsumerUtf8Parser::RecordHeader(name) => parse_utf8_header(message, name),
+        KafkaConsumerUtf8Parser::RecordHeaderHex(name) => parse_hex_header(message, name),
         KafkaConsumerUtf8Parser::RecordKey => parse_opt_utf8(message.key()),
+        KafkaConsumerUtf8Parser::RecordKeyHex => Ok(message.key().map(to_hex)),
         KafkaConsumerUtf8Parser::RecordPartition => Ok(Some(message.partition().to_string())),
+        KafkaConsumerUtf8Parser::RegexCapture {
+            source,
+            pattern,
+            group,
+        } => match parse_utf8_opt(source, message)? {
+            None => Ok(None),
+            Some(v) => match pattern.captures(&v) {
+                None => Ok(None),
+                Some(cap) => Ok(cap.get(*group).map(|m| m.as_str().to_string())),
+            },
+        },
     }
 }
 
@@ -143,6 +242,11 @@ pub fn parse_u128_opt(
         KafkaConsumerNumberParser::RecordKeyLittleEndian => parse_opt_le_as_u128(message.key()),
         KafkaConsumerNumberParser::RecordKeyUtf8 => parse_opt_utf8_as_u128(message.key()),
         KafkaConsumerNumberParser::RecordPartition => Ok(Some(message.partition() as u128)),
+        KafkaConsumerNumberParser::RecordTimestamp => Ok(message
+            .timestamp()
+            .to_millis()
+            .filter(|v| *v >= 0)
+            .map(|v| v as u128)),
     }
 }
 
@@ -166,6 +270,8 @@ pub fn parse_i64_opt(
         KafkaConsumerNumberParser::RecordKeyLittleEndian => parse_opt_le_as_i64(message.key()),
         KafkaConsumerNumberParser::RecordKeyUtf8 => parse_opt_utf8_as_i64(message.key()),
         KafkaConsumerNumberParser::RecordPartition => Ok(Some(message.partition() as i64)),
+        // NotAvailable is None, so the record is assigned the current time like with the None parser
+        KafkaConsumerNumberParser::RecordTimestamp => Ok(message.timestamp().to_millis()),
     }
 }
 
@@ -279,6 +385,29 @@ fn parse_utf8_header(
     }
 }
 
+fn parse_hex_header(
+    message: &BorrowedMessage<'_>,
+    name: &String,
+) -> Result<Option<String>, StoreError> {
+    match message.headers() {
+        None => return Ok(None),
+        Some(headers) => {
+            for i in 0..headers.count() {
+                let (n, v) = headers.get(i).unwrap();
+                if n == name {
+                    return Ok(Some(to_hex(v)));
+                }
+            }
+            return Ok(None);
+        }
+    }
+}
+
+/// lowercase hex of any bytes, which is always a valid key or keyspace name
+fn to_hex(v: &[u8]) -> String {
+    v.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 fn parse_utf8(v: &[u8]) -> Result<String, StoreError> {
     match str::from_utf8(v) {
         Ok(s) => Ok(s.to_string()),
@@ -315,31 +444,37 @@ fn parse_opt_utf8_as_u128(v: Option<&[u8]>) -> Result<Option<u128>, StoreError>
 }
 
 fn parse_le_as_u128(v: &[u8]) -> Result<Option<u128>, StoreError> {
-    match v.len() {
-        1 => Ok(Some(v[0] as u128)),
-        2 => Ok(Some(read_u16(v, 0)? as u128)),
-        4 => Ok(Some(read_u32(v, 0)? as u128)),
-        8 => Ok(Some(read_u64(v, 0)? as u128)),
-        16 => Ok(Some(read_u128(v, 0)?)),
-        _ => Err(StoreError::BadData(format!(
+    if v.len() == 0 || v.len() > 16 {
+        return Err(StoreError::BadData(format!(
             "u128 little endian header size {}",
             v.len()
-        ))),
+        )));
     }
+    Ok(Some(match v.len() {
+        1 => v[0] as u128,
+        2 => read_u16(v, 0)? as u128,
+        4 => read_u32(v, 0)? as u128,
+        8 => read_u64(v, 0)? as u128,
+        16 => read_u128(v, 0)?,
+        _ => v.iter().rev().fold(0u128, |acc, b| (acc << 8) | *b as u128),
+    }))
 }
 
 fn parse_be_as_u128(v: &[u8]) -> Result<Option<u128>, StoreError> {
-    match v.len() {
-        1 => Ok(Some(v[0] as u128)),
-        2 => Ok(Some(read_u16_be(v, 0)? as u128)),
-        4 => Ok(Some(read_u32_be(v, 0)? as u128)),
-        8 => Ok(Some(read_u64_be(v, 0)? as u128)),
-        16 => Ok(Some(read_u128_be(v, 0)?)),
-        _ => Err(StoreError::BadData(format!(
+    if v.len() == 0 || v.len() > 16 {
+        return Err(StoreError::BadData(format!(
             "u128 big endian header size {}",
             v.len()
-        ))),
+        )));
     }
+    Ok(Some(match v.len() {
+        1 => v[0] as u128,
+        2 => read_u16_be(v, 0)? as u128,
+        4 => read_u32_be(v, 0)? as u128,
+        8 => read_u64_be(v, 0)? as u128,
+        16 => read_u128_be(v, 0)?,
+        _ => v.iter().fold(0u128, |acc, b| (acc << 8) | *b as u128),
+    }))
 }
 
 fn parse_utf8_as_u128(v: &[u8]) -> Result<Option<u128>, StoreError> {
@@ -375,29 +510,47 @@ fn parse_opt_utf8_as_i64(v: Option<&[u8]>) -> Result<Option<i64>, StoreError> {
 }
 
 fn parse_le_as_i64(v: &[u8]) -> Result<Option<i64>, StoreError> {
-    match v.len() {
-        1 => Ok(Some(read_i8(v, 0)? as i64)),
-        2 => Ok(Some(read_i16(v, 0)? as i64)),
-        4 => Ok(Some(read_i32(v, 0)? as i64)),
-        8 => Ok(Some(read_i64(v, 0)?)),
-        _ => Err(StoreError::BadData(format!(
+    if v.len() == 0 || v.len() > 8 {
+        return Err(StoreError::BadData(format!(
             "i64 little endian header size {}",
             v.len()
-        ))),
+        )));
     }
+    Ok(Some(match v.len() {
+        1 => read_i8(v, 0)? as i64,
+        2 => read_i16(v, 0)? as i64,
+        4 => read_i32(v, 0)? as i64,
+        8 => read_i64(v, 0)?,
+        _ => sign_extend(
+            v.iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64),
+            v.len(),
+        ),
+    }))
 }
 
 fn parse_be_as_i64(v: &[u8]) -> Result<Option<i64>, StoreError> {
-    match v.len() {
-        1 => Ok(Some(read_i8_be(v, 0)? as i64)),
-        2 => Ok(Some(read_i16_be(v, 0)? as i64)),
-        4 => Ok(Some(read_i32_be(v, 0)? as i64)),
-        8 => Ok(Some(read_i64_be(v, 0)?)),
-        _ => Err(StoreError::BadData(format!(
+    if v.len() == 0 || v.len() > 8 {
+        return Err(StoreError::BadData(format!(
             "i64 big endian header size {}",
             v.len()
-        ))),
+        )));
     }
+    Ok(Some(match v.len() {
+        1 => read_i8_be(v, 0)? as i64,
+        2 => read_i16_be(v, 0)? as i64,
+        4 => read_i32_be(v, 0)? as i64,
+        8 => read_i64_be(v, 0)?,
+        _ => sign_extend(
+            v.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64),
+            v.len(),
+        ),
+    }))
+}
+
+/// interpret the low `width` bytes of value as a two's complement integer
+fn sign_extend(value: u64, width: usize) -> i64 {
+    let shift = 64 - width * 8;
+    ((value << shift) as i64) >> shift
 }
 
 fn parse_utf8_as_i64(v: &[u8]) -> Result<Option<i64>, StoreError> {
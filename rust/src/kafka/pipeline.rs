@@ -0,0 +1,189 @@
+use super::{KafkaConsumerBridge, KafkaConsumerBridgeConfig};
+use crate::*;
+use ini::Ini;
+use std::time::Duration;
+
+/// a stage of a StorePipeline, from the store outwards
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorePipelineStage {
+    S3Writer,
+    Batcher,
+    KafkaBridge,
+}
+impl StorePipelineStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorePipelineStage::S3Writer => "s3 writer",
+            StorePipelineStage::Batcher => "batcher",
+            StorePipelineStage::KafkaBridge => "kafka bridge",
+        }
+    }
+}
+
+/// an error from a single stage of a StorePipeline
+#[derive(Debug)]
+pub struct StorePipelineError {
+    pub stage: StorePipelineStage,
+    pub error: StoreError,
+}
+impl StorePipelineError {
+    fn new(stage: StorePipelineStage, error: StoreError) -> Self {
+        Self { stage, error }
+    }
+}
+impl ToString for StorePipelineError {
+    fn to_string(&self) -> String {
+        format!("{}: {}", self.stage.name(), self.error.to_string())
+    }
+}
+
+/// builds a StorePipeline from the config of each stage
+pub struct StorePipelineBuilder {
+    s3_config: Option<S3StoreConfig>,
+    batcher_config: Option<BatchingStoreWriterConfig>,
+    kafka_config: Option<KafkaConsumerBridgeConfig>,
+    ping: bool,
+}
+impl StorePipelineBuilder {
+    pub fn new() -> Self {
+        Self {
+            s3_config: None,
+            batcher_config: None,
+            kafka_config: None,
+            ping: true,
+        }
+    }
+    /// required, the config of the S3StoreWriter at the end of the pipeline
+    pub fn set_s3_config(mut self, v: S3StoreConfig) -> Self {
+        self.s3_config = Some(v);
+        self
+    }
+    /// required, the config of the BatchingStoreWriter between the bridge and the S3StoreWriter
+    pub fn set_batcher_config(mut self, v: BatchingStoreWriterConfig) -> Self {
+        self.batcher_config = Some(v);
+        self
+    }
+    /// required, the config of the KafkaConsumerBridge at the start of the pipeline
+    pub fn set_kafka_config(mut self, v: KafkaConsumerBridgeConfig) -> Self {
+        self.kafka_config = Some(v);
+        self
+    }
+    /// ping the bucket after creating the S3StoreWriter, so an unreachable bucket fails the build, defaults to true
+    pub fn set_ping(mut self, v: bool) -> Self {
+        self.ping = v;
+        self
+    }
+    /// load the config of every stage from an ini file
+    pub fn load(ini: &Ini) -> Result<Self, StorePipelineError> {
+        let s3_config = match S3StoreConfig::load(ini) {
+            Ok(v) => v,
+            Err(err) => return Err(StorePipelineError::new(StorePipelineStage::S3Writer, err)),
+        };
+        let batcher_config = match BatchingStoreWriterConfig::load(ini) {
+            Ok(v) => v,
+            Err(err) => return Err(StorePipelineError::new(StorePipelineStage::Batcher, err)),
+        };
+        let kafka_config = match KafkaConsumerBridgeConfig::load(ini) {
+            Ok(v) => v,
+            Err(err) => {
+                return Err(StorePipelineError::new(
+                    StorePipelineStage::KafkaBridge,
+                    err,
+                ))
+            }
+        };
+        Ok(Self::new()
+            .set_s3_config(s3_config)
+            .set_batcher_config(batcher_config)
+            .set_kafka_config(kafka_config))
+    }
+    /// instantiate and connect every stage, returning an error identifying the first stage that failed
+    pub fn build(self) -> Result<StorePipeline, StorePipelineError> {
+        let s3_config = match self.s3_config {
+            Some(v) => v,
+            None => {
+                return Err(StorePipelineError::new(
+                    StorePipelineStage::S3Writer,
+                    StoreError::BadConfiguration("s3 config not defined".to_string()),
+                ))
+            }
+        };
+        let batcher_config = match self.batcher_config {
+            Some(v) => v,
+            None => {
+                return Err(StorePipelineError::new(
+                    StorePipelineStage::Batcher,
+                    StoreError::BadConfiguration("batcher config not defined".to_string()),
+                ))
+            }
+        };
+        let kafka_config = match self.kafka_config {
+            Some(v) => v,
+            None => {
+                return Err(StorePipelineError::new(
+                    StorePipelineStage::KafkaBridge,
+                    StoreError::BadConfiguration("kafka config not defined".to_string()),
+                ))
+            }
+        };
+        let s3 = match S3StoreWriter::new(s3_config) {
+            Ok(v) => v,
+            Err(err) => return Err(StorePipelineError::new(StorePipelineStage::S3Writer, err)),
+        };
+        if self.ping {
+            if let Err(err) = s3.ping() {
+                return Err(StorePipelineError::new(StorePipelineStage::S3Writer, err));
+            }
+        }
+        let batcher = match BatchingStoreWriter::new(batcher_config, s3) {
+            Ok(v) => v,
+            Err(err) => return Err(StorePipelineError::new(StorePipelineStage::Batcher, err)),
+        };
+        let bridge = match KafkaConsumerBridge::new(kafka_config, batcher) {
+            Ok(v) => v,
+            Err(err) => {
+                return Err(StorePipelineError::new(
+                    StorePipelineStage::KafkaBridge,
+                    err,
+                ))
+            }
+        };
+        Ok(StorePipeline { bridge })
+    }
+}
+
+/// a KafkaConsumerBridge writing through a BatchingStoreWriter to an S3StoreWriter, ready to subscribe and poll
+pub struct StorePipeline {
+    bridge: KafkaConsumerBridge<BatchingStoreWriter<S3StoreWriter>>,
+}
+impl StorePipeline {
+    pub fn builder() -> StorePipelineBuilder {
+        StorePipelineBuilder::new()
+    }
+    /// the bridge at the start of the pipeline
+    pub fn bridge(&self) -> &KafkaConsumerBridge<BatchingStoreWriter<S3StoreWriter>> {
+        &self.bridge
+    }
+    pub fn subscribe(&self) -> Result<(), StoreError> {
+        self.bridge.subscribe()
+    }
+    pub fn poll(&self, timeout: Duration) -> Result<(), StoreError> {
+        self.bridge.poll(timeout)
+    }
+    /// flush and commit the bridge, then flush and stop the batcher
+    pub fn shutdown(self) -> Result<(), StorePipelineError> {
+        let batcher = match self.bridge.shutdown() {
+            Ok(v) => v,
+            Err(err) => {
+                return Err(StorePipelineError::new(
+                    StorePipelineStage::KafkaBridge,
+                    err,
+                ))
+            }
+        };
+        match batcher.shutdown() {
+            Ok(_) => Ok(()),
+            Err(err) => Err(StorePipelineError::new(StorePipelineStage::Batcher, err)),
+        }
+    }
+}
@@ -1,27 +1,81 @@
+extern crate attohttpc;
 extern crate awscreds;
+extern crate base64;
+extern crate hmac;
 extern crate ini;
 extern crate linked_hash_map;
+extern crate md5;
+#[cfg(feature = "parquet")]
+extern crate parquet;
+#[cfg(feature = "kafka")]
 extern crate rdkafka;
 extern crate regex;
 extern crate s3 as aws_s3;
+extern crate sha2;
 extern crate threadlanes;
 
 mod batching;
 mod common;
+#[cfg(feature = "parquet")]
+mod export;
+pub mod format;
+#[cfg(feature = "kafka")]
 mod kafka;
 mod s3;
+#[cfg(feature = "testing")]
+mod testing;
 
 pub type S3StoreConfig = s3::S3StoreConfig;
+pub type RetryPolicy = s3::RetryPolicy;
+pub type MigrationConfig = s3::MigrationConfig;
 pub type S3StoreReader = s3::S3StoreReader;
 pub type S3StoreWriter = s3::S3StoreWriter;
+pub type PageIterator<'a> = s3::PageIterator<'a>;
+pub use s3::{continuation_for_offset, exclusive_continuation, ReadEstimate, ReadStats};
 
 pub type BatchingStoreWriterConfig = batching::BatchingStoreWriterConfig;
 pub type BatchingStoreWriter<W> = batching::BatchingStoreWriter<W>;
+#[cfg(feature = "sync-batching")]
+pub type SyncBatchingStoreWriter<W> = batching::SyncBatchingStoreWriter<W>;
+pub type BatchStats = batching::BatchStats;
+pub type FlushReason = batching::FlushReason;
+pub use common::keypath::KeyPath;
+pub use common::time::{Clock, SystemClock};
+#[cfg(feature = "testing")]
+pub use s3::signing;
 
+#[cfg(feature = "parquet")]
+pub use export::export_parquet;
+
+#[cfg(feature = "testing")]
+pub type RecordingStoreWriter = testing::RecordingStoreWriter;
+#[cfg(feature = "testing")]
+pub type RecordedCall = testing::RecordedCall;
+#[cfg(feature = "testing")]
+pub type MockClock = testing::MockClock;
+#[cfg(feature = "testing")]
+pub type MemoryObjectStore = testing::MemoryObjectStore;
+
+#[cfg(feature = "kafka")]
 pub type KafkaConsumerBridgeConfig = kafka::KafkaConsumerBridgeConfig;
+#[cfg(feature = "kafka")]
 pub type KafkaConsumerBridge<W> = kafka::KafkaConsumerBridge<W>;
+#[cfg(feature = "kafka")]
+pub type KafkaConsumerCommitOn = kafka::KafkaConsumerCommitOn;
+#[cfg(feature = "kafka")]
+pub type KafkaCommitMode = rdkafka::consumer::CommitMode;
+#[cfg(feature = "kafka")]
 pub type KafkaConsumerNumberParser = kafka::KafkaConsumerNumberParser;
+#[cfg(feature = "kafka")]
 pub type KafkaConsumerUtf8Parser = kafka::KafkaConsumerUtf8Parser;
+#[cfg(feature = "kafka")]
+pub type StorePipeline = kafka::StorePipeline;
+#[cfg(feature = "kafka")]
+pub type StorePipelineBuilder = kafka::StorePipelineBuilder;
+#[cfg(feature = "kafka")]
+pub type StorePipelineError = kafka::StorePipelineError;
+#[cfg(feature = "kafka")]
+pub type StorePipelineStage = kafka::StorePipelineStage;
 
 /// A Key-Log Store Writer.
 /// Batching and nonce checking requires that a single key is bound to a single writer at any given time.
@@ -35,9 +89,29 @@ pub trait StoreWriter {
     fn flush_key(&self, keyspace: &str, key: &str) -> Result<(), StoreError>;
     /// flush all pending asynchronous operations
     fn flush_all(&self) -> Result<(), StoreError>;
+    /// flush all pending asynchronous operations like flush_all, reporting every key written since the last flush of all keys
+    /// and every key with a failed write, so a caller can acknowledge the keys that are durable.
+    /// defaults to flush_all with an empty report, so an error fails every key.
+    fn flush_all_with_report(&self) -> Result<FlushReport, StoreError> {
+        self.flush_all()?;
+        Ok(FlushReport {
+            flushed: Vec::new(),
+            failed: Vec::new(),
+        })
+    }
     /// should be called periodically for implementation that require it.
     /// this will trigger scheduled operations, like flushing a pending batch.
     fn duty_cycle(&self) -> Result<(), StoreError>;
+    /// the current time in the timestamp unit of the keyspace, assigned to insertions without a timestamp.
+    /// defaults to milliseconds since the epoch.
+    fn timestamp_now(&self, _keyspace: &str) -> Result<i64, StoreError> {
+        Ok(TimestampUnit::Millis.now())
+    }
+    /// the next offset of a key after the writes made by this writer, or None when the writer does not track offsets.
+    /// defaults to None.
+    fn next_offset(&self, _keyspace: &str, _key: &str) -> Result<Option<u64>, StoreError> {
+        Ok(None)
+    }
 }
 
 /// A Key-Log Store Reader.
@@ -52,6 +126,7 @@ pub trait StoreReader {
         key: &str,
     ) -> Result<Option<KeyMetadata>, StoreError>;
     /// read the first page of a log, returning an empty log if the key does not exist.
+    /// some implementations may be configured to instead return KeyspaceNotFound or KeyNotFound.
     /// the page will begin iteration from the given StartPosition, advancing in the given Direction
     /// if specified, page_size determines the max records to be returned, otherwise a configured default is used.
     /// the Page result will contain an optional continuation token that can be passed to the read_next_page function.
@@ -64,6 +139,7 @@ pub trait StoreReader {
         page_size: Option<u64>,
     ) -> Result<Page, StoreError>;
     /// read the next page of a log based on the given continuation token.
+    /// the page starts with the record after the last record of the previous page, so no record is returned twice.
     /// if specified, page_size determines the max records to be returned, otherwise a configured default is used.
     fn read_next_page(
         &self,
@@ -72,6 +148,61 @@ pub trait StoreReader {
         continuation: String,
         page_size: Option<u64>,
     ) -> Result<Page, StoreError>;
+    /// read_first_page, skipping records at exactly the start position when inclusive is false.
+    /// an exclusive start beyond the last possible offset, timestamp, or nonce returns an empty page.
+    /// defaults to read_first_page from the exclusive start position.
+    fn read_first_page_from(
+        &self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        inclusive: bool,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        let start = match inclusive {
+            true => start,
+            false => match start.exclusive(&direction) {
+                Some(v) => v,
+                None => {
+                    return Ok(Page {
+                        keyspace: keyspace.to_string(),
+                        key: key.to_string(),
+                        continuation: None,
+                        records: Vec::new(),
+                    })
+                }
+            },
+        };
+        self.read_first_page(keyspace, key, direction, start, page_size)
+    }
+    /// read_next_page, skipping the record at exactly the offset the continuation resumes at when inclusive is false.
+    /// a page continuation already resumes after the last record of its page, so this is for continuations built from an offset.
+    /// defaults to read_next_page from the exclusive continuation, which must be in the format of continuation_for_offset.
+    fn read_next_page_from(
+        &self,
+        keyspace: &str,
+        key: &str,
+        continuation: String,
+        inclusive: bool,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        let continuation = match inclusive {
+            true => continuation,
+            false => match exclusive_continuation(&continuation)? {
+                Some(v) => v,
+                None => {
+                    return Ok(Page {
+                        keyspace: keyspace.to_string(),
+                        key: key.to_string(),
+                        continuation: None,
+                        records: Vec::new(),
+                    })
+                }
+            },
+        };
+        self.read_next_page(keyspace, key, continuation, page_size)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -80,10 +211,18 @@ pub enum Direction {
     Backwards,
 }
 
+/// Errors returned by store operations.
+/// Use is_retryable to determine if the same operation may succeed when attempted again.
 #[derive(Debug)]
 pub enum StoreError {
     /// IO error communicating with underlying storage, likely retryable
     IOError(String),
+    /// Underlying storage is rate-limiting requests, retryable after backing off
+    Throttled(String),
+    /// Underlying storage is temporarily unavailable, retryable
+    Unavailable(String),
+    /// Request to underlying storage timed out, retryable
+    Timeout(String),
     // Underlying data has an issue, likely not retryable
     BadData(String),
     // Bad configuration
@@ -94,16 +233,56 @@ pub enum StoreError {
     KeyspaceAlreadyExists,
     // Keyspace not found
     KeyspaceNotFound,
+    // Key not found
+    KeyNotFound,
+    // Object not found at the given path
+    ObjectNotFound(String),
+    // Key already has data
+    KeyAlreadyExists,
+    // Another writer wrote the same offsets of a key
+    Conflict(String),
+    // Offsets recorded by a compaction manifest are no longer stored
+    DataLoss(String),
+}
+impl StoreError {
+    /// true when retrying the same operation may succeed: IOError, Throttled, Unavailable, and Timeout.
+    /// all other errors will fail again until the data, configuration, or request is changed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            StoreError::IOError(_) => true,
+            StoreError::Throttled(_) => true,
+            StoreError::Unavailable(_) => true,
+            StoreError::Timeout(_) => true,
+            StoreError::BadData(_) => false,
+            StoreError::BadConfiguration(_) => false,
+            StoreError::InvalidContinuation(_) => false,
+            StoreError::KeyspaceAlreadyExists => false,
+            StoreError::KeyspaceNotFound => false,
+            StoreError::KeyNotFound => false,
+            StoreError::ObjectNotFound(_) => false,
+            StoreError::KeyAlreadyExists => false,
+            StoreError::Conflict(_) => false,
+            StoreError::DataLoss(_) => false,
+        }
+    }
 }
 impl ToString for StoreError {
     fn to_string(&self) -> String {
         match self {
             StoreError::IOError(s) => format!("IOError({})", s),
+            StoreError::Throttled(s) => format!("Throttled({})", s),
+            StoreError::Unavailable(s) => format!("Unavailable({})", s),
+            StoreError::Timeout(s) => format!("Timeout({})", s),
             StoreError::BadData(s) => format!("BadData({})", s),
             StoreError::BadConfiguration(s) => format!("BadConfiguration({})", s),
             StoreError::InvalidContinuation(s) => format!("InvalidContinuation({})", s),
             StoreError::KeyspaceAlreadyExists => "KeyspaceAlreadyExists".to_string(),
+            StoreError::KeyAlreadyExists => "KeyAlreadyExists".to_string(),
             StoreError::KeyspaceNotFound => "KeyspaceNotFound".to_string(),
+            StoreError::KeyNotFound => "KeyNotFound".to_string(),
+            StoreError::ObjectNotFound(s) => format!("ObjectNotFound({})", s),
+            StoreError::Conflict(s) => format!("Conflict({})", s),
+            StoreError::DataLoss(s) => format!("DataLoss({})", s),
         }
     }
 }
@@ -113,9 +292,90 @@ pub struct CreatedKeyspace {
     pub keyspace: String,
 }
 
+/// the keys written by a writer since its last flush, for callers that acknowledge keys upstream once they are durable
+#[derive(Debug)]
+pub struct FlushReport {
+    pub flushed: Vec<FlushedKey>,
+    /// keys with a failed write since the last flush, which are never also in flushed
+    pub failed: Vec<FailedKey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlushedKey {
+    pub keyspace: String,
+    pub key: String,
+    /// next offset of the key after the write, None when the underlying writer does not track offsets
+    pub next_offset: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct FailedKey {
+    pub keyspace: String,
+    pub key: String,
+    pub error: StoreError,
+}
+
+/// progress of S3StoreWriter::migrate_keyspace, cumulative over every key processed so far
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    /// keys with records copied by this run
+    pub keys_migrated: u64,
+    /// keys that were already fully copied by an earlier run
+    pub keys_skipped: u64,
+    pub records_copied: u64,
+}
+/// Hook invoked by S3StoreWriter::migrate_keyspace after each key, with the key and the progress so far.
+pub type MigrationProgressListener = std::sync::Arc<dyn Fn(&str, &MigrationReport) + Send + Sync>;
+
 #[derive(Debug, Clone)]
 pub struct KeyspaceMetadata {
+    /// milliseconds since the epoch, regardless of the timestamp unit
     pub created_timestamp: i64,
+    pub timestamp_unit: TimestampUnit,
+    /// version of the record layout of data objects, keyspaces with a newer version than supported fail to load
+    pub format_version: u32,
+    /// records omit their trailing total length, so the keyspace can only be read forwards
+    pub forward_only: bool,
+    /// nonces are stored as u64 instead of u128
+    pub u64_nonces: bool,
+}
+
+/// Unit of record timestamps since the epoch, chosen per keyspace when it is created.
+/// Every record, object path, and StartPosition::Timestamp of a keyspace uses the same unit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampUnit {
+    Millis,
+    /// for sub-millisecond ordering, an i64 of nanoseconds reaches until the year 2262
+    Nanos,
+}
+impl TimestampUnit {
+    pub fn now(&self) -> i64 {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system time is negative");
+        match self {
+            TimestampUnit::Millis => since_epoch.as_millis() as i64,
+            TimestampUnit::Nanos => since_epoch.as_nanos() as i64,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimestampUnit::Millis => "millis",
+            TimestampUnit::Nanos => "nanos",
+        }
+    }
+}
+
+/// How appends treat a timestamp lower than the max timestamp of the key or of an earlier record of the same append.
+/// StartPosition::Timestamp seeks assume timestamps never decrease with offset, which Clamp and Reject guarantee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampPolicy {
+    /// write timestamps as given
+    Allow,
+    /// raise the timestamp to the max timestamp so far, logging a warning per written object
+    Clamp,
+    /// fail the append with BadData
+    Reject,
 }
 
 #[derive(Debug, Clone)]
@@ -124,12 +384,69 @@ pub struct KeyMetadata {
     pub next_nonce: u128,
 }
 
+#[derive(Debug, Clone)]
+pub struct KeySize {
+    pub object_count: u64,
+    pub total_size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyspaceSize {
+    pub key_count: u64,
+    pub object_count: u64,
+    pub total_size: u64,
+}
+
+/// Physical object layout of a key, decoded from object paths
+#[derive(Debug, Clone)]
+pub struct KeyLayout {
+    /// first offset of the object at the watermark, or None if compaction never advanced the watermark
+    pub watermark_offset: Option<u64>,
+    /// every data object of the key, in offset order
+    pub objects: Vec<ObjectLayout>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectLayout {
+    pub path: String,
+    pub first_offset: u64,
+    pub last_offset: u64,
+    pub min_timestamp: i64,
+    pub max_timestamp: i64,
+    pub first_nonce: u128,
+    pub next_nonce: u128,
+    pub size: u64,
+    pub prior_start_offset: u64,
+    /// true when the object starts at or before the watermark, so compaction will never rewrite it
+    pub sealed: bool,
+}
+
+/// What compaction would do for a key given its current state, computed without mutating anything
+#[derive(Debug, Clone)]
+pub struct CompactionPlan {
+    /// true when a compaction threshold has been reached
+    pub triggered: bool,
+    /// objects after the watermark, grouped by the resulting object each group would be merged into.
+    /// a group of one object is only rewritten when its link to the prior object changes.
+    pub groups: Vec<Vec<String>>,
+    /// size of the resulting object for each group
+    pub compacted_sizes: Vec<u64>,
+    /// true when the watermark would advance to the first offset of the last resulting object
+    pub advance_watermark: bool,
+    /// true when an object count compaction would seal all but the last resulting object behind the watermark
+    pub seal_watermark: bool,
+}
+
 #[derive(Debug)]
 pub struct Insertion {
     pub record: Vec<u8>,
     pub nonce: Option<u128>,
     pub timestamp: Option<i64>,
 }
+/// Hook invoked on each record before it is stored, to rewrite the record or set its timestamp.
+/// Returning an error rejects the whole append before anything is written.
+pub type RecordTransform =
+    std::sync::Arc<dyn Fn(&mut Insertion) -> Result<(), StoreError> + Send + Sync>;
 #[derive(Debug)]
 pub struct Append {
     pub keyspace: String,
@@ -137,13 +454,46 @@ pub struct Append {
     pub records: Vec<Insertion>,
 }
 
-#[derive(Debug)]
+/// Where a read begins. Every position is inclusive, so the first page starts with the record at the exact
+/// offset, timestamp, or nonce when it exists, and otherwise with the next one in the direction of the read.
+#[derive(Debug, Clone)]
 pub enum StartPosition {
+    /// the default start of the keyspace from the reader config, read in the direction of the read.
+    /// readers without defaults, or a default start of Default, read from First.
+    Default,
     First,
     Nonce(u128),
     Timestamp(i64),
     Offset(u64),
 }
+impl StartPosition {
+    /// the position that skips records at exactly this position in the given direction,
+    /// or None when no record can be beyond it. First has no boundary, so it is unchanged.
+    pub fn exclusive(&self, direction: &Direction) -> Option<StartPosition> {
+        match (self, direction) {
+            (StartPosition::Default, _) => Some(StartPosition::Default),
+            (StartPosition::First, _) => Some(StartPosition::First),
+            (StartPosition::Nonce(v), Direction::Forwards) => {
+                v.checked_add(1).map(StartPosition::Nonce)
+            }
+            (StartPosition::Nonce(v), Direction::Backwards) => {
+                v.checked_sub(1).map(StartPosition::Nonce)
+            }
+            (StartPosition::Timestamp(v), Direction::Forwards) => {
+                v.checked_add(1).map(StartPosition::Timestamp)
+            }
+            (StartPosition::Timestamp(v), Direction::Backwards) => {
+                v.checked_sub(1).map(StartPosition::Timestamp)
+            }
+            (StartPosition::Offset(v), Direction::Forwards) => {
+                v.checked_add(1).map(StartPosition::Offset)
+            }
+            (StartPosition::Offset(v), Direction::Backwards) => {
+                v.checked_sub(1).map(StartPosition::Offset)
+            }
+        }
+    }
+}
 #[derive(Debug, Clone)]
 pub struct Record {
     pub offset: u64,
@@ -151,6 +501,107 @@ pub struct Record {
     pub nonce: Option<u128>,
     pub value: Vec<u8>,
 }
+/// Structured event describing store activity, with fields instead of a pre-formatted log message
+#[derive(Debug, Clone)]
+pub enum StoreEvent {
+    /// an append wrote a data object
+    Appended {
+        keyspace: String,
+        key: String,
+        first_offset: u64,
+        last_offset: u64,
+        record_count: u64,
+        size: u64,
+    },
+    /// compaction merged the uncompacted objects of a key
+    Compacted {
+        keyspace: String,
+        key: String,
+        /// watermark of the key after compaction
+        watermark_offset: u64,
+        /// objects of the key still after the watermark once compaction completed, not the number of objects merged
+        uncompacted_objects: u64,
+    },
+    /// a read completed, result_count is records returned, or objects for read_raw_objects
+    Read {
+        operation: &'static str,
+        keyspace: String,
+        key: String,
+        result_count: u64,
+        list_operation_count: u64,
+        read_operation_count: u64,
+        read_size_total: u64,
+        continuation_miss_count: u64,
+        object_cache_hit_count: u64,
+    },
+    /// the kafka bridge committed offsets of a partition
+    PartitionCommitted {
+        topic: String,
+        partition: i32,
+        record_count: u64,
+        last_timestamp: Option<i64>,
+    },
+}
+/// Callback receiving every StoreEvent, in addition to the usual log output.
+/// Invoked on the calling thread, so it should hand events off rather than block.
+pub type StoreEventListener = std::sync::Arc<dyn Fn(&StoreEvent) + Send + Sync>;
+
+/// A record whose value borrows from the object it was read from, only valid within the callback it is given to
+#[derive(Debug, Clone, Copy)]
+pub struct RecordRef<'a> {
+    pub offset: u64,
+    pub timestamp: i64,
+    pub nonce: Option<u128>,
+    pub value: &'a [u8],
+}
+/// The header of a record without its value, for scans of offsets, timestamps, and nonces
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordMeta {
+    pub offset: u64,
+    pub timestamp: i64,
+    pub nonce: Option<u128>,
+    /// length of the value in bytes
+    pub value_length: u32,
+}
+/// Order of records merged from multiple keys
+#[derive(Debug, Clone)]
+pub enum MergeOrder {
+    /// by record timestamp, such as to read renamed or sharded keys as one timeline
+    Timestamp,
+    /// by record offset, such as to interleave keys that are appended in lockstep
+    Offset,
+}
+/// A record of a merged read, along with the key it was read from
+#[derive(Debug, Clone)]
+pub struct MergedRecord {
+    pub key: String,
+    pub record: Record,
+}
+/// A page of records merged from multiple keys of a keyspace
+#[derive(Debug, Clone)]
+pub struct MergedPage {
+    pub keyspace: String,
+    pub records: Vec<MergedRecord>,
+    pub continuation: Option<String>,
+}
+/// Fault to return from an S3 operation instead of performing it, see FaultInjector
+#[cfg(feature = "testing")]
+#[derive(Debug)]
+pub enum InjectedFault {
+    /// a GET returns the object as missing, and a LIST returns an empty final page. a PUT is performed as usual
+    Missing,
+    /// the operation fails with the given error, without retries
+    Error(StoreError),
+}
+/// Hook consulted by a reader before every GET and LIST with the operation name and the object path or listed prefix,
+/// and by a writer also before every unconditional PUT.
+/// Operations are get_object, get_object_range, list_page, and put_object. Returning None performs the operation as usual.
+/// Allows tests to make a specific object disappear, as if removed by a concurrent compaction, or a write fail.
+#[cfg(feature = "testing")]
+pub type FaultInjector = std::sync::Arc<dyn Fn(&str, &str) -> Option<InjectedFault> + Send + Sync>;
+/// Predicate applied to records as they are read, after the built-in offset, timestamp, and nonce filtering.
+/// Records for which it returns false are skipped and do not count towards the page size.
+pub type RecordPredicate = std::sync::Arc<dyn Fn(&Record) -> bool + Send + Sync>;
 #[derive(Debug, Clone)]
 pub struct Page {
     pub keyspace: String,
@@ -1,10 +1,95 @@
-use super::config::S3StoreConfig;
+use super::config::{RetryPolicy, S3StoreConfig};
+use super::rate_limit::RateLimiter;
+use super::signing;
+use crate::common::time::time_now_as_millis;
 use crate::*;
+use aws_s3::blocking::AttoRequest;
 use aws_s3::bucket::Bucket;
+use aws_s3::command::Command;
+use aws_s3::request_trait::Request;
 use aws_s3::Region;
 use awscreds::Credentials;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn create(config: &S3StoreConfig) -> Result<Bucket, StoreError> {
+/// an S3 bucket along with the policies applied to every operation against it
+#[derive(Clone)]
+pub struct S3Bucket {
+    pub bucket: Bucket,
+    pub retry_policy: RetryPolicy,
+    pub credentials: Arc<CredentialsProvider>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// largest object a single PUT may write
+    pub max_put_size: u64,
+    /// most pages of a single listing, 0 for unlimited
+    pub max_list_pages: u64,
+    /// headers of every PUT, from the put_headers of the config
+    pub put_headers: Arc<Vec<(String, String)>>,
+    /// headers of every GET and HEAD, the SSE-C put_headers that are needed to read what was written with them
+    pub get_headers: Arc<Vec<(String, String)>>,
+    #[cfg(feature = "testing")]
+    pub fault_injector: Option<FaultInjector>,
+    /// objects are stored here instead of the bucket when set
+    #[cfg(feature = "testing")]
+    pub memory_store: Option<crate::testing::MemoryObjectStore>,
+}
+
+/// resolves credentials from the config, re-resolving them when refreshed so temporary STS credentials are renewed.
+/// shared by every clone of a bucket, so a refresh is seen by all operations.
+pub struct CredentialsProvider {
+    config: S3StoreConfig,
+    refresh_interval_millis: u64,
+    current: RwLock<(Credentials, u64)>,
+}
+impl CredentialsProvider {
+    fn new(config: &S3StoreConfig) -> Result<Self, StoreError> {
+        let credentials = match resolve_credentials(config) {
+            Ok(v) => v,
+            Err(err) => {
+                return Err(StoreError::BadConfiguration(format!(
+                    "could not create credentials: {}",
+                    err
+                )))
+            }
+        };
+        Ok(Self {
+            config: config.clone(),
+            refresh_interval_millis: config.credentials_refresh_interval_millis,
+            current: RwLock::new((credentials, time_now_as_millis())),
+        })
+    }
+    /// current credentials, first refreshing them if the refresh interval elapsed
+    pub fn get(&self) -> Result<Credentials, StoreError> {
+        let (credentials, resolved_timestamp) = self.current.read().unwrap().clone();
+        if self.refresh_interval_millis > 0
+            && time_now_as_millis() >= resolved_timestamp + self.refresh_interval_millis
+        {
+            return self.refresh();
+        }
+        Ok(credentials)
+    }
+    /// re-resolve credentials from the config, called on a schedule or after a request is denied
+    pub fn refresh(&self) -> Result<Credentials, StoreError> {
+        let credentials = match resolve_credentials(&self.config) {
+            Ok(v) => v,
+            Err(err) => {
+                return Err(StoreError::IOError(format!(
+                    "could not refresh credentials: {}",
+                    err
+                )))
+            }
+        };
+        *self.current.write().unwrap() = (credentials.clone(), time_now_as_millis());
+        log::info!("refreshed s3 credentials");
+        Ok(credentials)
+    }
+}
+
+/// SSE-C headers give the customer key that objects are encrypted with, which is required again to read them
+const SSE_C_HEADER_PREFIX: &str = "x-amz-server-side-encryption-customer-";
+
+pub fn create(config: &S3StoreConfig) -> Result<S3Bucket, StoreError> {
     if let None = config.bucket_name {
         return Err(StoreError::BadConfiguration(
             "bucket_name not defined".to_string(),
@@ -30,6 +115,57 @@ pub fn create(config: &S3StoreConfig) -> Result<Bucket, StoreError> {
         }
     };
 
+    let credentials = CredentialsProvider::new(config)?;
+    let bucket = if config.path_style {
+        Bucket::new_with_path_style(&bucket_name, region, credentials.get()?)
+    } else {
+        Bucket::new(&bucket_name, region, credentials.get()?)
+    };
+    let mut bucket = match bucket {
+        Ok(bucket) => bucket,
+        Err(err) => {
+            return Err(StoreError::BadConfiguration(format!(
+                "could not create bucket: {}",
+                err.to_string(),
+            )))
+        }
+    };
+    // bound every request, so a hung response fails as a retryable Timeout.
+    // the client has no separate connect timeout, so connecting is only bounded by the operating system
+    bucket.set_request_timeout(Some(Duration::from_millis(config.request_timeout_millis)));
+    let mut get_headers: Vec<(String, String)> = Vec::new();
+    for (name, value) in config.put_headers.iter() {
+        let lower = name.to_lowercase();
+        if lower == "if-none-match" || lower == "x-amz-storage-class" {
+            return Err(StoreError::BadConfiguration(format!(
+                "put header {} is set by the store",
+                name
+            )));
+        }
+        if lower.starts_with(SSE_C_HEADER_PREFIX) {
+            get_headers.push((name.clone(), value.clone()));
+        }
+    }
+    return Ok(S3Bucket {
+        bucket,
+        retry_policy: config.retry_policy.clone(),
+        credentials: Arc::new(credentials),
+        rate_limiter: Arc::new(RateLimiter::new(
+            config.max_requests_per_second,
+            config.max_bytes_per_second,
+        )),
+        max_put_size: config.max_put_size,
+        max_list_pages: config.max_list_pages,
+        put_headers: Arc::new(config.put_headers.clone()),
+        get_headers: Arc::new(get_headers),
+        #[cfg(feature = "testing")]
+        fault_injector: None,
+        #[cfg(feature = "testing")]
+        memory_store: config.memory_store.clone(),
+    });
+}
+
+fn resolve_credentials(config: &S3StoreConfig) -> Result<Credentials, String> {
     let credentials = match config.use_default_credentials {
         true => Credentials::default(),
         false => {
@@ -62,55 +198,179 @@ pub fn create(config: &S3StoreConfig) -> Result<Bucket, StoreError> {
             )
         }
     };
-    if let Err(err) = credentials {
-        return Err(StoreError::BadConfiguration(format!(
-            "could not create credentials: {}",
-            err.to_string()
-        )));
+    match credentials {
+        Ok(v) => Ok(v),
+        Err(err) => Err(err.to_string()),
     }
-    let credentials = credentials.unwrap();
-    if config.path_style {
-        return match Bucket::new_with_path_style(&bucket_name, region, credentials) {
-            Ok(bucket) => Ok(bucket),
-            Err(err) => Err(StoreError::BadConfiguration(format!(
-                "could not create bucket: {}",
-                err.to_string(),
-            ))),
-        };
+}
+
+/// outcome of a single failed attempt of an S3 operation
+enum Failure {
+    Retryable(StoreError),
+    Fatal(StoreError),
+    /// request was denied, retried once after refreshing credentials in case they expired
+    Denied(StoreError),
+}
+
+/// 5xx, 408, and 429 codes are transient failures, everything else is the caller's problem
+fn code_failure(code: u16) -> Failure {
+    match code {
+        429 | 503 => Failure::Retryable(StoreError::Throttled(format!("code {}", code))),
+        408 | 504 => Failure::Retryable(StoreError::Timeout(format!("code {}", code))),
+        500..=599 => Failure::Retryable(StoreError::Unavailable(format!("code {}", code))),
+        403 => Failure::Denied(StoreError::IOError(format!("code {}", code))),
+        _ => Failure::Fatal(StoreError::IOError(format!("code {}", code))),
+    }
+}
+
+/// network errors from the client are always retryable
+fn client_failure<E: ToString>(err: E) -> Failure {
+    let msg = err.to_string();
+    if msg.to_lowercase().contains("timed out") {
+        Failure::Retryable(StoreError::Timeout(msg))
     } else {
-        return match Bucket::new(&bucket_name, region, credentials) {
-            Ok(bucket) => Ok(bucket),
-            Err(err) => Err(StoreError::BadConfiguration(format!(
-                "could not create bucket: {}",
-                err.to_string(),
-            ))),
-        };
+        Failure::Retryable(StoreError::IOError(msg))
     }
 }
 
+fn with_retry<T, F: FnMut(&Bucket) -> Result<T, Failure>>(
+    bucket: &S3Bucket,
+    op_name: &str,
+    mut op: F,
+) -> Result<T, StoreError> {
+    let policy = &bucket.retry_policy;
+    let mut attempt: u32 = 1;
+    let mut refreshed = false;
+    let mut current = bucket.bucket.clone();
+    current.credentials = bucket.credentials.get()?;
+    loop {
+        bucket.rate_limiter.acquire_request();
+        match op(&current) {
+            Ok(v) => return Ok(v),
+            Err(Failure::Fatal(err)) => return Err(err),
+            Err(Failure::Denied(err)) => {
+                if refreshed {
+                    return Err(err);
+                }
+                log::warn!(
+                    "s3 {} denied, refreshing credentials: {}",
+                    op_name,
+                    err.to_string()
+                );
+                current.credentials = bucket.credentials.refresh()?;
+                refreshed = true;
+            }
+            Err(Failure::Retryable(err)) => {
+                if attempt >= policy.max_attempts {
+                    return Err(err);
+                }
+                let delay = retry_delay_millis(policy, attempt);
+                log::warn!(
+                    "s3 {} attempt {} failed, retrying in {}ms: {}",
+                    op_name,
+                    attempt,
+                    delay,
+                    err.to_string()
+                );
+                thread::sleep(Duration::from_millis(delay));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// exponential backoff from the base delay plus up to max_jitter_millis of jitter
+fn retry_delay_millis(policy: &RetryPolicy, attempt: u32) -> u64 {
+    let backoff = policy
+        .base_delay_millis
+        .saturating_mul(1u64 << (attempt - 1).min(16));
+    let jitter = match policy.max_jitter_millis {
+        0 => 0,
+        max => {
+            // sub-second clock noise is plenty random enough to spread out retries
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system time is negative")
+                .subsec_nanos() as u64;
+            nanos % (max + 1)
+        }
+    };
+    backoff.saturating_add(jitter)
+}
+
 pub fn list_exhaustive(
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     prefix: &str,
-    mut start_from: Option<String>,
+    start_from: Option<String>,
 ) -> Result<Vec<String>, StoreError> {
     let mut results: Vec<String> = Vec::new();
-    let mut s3_cont_token: Option<String> = None;
-    loop {
-        let (list, next_cont_token) = list_page(bucket, prefix, start_from, s3_cont_token, None)?;
-        for obj in list {
-            results.push(obj);
+    for list in list_pages(bucket, prefix, start_from) {
+        results.extend(list?);
+    }
+    return Ok(results);
+}
+
+/// page through a listing, fetching each page as it is consumed. fails with IOError instead of listing a page past
+/// max_list_pages, so a backend that never ends a listing fails the operation instead of hanging it.
+pub fn list_pages<'a>(
+    bucket: &'a S3Bucket,
+    prefix: &'a str,
+    start_from: Option<String>,
+) -> ListPages<'a> {
+    ListPages {
+        bucket,
+        prefix,
+        start_from,
+        s3_cont_token: None,
+        pages: 0,
+        done: false,
+    }
+}
+
+pub struct ListPages<'a> {
+    bucket: &'a S3Bucket,
+    prefix: &'a str,
+    start_from: Option<String>,
+    s3_cont_token: Option<String>,
+    pages: u64,
+    done: bool,
+}
+impl<'a> Iterator for ListPages<'a> {
+    type Item = Result<Vec<String>, StoreError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        self.pages += 1;
+        if self.bucket.max_list_pages > 0 && self.pages > self.bucket.max_list_pages {
+            self.done = true;
+            return Some(Err(StoreError::IOError(format!(
+                "listing {} exceeded max_list_pages {}",
+                self.prefix, self.bucket.max_list_pages
+            ))));
         }
-        start_from = None;
-        if let None = next_cont_token {
-            break;
+        match list_page(
+            self.bucket,
+            self.prefix,
+            self.start_from.clone(),
+            self.s3_cont_token.take(),
+            None,
+        ) {
+            Ok((list, next_s3_cont_token)) => {
+                self.done = next_s3_cont_token.is_none();
+                self.s3_cont_token = next_s3_cont_token;
+                Some(Ok(list))
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
         }
-        s3_cont_token = next_cont_token;
     }
-    return Ok(results);
 }
 
 // pub fn list_first(
-//     bucket: &Bucket,
+//     bucket: &S3Bucket,
 //     prefix: &str,
 //     start_from: Option<String>,
 // ) -> Result<Option<String>, StoreError> {
@@ -127,60 +387,547 @@ pub fn list_exhaustive(
 // }
 
 pub fn list_page(
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     prefix: &str,
     start_from: Option<String>,
     s3_cont_token: Option<String>,
     max_results: Option<usize>,
 ) -> Result<(Vec<String>, Option<String>), StoreError> {
+    #[cfg(feature = "testing")]
+    match injected_fault(bucket, "list_page", prefix) {
+        Some(InjectedFault::Missing) => return Ok((Vec::new(), None)),
+        Some(InjectedFault::Error(err)) => return Err(err),
+        None => {}
+    }
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(store.list_page(prefix, start_from, s3_cont_token, max_results));
+    }
     let mut results: Vec<String> = Vec::new();
-    let list = match bucket.list_page(
-        prefix.to_string(),
-        None,
-        s3_cont_token,
-        start_from,
-        max_results,
-    ) {
-        Ok((list, 200)) => list,
-        Ok((_, code)) => return Err(StoreError::IOError(format!("code {}", code))),
-        Err(err) => return Err(StoreError::IOError(err.to_string())),
-    };
+    let list = with_retry(bucket, "list_page", |b| {
+        match b.list_page(
+            prefix.to_string(),
+            None,
+            s3_cont_token.clone(),
+            start_from.clone(),
+            max_results,
+        ) {
+            Ok((list, 200)) => Ok(list),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })?;
+    // a backend that keeps returning the token it was given would be paged forever
+    if s3_cont_token.is_some() && list.next_continuation_token == s3_cont_token {
+        return Err(StoreError::IOError(format!(
+            "listing {} returned its own continuation token",
+            prefix
+        )));
+    }
     for obj in &list.contents {
         results.push(obj.key.clone());
     }
     return Ok((results, list.next_continuation_token));
 }
 
-pub fn put_object(bucket: &Bucket, object_path: String, buffer: &[u8]) -> Result<(), StoreError> {
-    match bucket.put_object(object_path, &buffer) {
-        Ok((_, 200)) => Ok(()),
-        Ok((_, code)) => Err(StoreError::IOError(format!("code {}", code))),
-        Err(err) => Err(StoreError::IOError(err.to_string())),
+/// list at most one object under the prefix, validating connectivity and that the credentials can list the bucket
+pub fn ping(bucket: &S3Bucket, prefix: &str) -> Result<(), StoreError> {
+    #[cfg(feature = "testing")]
+    if let Some(_) = &bucket.memory_store {
+        return Ok(());
+    }
+    with_retry(bucket, "ping", |b| {
+        match b.list_page(prefix.to_string(), None, None, None, Some(1)) {
+            Ok((_, 200)) => Ok(()),
+            Ok((_, 403)) => Err(Failure::Denied(StoreError::BadConfiguration(format!(
+                "access denied listing bucket {} with prefix {}",
+                b.name, prefix
+            )))),
+            Ok((_, 404)) => Err(Failure::Fatal(StoreError::BadConfiguration(format!(
+                "bucket {} not found",
+                b.name
+            )))),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })
+}
+
+pub fn put_object(bucket: &S3Bucket, object_path: String, buffer: &[u8]) -> Result<(), StoreError> {
+    check_put_size(bucket, &object_path, buffer)?;
+    #[cfg(feature = "testing")]
+    if let Some(InjectedFault::Error(err)) = injected_fault(bucket, "put_object", &object_path) {
+        return Err(err);
+    }
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        store.put_object(&object_path, buffer, false);
+        return Ok(());
+    }
+    bucket.rate_limiter.consume_bytes(buffer.len() as u64);
+    let put_bucket = with_headers(bucket, &bucket.put_headers);
+    with_retry(&put_bucket, "put_object", |b| {
+        match b.put_object(&object_path, &buffer) {
+            Ok((_, 200)) => Ok(()),
+            Ok((_, 413)) => Err(Failure::Fatal(too_large(
+                &object_path,
+                buffer.len(),
+                "the PUT size limit of the backend".to_string(),
+            ))),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })
+}
+
+/// return BadData without sending the PUT when the buffer exceeds the max_put_size of the bucket
+fn check_put_size(bucket: &S3Bucket, object_path: &str, buffer: &[u8]) -> Result<(), StoreError> {
+    if buffer.len() as u64 > bucket.max_put_size {
+        return Err(too_large(
+            object_path,
+            buffer.len(),
+            format!("max_put_size {}", bucket.max_put_size),
+        ));
+    }
+    Ok(())
+}
+
+/// an object too large to PUT, with a hint for operators on how to write smaller objects
+fn too_large(object_path: &str, size: usize, limit: String) -> StoreError {
+    StoreError::BadData(format!(
+        "{} is {} bytes, over {}, lower max_object_size, compact_size_threshold, or compact_target_size",
+        object_path, size, limit
+    ))
+}
+
+/// result of a PUT made with If-None-Match: *
+pub enum ConditionalPut {
+    Created,
+    /// an object already existed at the path, nothing was written
+    AlreadyExists,
+    /// the backend does not support conditional writes, nothing was written
+    Unsupported,
+}
+
+/// put an object only if nothing exists at the path, so concurrent creators cannot overwrite each other.
+/// backends that ignore If-None-Match overwrite any existing object and report Created.
+/// a precondition failure after a retried attempt may be our own earlier attempt that succeeded without a response,
+/// so the object is read back and reported as Created when it holds exactly the given buffer.
+pub fn put_object_if_absent(
+    bucket: &S3Bucket,
+    object_path: String,
+    buffer: &[u8],
+) -> Result<ConditionalPut, StoreError> {
+    check_put_size(bucket, &object_path, buffer)?;
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(match store.put_object(&object_path, buffer, true) {
+            true => ConditionalPut::Created,
+            false => ConditionalPut::AlreadyExists,
+        });
+    }
+    bucket.rate_limiter.consume_bytes(buffer.len() as u64);
+    let mut conditional_bucket = with_headers(bucket, &bucket.put_headers);
+    conditional_bucket.bucket.add_header("If-None-Match", "*");
+    let mut attempts: u32 = 0;
+    let outcome = with_retry(&conditional_bucket, "put_object", |b| {
+        attempts += 1;
+        match b.put_object(&object_path, &buffer) {
+            Ok((_, 200)) => Ok(ConditionalPut::Created),
+            Ok((_, 412)) => Ok(ConditionalPut::AlreadyExists),
+            Ok((_, 501)) => Ok(ConditionalPut::Unsupported),
+            Ok((_, 413)) => Err(Failure::Fatal(too_large(
+                &object_path,
+                buffer.len(),
+                "the PUT size limit of the backend".to_string(),
+            ))),
+            // a concurrent conditional write to the same path is in progress, retrying sees its outcome
+            Ok((_, 409)) => Err(Failure::Retryable(StoreError::Unavailable(
+                "code 409".to_string(),
+            ))),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })?;
+    if let (ConditionalPut::AlreadyExists, true) = (&outcome, attempts > 1) {
+        if let Some(existing) = get_object_optional(bucket, object_path.clone())? {
+            if existing.as_slice() == buffer {
+                return Ok(ConditionalPut::Created);
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+/// put an object with the given storage class, or the bucket default storage class when None
+pub fn put_object_with_storage_class(
+    bucket: &S3Bucket,
+    object_path: String,
+    buffer: &[u8],
+    storage_class: &Option<String>,
+) -> Result<(), StoreError> {
+    match storage_class {
+        None => put_object(bucket, object_path, buffer),
+        Some(_) => put_object(
+            &with_storage_class(bucket, storage_class),
+            object_path,
+            buffer,
+        ),
+    }
+}
+
+/// a bucket whose PUTs use the given storage class, or the bucket default storage class when None
+pub fn with_storage_class(bucket: &S3Bucket, storage_class: &Option<String>) -> S3Bucket {
+    let mut class_bucket = bucket.clone();
+    if let Some(storage_class) = storage_class {
+        class_bucket
+            .bucket
+            .add_header("x-amz-storage-class", storage_class);
     }
+    class_bucket
 }
 
-pub fn delete_object(bucket: &Bucket, object_path: String) -> Result<(), StoreError> {
-    match bucket.delete_object(object_path) {
-        Ok((_, 200)) => Ok(()),
-        Ok((_, 204)) => Ok(()),
-        Ok((_, code)) => Err(StoreError::IOError(format!("code {}", code))),
-        Err(err) => Err(StoreError::IOError(err.to_string())),
+/// a bucket whose requests carry the given headers in addition to those of the bucket
+fn with_headers(bucket: &S3Bucket, headers: &[(String, String)]) -> S3Bucket {
+    let mut header_bucket = bucket.clone();
+    for (name, value) in headers {
+        header_bucket.bucket.add_header(name, value);
     }
+    header_bucket
 }
 
-pub fn get_object_optional(bucket: &Bucket, path: String) -> Result<Option<Vec<u8>>, StoreError> {
-    match bucket.get_object(path.clone()) {
+pub fn delete_object(bucket: &S3Bucket, object_path: String) -> Result<(), StoreError> {
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        store.delete_object("delete_object", &object_path, None);
+        return Ok(());
+    }
+    with_retry(bucket, "delete_object", |b| {
+        match b.delete_object(&object_path) {
+            Ok((_, 200)) => Ok(()),
+            Ok((_, 204)) => Ok(()),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })
+}
+
+/// delete an object only if its ETag still matches, returning false when it changed or no longer exists.
+/// backends that ignore If-Match delete it regardless.
+pub fn delete_object_if_match(
+    bucket: &S3Bucket,
+    object_path: String,
+    etag: &str,
+) -> Result<bool, StoreError> {
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(store.delete_object("delete_object", &object_path, Some(etag)));
+    }
+    let mut conditional_bucket = bucket.clone();
+    conditional_bucket.bucket.add_header("If-Match", etag);
+    with_retry(&conditional_bucket, "delete_object", |b| {
+        match b.delete_object(&object_path) {
+            Ok((_, 200)) => Ok(true),
+            Ok((_, 204)) => Ok(true),
+            Ok((_, 404)) => Ok(false),
+            Ok((_, 412)) => Ok(false),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })
+}
+
+/// max objects removed by a single S3 multi-object delete request
+const DELETE_OBJECTS_BATCH_SIZE: usize = 1000;
+
+/// delete many objects with S3 multi-object delete, one POST ?delete per batch of up to 1000 objects.
+/// rust-s3 0.28 does not expose multi-object delete, so the request is signed and sent here.
+/// backends without multi-object delete, which answer 501, fall back to one DELETE per object.
+pub fn delete_objects(bucket: &S3Bucket, object_paths: Vec<String>) -> Result<(), StoreError> {
+    for batch in object_paths.chunks(DELETE_OBJECTS_BATCH_SIZE) {
+        #[cfg(feature = "testing")]
+        if let Some(store) = &bucket.memory_store {
+            store.delete_objects(batch);
+            continue;
+        }
+        let body = delete_objects_body(batch);
+        let supported = with_retry(bucket, "delete_objects", |b| post_delete_objects(b, &body))?;
+        if !supported {
+            for object_path in batch {
+                delete_object(bucket, object_path.clone())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// body of a quiet multi-object delete, whose response only lists the objects that failed to delete
+fn delete_objects_body(object_paths: &[String]) -> Vec<u8> {
+    let mut body = String::from("<Delete><Quiet>true</Quiet>");
+    for object_path in object_paths {
+        body.push_str("<Object><Key>");
+        for c in object_path.chars() {
+            match c {
+                '&' => body.push_str("&amp;"),
+                '<' => body.push_str("&lt;"),
+                '>' => body.push_str("&gt;"),
+                '"' => body.push_str("&quot;"),
+                '\'' => body.push_str("&apos;"),
+                c => body.push(c),
+            }
+        }
+        body.push_str("</Key></Object>");
+    }
+    body.push_str("</Delete>");
+    body.into_bytes()
+}
+
+/// send a signed multi-object delete, returning false when the backend does not support it.
+/// a response listing any failed object is retried, since deleting the others again is harmless.
+fn post_delete_objects(b: &Bucket, body: &[u8]) -> Result<bool, Failure> {
+    let (access_key, secret_key) = match (&b.credentials.access_key, &b.credentials.secret_key) {
+        (Some(access_key), Some(secret_key)) => (access_key, secret_key),
+        _ => {
+            return Err(Failure::Fatal(StoreError::BadConfiguration(
+                "multi-object delete requires an access key and secret key".to_string(),
+            )))
+        }
+    };
+    let url = b.url();
+    // the url is the endpoint, followed by the bucket name for path style buckets
+    let authority = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url.as_str(),
+    };
+    let (host, uri) = match authority.find('/') {
+        Some(i) => (&authority[..i], &authority[i..]),
+        None => (authority, "/"),
+    };
+    let region = b.region.to_string();
+    let mut headers: Vec<(&'static str, String)> = vec![
+        ("host", host.to_string()),
+        ("content-md5", base64::encode(md5::compute(body).0)),
+        ("x-amz-content-sha256", signing::payload_sha256(body)),
+        ("x-amz-date", signing::amz_date(time_now_as_millis())),
+    ];
+    let token = match (&b.credentials.security_token, &b.credentials.session_token) {
+        (Some(token), _) => Some(token),
+        (None, token) => token.as_ref(),
+    };
+    if let Some(token) = token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let authorization = signing::authorization(
+        "POST", uri, "delete=", &headers, access_key, secret_key, &region,
+    );
+    let mut request = attohttpc::post(format!("{}?delete", url))
+        .header("authorization", authorization.as_str())
+        .header("content-type", "application/xml");
+    for (name, value) in headers.iter() {
+        request = request.header(*name, value.as_str());
+    }
+    if let Some(timeout) = b.request_timeout {
+        request = request.timeout(timeout);
+    }
+    let response = match request.bytes(body).send() {
+        Ok(v) => v,
+        Err(err) => return Err(client_failure(err)),
+    };
+    match response.status().as_u16() {
+        200 => {}
+        501 => return Ok(false),
+        code => return Err(code_failure(code)),
+    }
+    let text = match response.text() {
+        Ok(v) => v,
+        Err(err) => return Err(client_failure(err)),
+    };
+    match text.find("<Error>") {
+        None => Ok(true),
+        Some(i) => Err(Failure::Retryable(StoreError::IOError(format!(
+            "multi-object delete failed: {}",
+            text[i..].chars().take(256).collect::<String>()
+        )))),
+    }
+}
+
+pub fn get_object_optional(bucket: &S3Bucket, path: String) -> Result<Option<Vec<u8>>, StoreError> {
+    #[cfg(feature = "testing")]
+    match injected_fault(bucket, "get_object", &path) {
+        Some(InjectedFault::Missing) => return Ok(None),
+        Some(InjectedFault::Error(err)) => return Err(err),
+        None => {}
+    }
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(store
+            .get_object("get_object", &path)
+            .map(|(contents, _)| contents));
+    }
+    let get_bucket = with_headers(bucket, &bucket.get_headers);
+    let contents = with_retry(&get_bucket, "get_object", |b| match b.get_object(&path) {
         Ok((contents, 200)) => Ok(Some(contents)),
         Ok((_, 404)) => Ok(None),
-        Ok((_, code)) => Err(StoreError::IOError(format!("code {}", code))),
-        Err(err) => Err(StoreError::IOError(err.to_string())),
+        Ok((_, code)) => Err(code_failure(code)),
+        Err(err) => Err(client_failure(err)),
+    })?;
+    if let Some(contents) = &contents {
+        bucket.rate_limiter.consume_bytes(contents.len() as u64);
+    }
+    Ok(contents)
+}
+
+/// get bytes start through end inclusive of an object, or None if it does not exist
+pub fn get_object_range_optional(
+    bucket: &S3Bucket,
+    path: String,
+    start: u64,
+    end: u64,
+) -> Result<Option<Vec<u8>>, StoreError> {
+    #[cfg(feature = "testing")]
+    match injected_fault(bucket, "get_object_range", &path) {
+        Some(InjectedFault::Missing) => return Ok(None),
+        Some(InjectedFault::Error(err)) => return Err(err),
+        None => {}
+    }
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(store
+            .get_object("get_object_range", &path)
+            .map(|(contents, _)| {
+                let start = std::cmp::min(start as usize, contents.len());
+                let end = std::cmp::min(end as usize + 1, contents.len());
+                contents[start..end].to_vec()
+            }));
+    }
+    let get_bucket = with_headers(bucket, &bucket.get_headers);
+    let contents = with_retry(&get_bucket, "get_object_range", |b| {
+        match b.get_object_range(&path, start, Some(end)) {
+            Ok((contents, 200)) => Ok(Some(contents)),
+            Ok((contents, 206)) => Ok(Some(contents)),
+            Ok((_, 404)) => Ok(None),
+            Ok((_, code)) => Err(code_failure(code)),
+            Err(err) => Err(client_failure(err)),
+        }
+    })?;
+    if let Some(contents) = &contents {
+        bucket.rate_limiter.consume_bytes(contents.len() as u64);
+    }
+    Ok(contents)
+}
+
+/// result of a GET made with If-None-Match
+pub enum ConditionalGet {
+    /// the object still matches the given ETag
+    NotModified,
+    /// the object changed, along with its new contents and ETag
+    Modified(Vec<u8>, Option<String>),
+    NotFound,
+}
+
+/// get an object along with the ETag of the same response, so the contents can later be revalidated with get_object_if_none_match
+pub fn get_object_optional_with_etag(
+    bucket: &S3Bucket,
+    path: String,
+) -> Result<Option<(Vec<u8>, Option<String>)>, StoreError> {
+    #[cfg(feature = "testing")]
+    match injected_fault(bucket, "get_object", &path) {
+        Some(InjectedFault::Missing) => return Ok(None),
+        Some(InjectedFault::Error(err)) => return Err(err),
+        None => {}
+    }
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(store
+            .get_object("get_object", &path)
+            .map(|(contents, etag)| (contents, Some(etag))));
+    }
+    let get_bucket = with_headers(bucket, &bucket.get_headers);
+    let result = with_retry(&get_bucket, "get_object", |b| {
+        match get_object_with_etag(b, &path)? {
+            (contents, 200, etag) => Ok(Some((contents, etag))),
+            (_, 404, _) => Ok(None),
+            (_, code, _) => Err(code_failure(code)),
+        }
+    })?;
+    if let Some((contents, _)) = &result {
+        bucket.rate_limiter.consume_bytes(contents.len() as u64);
+    }
+    Ok(result)
+}
+
+/// get an object only if its ETag no longer matches the given ETag, a match costs a 304 with no body
+pub fn get_object_if_none_match(
+    bucket: &S3Bucket,
+    path: String,
+    etag: &str,
+) -> Result<ConditionalGet, StoreError> {
+    #[cfg(feature = "testing")]
+    match injected_fault(bucket, "get_object", &path) {
+        Some(InjectedFault::Missing) => return Ok(ConditionalGet::NotFound),
+        Some(InjectedFault::Error(err)) => return Err(err),
+        None => {}
+    }
+    #[cfg(feature = "testing")]
+    if let Some(store) = &bucket.memory_store {
+        return Ok(match store.get_object("get_object", &path) {
+            None => ConditionalGet::NotFound,
+            Some((_, current)) if current == etag => ConditionalGet::NotModified,
+            Some((contents, current)) => ConditionalGet::Modified(contents, Some(current)),
+        });
+    }
+    let mut conditional_bucket = with_headers(bucket, &bucket.get_headers);
+    conditional_bucket.bucket.add_header("If-None-Match", etag);
+    let result = with_retry(
+        &conditional_bucket,
+        "get_object",
+        |b| match get_object_with_etag(b, &path)? {
+            (contents, 200, etag) => Ok(ConditionalGet::Modified(contents, etag)),
+            (_, 304, _) => Ok(ConditionalGet::NotModified),
+            (_, 404, _) => Ok(ConditionalGet::NotFound),
+            (_, code, _) => Err(code_failure(code)),
+        },
+    )?;
+    if let ConditionalGet::Modified(contents, _) = &result {
+        bucket.rate_limiter.consume_bytes(contents.len() as u64);
+    }
+    Ok(result)
+}
+
+/// contents, status code, and ETag of a single GET.
+/// Bucket::get_object drops the response headers, so the request is sent directly.
+fn get_object_with_etag(b: &Bucket, path: &str) -> Result<(Vec<u8>, u16, Option<String>), Failure> {
+    let response = match AttoRequest::new(b, path, Command::GetObject).response() {
+        Ok(v) => v,
+        Err(err) => return Err(client_failure(err)),
+    };
+    let code = response.status().as_u16();
+    let etag = match response.headers().get("ETag") {
+        None => None,
+        Some(v) => match v.to_str() {
+            Ok(v) => Some(v.to_string()),
+            Err(_) => None,
+        },
+    };
+    match response.bytes() {
+        Ok(contents) => Ok((contents, code, etag)),
+        Err(err) => Err(client_failure(err)),
+    }
+}
+
+/// fault returned by the fault injector of the bucket for the given operation and path, if any
+#[cfg(feature = "testing")]
+fn injected_fault(bucket: &S3Bucket, operation: &str, path: &str) -> Option<InjectedFault> {
+    match &bucket.fault_injector {
+        None => None,
+        Some(injector) => injector(operation, path),
     }
 }
 
-pub fn get_object_required(bucket: &Bucket, path: String) -> Result<Vec<u8>, StoreError> {
-    match get_object_optional(bucket, path) {
+pub fn get_object_required(bucket: &S3Bucket, path: String) -> Result<Vec<u8>, StoreError> {
+    match get_object_optional(bucket, path.clone()) {
         Ok(Some(contents)) => Ok(contents),
-        Ok(None) => Err(StoreError::BadData(format!("object not found"))),
+        Ok(None) => Err(StoreError::ObjectNotFound(path)),
         Err(err) => Err(err),
     }
 }
@@ -2,7 +2,6 @@ use super::bucket::*;
 use crate::common::cache::*;
 use crate::common::keypath::*;
 use crate::*;
-use aws_s3::bucket::Bucket;
 
 #[derive(Clone, Debug)]
 pub struct CachedKey {
@@ -12,26 +11,39 @@ pub struct CachedKey {
     pub uncompacted_size: u64,
     pub prior_start_offset: u64,
     pub watermark: Watermark,
+    /// max timestamp written to the key, used to keep timestamps monotonic
+    pub max_timestamp: i64,
 }
 
 pub struct S3CacheFetcher {
-    bucket: Bucket,
+    bucket: S3Bucket,
     root_prefix: String,
     key_path_parser: KeyPathParser,
     compact_records_threshold: u64,
+    key_prefix_sharding: bool,
+    nonce_resets: bool,
 }
 impl S3CacheFetcher {
-    pub fn new(bucket: Bucket, root_prefix: String, compact_records_threshold: u64) -> Self {
+    pub fn new(
+        bucket: S3Bucket,
+        root_prefix: String,
+        compact_records_threshold: u64,
+        key_prefix_sharding: bool,
+        nonce_resets: bool,
+    ) -> Self {
         Self {
             bucket,
             root_prefix,
             key_path_parser: KeyPathParser::new(),
             compact_records_threshold,
+            key_prefix_sharding,
+            nonce_resets,
         }
     }
 }
 impl CacheFetcher<CachedKey> for S3CacheFetcher {
     fn load_key(&self, keyspace: &str, key: &str) -> Result<CachedKey, StoreError> {
+        let key = &key_path_segment(keyspace, key, self.key_prefix_sharding);
         let watermark_path = Watermark::path(&self.root_prefix, keyspace, key);
         let watermark_contents_opt = get_object_optional(&self.bucket, watermark_path)?;
         let watermark_opt = match watermark_contents_opt {
@@ -52,18 +64,33 @@ impl CacheFetcher<CachedKey> for S3CacheFetcher {
         };
         // list exhaustive from watermark
         let list = list_exhaustive(&self.bucket, &key_data_prefix, list_from)?;
+        let nonce_reset = match self.nonce_resets {
+            false => None,
+            true => {
+                let nonce_reset_path = NonceReset::path(&self.root_prefix, keyspace, key);
+                match get_object_optional(&self.bucket, nonce_reset_path)? {
+                    None => None,
+                    Some(contents) => Some(NonceReset::from(&contents)?),
+                }
+            }
+        };
         if list.is_empty() {
             // empty key, return default
+            let mut metadata = KeyMetadata {
+                next_nonce: 0,
+                next_offset: 1,
+            };
+            if let Some(nonce_reset) = &nonce_reset {
+                nonce_reset.apply(&mut metadata);
+            }
             return Ok(CachedKey {
-                metadata: KeyMetadata {
-                    next_nonce: 0,
-                    next_offset: 1,
-                },
+                metadata,
                 uncompacted_records: 0,
                 uncompacted_objects: 0,
                 uncompacted_size: 0,
                 prior_start_offset: 0,
                 watermark: Watermark::new(0),
+                max_timestamp: i64::MIN,
             });
         }
         // summarize pending from watermark
@@ -73,6 +100,7 @@ impl CacheFetcher<CachedKey> for S3CacheFetcher {
         let mut next_nonce: u128 = 0;
         let mut next_offset: u64 = 0;
         let mut prior_start_offset: u64 = 0;
+        let mut max_timestamp: i64 = i64::MIN;
         for i in 0..list.len() {
             match &self.key_path_parser.parse(&list[i]) {
                 Some(key) => {
@@ -80,6 +108,7 @@ impl CacheFetcher<CachedKey> for S3CacheFetcher {
                     next_nonce = key.next_nonce;
                     next_offset = key.last_offset + 1;
                     prior_start_offset = key.prior_start_offset;
+                    max_timestamp = std::cmp::max(max_timestamp, key.max_timestamp);
                     if i == 0 && object_records_count >= self.compact_records_threshold {
                         // first record at watermark is a complete batch, skip it for counts
                     } else {
@@ -91,11 +120,15 @@ impl CacheFetcher<CachedKey> for S3CacheFetcher {
                 None => return Err(StoreError::BadData(format!("invalid key {}", list[i]))),
             }
         }
+        let mut metadata = KeyMetadata {
+            next_nonce,
+            next_offset,
+        };
+        if let Some(nonce_reset) = &nonce_reset {
+            nonce_reset.apply(&mut metadata);
+        }
         return Ok(CachedKey {
-            metadata: KeyMetadata {
-                next_nonce,
-                next_offset,
-            },
+            metadata,
             uncompacted_records,
             uncompacted_objects,
             uncompacted_size,
@@ -104,6 +137,7 @@ impl CacheFetcher<CachedKey> for S3CacheFetcher {
                 Some(wm) => wm,
                 None => Watermark::new(0),
             },
+            max_timestamp,
         });
     }
 }
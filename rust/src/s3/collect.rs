@@ -1,9 +1,10 @@
 use super::bucket::*;
+use super::object_cache::*;
 use crate::common::keypath::*;
 use crate::common::records::*;
 use crate::*;
-use aws_s3::bucket::Bucket;
 use regex::Regex;
+use std::sync::Arc;
 
 pub struct ContinuationParser {
     rex: Regex,
@@ -11,7 +12,7 @@ pub struct ContinuationParser {
 impl ContinuationParser {
     pub fn new() -> Self {
         Self {
-            rex: Regex::new(r"^[fb]:(\d+):(\d+)$").unwrap(),
+            rex: Regex::new(r"^([fb]):(\d+):(\d+)$").unwrap(),
         }
     }
     fn parse(&self, s: &str) -> Result<(Direction, Position), StoreError> {
@@ -31,18 +32,69 @@ impl ContinuationParser {
                     Ok(v) => v,
                     Err(_) => return Err(StoreError::InvalidContinuation(s.to_string())),
                 };
+                // offsets start at 1, so an anchor of 0 marks a continuation from continuation_for_offset
                 Ok((direction, Position::new(next_offset, last_start_offset)))
             }
         }
     }
+    /// the continuation that skips the record at exactly the next offset of the given one, or None when no record can be beyond it.
+    /// the anchor is kept while the object it points to can still hold the new offset, otherwise the continuation is unanchored.
+    pub fn exclusive(&self, s: &str) -> Result<Option<String>, StoreError> {
+        let (direction, position) = self.parse(s)?;
+        let next_offset = match direction {
+            Direction::Forwards => position.next_offset.checked_add(1),
+            Direction::Backwards => position.next_offset.checked_sub(1).filter(|v| *v > 0),
+        };
+        let next_offset = match next_offset {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+        let anchor_start_offset = match position.anchor_start_offset <= next_offset {
+            true => position.anchor_start_offset,
+            false => 0,
+        };
+        Ok(Some(CollectOutcome::format_continuation(
+            &direction,
+            &Position::new(next_offset, anchor_start_offset),
+        )))
+    }
+}
+
+/// a continuation that read_next_page accepts to read from the given offset in the given direction, without reading prior pages.
+/// it is not anchored to an object, so its first read seeks to the offset the same as a StartPosition::Offset read.
+pub fn continuation_for_offset(offset: u64, direction: Direction) -> String {
+    let direction = match direction {
+        Direction::Forwards => "f",
+        Direction::Backwards => "b",
+    };
+    format!("{}:{}:0", direction, offset)
 }
 
+/// the continuation that skips the record at exactly the offset the given continuation resumes at, or None when no record can be beyond it.
+/// accepts continuations in the format of continuation_for_offset and of S3StoreReader pages.
+pub fn exclusive_continuation(continuation: &str) -> Result<Option<String>, StoreError> {
+    ContinuationParser::new().exclusive(continuation)
+}
+
+/// the LIST and GET requests made by a read, and how many objects were served from the object cache instead
 #[derive(Debug, Clone)]
 pub struct ReadStats {
     pub list_operation_count: u64,
     pub read_operation_count: u64,
     pub read_size_total: u64,
     pub continuation_miss_count: u64,
+    pub object_cache_hit_count: u64,
+}
+
+/// the projected cost of a first page read, from the seek and object paths without reading any object
+#[derive(Debug, Clone)]
+pub struct ReadEstimate {
+    /// LIST and GET requests made by the seek and listing, plus the projected GET requests and bytes of reading data objects
+    pub stats: ReadStats,
+    /// objects the read is projected to get
+    pub object_count: u64,
+    /// records the page is projected to contain
+    pub record_count: u64,
 }
 
 #[derive(Clone, Debug)]
@@ -58,7 +110,7 @@ impl Position {
         }
     }
     pub fn get_start_from(&self, root_prefix: &str, keyspace: &str, key: &str) -> String {
-        // last_start_offset defaults to 1, so -1 does not need safety checking
+        // last_start_offset defaults to 1 and unanchored continuations are searched instead, so -1 is safe
         KeyPath::after_offset_prefix(root_prefix, keyspace, key, self.anchor_start_offset - 1)
     }
 }
@@ -70,70 +122,93 @@ pub struct CollectOutcome {
     pub position: Option<Position>,
     /// marks final object as missing
     pub requires_retry: bool,
+    /// direction of iteration, encoded in the continuation token
+    pub direction: Direction,
 }
 impl CollectOutcome {
-    pub fn finished(records: Vec<Record>) -> Self {
+    pub fn finished(records: Vec<Record>, direction: &Direction) -> Self {
         Self {
             records: records,
             position: None,
             requires_retry: false,
+            direction: direction.clone(),
         }
     }
     pub fn progress(
         records: Vec<Record>,
         last_position: &Position,
         anchor_start_offset: u64,
+        direction: &Direction,
     ) -> Self {
-        if records.is_empty() {
-            return Self {
-                records: records,
-                position: Some(last_position.clone()),
-                requires_retry: false,
-            };
-        } else {
-            return Self {
-                position: Some(Position::new(
-                    records.last().unwrap().offset + 1,
-                    anchor_start_offset,
-                )),
-                records: records,
-                requires_retry: false,
-            };
+        Self {
+            position: Some(Self::next_position(
+                &records,
+                last_position,
+                anchor_start_offset,
+                direction,
+            )),
+            records: records,
+            requires_retry: false,
+            direction: direction.clone(),
         }
     }
     pub fn missing(
         records: Vec<Record>,
         last_position: &Position,
         anchor_start_offset: u64,
+        direction: &Direction,
     ) -> Self {
-        if records.is_empty() {
-            return Self {
-                records: records,
-                position: Some(last_position.clone()),
-                requires_retry: true,
-            };
-        } else {
-            return Self {
-                position: Some(Position::new(
-                    records.last().unwrap().offset + 1,
-                    anchor_start_offset,
-                )),
-                records: records,
-                requires_retry: true,
-            };
+        Self {
+            position: Some(Self::next_position(
+                &records,
+                last_position,
+                anchor_start_offset,
+                direction,
+            )),
+            records: records,
+            requires_retry: true,
+            direction: direction.clone(),
+        }
+    }
+    /// position of the first record after the last returned record, in the direction of iteration.
+    /// the next page must exclude the last returned record, or it would be returned twice.
+    fn next_position(
+        records: &Vec<Record>,
+        last_position: &Position,
+        anchor_start_offset: u64,
+        direction: &Direction,
+    ) -> Position {
+        match records.last() {
+            None => last_position.clone(),
+            Some(last) => match direction {
+                Direction::Forwards => Position::new(last.offset + 1, anchor_start_offset),
+                Direction::Backwards => Position::new(last.offset - 1, anchor_start_offset),
+            },
         }
     }
     pub fn continuation(&self) -> Option<String> {
         match &self.position {
             None => None,
-            Some(p) => Some(format!("{}:{}", p.next_offset, p.anchor_start_offset)),
+            Some(p) => Some(Self::format_continuation(&self.direction, p)),
         }
     }
+    fn format_continuation(direction: &Direction, position: &Position) -> String {
+        let direction = match direction {
+            Direction::Forwards => "f",
+            Direction::Backwards => "b",
+        };
+        format!(
+            "{}:{}:{}",
+            direction, position.next_offset, position.anchor_start_offset
+        )
+    }
 }
 
 pub fn collect_first_page(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
     root_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -142,34 +217,49 @@ pub fn collect_first_page(
     max_results: u64,
     key_path_parser: &KeyPathParser,
     direction: &Direction,
+    use_key_index: bool,
+    linear_timestamp_seek: bool,
+    predicate: &Option<RecordPredicate>,
+    layout: RecordLayout,
 ) -> Result<CollectOutcome, StoreError> {
     // create record filter with min/max defaults to avoid Option checks
-    let record_filter = RecordFilter::from(start, max_results, direction.clone());
+    let record_filter = RecordFilter::from(start, max_results, direction.clone())
+        .with_predicate(predicate)
+        .with_layout(layout);
 
     // no continuation for first page, use filter
-    let position = match search_start_from(
-        stats,
-        bucket,
-        root_prefix,
-        keyspace,
-        key,
-        data_prefix,
-        &record_filter,
-        key_path_parser,
-    )? {
+    let position = match start {
+        StartPosition::Timestamp(_) if linear_timestamp_seek => {
+            linear_search_start_from(stats, bucket, data_prefix, &record_filter, key_path_parser)?
+        }
+        _ => search_start_from(
+            stats,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+            use_key_index,
+        )?,
+    };
+    let position = match position {
         // no filter match -> no results
         None => {
-            return Ok(CollectOutcome::finished(Vec::new()));
+            return Ok(CollectOutcome::finished(Vec::new(), direction));
         }
         // filter match -> start from there
         Some(position) => position,
     };
 
     // iterate and parse until max_results is filled or end of paging is reached
-    return collect_records_from_position(
+    let collect_outcome = collect_records_from_position(
         stats,
         &position,
         bucket,
+        object_cache,
+        ranged_read_size,
         root_prefix,
         keyspace,
         key,
@@ -177,12 +267,30 @@ pub fn collect_first_page(
         &record_filter,
         key_path_parser,
         direction,
+    )?;
+    return resolve_missing(
+        stats,
+        collect_outcome,
+        bucket,
+        object_cache,
+        ranged_read_size,
+        root_prefix,
+        keyspace,
+        key,
+        data_prefix,
+        max_results,
+        key_path_parser,
+        use_key_index,
+        predicate,
+        layout,
     );
 }
 
 pub fn collect_next_page(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
     root_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -191,17 +299,42 @@ pub fn collect_next_page(
     key_path_parser: &KeyPathParser,
     continuation: &String,
     continuation_parser: &ContinuationParser,
+    use_key_index: bool,
+    predicate: &Option<RecordPredicate>,
+    layout: RecordLayout,
 ) -> Result<CollectOutcome, StoreError> {
-    // create record filter with min/max defaults to avoid Option checks
     let (direction, position) = continuation_parser.parse(continuation)?;
-    let mut record_filter =
-        RecordFilter::for_offset(position.next_offset, max_results, direction.clone());
+    let record_filter =
+        RecordFilter::for_offset(position.next_offset, max_results, direction.clone())
+            .with_predicate(predicate)
+            .with_layout(layout);
 
-    // try to use continuation
+    let position = match position.anchor_start_offset {
+        // unanchored, so seek to the offset the same as a first page, which is not a continuation miss
+        0 => match search_start_from(
+            stats,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+            use_key_index,
+        )? {
+            None => return Ok(CollectOutcome::finished(Vec::new(), &direction)),
+            Some(position) => position,
+        },
+        _ => position,
+    };
+
+    // try to use continuation, a missing object falls back to a filter search from the continuation position
     let collect_outcome = collect_records_from_position(
         stats,
         &position,
         bucket,
+        object_cache,
+        ranged_read_size,
         root_prefix,
         keyspace,
         key,
@@ -210,53 +343,261 @@ pub fn collect_next_page(
         key_path_parser,
         &direction,
     )?;
-    if collect_outcome.requires_retry {
-        // failed, fall back to normal filter search using continuation position
-        stats.continuation_miss_count += 1;
-        record_filter.start_offset = position.next_offset;
-    } else {
-        // continuation worked, return results
-        return Ok(collect_outcome);
-    }
-
-    // continuation failed use filter
-    let position = match search_start_from(
+    return resolve_missing(
         stats,
+        collect_outcome,
         bucket,
+        object_cache,
+        ranged_read_size,
         root_prefix,
         keyspace,
         key,
         data_prefix,
-        &record_filter,
+        max_results,
         key_path_parser,
-    )? {
-        // no filter match -> no results
-        None => {
-            return Ok(CollectOutcome::finished(Vec::new()));
-        }
-        // filter match -> start from there
+        use_key_index,
+        predicate,
+        layout,
+    );
+}
+
+/// max searches made to complete a single page that keeps hitting objects removed by concurrent compaction
+const MAX_RESOLVE_MISSING_ATTEMPTS: u32 = 3;
+
+/// complete a page that hit an object removed by concurrent compaction by searching for its next position.
+/// compaction writes new objects before deleting the old ones, so the search finds the compacted records and
+/// the caller receives a full page instead of a partial page with a continuation that hits the same miss.
+fn resolve_missing(
+    stats: &mut ReadStats,
+    mut collect_outcome: CollectOutcome,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    max_results: u64,
+    key_path_parser: &KeyPathParser,
+    use_key_index: bool,
+    predicate: &Option<RecordPredicate>,
+    layout: RecordLayout,
+) -> Result<CollectOutcome, StoreError> {
+    let mut attempts: u32 = 0;
+    while collect_outcome.requires_retry && attempts < MAX_RESOLVE_MISSING_ATTEMPTS {
+        attempts += 1;
+        stats.continuation_miss_count += 1;
+        let direction = collect_outcome.direction.clone();
+        let position = match &collect_outcome.position {
+            None => break,
+            Some(v) => v.clone(),
+        };
+        let remaining = max_results.saturating_sub(collect_outcome.records.len() as u64);
+        let record_filter =
+            RecordFilter::for_offset(position.next_offset, remaining, direction.clone())
+                .with_predicate(predicate)
+                .with_layout(layout);
+        let position = match search_start_from(
+            stats,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+            use_key_index,
+        )? {
+            // no filter match -> nothing after the records collected so far
+            None => {
+                return Ok(CollectOutcome::finished(
+                    collect_outcome.records,
+                    &direction,
+                ));
+            }
+            Some(position) => position,
+        };
+        let mut next_outcome = collect_records_from_position(
+            stats,
+            &position,
+            bucket,
+            object_cache,
+            ranged_read_size,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+            &direction,
+        )?;
+        let mut records = collect_outcome.records;
+        records.append(&mut next_outcome.records);
+        next_outcome.records = records;
+        collect_outcome = next_outcome;
+    }
+    return Ok(collect_outcome);
+}
+
+/// project the ReadStats of collect_first_page by running its seek and object listing, sizing the GETs of each object from its path.
+/// the seek is not projected, so its GETs of the key index and watermark are performed and counted as the read would count them.
+/// records are assumed to be evenly sized with contiguous offsets, a timestamp or nonce start is assumed to match from the first
+/// record of its object, and the object cache is assumed to miss, so the estimate errs towards reading more than the page needs.
+pub fn estimate_first_page(
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    start: &StartPosition,
+    max_results: u64,
+    key_path_parser: &KeyPathParser,
+    direction: &Direction,
+    use_key_index: bool,
+    linear_timestamp_seek: bool,
+) -> Result<ReadEstimate, StoreError> {
+    let mut estimate = ReadEstimate {
+        stats: ReadStats {
+            list_operation_count: 0,
+            read_operation_count: 0,
+            read_size_total: 0,
+            continuation_miss_count: 0,
+            object_cache_hit_count: 0,
+        },
+        object_count: 0,
+        record_count: 0,
+    };
+    let record_filter = RecordFilter::from(start, max_results, direction.clone());
+
+    // the seek is run as the read would run it, including its GETs of the key index and watermark
+    let position = match start {
+        StartPosition::Timestamp(_) if linear_timestamp_seek => linear_search_start_from(
+            &mut estimate.stats,
+            bucket,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+        )?,
+        _ => search_start_from(
+            &mut estimate.stats,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+            use_key_index,
+        )?,
+    };
+    let mut position = match position {
+        // no filter match -> no reads
+        None => return Ok(estimate),
         Some(position) => position,
     };
 
-    // iterate and parse until max_results is filled or end of paging is reached
-    return collect_records_from_position(
-        stats,
-        &position,
-        bucket,
-        root_prefix,
-        keyspace,
-        key,
-        data_prefix,
-        &record_filter,
-        key_path_parser,
-        &direction,
-    );
+    match direction {
+        Direction::Forwards => {
+            let start_from = position.get_start_from(root_prefix, keyspace, key);
+            let mut objects = ObjectPaths::new(bucket, data_prefix, start_from, key_path_parser);
+            while let Some((_, key_path)) = objects.next(&mut estimate.stats)? {
+                if position.next_offset > key_path.last_offset {
+                    continue;
+                }
+                let first_read = std::cmp::max(position.next_offset, key_path.first_offset);
+                estimate_object(
+                    &mut estimate,
+                    object_cache,
+                    ranged_read_size,
+                    &key_path,
+                    first_read - key_path.first_offset,
+                    key_path.last_offset - first_read + 1,
+                    max_results,
+                );
+                if estimate.record_count >= max_results || key_path.last_offset == u64::MAX {
+                    return Ok(estimate);
+                }
+                position.next_offset = key_path.last_offset + 1;
+            }
+            return Ok(estimate);
+        }
+        Direction::Backwards => loop {
+            // backwards iteration lists one object per request by following linked start offsets
+            let start_from = position.get_start_from(root_prefix, keyspace, key);
+            let (list, _) = list_page(bucket, data_prefix, Some(start_from), None, Some(1))?;
+            estimate.stats.list_operation_count += 1;
+            let key_path = match list.first() {
+                None => return Ok(estimate),
+                Some(object_key) => key_path_parser.parse_or_error(object_key)?,
+            };
+            if position.next_offset < key_path.first_offset {
+                return Ok(estimate);
+            }
+            let first_read = std::cmp::min(position.next_offset, key_path.last_offset);
+            estimate_object(
+                &mut estimate,
+                object_cache,
+                ranged_read_size,
+                &key_path,
+                key_path.last_offset - first_read,
+                first_read - key_path.first_offset + 1,
+                max_results,
+            );
+            if estimate.record_count >= max_results
+                || key_path.first_offset == 0
+                || key_path.prior_start_offset == 0
+            {
+                // page is full, or the first object of the key was reached
+                return Ok(estimate);
+            }
+            position = Position::new(key_path.first_offset - 1, key_path.prior_start_offset);
+        },
+    }
+}
+
+/// add the projected GETs of reading up to `matching` records from an object, after skipping `skipped` records in the direction of the read.
+/// ranged reads double their range from ranged_read_size until the records are covered, the same as collect_object_ranged.
+fn estimate_object(
+    estimate: &mut ReadEstimate,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
+    key_path: &KeyPath,
+    skipped: u64,
+    matching: u64,
+    max_results: u64,
+) {
+    let needed = std::cmp::min(matching, max_results.saturating_sub(estimate.record_count));
+    estimate.object_count += 1;
+    estimate.record_count += needed;
+    if ranged_read_size == 0 || key_path.size <= ranged_read_size || object_cache.is_enabled() {
+        estimate.stats.read_operation_count += 1;
+        estimate.stats.read_size_total += key_path.size;
+        return;
+    }
+    let object_records = (key_path.last_offset - key_path.first_offset) as u128 + 1;
+    let needed_size = ((key_path.size as u128 * (skipped + needed) as u128 + object_records - 1)
+        / object_records) as u64;
+    let mut range_size = ranged_read_size;
+    loop {
+        let target_size = std::cmp::min(range_size, key_path.size);
+        estimate.stats.read_operation_count += 1;
+        if target_size >= needed_size || target_size == key_path.size {
+            // each range extends the previous one, so only the final range is read in total
+            estimate.stats.read_size_total += target_size;
+            return;
+        }
+        range_size = range_size.saturating_mul(2);
+    }
 }
 
 pub fn collect_records_from_position(
     stats: &mut ReadStats,
     start_position: &Position,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
     root_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -270,6 +611,8 @@ pub fn collect_records_from_position(
             stats,
             start_position,
             bucket,
+            object_cache,
+            ranged_read_size,
             root_prefix,
             keyspace,
             key,
@@ -281,6 +624,8 @@ pub fn collect_records_from_position(
             stats,
             start_position,
             bucket,
+            object_cache,
+            ranged_read_size,
             root_prefix,
             keyspace,
             key,
@@ -291,10 +636,48 @@ pub fn collect_records_from_position(
     }
 }
 
+/// the data objects of a key after a listing start in offset order, each with its parsed path.
+/// listing pages are fetched as objects are consumed, so readers that stop early do not list the rest of the key.
+struct ObjectPaths<'a> {
+    pages: ListPages<'a>,
+    page: std::vec::IntoIter<String>,
+    key_path_parser: &'a KeyPathParser,
+}
+impl<'a> ObjectPaths<'a> {
+    fn new(
+        bucket: &'a S3Bucket,
+        data_prefix: &'a str,
+        start_from: String,
+        key_path_parser: &'a KeyPathParser,
+    ) -> Self {
+        ObjectPaths {
+            pages: list_pages(bucket, data_prefix, Some(start_from)),
+            page: Vec::new().into_iter(),
+            key_path_parser,
+        }
+    }
+    /// the next object and its parsed path, or None after the last object, counting each LIST in stats
+    fn next(&mut self, stats: &mut ReadStats) -> Result<Option<(String, KeyPath)>, StoreError> {
+        loop {
+            if let Some(object_key) = self.page.next() {
+                let key_path = self.key_path_parser.parse_or_error(&object_key)?;
+                return Ok(Some((object_key, key_path)));
+            }
+            self.page = match self.pages.next() {
+                None => return Ok(None),
+                Some(page) => page?.into_iter(),
+            };
+            stats.list_operation_count += 1;
+        }
+    }
+}
+
 pub fn collect_records_forward_from_position(
     stats: &mut ReadStats,
     start_position: &Position,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
     root_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -303,76 +686,102 @@ pub fn collect_records_forward_from_position(
     key_path_parser: &KeyPathParser,
 ) -> Result<CollectOutcome, StoreError> {
     let mut records: Vec<Record> = Vec::new();
-    let mut s3_cont_token: Option<String> = None;
     let mut cur_position = start_position.clone();
     let start_from = cur_position.get_start_from(root_prefix, keyspace, key);
-    loop {
-        let (list, next_s3_cont_token) = list_page(
+    let mut objects = ObjectPaths::new(bucket, data_prefix, start_from, key_path_parser);
+    let mut listed_any = false;
+    while let Some((object_key, key_path)) = objects.next(stats)? {
+        listed_any = true;
+        // read, deserialize, and further filter next object
+        if cur_position.next_offset < key_path.first_offset {
+            // concurrent compaction of expected object lead to object missing since last page, return results so far
+            return Ok(CollectOutcome::missing(
+                records,
+                &cur_position,
+                key_path.first_offset,
+                &Direction::Forwards,
+            ));
+        }
+        let (new_records, read_fully) = collect_object(
+            stats,
             bucket,
-            data_prefix,
-            Some(start_from.clone()),
-            s3_cont_token,
-            None,
+            object_cache,
+            ranged_read_size,
+            &object_key,
+            key_path.size,
+            &record_filter.remaining(records.len()),
+            &cur_position,
         )?;
-        stats.list_operation_count += 1;
-        for object_key in list {
-            // read, deserialize, and further filter next object
-            let key_path = key_path_parser.parse_or_error(&object_key)?;
-            if cur_position.next_offset < key_path.first_offset {
+        match new_records {
+            None => {
                 // concurrent compaction of expected object lead to object missing since last page, return results so far
                 return Ok(CollectOutcome::missing(
                     records,
                     &cur_position,
                     key_path.first_offset,
+                    &Direction::Forwards,
                 ));
             }
-            let (new_records, read_fully) =
-                collect_object(stats, bucket, &object_key, record_filter, &cur_position)?;
-            match new_records {
-                None => {
-                    // concurrent compaction of expected object lead to object missing since last page, return results so far
-                    return Ok(CollectOutcome::missing(
-                        records,
-                        &cur_position,
-                        key_path.first_offset,
-                    ));
-                }
-                Some(mut new_records) => {
-                    records.append(&mut new_records);
-                }
-            };
-
-            let anchor = match read_fully {
-                false => key_path.first_offset,   // keep anchoring to current offset
-                true => key_path.last_offset + 1, // anchor to next object
-            };
-
-            if records.len() > 0 && records.last().unwrap().offset == u64::MAX {
-                // reached end of offset space
-                return Ok(CollectOutcome::finished(records));
+            Some(mut new_records) => {
+                records.append(&mut new_records);
             }
+        };
 
-            if records.len() as u64 >= record_filter.max_size {
-                // max results have been retreived, return full page
-                return Ok(CollectOutcome::progress(records, &cur_position, anchor));
-            }
+        let anchor = match read_fully {
+            false => key_path.first_offset,   // keep anchoring to current offset
+            true => key_path.last_offset + 1, // anchor to next object
+        };
 
-            // advance position for next page
-            cur_position.next_offset = key_path.last_offset + 1;
-            cur_position.anchor_start_offset = anchor;
+        if records.len() > 0 && records.last().unwrap().offset == u64::MAX {
+            // reached end of offset space
+            return Ok(CollectOutcome::finished(records, &Direction::Forwards));
         }
-        if let None = next_s3_cont_token {
-            // no more data to find
-            return Ok(CollectOutcome::finished(records));
+
+        if records.len() as u64 >= record_filter.max_size {
+            // max results have been retreived, return full page
+            return Ok(CollectOutcome::progress(
+                records,
+                &cur_position,
+                anchor,
+                &Direction::Forwards,
+            ));
         }
-        s3_cont_token = next_s3_cont_token;
+
+        // advance position for next page
+        cur_position.next_offset = key_path.last_offset + 1;
+        cur_position.anchor_start_offset = anchor;
     }
+    if !listed_any
+        && lists_offset(
+            stats,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            key_path_parser,
+            cur_position.next_offset,
+        )?
+    {
+        // nothing at or after the anchor, but an object holds next_offset, so concurrent compaction merged the anchor
+        // object into an object starting before it. search for next_offset to resume in the merged object
+        return Ok(CollectOutcome::missing(
+            records,
+            &cur_position,
+            cur_position.anchor_start_offset,
+            &Direction::Forwards,
+        ));
+    }
+    // no more data to find, including a continuation from the end of the key
+    return Ok(CollectOutcome::finished(records, &Direction::Forwards));
 }
 
 fn collect_records_backward_from_position(
     stats: &mut ReadStats,
     start_position: &Position,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
     root_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -391,16 +800,28 @@ fn collect_records_backward_from_position(
         let (list, _) = list_page(bucket, data_prefix, Some(start_from.clone()), None, Some(1))?;
         stats.list_operation_count += 1;
         if list.len() == 0 {
-            // concurrent compaction lead to a bad read and the expected first object is no longer there, return nothing
+            // concurrent compaction merged the anchor object into an object starting before it, return results so far
             return Ok(CollectOutcome::missing(
-                Vec::new(),
-                &start_position,
-                0, // not used
+                records,
+                &cur_position,
+                cur_position.anchor_start_offset,
+                &Direction::Backwards,
             ));
         }
         let object_key = list.first().unwrap();
         let key_path = key_path_parser.parse_or_error(&object_key)?;
 
+        if cur_position.next_offset < key_path.first_offset {
+            // concurrent compaction merged the anchor object into an object starting before it, so the listing
+            // skipped to a later object. its records were already returned, so search for next_offset instead
+            return Ok(CollectOutcome::missing(
+                records,
+                &cur_position,
+                cur_position.anchor_start_offset,
+                &Direction::Backwards,
+            ));
+        }
+
         // read, deserialize, and further filter next object
         if cur_position.next_offset > key_path.last_offset {
             // concurrent compaction of expected object lead to object missing since last page, return results so far
@@ -408,11 +829,20 @@ fn collect_records_backward_from_position(
                 records,
                 &cur_position,
                 key_path.prior_start_offset,
+                &Direction::Backwards,
             ));
         }
 
-        let (new_records, read_fully) =
-            collect_object(stats, bucket, &object_key, record_filter, &cur_position)?;
+        let (new_records, read_fully) = collect_object(
+            stats,
+            bucket,
+            object_cache,
+            ranged_read_size,
+            &object_key,
+            key_path.size,
+            &record_filter.remaining(records.len()),
+            &cur_position,
+        )?;
         match new_records {
             None => {
                 // concurrent compaction of expected object lead to object missing since list, return results so far
@@ -420,6 +850,7 @@ fn collect_records_backward_from_position(
                     records,
                     &cur_position,
                     key_path.first_offset,
+                    &Direction::Backwards,
                 ));
             }
             Some(mut new_records) => {
@@ -432,24 +863,31 @@ fn collect_records_backward_from_position(
             true => key_path.prior_start_offset, // anchor to next object
         };
 
-        if records.len() == 0 {
-            // nothing was read from the object, hit an unexpected end
-            return Ok(CollectOutcome::finished(records));
+        if records.len() == 0 && record_filter.predicate.is_none() {
+            // nothing was read from the object, hit an unexpected end.
+            // a predicate may reject every record of an object, so keep iterating when one is given,
+            // which scans back to the first object of the key when the predicate never matches
+            return Ok(CollectOutcome::finished(records, &Direction::Backwards));
         }
 
         if records.len() > 0 && records.last().unwrap().offset == u64::MAX {
             // reached end of offset space
-            return Ok(CollectOutcome::finished(records));
+            return Ok(CollectOutcome::finished(records, &Direction::Backwards));
         }
 
-        if cur_position.next_offset == 0 {
-            // no more data to find
-            return Ok(CollectOutcome::finished(records));
+        if cur_position.next_offset == 0 || (read_fully && key_path.prior_start_offset == 0) {
+            // no more data to find, the first object of the key links to no prior object
+            return Ok(CollectOutcome::finished(records, &Direction::Backwards));
         }
 
         if records.len() as u64 >= record_filter.max_size {
             // max results have been retreived, return full page
-            return Ok(CollectOutcome::progress(records, &cur_position, anchor));
+            return Ok(CollectOutcome::progress(
+                records,
+                &cur_position,
+                anchor,
+                &Direction::Backwards,
+            ));
         }
 
         // advance position for next page
@@ -460,34 +898,389 @@ fn collect_records_backward_from_position(
 
 fn collect_object(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    ranged_read_size: u64,
     object_key: &str,
+    object_size: u64,
     record_filter: &RecordFilter,
     position: &Position,
 ) -> Result<(Option<Vec<Record>>, bool), StoreError> {
+    if ranged_read_size > 0 && object_size > ranged_read_size && !object_cache.is_enabled() {
+        return collect_object_ranged(
+            stats,
+            bucket,
+            ranged_read_size,
+            object_key,
+            object_size,
+            record_filter,
+            position,
+        );
+    }
     // read, deserialize, and further filter next object
     let mut records: Vec<Record> = Vec::new();
-    let contents = match get_object_optional(bucket, object_key.to_string())? {
+    let contents = match fetch_object(stats, bucket, object_cache, object_key)? {
         None => return Ok((None, false)), // compaction may have invalidated next object
-        Some(contents) => contents,
+        Some(v) => v,
     };
-    stats.read_operation_count += 1;
-    stats.read_size_total += contents.len() as u64;
-    let read_fully =
-        deserialize_and_filter_records(&contents, &mut records, record_filter, position.next_offset)?;
+    let read_fully = deserialize_and_filter_records(
+        &contents,
+        &mut records,
+        record_filter,
+        position.next_offset,
+    )?;
     return Ok((Some(records), read_fully));
 }
 
+/// read an object in ranges from its start, or from its end when reading backwards, doubling the range until the page is filled.
+/// only the records fully contained in a range are parsed, and the trailing total length of each record lets a tail range be
+/// parsed without knowing where its first record starts. returns None if compaction removed the object.
+fn collect_object_ranged(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    ranged_read_size: u64,
+    object_key: &str,
+    object_size: u64,
+    record_filter: &RecordFilter,
+    position: &Position,
+) -> Result<(Option<Vec<Record>>, bool), StoreError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut range_size = ranged_read_size;
+    loop {
+        // extend the range read so far
+        let target_size = std::cmp::min(range_size, object_size);
+        let (start, end) = match record_filter.direction {
+            Direction::Forwards => (buffer.len() as u64, target_size - 1),
+            Direction::Backwards => (
+                object_size - target_size,
+                object_size - buffer.len() as u64 - 1,
+            ),
+        };
+        let path = object_key.to_string();
+        let contents = match get_object_range_optional(bucket, path, start, end)? {
+            None => return Ok((None, false)), // compaction may have invalidated next object
+            Some(v) => v,
+        };
+        stats.read_operation_count += 1;
+        stats.read_size_total += contents.len() as u64;
+        if contents.len() as u64 != end - start + 1 {
+            return Err(StoreError::BadData(format!(
+                "{} returned {} bytes for range {}-{}",
+                object_key,
+                contents.len(),
+                start,
+                end
+            )));
+        }
+        buffer = match record_filter.direction {
+            Direction::Forwards => [buffer, contents].concat(),
+            Direction::Backwards => [contents, buffer].concat(),
+        };
+        let read_whole_object = buffer.len() as u64 == object_size;
+
+        // parse the complete records read so far
+        let mut records: Vec<Record> = Vec::new();
+        let read_fully = match record_filter.direction {
+            Direction::Forwards => {
+                let complete_length = match read_whole_object {
+                    true => buffer.len(),
+                    false => complete_prefix_length(&buffer, record_filter.layout)?,
+                };
+                deserialize_and_filter_records(
+                    &buffer[..complete_length],
+                    &mut records,
+                    record_filter,
+                    position.next_offset,
+                )?
+            }
+            Direction::Backwards => {
+                let complete_start = match read_whole_object {
+                    true => 0,
+                    false => complete_suffix_start(&buffer)?,
+                };
+                deserialize_and_filter_records(
+                    &buffer[complete_start..],
+                    &mut records,
+                    record_filter,
+                    position.next_offset,
+                )?
+            }
+        };
+        if read_whole_object {
+            return Ok((Some(records), read_fully));
+        }
+        if records.len() as u64 >= record_filter.max_size {
+            // page is full, the rest of the object is read by the next page
+            return Ok((Some(records), false));
+        }
+        range_size = range_size.saturating_mul(2);
+    }
+}
+
+/// get the contents of an object through the object cache, or None if compaction removed it
+fn fetch_object(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    object_key: &str,
+) -> Result<Option<Arc<Vec<u8>>>, StoreError> {
+    match object_cache.get(object_key) {
+        CacheLookup::Fresh(contents) => {
+            stats.object_cache_hit_count += 1;
+            Ok(Some(contents))
+        }
+        CacheLookup::Stale(contents, etag) => {
+            match get_object_if_none_match(bucket, object_key.to_string(), &etag)? {
+                ConditionalGet::NotModified => {
+                    stats.object_cache_hit_count += 1;
+                    object_cache.refresh(object_key);
+                    Ok(Some(contents))
+                }
+                ConditionalGet::Modified(contents, etag) => {
+                    stats.read_operation_count += 1;
+                    stats.read_size_total += contents.len() as u64;
+                    let contents = Arc::new(contents);
+                    object_cache.put(object_key, contents.clone(), etag);
+                    Ok(Some(contents))
+                }
+                ConditionalGet::NotFound => {
+                    // compaction removed the object since it was cached
+                    object_cache.remove(object_key);
+                    Ok(None)
+                }
+            }
+        }
+        CacheLookup::Miss => {
+            let (contents, etag) = match object_cache.is_enabled() {
+                true => match get_object_optional_with_etag(bucket, object_key.to_string())? {
+                    None => return Ok(None),
+                    Some(v) => v,
+                },
+                false => match get_object_optional(bucket, object_key.to_string())? {
+                    None => return Ok(None),
+                    Some(contents) => (contents, None),
+                },
+            };
+            stats.read_operation_count += 1;
+            stats.read_size_total += contents.len() as u64;
+            let contents = Arc::new(contents);
+            object_cache.put(object_key, contents.clone(), etag);
+            Ok(Some(contents))
+        }
+    }
+}
+
+/// collect the paths and serialized contents of every object holding offsets from start_offset through end_offset.
+/// an object removed by a concurrent compaction restarts the search, and the compacted object it finds replaces the collected objects it covers.
+pub fn collect_raw_objects(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    start_offset: u64,
+    end_offset: u64,
+    key_path_parser: &KeyPathParser,
+    use_key_index: bool,
+) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+    let mut objects: Vec<(String, u64, Vec<u8>)> = Vec::new();
+    visit_objects(
+        stats,
+        bucket,
+        object_cache,
+        root_prefix,
+        keyspace,
+        key,
+        data_prefix,
+        start_offset,
+        end_offset,
+        key_path_parser,
+        use_key_index,
+        &mut |object_key, key_path, contents, _| {
+            objects.retain(|(_, first_offset, _)| *first_offset < key_path.first_offset);
+            objects.push((
+                object_key.to_string(),
+                key_path.first_offset,
+                contents.to_vec(),
+            ));
+            Ok(())
+        },
+    )?;
+    Ok(objects
+        .into_iter()
+        .map(|(object_key, _, contents)| (object_key, contents))
+        .collect())
+}
+
+/// decode the paths of every object holding offsets from start_offset through end_offset, without reading the objects.
+/// listing starts from the object found by the same search as collect_raw_objects, and stops at the first object after end_offset.
+pub fn collect_object_paths(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    start_offset: u64,
+    end_offset: u64,
+    key_path_parser: &KeyPathParser,
+    use_key_index: bool,
+) -> Result<Vec<KeyPath>, StoreError> {
+    let record_filter = RecordFilter::for_offset(start_offset, u64::MAX, Direction::Forwards);
+    let position = match search_start_from(
+        stats,
+        bucket,
+        root_prefix,
+        keyspace,
+        key,
+        data_prefix,
+        &record_filter,
+        key_path_parser,
+        use_key_index,
+    )? {
+        None => return Ok(Vec::new()),
+        Some(v) => v,
+    };
+    let mut key_paths: Vec<KeyPath> = Vec::new();
+    let start_from = position.get_start_from(root_prefix, keyspace, key);
+    let mut objects = ObjectPaths::new(bucket, data_prefix, start_from, key_path_parser);
+    while let Some((_, key_path)) = objects.next(stats)? {
+        if key_path.first_offset > end_offset {
+            return Ok(key_paths);
+        }
+        if key_path.last_offset < start_offset {
+            continue;
+        }
+        key_paths.push(key_path);
+    }
+    return Ok(key_paths);
+}
+
+/// invoke f with every record from start_offset through end_offset inclusive in offset order, borrowing values from each object.
+/// an object removed by a concurrent compaction restarts the search after the last visited object, so no record is visited twice.
+pub fn visit_records(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    start_offset: u64,
+    end_offset: u64,
+    key_path_parser: &KeyPathParser,
+    use_key_index: bool,
+    layout: RecordLayout,
+    f: &mut dyn FnMut(RecordRef),
+) -> Result<(), StoreError> {
+    visit_objects(
+        stats,
+        bucket,
+        object_cache,
+        root_prefix,
+        keyspace,
+        key,
+        data_prefix,
+        start_offset,
+        end_offset,
+        key_path_parser,
+        use_key_index,
+        &mut |_, _, contents, next_offset| {
+            visit_records_in_range(contents, layout, next_offset, end_offset, f)
+        },
+    )
+}
+
+/// invoke f with the path, decoded path, and contents of every object holding offsets from start_offset through end_offset in offset order,
+/// along with the first offset of the object that was not in an earlier object.
+/// an object removed by a concurrent compaction restarts the search after the last visited object,
+/// so the compacted object that is found may start before offsets that were already visited.
+fn visit_objects(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    object_cache: &ObjectCache,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    start_offset: u64,
+    end_offset: u64,
+    key_path_parser: &KeyPathParser,
+    use_key_index: bool,
+    f: &mut dyn FnMut(&str, &KeyPath, &[u8], u64) -> Result<(), StoreError>,
+) -> Result<(), StoreError> {
+    let mut next_offset = start_offset;
+    let mut attempts: u32 = 0;
+    'search: loop {
+        let record_filter = RecordFilter::for_offset(next_offset, u64::MAX, Direction::Forwards);
+        let position = match search_start_from(
+            stats,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            data_prefix,
+            &record_filter,
+            key_path_parser,
+            use_key_index,
+        )? {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let start_from = position.get_start_from(root_prefix, keyspace, key);
+        let mut objects = ObjectPaths::new(bucket, data_prefix, start_from, key_path_parser);
+        while let Some((object_key, key_path)) = objects.next(stats)? {
+            if key_path.first_offset > end_offset {
+                return Ok(());
+            }
+            if key_path.last_offset < next_offset {
+                continue;
+            }
+            let contents = match fetch_object(stats, bucket, object_cache, &object_key)? {
+                Some(v) => v,
+                None if attempts < MAX_RESOLVE_MISSING_ATTEMPTS => {
+                    attempts += 1;
+                    stats.continuation_miss_count += 1;
+                    continue 'search;
+                }
+                None => {
+                    return Err(StoreError::IOError(format!(
+                        "{} was removed while reading",
+                        object_key
+                    )))
+                }
+            };
+            f(&object_key, &key_path, &contents, next_offset)?;
+            if key_path.last_offset >= end_offset {
+                return Ok(());
+            }
+            next_offset = key_path.last_offset + 1;
+        }
+        return Ok(());
+    }
+}
+
 fn search_start_from(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     object_prefix: &str,
     keyspace: &str,
     key: &str,
     data_prefix: &str,
     filter: &RecordFilter,
     key_path_parser: &KeyPathParser,
+    use_key_index: bool,
 ) -> Result<Option<Position>, StoreError> {
+    if use_key_index {
+        if let Some(paths) =
+            list_with_key_index(stats, bucket, object_prefix, keyspace, key, data_prefix)?
+        {
+            return Ok(find_start_from_in_page(&paths, filter, key_path_parser));
+        }
+    }
+
     // always check first 1000 results first
     // for many keys it is the only page and it gives us a start offset for the key.
     let (first_page, first_page_cont_token) = list_page(bucket, data_prefix, None, None, None)?;
@@ -562,9 +1355,72 @@ fn search_start_from(
         last_path_in_key.last_offset,
     );
 }
+/// list every object in the key from the key index plus a single page of objects after it.
+/// returns None when there is no index or too many objects were written after it, to fall back to a listing search.
+fn list_with_key_index(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    object_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+) -> Result<Option<Vec<String>>, StoreError> {
+    let index_path = KeyIndex::path(object_prefix, keyspace, key);
+    let index = match get_object_optional(bucket, index_path)? {
+        None => return Ok(None),
+        Some(v) => {
+            stats.read_operation_count += 1;
+            stats.read_size_total += v.len() as u64;
+            KeyIndex::from(&v)?
+        }
+    };
+    let start_from =
+        KeyPath::after_offset_prefix(object_prefix, keyspace, key, index.last_start_offset());
+    let (tail, s3_cont_token) = list_page(bucket, data_prefix, Some(start_from), None, None)?;
+    stats.list_operation_count += 1;
+    if let Some(_) = s3_cont_token {
+        // index is far behind, a listing search is cheaper than paging through the tail
+        return Ok(None);
+    }
+    let mut paths: Vec<String> = index
+        .objects
+        .iter()
+        .map(|obj| obj.to_path(object_prefix, keyspace, key))
+        .collect();
+    paths.extend(tail);
+    return Ok(Some(paths));
+}
+/// whether a single LIST from the watermark of the key finds an object holding offset, to tell a continuation from the end
+/// of the key apart from one whose anchor object was merged by a concurrent compaction. objects are only listed from the
+/// watermark, which never passes the last object, and a listing that does not fit in one page is assumed to hold it.
+fn lists_offset(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    object_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    data_prefix: &str,
+    key_path_parser: &KeyPathParser,
+    offset: u64,
+) -> Result<bool, StoreError> {
+    let watermark_path = Watermark::path(object_prefix, keyspace, key);
+    let start_from = match get_object_optional(bucket, watermark_path)? {
+        Some(v) => Some(Watermark::from(&v)?.start_from(object_prefix, keyspace, key)),
+        None => None,
+    };
+    let (page, s3_cont_token) = list_page(bucket, data_prefix, start_from, None, None)?;
+    stats.list_operation_count += 1;
+    if let Some(_) = s3_cont_token {
+        return Ok(true);
+    }
+    match page.last() {
+        None => Ok(false),
+        Some(path) => Ok(key_path_parser.parse_or_error(path)?.last_offset >= offset),
+    }
+}
 fn last_path_for_key(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     object_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -577,30 +1433,19 @@ fn last_path_for_key(
         None => None,
     };
     let mut last: Option<String> = None;
-    let mut next_cont_token: Option<String> = None;
-    loop {
-        let (page, cont) = list_page(
-            bucket,
-            data_prefix,
-            start_from.clone(),
-            next_cont_token,
-            None,
-        )?;
+    for page in list_pages(bucket, data_prefix, start_from) {
+        let page = page?;
         stats.list_operation_count += 1;
-        if !page.is_empty() {
-            last = Some(page.last().unwrap().to_string());
-        }
-        if let Some(_) = cont {
-            next_cont_token = cont;
-        } else {
-            return Ok(last);
+        if let Some(path) = page.last() {
+            last = Some(path.to_string());
         }
     }
+    return Ok(last);
 }
 
 fn binary_search_start_from(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     object_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -640,7 +1485,7 @@ fn binary_search_start_from(
 
 fn binary_search_start_from_forwards(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     object_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -712,7 +1557,7 @@ fn binary_search_start_from_forwards(
 
 fn binary_search_start_from_backwards(
     stats: &mut ReadStats,
-    bucket: &Bucket,
+    bucket: &S3Bucket,
     object_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -785,6 +1630,23 @@ fn binary_search_start_from_backwards(
     }
 }
 
+/// find the start position by listing every object of the key, which is correct even when the timestamp ranges of objects
+/// overlap or decrease with offset, where a binary search comparing against min/max timestamps could overshoot.
+fn linear_search_start_from(
+    stats: &mut ReadStats,
+    bucket: &S3Bucket,
+    data_prefix: &str,
+    filter: &RecordFilter,
+    key_path_parser: &KeyPathParser,
+) -> Result<Option<Position>, StoreError> {
+    let mut paths: Vec<String> = Vec::new();
+    for page in list_pages(bucket, data_prefix, None) {
+        paths.extend(page?);
+        stats.list_operation_count += 1;
+    }
+    return Ok(find_start_from_in_page(&paths, filter, key_path_parser));
+}
+
 fn find_start_from_in_page(
     page_list: &Vec<String>,
     filter: &RecordFilter,
@@ -1,7 +1,74 @@
-use crate::StoreError;
+use crate::common::records::RecordLayout;
+use crate::{MigrationProgressListener, StartPosition, StoreError, TimestampPolicy};
 use ini::Ini;
+use std::collections::HashMap;
 use std::str::FromStr;
 
+/// retry policy applied to every S3 operation.
+/// only 5xx responses and client/network errors are retried, other failures such as 404 are returned immediately.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_millis: u64,
+    pub max_jitter_millis: u64,
+}
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_millis: 100,
+            max_jitter_millis: 100,
+        }
+    }
+    /// a policy that never retries
+    pub fn none() -> Self {
+        Self::new().set_max_attempts(1)
+    }
+    /// set the max attempts per operation, including the first. defaults to 3.
+    pub fn set_max_attempts(mut self, v: u32) -> Self {
+        self.max_attempts = v;
+        self
+    }
+    /// set the delay before the first retry, doubling for each subsequent retry. defaults to 100 milliseconds.
+    pub fn set_base_delay_millis(mut self, v: u64) -> Self {
+        self.base_delay_millis = v;
+        self
+    }
+    /// set the max random jitter added to each retry delay. defaults to 100 milliseconds.
+    pub fn set_max_jitter_millis(mut self, v: u64) -> Self {
+        self.max_jitter_millis = v;
+        self
+    }
+}
+
+/// target of S3StoreWriter::migrate_keyspace, which copies every key of a keyspace into a new keyspace
+#[derive(Clone)]
+pub struct MigrationConfig {
+    pub target_keyspace: String,
+    pub layout: RecordLayout,
+    pub progress_listener: Option<MigrationProgressListener>,
+}
+impl MigrationConfig {
+    pub fn new(target_keyspace: String) -> Self {
+        Self {
+            target_keyspace,
+            layout: RecordLayout::new(),
+            progress_listener: None,
+        }
+    }
+    /// set the record layout of the target keyspace, such as RecordLayout::new().set_u64_nonces(true), defaults to the default layout.
+    /// ignored when resuming into a target keyspace that already exists.
+    pub fn set_layout(mut self, v: RecordLayout) -> Self {
+        self.layout = v;
+        self
+    }
+    /// receive the key and cumulative progress after each key is migrated or skipped
+    pub fn set_progress_listener(mut self, v: MigrationProgressListener) -> Self {
+        self.progress_listener = Some(v);
+        self
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct S3StoreConfig {
     pub object_prefix: String,
@@ -16,12 +83,53 @@ pub struct S3StoreConfig {
     pub session_token: Option<String>,
     pub profile: Option<String>,
     pub max_cached_keys: usize,
+    pub max_cached_bytes: usize,
     pub compact_records_threshold: u64,
     pub compact_size_threshold: u64,
     pub compact_objects_threshold: u64,
+    pub compact_target_size: u64,
+    pub max_object_size: u64,
+    pub max_put_size: u64,
+    pub append_buffer_size_threshold: u64,
+    pub append_buffer_max_age_millis: u64,
     pub default_page_size: u64,
+    pub max_page_size: u64,
+    pub object_cache_capacity: usize,
+    pub object_cache_ttl_millis: u64,
+    pub ranged_read_size: u64,
+    pub retry_policy: RetryPolicy,
+    pub request_timeout_millis: u64,
+    pub credentials_refresh_interval_millis: u64,
+    pub max_requests_per_second: u64,
+    pub max_bytes_per_second: u64,
+    pub max_list_pages: u64,
+    pub error_on_missing_key: bool,
+    pub dedup_nonces_on_read: bool,
+    pub sort_pages_by_timestamp: bool,
+    pub read_your_writes_timeout_millis: u64,
+    pub linear_timestamp_seek: bool,
+    pub default_start: StartPosition,
+    pub keyspace_default_starts: HashMap<String, StartPosition>,
+    pub require_keyspace: bool,
+    pub timestamp_policy: TimestampPolicy,
+    /// alias of a timestamp_policy of Clamp when true, which applies while timestamp_policy is Allow
+    #[deprecated(note = "use timestamp_policy")]
+    pub monotonic_timestamps: bool,
+    pub key_index: bool,
+    pub compaction_manifest: bool,
+    pub nonce_resets: bool,
+    pub conditional_writes: bool,
+    pub compaction_lease_ttl_millis: u64,
+    pub storage_class: Option<String>,
+    pub compacted_storage_class: Option<String>,
+    pub put_headers: Vec<(String, String)>,
+    pub key_prefix_sharding: bool,
+    #[cfg(feature = "testing")]
+    pub memory_store: Option<crate::testing::MemoryObjectStore>,
 }
 impl S3StoreConfig {
+    // initializes the deprecated monotonic_timestamps alias
+    #[allow(deprecated)]
     pub fn new() -> Self {
         Self {
             object_prefix: "".to_string(),
@@ -36,10 +144,47 @@ impl S3StoreConfig {
             session_token: None,
             profile: None,
             max_cached_keys: 100 * 1024, // 100k
+            max_cached_bytes: 0,
             compact_records_threshold: 1000,
             compact_size_threshold: 1024 * 1024, // 1MB
             compact_objects_threshold: 100,
+            compact_target_size: u64::MAX,
+            max_object_size: u64::MAX,
+            max_put_size: 5 * 1024 * 1024 * 1024, // 5GiB
+            append_buffer_size_threshold: 0,
+            append_buffer_max_age_millis: 0,
             default_page_size: 1000,
+            max_page_size: u64::MAX,
+            object_cache_capacity: 0,
+            object_cache_ttl_millis: 60 * 1000, // 1 minute
+            ranged_read_size: 0,
+            retry_policy: RetryPolicy::new(),
+            request_timeout_millis: 30 * 1000, // 30 seconds
+            credentials_refresh_interval_millis: 0,
+            max_requests_per_second: 0,
+            max_bytes_per_second: 0,
+            max_list_pages: 1000 * 1000, // 1 billion objects
+            error_on_missing_key: false,
+            dedup_nonces_on_read: false,
+            sort_pages_by_timestamp: false,
+            read_your_writes_timeout_millis: 0,
+            linear_timestamp_seek: false,
+            default_start: StartPosition::First,
+            keyspace_default_starts: HashMap::new(),
+            require_keyspace: false,
+            timestamp_policy: TimestampPolicy::Allow,
+            monotonic_timestamps: false,
+            key_index: false,
+            compaction_manifest: false,
+            nonce_resets: false,
+            conditional_writes: false,
+            compaction_lease_ttl_millis: 0,
+            storage_class: None,
+            compacted_storage_class: None,
+            put_headers: Vec::new(),
+            key_prefix_sharding: false,
+            #[cfg(feature = "testing")]
+            memory_store: None,
         }
     }
     /// object prefix, defaults to an empty string, which would put the keyspace at the root of the bucket
@@ -102,6 +247,12 @@ impl S3StoreConfig {
         self.max_cached_keys = v;
         self
     }
+    /// also evict cached keys while their estimated memory exceeds this many bytes, defaults to 0 which only bounds the key count.
+    /// the estimate counts the keyspace and key names of each entry, so it is a tighter bound than max_cached_keys for long names.
+    pub fn set_max_cached_bytes(mut self, v: usize) -> Self {
+        self.max_cached_bytes = v;
+        self
+    }
     /// set the record count threshold to trigger compaction of a complete batch, defaults to 1000
     pub fn set_compact_records_threshold(mut self, v: u64) -> Self {
         self.compact_records_threshold = v;
@@ -117,11 +268,235 @@ impl S3StoreConfig {
         self.compact_size_threshold = v;
         self
     }
+    /// set the target size of objects produced by compaction, splitting a compaction into multiple objects when exceeded.
+    /// defaults to unbounded, which merges all objects after the watermark into a single object
+    pub fn set_compact_target_size(mut self, v: u64) -> Self {
+        self.compact_target_size = v;
+        self
+    }
+    /// split an append into multiple linked objects of at most this size, defaults to unbounded which writes one object per append.
+    /// a single record larger than this size is still written, as an object of its own.
+    /// a split append is not atomic: its objects are written in order, so a failed append may leave its first objects written.
+    pub fn set_max_object_size(mut self, v: u64) -> Self {
+        self.max_object_size = v;
+        self
+    }
+    /// fail a PUT larger than this size with BadData before sending it, defaults to 5GiB, the single PUT limit of S3.
+    /// lower it for backends with a smaller limit, so oversized objects fail with a clear error instead of an HTTP code.
+    pub fn set_max_put_size(mut self, v: u64) -> Self {
+        self.max_put_size = v;
+        self
+    }
+    /// buffer appended records per key in the writer until their size reaches the threshold, defaults to 0 which writes one object per append.
+    /// buffered records are only written once the threshold or append_buffer_max_age_millis is reached, flush_key or flush_all is called,
+    /// or the writer is dropped, so they are lost on a crash.
+    pub fn set_append_buffer_size_threshold(mut self, v: u64) -> Self {
+        self.append_buffer_size_threshold = v;
+        self
+    }
+    /// also write an append buffer once its first record is older than this many millis, defaults to 0 which only writes full buffers.
+    /// the age is checked on every append to the key, and for every key by duty_cycle, which must be called periodically for idle keys.
+    pub fn set_append_buffer_max_age_millis(mut self, v: u64) -> Self {
+        self.append_buffer_max_age_millis = v;
+        self
+    }
     /// set the default number of max results used when none is defined in the request
     pub fn set_default_page_size(mut self, v: u64) -> Self {
         self.default_page_size = v;
         self
     }
+    /// cap the page size of every read, including requested page sizes, defaults to unbounded.
+    /// set this when page sizes come from untrusted callers, so a single request cannot exhaust memory.
+    pub fn set_max_page_size(mut self, v: u64) -> Self {
+        self.max_page_size = v;
+        self
+    }
+    /// set the max number of data objects cached in memory by the reader, defaults to 0 which disables caching.
+    /// data objects are immutable, so cached objects avoid repeated GETs for hot keys without revalidation.
+    pub fn set_object_cache_capacity(mut self, v: usize) -> Self {
+        self.object_cache_capacity = v;
+        self
+    }
+    /// set how long a cached data object is served before it is revalidated with an If-None-Match GET, defaults to 1 minute
+    pub fn set_object_cache_ttl_millis(mut self, v: u64) -> Self {
+        self.object_cache_ttl_millis = v;
+        self
+    }
+    /// read objects larger than this size in byte ranges, starting with a range of this size and doubling it until the page is filled.
+    /// defaults to 0 which always reads whole objects. ranged reads bypass the object cache, so they only apply when it is disabled.
+    pub fn set_ranged_read_size(mut self, v: u64) -> Self {
+        self.ranged_read_size = v;
+        self
+    }
+    /// set the retry policy applied to all S3 operations, defaults to 3 attempts with 100ms exponential backoff and 100ms jitter
+    pub fn set_retry_policy(mut self, v: RetryPolicy) -> Self {
+        self.retry_policy = v;
+        self
+    }
+    /// set the timeout of each S3 request attempt, defaults to 30 seconds.
+    /// a timed out attempt fails with a retryable Timeout and is retried according to the retry policy.
+    /// the S3 client does not expose a separate connect timeout, so connecting is only bounded by the operating system.
+    pub fn set_request_timeout_millis(mut self, v: u64) -> Self {
+        self.request_timeout_millis = v;
+        self
+    }
+    /// set how often credentials are re-resolved, defaults to 0 which only refreshes them after a request is denied.
+    /// set below the STS session duration when using temporary credentials from an assumed role.
+    pub fn set_credentials_refresh_interval_millis(mut self, v: u64) -> Self {
+        self.credentials_refresh_interval_millis = v;
+        self
+    }
+    /// limit S3 requests per second, including retries, blocking operations once reached, defaults to 0 which is unlimited.
+    /// the limit is shared by clones of a reader or writer, such as the threads of a BatchingStoreWriter, but separate readers and writers each have their own.
+    pub fn set_max_requests_per_second(mut self, v: u64) -> Self {
+        self.max_requests_per_second = v;
+        self
+    }
+    /// limit bytes transferred by GETs and PUTs per second, blocking operations once reached, defaults to 0 which is unlimited.
+    /// a transfer larger than the limit is not split, it delays the operations after it instead.
+    pub fn set_max_bytes_per_second(mut self, v: u64) -> Self {
+        self.max_bytes_per_second = v;
+        self
+    }
+    /// fail a listing with IOError once it has paged through this many pages, defaults to 1 million, and 0 is unlimited.
+    /// protects against S3-compatible backends that never end a listing, on top of always failing on a repeated continuation token.
+    pub fn set_max_list_pages(mut self, v: u64) -> Self {
+        self.max_list_pages = v;
+        self
+    }
+    /// return KeyspaceNotFound or KeyNotFound from read_first_page instead of an empty page when the key does not exist, defaults to false
+    pub fn set_error_on_missing_key(mut self, v: bool) -> Self {
+        self.error_on_missing_key = v;
+        self
+    }
+    /// drop records from a page whose nonce was already returned in the same page, defaults to false.
+    /// protects readers from a record exposed twice by overlapping objects while compaction replaces them, at the cost of tracking the nonces of each page.
+    /// a page with dropped records keeps reading the key until it is full again, so it may make more requests than the page size suggests.
+    pub fn set_dedup_nonces_on_read(mut self, v: bool) -> Self {
+        self.dedup_nonces_on_read = v;
+        self
+    }
+    /// sort the records of each page by timestamp, tie-broken by offset, instead of by offset, defaults to false.
+    /// only records within a page are sorted, so pages are only in timestamp order overall when timestamps never decrease with offset.
+    pub fn set_sort_pages_by_timestamp(mut self, v: bool) -> Self {
+        self.sort_pages_by_timestamp = v;
+        self
+    }
+    /// when a read from an offset finds nothing but key metadata shows the offset was written, list again until this timeout elapses.
+    /// defaults to 0 which never waits. only needed for backends whose listings are eventually consistent.
+    /// metadata is read once per read, and is only as consistent as its own LIST, so it helps when one listing lags another.
+    pub fn set_read_your_writes_timeout_millis(mut self, v: u64) -> Self {
+        self.read_your_writes_timeout_millis = v;
+        self
+    }
+    /// seek StartPosition::Timestamp reads by listing every object of the key instead of a binary search, defaults to false.
+    /// finds the first matching object even when timestamps decrease with offset, at the cost of one LIST per 1000 objects.
+    pub fn set_linear_timestamp_seek(mut self, v: bool) -> Self {
+        self.linear_timestamp_seek = v;
+        self
+    }
+    /// the start of reads from StartPosition::Default, which keep the direction they were called with, defaults to First
+    pub fn set_default_start(mut self, v: StartPosition) -> Self {
+        self.default_start = v;
+        self
+    }
+    /// the start of reads of the keyspace from StartPosition::Default, overriding default_start
+    pub fn set_keyspace_default_start(mut self, keyspace: String, start: StartPosition) -> Self {
+        self.keyspace_default_starts.insert(keyspace, start);
+        self
+    }
+    /// return KeyspaceNotFound from append when the keyspace was never created, defaults to false
+    pub fn set_require_keyspace(mut self, v: bool) -> Self {
+        self.require_keyspace = v;
+        self
+    }
+    /// clamp timestamps that would decrease with offset to the max timestamp of the key, defaults to false.
+    /// this keeps the min/max timestamp range of each object meaningful for timestamp seeks.
+    /// equivalent to a timestamp_policy of Clamp when true, or Allow when false.
+    pub fn set_monotonic_timestamps(mut self, v: bool) -> Self {
+        self.timestamp_policy = match v {
+            true => TimestampPolicy::Clamp,
+            false => TimestampPolicy::Allow,
+        };
+        #[allow(deprecated)]
+        {
+            self.monotonic_timestamps = v;
+        }
+        self
+    }
+    /// how appends treat timestamps lower than the max timestamp of the key or of an earlier record, defaults to Allow.
+    /// Clamp raises them to that max with a warning, and Reject fails the append with BadData before anything is written.
+    pub fn set_timestamp_policy(mut self, v: TimestampPolicy) -> Self {
+        self.timestamp_policy = v;
+        self
+    }
+    /// the timestamp_policy, or Clamp when it is Allow and the deprecated monotonic_timestamps field was set directly
+    pub fn effective_timestamp_policy(&self) -> TimestampPolicy {
+        #[allow(deprecated)]
+        match (self.timestamp_policy, self.monotonic_timestamps) {
+            (TimestampPolicy::Allow, true) => TimestampPolicy::Clamp,
+            (v, _) => v,
+        }
+    }
+    /// maintain an index object per key of all compacted objects, and seek reads with it instead of searching listings, defaults to false.
+    /// the writer rewrites the index each time compaction advances the watermark, costing an extra GET, LIST, and PUT.
+    pub fn set_key_index(mut self, v: bool) -> Self {
+        self.key_index = v;
+        self
+    }
+    /// maintain a manifest object per key of the offset range and record count of every compacted object, defaults to false.
+    /// readers cross-check key metadata and reads against it, returning DataLoss instead of silently returning fewer records.
+    pub fn set_compaction_manifest(mut self, v: bool) -> Self {
+        self.compaction_manifest = v;
+        self
+    }
+    /// allow S3StoreWriter::reset_nonce and apply its resets when loading keys and reading key metadata, defaults to false.
+    /// costs a GET of the reset object of a key on every key load and metadata read, so leave it disabled unless nonce sequences restart.
+    pub fn set_nonce_resets(mut self, v: bool) -> Self {
+        self.nonce_resets = v;
+        self
+    }
+    /// fail appends with Conflict instead of overwriting when another writer already wrote the same offsets of a key, defaults to false.
+    /// costs a one-object LIST and a marker object per append, created with If-None-Match, which the backend must support.
+    pub fn set_conditional_writes(mut self, v: bool) -> Self {
+        self.conditional_writes = v;
+        self
+    }
+    /// hold a lease object per key while compacting it, skipping compaction while another writer holds it, defaults to 0 which disables leases.
+    /// the lease expires after this many millis, so it must exceed the longest compaction. requires a backend supporting If-None-Match.
+    pub fn set_compaction_lease_ttl_millis(mut self, v: u64) -> Self {
+        self.compaction_lease_ttl_millis = v;
+        self
+    }
+    /// optional, the storage class of data objects written by appends, defaults to the bucket default storage class
+    pub fn set_storage_class(mut self, v: String) -> Self {
+        self.storage_class = Some(v);
+        self
+    }
+    /// optional, the storage class of data objects written by compaction, defaults to storage_class
+    pub fn set_compacted_storage_class(mut self, v: String) -> Self {
+        self.compacted_storage_class = Some(v);
+        self
+    }
+    /// add a header to every PUT, such as x-amz-server-side-encryption-aws-kms-key-id or x-amz-acl, defaults to no headers.
+    /// SSE-C headers, starting with x-amz-server-side-encryption-customer-, are also added to every GET and HEAD.
+    pub fn set_put_header(mut self, name: String, v: String) -> Self {
+        self.put_headers.retain(|(existing, _)| existing != &name);
+        self.put_headers.push((name, v));
+        self
+    }
+    /// prefix the path of every key with a two hex digit hash of keyspace/key, spreading keys of a hot keyspace across S3 partitions, defaults to false.
+    /// changes the path layout, so every reader and writer of a bucket must use the same setting, and existing keys are not found after changing it.
+    pub fn set_key_prefix_sharding(mut self, v: bool) -> Self {
+        self.key_prefix_sharding = v;
+        self
+    }
+    /// store objects in the given in-memory store instead of the bucket, for tests of readers and writers without S3
+    #[cfg(feature = "testing")]
+    pub fn set_memory_store(mut self, v: crate::testing::MemoryObjectStore) -> Self {
+        self.memory_store = Some(v);
+        self
+    }
     pub fn load(ini: &Ini) -> Result<Self, StoreError> {
         let s3 = match ini.section(Some("s3")) {
             None => {
@@ -185,6 +560,16 @@ impl S3StoreConfig {
                 }
             }
         }
+        if let Some(v) = s3.get("max_cached_bytes") {
+            match usize::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_cached_bytes(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 max_cached_bytes".to_string(),
+                    ))
+                }
+            }
+        }
         if let Some(v) = s3.get("compact_records_threshold") {
             match u64::from_str(v) {
                 Ok(v) => cfg = cfg.set_compact_records_threshold(v),
@@ -215,6 +600,32 @@ impl S3StoreConfig {
                 }
             }
         }
+        if let Some(v) = s3.get("compact_target_size") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_compact_target_size(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 compact_target_size".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("max_object_size") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_object_size(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 max_object_size".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("max_put_size") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_put_size(v),
+                Err(_) => return Err(StoreError::BadConfiguration("s3 max_put_size".to_string())),
+            }
+        }
         if let Some(v) = s3.get("default_page_size") {
             match u64::from_str(v) {
                 Ok(v) => cfg = cfg.set_default_page_size(v),
@@ -225,6 +636,357 @@ impl S3StoreConfig {
                 }
             }
         }
+        if let Some(v) = s3.get("max_page_size") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_page_size(v),
+                Err(_) => return Err(StoreError::BadConfiguration("s3 max_page_size".to_string())),
+            }
+        }
+        if let Some(v) = s3.get("object_cache_capacity") {
+            match usize::from_str(v) {
+                Ok(v) => cfg = cfg.set_object_cache_capacity(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 object_cache_capacity".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("object_cache_ttl_millis") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_object_cache_ttl_millis(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 object_cache_ttl_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("request_timeout_millis") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_request_timeout_millis(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 request_timeout_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("credentials_refresh_interval_millis") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_credentials_refresh_interval_millis(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 credentials_refresh_interval_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("max_requests_per_second") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_requests_per_second(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 max_requests_per_second".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("max_bytes_per_second") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_bytes_per_second(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 max_bytes_per_second".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("max_list_pages") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_max_list_pages(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 max_list_pages".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("retry_max_attempts") {
+            match u32::from_str(v) {
+                Ok(v) => {
+                    let policy = cfg.retry_policy.clone().set_max_attempts(v);
+                    cfg = cfg.set_retry_policy(policy);
+                }
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 retry_max_attempts".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("retry_base_delay_millis") {
+            match u64::from_str(v) {
+                Ok(v) => {
+                    let policy = cfg.retry_policy.clone().set_base_delay_millis(v);
+                    cfg = cfg.set_retry_policy(policy);
+                }
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 retry_base_delay_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("retry_max_jitter_millis") {
+            match u64::from_str(v) {
+                Ok(v) => {
+                    let policy = cfg.retry_policy.clone().set_max_jitter_millis(v);
+                    cfg = cfg.set_retry_policy(policy);
+                }
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 retry_max_jitter_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("ranged_read_size") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_ranged_read_size(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 ranged_read_size".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("error_on_missing_key") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_error_on_missing_key(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 error_on_missing_key".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("dedup_nonces_on_read") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_dedup_nonces_on_read(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 dedup_nonces_on_read".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("sort_pages_by_timestamp") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_sort_pages_by_timestamp(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 sort_pages_by_timestamp".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("read_your_writes_timeout_millis") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_read_your_writes_timeout_millis(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 read_your_writes_timeout_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("linear_timestamp_seek") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_linear_timestamp_seek(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 linear_timestamp_seek".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("default_start") {
+            match parse_start_position(v) {
+                Some(v) => cfg = cfg.set_default_start(v),
+                None => return Err(StoreError::BadConfiguration("s3 default_start".to_string())),
+            }
+        }
+        if let Some(v) = s3.get("require_keyspace") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_require_keyspace(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 require_keyspace".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("monotonic_timestamps") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_monotonic_timestamps(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 monotonic_timestamps".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("timestamp_policy") {
+            match v {
+                "allow" => cfg = cfg.set_timestamp_policy(TimestampPolicy::Allow),
+                "clamp" => cfg = cfg.set_timestamp_policy(TimestampPolicy::Clamp),
+                "reject" => cfg = cfg.set_timestamp_policy(TimestampPolicy::Reject),
+                _ => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 timestamp_policy".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("key_prefix_sharding") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_key_prefix_sharding(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 key_prefix_sharding".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("key_index") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_key_index(v),
+                Err(_) => return Err(StoreError::BadConfiguration("s3 key_index".to_string())),
+            }
+        }
+        if let Some(v) = s3.get("compaction_manifest") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_compaction_manifest(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 compaction_manifest".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("nonce_resets") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_nonce_resets(v),
+                Err(_) => return Err(StoreError::BadConfiguration("s3 nonce_resets".to_string())),
+            }
+        }
+        if let Some(v) = s3.get("append_buffer_size_threshold") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_append_buffer_size_threshold(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 append_buffer_size_threshold".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("append_buffer_max_age_millis") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_append_buffer_max_age_millis(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 append_buffer_max_age_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("conditional_writes") {
+            match bool::from_str(v) {
+                Ok(v) => cfg = cfg.set_conditional_writes(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 conditional_writes".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("compaction_lease_ttl_millis") {
+            match u64::from_str(v) {
+                Ok(v) => cfg = cfg.set_compaction_lease_ttl_millis(v),
+                Err(_) => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 compaction_lease_ttl_millis".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(v) = s3.get("storage_class") {
+            match is_readable_storage_class(v) {
+                true => cfg = cfg.set_storage_class(v.to_string()),
+                false => return Err(StoreError::BadConfiguration("s3 storage_class".to_string())),
+            }
+        }
+        if let Some(v) = s3.get("compacted_storage_class") {
+            match is_readable_storage_class(v) {
+                true => cfg = cfg.set_compacted_storage_class(v.to_string()),
+                false => {
+                    return Err(StoreError::BadConfiguration(
+                        "s3 compacted_storage_class".to_string(),
+                    ))
+                }
+            }
+        }
+        if let Some(headers) = ini.section(Some("s3_put_headers")) {
+            for (name, v) in headers.iter() {
+                cfg = cfg.set_put_header(name.to_string(), v.to_string());
+            }
+        }
+        if let Some(starts) = ini.section(Some("s3_keyspace_default_starts")) {
+            for (keyspace, v) in starts.iter() {
+                match parse_start_position(v) {
+                    Some(start) => {
+                        cfg = cfg.set_keyspace_default_start(keyspace.to_string(), start)
+                    }
+                    None => {
+                        return Err(StoreError::BadConfiguration(format!(
+                            "s3_keyspace_default_starts {}",
+                            keyspace
+                        )))
+                    }
+                }
+            }
+        }
         return Ok(cfg);
     }
 }
+
+/// parse first, or offset, timestamp, or nonce followed by a colon and its value, such as offset:100
+fn parse_start_position(v: &str) -> Option<StartPosition> {
+    if v == "first" {
+        return Some(StartPosition::First);
+    }
+    let (kind, value) = match v.find(':') {
+        None => return None,
+        Some(i) => (&v[..i], &v[i + 1..]),
+    };
+    match kind {
+        "offset" => u64::from_str(value).ok().map(StartPosition::Offset),
+        "timestamp" => i64::from_str(value).ok().map(StartPosition::Timestamp),
+        "nonce" => u128::from_str(value).ok().map(StartPosition::Nonce),
+        _ => None,
+    }
+}
+
+/// storage classes that can be read with a GET, archive classes would require a restore before every read
+fn is_readable_storage_class(v: &str) -> bool {
+    match v {
+        "STANDARD"
+        | "REDUCED_REDUNDANCY"
+        | "STANDARD_IA"
+        | "ONEZONE_IA"
+        | "INTELLIGENT_TIERING"
+        | "GLACIER_IR" => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,181 @@
+use crate::*;
+
+/// position of a single key within a merged read
+#[derive(Debug, Clone)]
+pub enum MergeCursor {
+    /// offset of the next record of the key to return, in the direction of iteration
+    Offset(u64),
+    /// every record of the key has been returned
+    Done,
+}
+
+/// records read from a single key, to be merged with the records of other keys
+pub struct MergeSource {
+    pub records: Vec<Record>,
+    /// continuation of the key when it has records after the last of the given records
+    pub continuation: Option<String>,
+}
+
+/// merge records of multiple keys in the given order, returning at most page_size records and the cursor of each key.
+/// records that are equal in the order are ordered by the position of their key in keys, so the merge is stable.
+/// records of a single key always keep their offset order, even if their timestamps are not monotonic.
+/// when every read record of a key was merged and the key has more, read_next is called with the index of the key,
+/// its continuation, and the number of records still missing from the page, so each key is only read as far as the page needs.
+pub fn merge_sources<F>(
+    keys: &[&str],
+    mut sources: Vec<MergeSource>,
+    order: &MergeOrder,
+    direction: &Direction,
+    page_size: u64,
+    mut read_next: F,
+) -> Result<(Vec<MergedRecord>, Vec<MergeCursor>), StoreError>
+where
+    F: FnMut(usize, String, u64) -> Result<MergeSource, StoreError>,
+{
+    let mut consumed: Vec<usize> = vec![0; sources.len()];
+    let mut merged: Vec<MergedRecord> = Vec::new();
+    while (merged.len() as u64) < page_size {
+        let mut next: Option<usize> = None;
+        for i in 0..sources.len() {
+            if consumed[i] == sources[i].records.len() {
+                if let Some(continuation) = sources[i].continuation.take() {
+                    // unread records of this key could come next, read them before merging further
+                    sources[i] = read_next(i, continuation, page_size - merged.len() as u64)?;
+                    consumed[i] = 0;
+                }
+            }
+            let record = match sources[i].records.get(consumed[i]) {
+                None => continue,
+                Some(v) => v,
+            };
+            next = match next {
+                None => Some(i),
+                Some(n) => {
+                    let current = &sources[n].records[consumed[n]];
+                    let precedes = match order {
+                        MergeOrder::Timestamp => {
+                            precedes(record.timestamp, current.timestamp, direction)
+                        }
+                        MergeOrder::Offset => precedes(record.offset, current.offset, direction),
+                    };
+                    match precedes {
+                        true => Some(i),
+                        false => Some(n),
+                    }
+                }
+            };
+        }
+        match next {
+            None => break,
+            Some(i) => {
+                merged.push(MergedRecord {
+                    key: keys[i].to_string(),
+                    record: sources[i].records[consumed[i]].clone(),
+                });
+                consumed[i] += 1;
+            }
+        }
+    }
+    let cursors = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| match source.records.get(consumed[i]) {
+            Some(record) => MergeCursor::Offset(record.offset),
+            None => match (&source.continuation, source.records.last()) {
+                (Some(_), Some(last)) => next_offset(last.offset, direction),
+                _ => MergeCursor::Done,
+            },
+        })
+        .collect();
+    Ok((merged, cursors))
+}
+
+/// true when a strictly comes before b in the direction of iteration
+fn precedes<T: PartialOrd>(a: T, b: T, direction: &Direction) -> bool {
+    match direction {
+        Direction::Forwards => a < b,
+        Direction::Backwards => a > b,
+    }
+}
+
+fn next_offset(offset: u64, direction: &Direction) -> MergeCursor {
+    match direction {
+        Direction::Forwards if offset < u64::MAX => MergeCursor::Offset(offset + 1),
+        Direction::Backwards if offset > 1 => MergeCursor::Offset(offset - 1),
+        _ => MergeCursor::Done,
+    }
+}
+
+/// encode cursors as a continuation token, None when every key is done.
+/// the token is the direction, the order, and the cursor of each key in order, such as `f:t:12,-,40`.
+pub fn merged_continuation(
+    direction: &Direction,
+    order: &MergeOrder,
+    cursors: &Vec<MergeCursor>,
+) -> Option<String> {
+    if cursors.iter().all(|c| match c {
+        MergeCursor::Done => true,
+        MergeCursor::Offset(_) => false,
+    }) {
+        return None;
+    }
+    let direction = match direction {
+        Direction::Forwards => "f",
+        Direction::Backwards => "b",
+    };
+    let cursors = cursors
+        .iter()
+        .map(|c| match c {
+            MergeCursor::Offset(v) => v.to_string(),
+            MergeCursor::Done => "-".to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+    let order = match order {
+        MergeOrder::Timestamp => "t",
+        MergeOrder::Offset => "o",
+    };
+    Some(format!("{}:{}:{}", direction, order, cursors))
+}
+
+/// parse a token from merged_continuation, which must have a cursor for each of key_count keys.
+/// tokens without an order, returned before the order could be chosen, are merged by timestamp.
+pub fn parse_merged_continuation(
+    s: &str,
+    key_count: usize,
+) -> Result<(Direction, MergeOrder, Vec<MergeCursor>), StoreError> {
+    let (direction, cursors) = match s.split_once(':') {
+        None => return Err(StoreError::InvalidContinuation(s.to_string())),
+        Some(v) => v,
+    };
+    let direction = match direction {
+        "f" => Direction::Forwards,
+        "b" => Direction::Backwards,
+        _ => return Err(StoreError::InvalidContinuation(s.to_string())),
+    };
+    let (order, cursors) = match cursors.split_once(':') {
+        None => (MergeOrder::Timestamp, cursors),
+        Some(("t", cursors)) => (MergeOrder::Timestamp, cursors),
+        Some(("o", cursors)) => (MergeOrder::Offset, cursors),
+        Some(_) => return Err(StoreError::InvalidContinuation(s.to_string())),
+    };
+    let mut parsed: Vec<MergeCursor> = Vec::new();
+    for cursor in cursors.split(',') {
+        parsed.push(match cursor {
+            "-" => MergeCursor::Done,
+            v => match v.parse::<u64>() {
+                Ok(v) if v > 0 => MergeCursor::Offset(v),
+                _ => return Err(StoreError::InvalidContinuation(s.to_string())),
+            },
+        });
+    }
+    if parsed.len() != key_count {
+        return Err(StoreError::InvalidContinuation(format!(
+            "{} has {} cursors for {} keys",
+            s,
+            parsed.len(),
+            key_count
+        )));
+    }
+    Ok((direction, order, parsed))
+}
@@ -2,9 +2,17 @@ mod bucket;
 mod cache;
 mod collect;
 mod config;
+mod merge;
+mod object_cache;
+mod rate_limit;
 mod reader;
+pub mod signing;
 mod writer;
 
 pub type S3StoreConfig = self::config::S3StoreConfig;
+pub type RetryPolicy = self::config::RetryPolicy;
+pub type MigrationConfig = self::config::MigrationConfig;
 pub type S3StoreWriter = self::writer::S3StoreWriter;
 pub type S3StoreReader = self::reader::S3StoreReader;
+pub type PageIterator<'a> = self::reader::PageIterator<'a>;
+pub use self::collect::{continuation_for_offset, exclusive_continuation, ReadEstimate, ReadStats};
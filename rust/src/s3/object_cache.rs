@@ -0,0 +1,87 @@
+use crate::common::time::time_now_as_millis;
+use linked_hash_map::LinkedHashMap;
+use std::sync::{Arc, Mutex};
+
+struct CachedObject {
+    contents: Arc<Vec<u8>>,
+    etag: Option<String>,
+    expire_timestamp: u64,
+}
+
+/// result of looking up an object in the cache
+pub enum CacheLookup {
+    Fresh(Arc<Vec<u8>>),
+    /// ttl elapsed, the contents may be served again after revalidating the ETag
+    Stale(Arc<Vec<u8>>, String),
+    Miss,
+}
+
+/// LRU cache of data object contents by object path.
+/// Data objects are immutable once written, so a cached object never changes.
+/// The TTL only bounds how long an object removed by compaction can still be served before it is revalidated.
+pub struct ObjectCache {
+    capacity: usize,
+    ttl_millis: u64,
+    objects: Mutex<LinkedHashMap<String, CachedObject>>,
+}
+impl ObjectCache {
+    /// create a cache holding up to capacity objects, a capacity of 0 disables caching
+    pub fn new(capacity: usize, ttl_millis: u64) -> Self {
+        Self {
+            capacity,
+            ttl_millis,
+            objects: Mutex::new(LinkedHashMap::new()),
+        }
+    }
+    /// false when capacity is 0, so callers can skip fetching ETags that would never be used
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+    pub fn get(&self, object_path: &str) -> CacheLookup {
+        if self.capacity == 0 {
+            return CacheLookup::Miss;
+        }
+        let mut objects = self.objects.lock().unwrap();
+        match objects.get_refresh(object_path) {
+            None => return CacheLookup::Miss,
+            Some(object) if time_now_as_millis() < object.expire_timestamp => {
+                return CacheLookup::Fresh(object.contents.clone())
+            }
+            Some(object) => {
+                if let Some(etag) = &object.etag {
+                    return CacheLookup::Stale(object.contents.clone(), etag.clone());
+                }
+            }
+        }
+        // expired and cannot be revalidated
+        objects.remove(object_path);
+        CacheLookup::Miss
+    }
+    pub fn put(&self, object_path: &str, contents: Arc<Vec<u8>>, etag: Option<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut objects = self.objects.lock().unwrap();
+        objects.insert(
+            object_path.to_string(),
+            CachedObject {
+                contents,
+                etag,
+                expire_timestamp: time_now_as_millis() + self.ttl_millis,
+            },
+        );
+        while objects.len() > self.capacity {
+            objects.pop_front();
+        }
+    }
+    /// restart the ttl of an object after its ETag was revalidated
+    pub fn refresh(&self, object_path: &str) {
+        let mut objects = self.objects.lock().unwrap();
+        if let Some(object) = objects.get_refresh(object_path) {
+            object.expire_timestamp = time_now_as_millis() + self.ttl_millis;
+        }
+    }
+    pub fn remove(&self, object_path: &str) {
+        self.objects.lock().unwrap().remove(object_path);
+    }
+}
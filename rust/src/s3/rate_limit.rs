@@ -0,0 +1,86 @@
+use crate::common::time::time_now_as_millis;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// token buckets bounding the requests and bytes per second of every operation against a bucket, shared by its clones.
+/// bytes are only known once a transfer is made, so a large transfer may overdraw the byte budget,
+/// which delays the requests after it instead of the transfer itself.
+pub struct RateLimiter {
+    requests: Option<Mutex<TokenBucket>>,
+    bytes: Option<Mutex<TokenBucket>>,
+}
+impl RateLimiter {
+    /// create a limiter, a limit of 0 is unlimited
+    pub fn new(requests_per_second: u64, bytes_per_second: u64) -> Self {
+        Self {
+            requests: match requests_per_second {
+                0 => None,
+                v => Some(Mutex::new(TokenBucket::new(v))),
+            },
+            bytes: match bytes_per_second {
+                0 => None,
+                v => Some(Mutex::new(TokenBucket::new(v))),
+            },
+        }
+    }
+    /// block until a request is within the request budget and any overdrawn byte budget has recovered
+    pub fn acquire_request(&self) {
+        if let Some(bytes) = &self.bytes {
+            wait_for(bytes, 0.0);
+        }
+        if let Some(requests) = &self.requests {
+            wait_for(requests, 1.0);
+        }
+    }
+    /// spend bytes transferred by a request, which may overdraw the byte budget
+    pub fn consume_bytes(&self, count: u64) {
+        if let Some(bytes) = &self.bytes {
+            let mut bytes = bytes.lock().unwrap();
+            bytes.refill();
+            bytes.tokens -= count as f64;
+        }
+    }
+}
+
+fn wait_for(bucket: &Mutex<TokenBucket>, cost: f64) {
+    loop {
+        // sleep outside of the lock, so other threads can refill and spend in the meantime
+        let wait_millis = bucket.lock().unwrap().try_spend(cost);
+        if wait_millis == 0 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(wait_millis));
+    }
+}
+
+/// tokens accumulate at rate per second, up to one second of burst
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refill_timestamp: u64,
+}
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refill_timestamp: time_now_as_millis(),
+        }
+    }
+    fn refill(&mut self) {
+        let now = time_now_as_millis();
+        let elapsed = now.saturating_sub(self.refill_timestamp);
+        self.tokens = f64::min(self.rate, self.tokens + self.rate * elapsed as f64 / 1000.0);
+        self.refill_timestamp = now;
+    }
+    /// spend cost when enough tokens are available and return 0, otherwise return the millis until they will be
+    fn try_spend(&mut self, cost: f64) -> u64 {
+        self.refill();
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            return 0;
+        }
+        f64::max(1.0, ((cost - self.tokens) * 1000.0 / self.rate).ceil()) as u64
+    }
+}
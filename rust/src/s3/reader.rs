@@ -1,225 +1,1525 @@
 use super::bucket::*;
 use super::collect::*;
+use super::merge::*;
+use super::object_cache::ObjectCache;
 use crate::common::config::*;
 use crate::common::keypath::*;
+use crate::common::records::*;
+use crate::common::time::time_now_as_millis;
 use crate::s3::S3StoreConfig;
 use crate::s3::*;
 use crate::*;
-use aws_s3::bucket::Bucket;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// first delay between listings while waiting for a written offset to become visible, doubled after each attempt
+const READ_YOUR_WRITES_BASE_DELAY_MILLIS: u64 = 50;
 
 pub struct S3StoreReader {
-    bucket: Bucket,
+    bucket: S3Bucket,
     config: S3StoreConfig,
     key_path_parser: KeyPathParser,
     continuation_parser: ContinuationParser,
+    object_cache: ObjectCache,
+    event_listener: Option<StoreEventListener>,
+    keyspace_layouts: Mutex<HashMap<String, RecordLayout>>,
 }
 impl S3StoreReader {
     pub fn new(config: S3StoreConfig) -> Result<Self, StoreError> {
         let bucket = bucket::create(&config)?;
-        Ok(Self {
-            bucket: bucket.clone(),
+        Ok(Self::with_bucket(config, bucket))
+    }
+    /// clones share the bucket, so they share credentials and rate limits
+    fn with_bucket(config: S3StoreConfig, bucket: S3Bucket) -> Self {
+        let object_cache =
+            ObjectCache::new(config.object_cache_capacity, config.object_cache_ttl_millis);
+        Self {
+            bucket,
             config: config,
             key_path_parser: KeyPathParser::new(),
             continuation_parser: ContinuationParser::new(),
-        })
+            object_cache,
+            event_listener: None,
+            keyspace_layouts: Mutex::new(HashMap::new()),
+        }
     }
-}
-impl Clone for S3StoreReader {
-    fn clone(&self) -> Self {
-        Self::new(self.config.clone()).unwrap()
+    /// receive a StoreEvent::Read with the operation counts of every read
+    pub fn set_event_listener(mut self, v: StoreEventListener) -> Self {
+        self.event_listener = Some(v);
+        self
+    }
+    /// consult the given hook before every GET and LIST, to inject missing objects or errors in tests
+    #[cfg(feature = "testing")]
+    pub fn set_fault_injector(mut self, v: FaultInjector) -> Self {
+        self.bucket.fault_injector = Some(v);
+        self
     }
 }
-impl StoreReader for S3StoreReader {
-    fn read_keyspace_metadata(&self, keyspace: &str) -> Result<KeyspaceMetadata, StoreError> {
-        let conf_path = KeyspacePath::config_path(&self.config.object_prefix, &keyspace);
-        let contents = match self.bucket.get_object(conf_path.clone()) {
-            Ok((contents, 200)) => contents,
-            Ok((_, code)) => return Err(StoreError::IOError(format!("code {}", code))),
-            Err(err) => {
-                return Err(StoreError::IOError(err.to_string()));
+impl S3StoreReader {
+    /// validate connectivity and credentials with a cheap LIST against the bucket and object prefix.
+    /// returns BadConfiguration when access is denied or the bucket does not exist, so startup can fail fast.
+    pub fn ping(&self) -> Result<(), StoreError> {
+        ping(&self.bucket, &self.config.object_prefix)
+    }
+    /// count data objects for a key and sum their sizes, using only object paths from listing
+    pub fn read_key_size(&self, keyspace: &str, key: &str) -> Result<KeySize, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let list = list_exhaustive(
+            &self.bucket,
+            &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key),
+            None,
+        )?;
+        let mut total_size: u64 = 0;
+        for path in list.iter() {
+            total_size += self.key_path_parser.parse_or_error(path)?.size;
+        }
+        Ok(KeySize {
+            object_count: list.len() as u64,
+            total_size,
+        })
+    }
+    /// count keys and data objects of a keyspace and sum their sizes, using only object paths from listing.
+    /// listing pages are processed as they arrive, so memory stays bounded for keyspaces with any number of objects.
+    pub fn keyspace_size(&self, keyspace: &str) -> Result<KeyspaceSize, StoreError> {
+        let mut size = KeyspaceSize {
+            key_count: 0,
+            object_count: 0,
+            total_size: 0,
+        };
+        let mut last_key: Option<String> = None;
+        self.for_each_keyspace_object(keyspace, |_, segment, key_path| {
+            size.object_count += 1;
+            size.total_size += key_path.size;
+            if last_key.as_deref() != Some(segment) {
+                size.key_count += 1;
+                last_key = Some(segment.to_string());
+            }
+        })?;
+        Ok(size)
+    }
+    /// list every key of a keyspace that has data objects, in listing order, using only object paths from listing.
+    /// keys are sorted by name, or by shard and then name when key_prefix_sharding is enabled.
+    pub fn list_keys(&self, keyspace: &str) -> Result<Vec<String>, StoreError> {
+        let mut keys: Vec<String> = Vec::new();
+        let key_prefix_sharding = self.config.key_prefix_sharding;
+        self.for_each_keyspace_object(keyspace, |_, segment, _| {
+            let key = key_from_path_segment(segment, key_prefix_sharding);
+            if keys.last().map(|v| v.as_str()) != Some(key) {
+                keys.push(key.to_string());
+            }
+        })?;
+        Ok(keys)
+    }
+    /// call f with the path, key path segment, and parsed path of every data object of a keyspace, in listing order,
+    /// skipping watermark and index objects. objects of a key share a prefix, so they are listed together.
+    fn for_each_keyspace_object<F: FnMut(&str, &str, KeyPath)>(
+        &self,
+        keyspace: &str,
+        mut f: F,
+    ) -> Result<(), StoreError> {
+        let prefix = KeyspacePath::prefix(&self.config.object_prefix, keyspace);
+        for list in list_pages(&self.bucket, &prefix, None) {
+            for path in list? {
+                let key_path = match self.key_path_parser.parse(&path) {
+                    None => continue,
+                    Some(v) => v,
+                };
+                let segment = match path.rfind("/data_") {
+                    None => continue,
+                    Some(end) => &path[prefix.len()..end],
+                };
+                f(&path, segment, key_path);
+            }
+        }
+        Ok(())
+    }
+    /// read the single record at the given offset, returning None if it does not exist.
+    /// uses the same binary-search seek as read_first_page to locate the containing object.
+    pub fn read_record(
+        &self,
+        keyspace: &str,
+        key: &str,
+        offset: u64,
+    ) -> Result<Option<Record>, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let mut stats = ReadStats {
+            list_operation_count: 0,
+            read_operation_count: 0,
+            read_size_total: 0,
+            continuation_miss_count: 0,
+            object_cache_hit_count: 0,
+        };
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let start = StartPosition::Offset(offset);
+        let layout = self.keyspace_layout(keyspace)?;
+        let mut collect_outcome = collect_first_page(
+            &mut stats,
+            &self.bucket,
+            &self.object_cache,
+            self.config.ranged_read_size,
+            &self.config.object_prefix,
+            keyspace,
+            &path_key,
+            &data_prefix,
+            &start,
+            1,
+            &self.key_path_parser,
+            &Direction::Forwards,
+            self.config.key_index,
+            self.config.linear_timestamp_seek,
+            &None,
+            layout,
+        )?;
+        if collect_outcome.requires_retry && collect_outcome.records.is_empty() {
+            // containing object was likely removed by a concurrent compaction, seek again
+            collect_outcome = collect_first_page(
+                &mut stats,
+                &self.bucket,
+                &self.object_cache,
+                self.config.ranged_read_size,
+                &self.config.object_prefix,
+                keyspace,
+                &path_key,
+                &data_prefix,
+                &start,
+                1,
+                &self.key_path_parser,
+                &Direction::Forwards,
+                self.config.key_index,
+                self.config.linear_timestamp_seek,
+                &None,
+                layout,
+            )?;
+        }
+        let started = time_now_as_millis();
+        let mut attempt: u32 = 0;
+        let mut next_offset: Option<u64> = None;
+        while collect_outcome.records.is_empty()
+            && self.wait_for_listed_offset(
+                keyspace,
+                key,
+                offset,
+                started,
+                attempt,
+                &mut next_offset,
+            )?
+        {
+            attempt += 1;
+            collect_outcome = collect_first_page(
+                &mut stats,
+                &self.bucket,
+                &self.object_cache,
+                self.config.ranged_read_size,
+                &self.config.object_prefix,
+                keyspace,
+                &path_key,
+                &data_prefix,
+                &start,
+                1,
+                &self.key_path_parser,
+                &Direction::Forwards,
+                self.config.key_index,
+                self.config.linear_timestamp_seek,
+                &None,
+                layout,
+            )?;
+        }
+
+        self.log_read_stats(
+            "read_record",
+            keyspace,
+            key,
+            collect_outcome.records.len() as u64,
+            &stats,
+        );
+
+        // first record at or after offset, only a match if offsets are equal
+        match collect_outcome.records.into_iter().next() {
+            Some(record) if record.offset == offset => Ok(Some(record)),
+            _ => {
+                if let Some(manifest) = self.read_compaction_manifest(keyspace, key)? {
+                    if manifest
+                        .segments
+                        .iter()
+                        .any(|s| s.first_offset <= offset && offset <= s.last_offset)
+                    {
+                        return Err(StoreError::DataLoss(format!(
+                            "{}/{} offset {} is in the compaction manifest but was not found",
+                            keyspace, key, offset
+                        )));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+    /// read the paths and serialized contents of the data objects holding offsets from start_offset through end_offset inclusive.
+    /// objects are returned whole in offset order, so they may contain records outside of the range.
+    /// allows consumers to process or copy the serialized record format without allocating a Record per record.
+    pub fn read_raw_objects(
+        &self,
+        keyspace: &str,
+        key: &str,
+        start_offset: u64,
+        end_offset: u64,
+    ) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let mut stats = ReadStats {
+            list_operation_count: 0,
+            read_operation_count: 0,
+            read_size_total: 0,
+            continuation_miss_count: 0,
+            object_cache_hit_count: 0,
+        };
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let objects = collect_raw_objects(
+            &mut stats,
+            &self.bucket,
+            &self.object_cache,
+            &self.config.object_prefix,
+            keyspace,
+            &path_key,
+            &data_prefix,
+            start_offset,
+            end_offset,
+            &self.key_path_parser,
+            self.config.key_index,
+        )?;
+        self.log_read_stats(
+            "read_raw_objects",
+            keyspace,
+            key,
+            objects.len() as u64,
+            &stats,
+        );
+        Ok(objects)
+    }
+    /// decode the paths of the data objects holding offsets from start_offset through end_offset inclusive, in offset order.
+    /// objects are listed but never read, so this is the listing building block of external compactors and index tools.
+    pub fn list_objects_in_range(
+        &self,
+        keyspace: &str,
+        key: &str,
+        start_offset: u64,
+        end_offset: u64,
+    ) -> Result<Vec<KeyPath>, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let mut stats = ReadStats {
+            list_operation_count: 0,
+            read_operation_count: 0,
+            read_size_total: 0,
+            continuation_miss_count: 0,
+            object_cache_hit_count: 0,
+        };
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let key_paths = collect_object_paths(
+            &mut stats,
+            &self.bucket,
+            &self.config.object_prefix,
+            keyspace,
+            &path_key,
+            &data_prefix,
+            start_offset,
+            end_offset,
+            &self.key_path_parser,
+            self.config.key_index,
+        )?;
+        self.log_read_stats(
+            "list_objects_in_range",
+            keyspace,
+            key,
+            key_paths.len() as u64,
+            &stats,
+        );
+        Ok(key_paths)
+    }
+    /// invoke f with every record from start_offset through end_offset inclusive, in offset order.
+    /// each RecordRef borrows its value from the object buffer, avoiding the per-record copy of a page read.
+    /// records are visited as objects are read, so an error may be returned after some records were visited.
+    pub fn for_each_record<F: FnMut(RecordRef)>(
+        &self,
+        keyspace: &str,
+        key: &str,
+        start_offset: u64,
+        end_offset: u64,
+        mut f: F,
+    ) -> Result<(), StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let mut stats = ReadStats {
+            list_operation_count: 0,
+            read_operation_count: 0,
+            read_size_total: 0,
+            continuation_miss_count: 0,
+            object_cache_hit_count: 0,
+        };
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let layout = self.keyspace_layout(keyspace)?;
+        let mut record_count: u64 = 0;
+        visit_records(
+            &mut stats,
+            &self.bucket,
+            &self.object_cache,
+            &self.config.object_prefix,
+            keyspace,
+            &path_key,
+            &data_prefix,
+            start_offset,
+            end_offset,
+            &self.key_path_parser,
+            self.config.key_index,
+            layout,
+            &mut |record| {
+                record_count += 1;
+                f(record)
+            },
+        )?;
+        self.log_read_stats("for_each_record", keyspace, key, record_count, &stats);
+        Ok(())
+    }
+    /// read the header of every record from start_offset through end_offset inclusive, in offset order, without copying any value.
+    /// objects are still read whole, but only headers are kept, so metadata-only scans such as building a secondary index need little memory.
+    pub fn read_headers(
+        &self,
+        keyspace: &str,
+        key: &str,
+        start_offset: u64,
+        end_offset: u64,
+    ) -> Result<Vec<RecordMeta>, StoreError> {
+        let mut headers: Vec<RecordMeta> = Vec::new();
+        self.for_each_record(keyspace, key, start_offset, end_offset, |record| {
+            headers.push(RecordMeta {
+                offset: record.offset,
+                timestamp: record.timestamp,
+                nonce: record.nonce,
+                value_length: record.value.len() as u32,
+            })
+        })?;
+        Ok(headers)
+    }
+    /// decode the path of every data object of a key along with the watermark, without reading object bodies.
+    /// useful to understand how many objects a seek has to search and which objects compaction may still rewrite.
+    pub fn describe_key(&self, keyspace: &str, key: &str) -> Result<KeyLayout, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let watermark_path = Watermark::path(&self.config.object_prefix, keyspace, &path_key);
+        let watermark_offset = match get_object_optional(&self.bucket, watermark_path)? {
+            None => None,
+            Some(contents) => Some(Watermark::from(&contents)?.offset),
+        };
+        let list = list_exhaustive(
+            &self.bucket,
+            &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key),
+            None,
+        )?;
+        let mut objects: Vec<ObjectLayout> = Vec::new();
+        for path in list {
+            let key_path = self.key_path_parser.parse_or_error(&path)?;
+            objects.push(ObjectLayout {
+                sealed: match watermark_offset {
+                    None => false,
+                    Some(v) => key_path.first_offset <= v,
+                },
+                path,
+                first_offset: key_path.first_offset,
+                last_offset: key_path.last_offset,
+                min_timestamp: key_path.min_timestamp,
+                max_timestamp: key_path.max_timestamp,
+                first_nonce: key_path.first_nonce,
+                next_nonce: key_path.next_nonce,
+                size: key_path.size,
+                prior_start_offset: key_path.prior_start_offset,
+            });
+        }
+        Ok(KeyLayout {
+            watermark_offset,
+            objects,
+        })
+    }
+    /// read every record of a single data object by its full path, such as an ObjectLayout path from describe_key.
+    /// a low-level escape hatch for debugging and external tooling, which bypasses the watermark and object cache.
+    pub fn read_object(&self, object_path: &str) -> Result<Vec<Record>, StoreError> {
+        let contents = match get_object_optional(&self.bucket, object_path.to_string())? {
+            None => return Err(StoreError::ObjectNotFound(object_path.to_string())),
+            Some(v) => v,
+        };
+        // the keyspace is the first path segment after the prefix, which determines the record layout
+        let keyspace = object_path
+            .strip_prefix(&self.config.object_prefix)
+            .and_then(|v| v.split('/').next());
+        let layout = match keyspace {
+            None => RecordLayout::new(),
+            Some(keyspace) => self.keyspace_layout(keyspace)?,
+        };
+        let filter = RecordFilter::from(&StartPosition::First, u64::MAX, Direction::Forwards)
+            .with_layout(layout);
+        let mut records: Vec<Record> = Vec::new();
+        deserialize_and_filter_records(&contents, &mut records, &filter, u64::MIN)?;
+        Ok(records)
+    }
+    /// read the first page of multiple keys merged into a single stream in the given order.
+    /// records that are equal in the order are ordered by the position of their key in keys, and records of each key keep their offset order.
+    /// useful to read keys that were renamed, or that were written by sharded producers, as one log.
+    /// a key that does not exist is read as empty.
+    pub fn read_merged(
+        &self,
+        keyspace: &str,
+        keys: &[&str],
+        order: MergeOrder,
+        direction: Direction,
+        start: StartPosition,
+        page_size: Option<u64>,
+    ) -> Result<MergedPage, StoreError> {
+        let start = self.resolve_default_start(keyspace, start);
+        let starts = keys.iter().map(|_| Some(start.clone())).collect();
+        self.read_merged_from(keyspace, keys, order, direction, starts, page_size)
+    }
+    /// read the next page of a merged read based on the given continuation token.
+    /// keys must be the same keys in the same order as the read that returned the token.
+    pub fn read_merged_next(
+        &self,
+        keyspace: &str,
+        keys: &[&str],
+        continuation: String,
+        page_size: Option<u64>,
+    ) -> Result<MergedPage, StoreError> {
+        let (direction, order, cursors) = parse_merged_continuation(&continuation, keys.len())?;
+        let starts = cursors
+            .iter()
+            .map(|c| match c {
+                MergeCursor::Offset(v) => Some(StartPosition::Offset(*v)),
+                MergeCursor::Done => None,
+            })
+            .collect();
+        self.read_merged_from(keyspace, keys, order, direction, starts, page_size)
+    }
+    /// read a share of the page from each key that is not done, then merge them into a single page,
+    /// reading further records of a key only once all of its read records were merged.
+    /// keys are read in shares so the records read past the end of the page, which the next page reads again, stay within about a page.
+    fn read_merged_from(
+        &self,
+        keyspace: &str,
+        keys: &[&str],
+        order: MergeOrder,
+        direction: Direction,
+        starts: Vec<Option<StartPosition>>,
+        page_size: Option<u64>,
+    ) -> Result<MergedPage, StoreError> {
+        let page_size = self.effective_page_size(page_size);
+        let key_count = std::cmp::max(keys.len() as u64, 1);
+        let share = std::cmp::max((page_size + key_count - 1) / key_count, 1);
+        let mut sources: Vec<MergeSource> = Vec::new();
+        for (key, start) in keys.iter().zip(starts.into_iter()) {
+            let start = match start {
+                None => {
+                    sources.push(MergeSource {
+                        records: Vec::new(),
+                        continuation: None,
+                    });
+                    continue;
+                }
+                Some(v) => v,
+            };
+            let page = self.read_first_page(keyspace, key, direction.clone(), start, Some(share));
+            sources.push(self.merge_source(keyspace, key, page, share)?);
+        }
+        let (records, cursors) = merge_sources(
+            keys,
+            sources,
+            &order,
+            &direction,
+            page_size,
+            |i, continuation, remaining| {
+                let page = self.read_next_page(keyspace, keys[i], continuation, Some(remaining));
+                self.merge_source(keyspace, keys[i], page, remaining)
+            },
+        )?;
+        Ok(MergedPage {
+            keyspace: keyspace.to_string(),
+            records,
+            continuation: merged_continuation(&direction, &order, &cursors),
+        })
+    }
+    /// the records of a page read for a merged read, following empty pages to the next records.
+    /// a key that does not exist is an empty source.
+    fn merge_source(
+        &self,
+        keyspace: &str,
+        key: &str,
+        page: Result<Page, StoreError>,
+        page_size: u64,
+    ) -> Result<MergeSource, StoreError> {
+        let mut page = match page {
+            Err(StoreError::KeyNotFound) => {
+                return Ok(MergeSource {
+                    records: Vec::new(),
+                    continuation: None,
+                })
+            }
+            Err(err) => return Err(err),
+            Ok(v) => v,
+        };
+        while page.records.is_empty() && page.continuation.is_some() {
+            // a page may come back empty while compaction is replacing objects, follow it to the next records
+            page =
+                self.read_next_page(keyspace, key, page.continuation.unwrap(), Some(page_size))?;
+        }
+        Ok(MergeSource {
+            records: page.records,
+            continuation: page.continuation,
+        })
+    }
+    /// audit offset continuity of a key using only object paths from listing, without reading object bodies.
+    /// returns inclusive offset ranges that are either missing between consecutive objects or covered by more than one object.
+    /// overlaps may be observed transiently while a concurrent compaction is replacing objects.
+    pub fn verify_contiguity(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Vec<(u64, u64)>, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let list = list_exhaustive(
+            &self.bucket,
+            &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key),
+            None,
+        )?;
+        let mut discontinuities: Vec<(u64, u64)> = Vec::new();
+        let mut last_offset: Option<u64> = None;
+        for path in list.iter() {
+            let key_path = self.key_path_parser.parse_or_error(path)?;
+            if let Some(prev_last_offset) = last_offset {
+                if key_path.first_offset > prev_last_offset + 1 {
+                    // gap between objects
+                    discontinuities.push((prev_last_offset + 1, key_path.first_offset - 1));
+                } else if key_path.first_offset <= prev_last_offset {
+                    // overlap with a prior object
+                    discontinuities.push((
+                        key_path.first_offset,
+                        std::cmp::min(prev_last_offset, key_path.last_offset),
+                    ));
+                }
+            }
+            last_offset = match last_offset {
+                Some(v) if v > key_path.last_offset => Some(v),
+                _ => Some(key_path.last_offset),
+            };
+        }
+        // gaps at either end are not between objects, so only the compaction manifest reveals them
+        if let Some(manifest) = self.read_compaction_manifest(keyspace, key)? {
+            if let Some((manifest_first, manifest_last)) = manifest.offset_range() {
+                let first_offset = match list.first() {
+                    None => None,
+                    Some(path) => Some(self.key_path_parser.parse_or_error(path)?.first_offset),
+                };
+                match (first_offset, last_offset) {
+                    (Some(first_offset), Some(last_offset)) => {
+                        if manifest_first < first_offset {
+                            discontinuities.insert(0, (manifest_first, first_offset - 1));
+                        }
+                        if manifest_last > last_offset {
+                            discontinuities.push((last_offset + 1, manifest_last));
+                        }
+                    }
+                    _ => discontinuities.push((manifest_first, manifest_last)),
+                }
+            }
+        }
+        Ok(discontinuities)
+    }
+    /// debug log the stats of a read and emit them to the event listener
+    fn log_read_stats(
+        &self,
+        operation: &'static str,
+        keyspace: &str,
+        key: &str,
+        result_count: u64,
+        stats: &ReadStats,
+    ) {
+        log::debug!("s3 {} stats: {:#?}", operation, stats);
+        if let Some(listener) = &self.event_listener {
+            listener(&StoreEvent::Read {
+                operation,
+                keyspace: keyspace.to_string(),
+                key: key.to_string(),
+                result_count,
+                list_operation_count: stats.list_operation_count,
+                read_operation_count: stats.read_operation_count,
+                read_size_total: stats.read_size_total,
+                continuation_miss_count: stats.continuation_miss_count,
+                object_cache_hit_count: stats.object_cache_hit_count,
+            });
+        }
+    }
+    /// when read_your_writes_timeout_millis is set and key metadata shows the offset was written, sleep before the caller lists again.
+    /// returns false once the offset is not known to exist or the timeout elapsed, so the caller stops retrying.
+    /// next_offset caches the next offset from key metadata across the attempts of one read, so metadata is read at most once per read.
+    /// a next offset only grows, so once it covers the offset, the offset is known to exist for the rest of the read.
+    fn wait_for_listed_offset(
+        &self,
+        keyspace: &str,
+        key: &str,
+        offset: u64,
+        started: u64,
+        attempt: u32,
+        next_offset: &mut Option<u64>,
+    ) -> Result<bool, StoreError> {
+        let timeout = self.config.read_your_writes_timeout_millis;
+        if timeout == 0 {
+            return Ok(false);
+        }
+        let elapsed = time_now_as_millis().saturating_sub(started);
+        if elapsed >= timeout {
+            return Ok(false);
+        }
+        if let None = next_offset {
+            *next_offset = Some(match self.read_key_metadata(keyspace, key)? {
+                None => 0,
+                Some(metadata) => metadata.next_offset,
+            });
+        }
+        if offset >= next_offset.unwrap() {
+            return Ok(false);
+        }
+        let delay = std::cmp::min(
+            READ_YOUR_WRITES_BASE_DELAY_MILLIS << std::cmp::min(attempt, 10),
+            timeout - elapsed,
+        );
+        log::debug!(
+            "offset {} of {}/{} is not listed yet, listing again in {}ms",
+            offset,
+            keyspace,
+            key,
+            delay
+        );
+        thread::sleep(Duration::from_millis(delay));
+        Ok(true)
+    }
+    /// compaction manifest of a key, or None when compaction_manifest is disabled or nothing was compacted
+    fn read_compaction_manifest(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Option<CompactionManifest>, StoreError> {
+        if !self.config.compaction_manifest {
+            return Ok(None);
+        }
+        let path_key = self.path_key(keyspace, key);
+        let path = CompactionManifest::path(&self.config.object_prefix, keyspace, &path_key);
+        match get_object_optional(&self.bucket, path)? {
+            None => Ok(None),
+            Some(contents) => Ok(Some(CompactionManifest::from(&contents)?)),
+        }
+    }
+    /// return DataLoss when the compaction manifest covers offsets at or after next_offset, which means the last objects were lost
+    fn check_compaction_manifest(
+        &self,
+        keyspace: &str,
+        key: &str,
+        next_offset: u64,
+    ) -> Result<(), StoreError> {
+        let manifest = match self.read_compaction_manifest(keyspace, key)? {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        match manifest.offset_range() {
+            Some((_, last_offset)) if last_offset >= next_offset => {
+                Err(StoreError::DataLoss(format!(
+                    "{}/{} manifest covers offsets through {} but objects end before {}",
+                    keyspace, key, last_offset, next_offset
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// next nonce set by reset_nonce, when nonce_resets is enabled and no object was written since the reset
+    fn apply_nonce_reset(
+        &self,
+        keyspace: &str,
+        key: &str,
+        mut metadata: KeyMetadata,
+    ) -> Result<KeyMetadata, StoreError> {
+        if !self.config.nonce_resets {
+            return Ok(metadata);
+        }
+        let path_key = self.path_key(keyspace, key);
+        let path = NonceReset::path(&self.config.object_prefix, keyspace, &path_key);
+        if let Some(contents) = get_object_optional(&self.bucket, path)? {
+            NonceReset::from(&contents)?.apply(&mut metadata);
+        }
+        Ok(metadata)
+    }
+    /// requested page size, or default_page_size when none is given, capped at max_page_size
+    fn effective_page_size(&self, page_size: Option<u64>) -> u64 {
+        let page_size = match page_size {
+            None => self.config.default_page_size,
+            Some(v) => v,
+        };
+        std::cmp::min(page_size, self.config.max_page_size)
+    }
+    /// stable sort records of the page by timestamp then offset in the direction of the read, when sort_pages_by_timestamp is enabled
+    fn sort_by_timestamp(&self, mut records: Vec<Record>, direction: &Direction) -> Vec<Record> {
+        if !self.config.sort_pages_by_timestamp {
+            return records;
+        }
+        match direction {
+            Direction::Forwards => records.sort_by_key(|r| (r.timestamp, r.offset)),
+            Direction::Backwards => {
+                records.sort_by_key(|r| std::cmp::Reverse((r.timestamp, r.offset)))
             }
+        }
+        records
+    }
+    /// metadata from the config of a keyspace, or None if the keyspace was never created
+    fn read_keyspace_config(&self, keyspace: &str) -> Result<Option<KeyspaceMetadata>, StoreError> {
+        let conf_path = KeyspacePath::config_path(&self.config.object_prefix, &keyspace);
+        let contents = match get_object_optional(&self.bucket, conf_path)? {
+            Some(contents) => contents,
+            None => return Ok(None),
         };
         let ini_string = match std::str::from_utf8(&contents) {
             Ok(v) => v,
             Err(err) => return Err(StoreError::IOError(err.to_string())),
         };
         match ini::Ini::load_from_str(ini_string) {
-            Ok(ini) => keyspace_metadata_from_ini(&ini),
+            Ok(ini) => Ok(Some(keyspace_metadata_from_ini(&ini)?)),
             Err(_) => Err(StoreError::IOError(format!(
                 "could not load config for keyspace {}",
                 keyspace
             ))),
         }
     }
-    fn read_key_metadata(
+    /// record layout of the data objects of a keyspace, from the format version of its config.
+    /// remembered once read, since the format of a keyspace never changes. a keyspace without a config but with objects
+    /// was written before configs existed and is remembered with the first format, while one without objects is read
+    /// again next time, since it may still be created with another format.
+    fn keyspace_layout(&self, keyspace: &str) -> Result<RecordLayout, StoreError> {
+        if let Some(v) = self.keyspace_layouts.lock().unwrap().get(keyspace) {
+            return Ok(*v);
+        }
+        let layout = match self.read_keyspace_config(keyspace)? {
+            None => {
+                let prefix = KeyspacePath::prefix(&self.config.object_prefix, keyspace);
+                let (list, _) = list_page(&self.bucket, &prefix, None, None, Some(1))?;
+                if list.is_empty() {
+                    return Ok(RecordLayout::new());
+                }
+                RecordLayout::new()
+            }
+            Some(metadata) => RecordLayout::for_keyspace(&metadata)?,
+        };
+        self.keyspace_layouts
+            .lock()
+            .unwrap()
+            .insert(keyspace.to_string(), layout);
+        Ok(layout)
+    }
+    /// metadata of a keyspace written without create_keyspace, such as by older tooling, inferred from its data objects.
+    /// the created timestamp is the min timestamp of every data object, assuming the millisecond unit of legacy keyspaces,
+    /// and the format version is inferred from the records of the first data object. lists the whole keyspace.
+    /// returns None when the keyspace has no data objects either.
+    fn infer_keyspace_metadata(
         &self,
         keyspace: &str,
-        key: &str,
-    ) -> Result<Option<KeyMetadata>, StoreError> {
-        let watermark_path = Watermark::path(&self.config.object_prefix, keyspace, key);
-        let watermark_content_opt = get_object_optional(&self.bucket, watermark_path)?;
-
-        if let Some(watermark_content) = watermark_content_opt {
-            // use available watermark
-            let watermark = Watermark::from(&watermark_content)?;
-            // increasing details all come from last key, start from watermark
-            let list = list_exhaustive(
-                &self.bucket,
-                &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, key),
-                Some(watermark.start_from(&self.config.object_prefix, keyspace, key)),
-            )?;
-            if list.is_empty() {
-                return Err(StoreError::IOError(format!(
-                    "{} is not pointing to any data",
-                    Watermark::path(&self.config.object_prefix, keyspace, key),
-                )));
+    ) -> Result<Option<KeyspaceMetadata>, StoreError> {
+        let mut created_timestamp: Option<i64> = None;
+        let mut first_object: Option<(String, KeyPath)> = None;
+        self.for_each_keyspace_object(keyspace, |path, _, key_path| {
+            created_timestamp = match created_timestamp {
+                Some(v) if v <= key_path.min_timestamp => Some(v),
+                _ => Some(key_path.min_timestamp),
+            };
+            if let None = first_object {
+                first_object = Some((path.to_string(), key_path));
             }
-            return Ok(Some(
-                self.key_path_parser
-                    .parse_or_error(&list.last().unwrap())?
-                    .to_metadata(),
-            ));
-        } else {
-            // no watermark, list all data files for key
-            let list = list_exhaustive(
-                &self.bucket,
-                &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, key),
-                None,
-            )?;
-            if list.is_empty() {
-                // empty, key does not exist
-                return Ok(None);
+        })?;
+        let (path, key_path) = match first_object {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+        log::debug!("inferred metadata of keyspace {} without config", keyspace);
+        let layout = self.infer_layout(&path, &key_path)?;
+        Ok(Some(KeyspaceMetadata {
+            created_timestamp: created_timestamp.unwrap_or(key_path.min_timestamp),
+            timestamp_unit: TimestampUnit::Millis,
+            format_version: RECORD_FORMAT_VERSION,
+            forward_only: !layout.trailer,
+            u64_nonces: layout.u64_nonces,
+        }))
+    }
+    /// the first record layout that frames exactly the records of a data object, trying the default layout first
+    fn infer_layout(&self, path: &str, key_path: &KeyPath) -> Result<RecordLayout, StoreError> {
+        let contents = match get_object_optional(&self.bucket, path.to_string())? {
+            None => return Err(StoreError::ObjectNotFound(path.to_string())),
+            Some(v) => v,
+        };
+        for (forward_only, u64_nonces) in
+            &[(false, false), (true, false), (false, true), (true, true)]
+        {
+            let layout = RecordLayout::new()
+                .set_forward_only(*forward_only)
+                .set_u64_nonces(*u64_nonces);
+            if layout_matches(
+                &contents,
+                layout,
+                key_path.first_offset,
+                key_path.last_offset,
+            ) {
+                return Ok(layout);
             }
-            return Ok(Some(
-                self.key_path_parser
-                    .parse_or_error(&list.last().unwrap())?
-                    .to_metadata(),
-            ));
         }
+        Err(StoreError::BadData(format!(
+            "no record layout matches the records of {}",
+            path
+        )))
     }
-    fn read_first_page(
+    /// the start of a read, replacing StartPosition::Default with the default start of the keyspace.
+    /// the direction of the read is always the one it was called with.
+    fn resolve_default_start(&self, keyspace: &str, start: StartPosition) -> StartPosition {
+        match start {
+            StartPosition::Default => match self.config.keyspace_default_starts.get(keyspace) {
+                Some(start) => start.clone(),
+                None => self.config.default_start.clone(),
+            },
+            start => start,
+        }
+    }
+    /// the key as it appears in object paths, prefixed by its shard when key_prefix_sharding is enabled
+    fn path_key(&self, keyspace: &str, key: &str) -> String {
+        key_path_segment(keyspace, key, self.config.key_prefix_sharding)
+    }
+    /// return KeyNotFound if the key has no data objects, or KeyspaceNotFound if the keyspace does not exist either
+    fn check_key_exists(&self, keyspace: &str, data_prefix: &str) -> Result<(), StoreError> {
+        let (list, _) = list_page(&self.bucket, data_prefix, None, None, Some(1))?;
+        if !list.is_empty() {
+            return Ok(());
+        }
+        self.read_keyspace_metadata(keyspace)?;
+        Err(StoreError::KeyNotFound)
+    }
+    /// read the last n records of a key, returned in offset order.
+    /// seeks to the tail and reads backwards, so key metadata is not read first, except for forward-only
+    /// keyspaces which can not be read backwards, whose last n offsets are read forwards from key metadata.
+    pub fn read_latest(
+        &self,
+        keyspace: &str,
+        key: &str,
+        n: u64,
+    ) -> Result<Vec<Record>, StoreError> {
+        let mut records: Vec<Record> = Vec::new();
+        if n == 0 {
+            return Ok(records);
+        }
+        let (direction, start) = match self.keyspace_layout(keyspace)?.trailer {
+            true => (Direction::Backwards, StartPosition::First),
+            false => match self.read_key_metadata(keyspace, key)? {
+                None => return Ok(records),
+                Some(metadata) => (
+                    Direction::Forwards,
+                    StartPosition::Offset(std::cmp::max(1, metadata.next_offset.saturating_sub(n))),
+                ),
+            },
+        };
+        let mut page =
+            self.read_first_page_filtered(keyspace, key, direction, start, Some(n), None)?;
+        loop {
+            records.extend(page.records);
+            if records.len() as u64 >= n {
+                break;
+            }
+            // pages may be smaller than n when capped by max_page_size
+            let continuation = match page.continuation {
+                None => break,
+                Some(v) => v,
+            };
+            let remaining = n - records.len() as u64;
+            page =
+                self.read_next_page_filtered(keyspace, key, continuation, Some(remaining), None)?;
+        }
+        // pages may be sorted by timestamp, and a forward read may see records appended since the metadata was read
+        records.sort_by_key(|record| record.offset);
+        if records.len() as u64 > n {
+            records.drain(..records.len() - n as usize);
+        }
+        Ok(records)
+    }
+    /// project the LIST and GET requests and bytes of read_first_page without reading any data object, to reject or rate-limit expensive reads.
+    /// the seek and object listing are run as the read would run them, so the seek still GETs the key index and watermark,
+    /// while the GETs of data objects are sized from object paths.
+    pub fn estimate_read(
         &self,
         keyspace: &str,
         key: &str,
         direction: Direction,
         start: StartPosition,
         page_size: Option<u64>,
+    ) -> Result<ReadEstimate, StoreError> {
+        let start = self.resolve_default_start(keyspace, start);
+        let path_key = self.path_key(keyspace, key);
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let page_size = self.effective_page_size(page_size);
+        let layout = self.keyspace_layout(keyspace)?;
+        if let (Direction::Backwards, false) = (&direction, layout.trailer) {
+            return Err(StoreError::BadConfiguration(format!(
+                "keyspace {} is forward-only and can not be read backwards",
+                keyspace
+            )));
+        }
+        estimate_first_page(
+            &self.bucket,
+            &self.object_cache,
+            self.config.ranged_read_size,
+            &self.config.object_prefix,
+            keyspace,
+            &path_key,
+            &data_prefix,
+            &start,
+            page_size,
+            &self.key_path_parser,
+            &direction,
+            self.config.key_index,
+            self.config.linear_timestamp_seek,
+        )
+    }
+    /// read_first_page, skipping records for which the predicate returns false.
+    /// the predicate runs after the built-in offset, timestamp, and nonce filtering, and only matching records count towards page_size.
+    /// there is no limit on the records scanned to fill a page, so a predicate that rarely matches reads objects until the page is full
+    /// or the key ends, which can be every object of the key in either direction.
+    pub fn read_first_page_filtered(
+        &self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        page_size: Option<u64>,
+        predicate: Option<RecordPredicate>,
     ) -> Result<Page, StoreError> {
+        let (page, _) = self.read_first_page_with_stats(
+            keyspace, key, direction, start, true, page_size, predicate,
+        )?;
+        Ok(page)
+    }
+    /// read pages from start in the given direction, following continuations until the last page.
+    /// each page comes with the ReadStats of the LIST and GET requests it took, to observe the cost of bulk reads.
+    /// iteration ends after the first error.
+    pub fn read_pages<'a>(
+        &'a self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        page_size: Option<u64>,
+    ) -> PageIterator<'a> {
+        PageIterator {
+            reader: self,
+            keyspace: keyspace.to_string(),
+            key: key.to_string(),
+            page_size,
+            next: Some(PageRequest::First(direction, start)),
+        }
+    }
+    fn read_first_page_with_stats(
+        &self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        inclusive: bool,
+        page_size: Option<u64>,
+        predicate: Option<RecordPredicate>,
+    ) -> Result<(Page, ReadStats), StoreError> {
+        let path_key = self.path_key(keyspace, key);
         let mut stats = ReadStats {
             list_operation_count: 0,
             read_operation_count: 0,
             read_size_total: 0,
             continuation_miss_count: 0,
+            object_cache_hit_count: 0,
         };
-        let data_prefix = KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, key);
-        let page_size = match page_size {
-            None => self.config.default_page_size,
-            Some(v) => v,
+        let start = match (self.resolve_default_start(keyspace, start), inclusive) {
+            (start, true) => start,
+            (start, false) => match start.exclusive(&direction) {
+                Some(v) => v,
+                None => {
+                    let page = Page {
+                        keyspace: keyspace.to_string(),
+                        key: key.to_string(),
+                        continuation: None,
+                        records: Vec::new(),
+                    };
+                    return Ok((page, stats));
+                }
+            },
         };
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let page_size = self.effective_page_size(page_size);
+        let layout = self.keyspace_layout(keyspace)?;
+        if let (Direction::Backwards, false) = (&direction, layout.trailer) {
+            return Err(StoreError::BadConfiguration(format!(
+                "keyspace {} is forward-only and can not be read backwards",
+                keyspace
+            )));
+        }
 
         // try collecting first page of records
-        let collect_outcome = collect_first_page(
+        let mut collect_outcome = collect_first_page(
             &mut stats,
             &self.bucket,
+            &self.object_cache,
+            self.config.ranged_read_size,
             &self.config.object_prefix,
             keyspace,
-            key,
+            &path_key,
             &data_prefix,
             &start,
             page_size,
             &self.key_path_parser,
             &direction,
+            self.config.key_index,
+            self.config.linear_timestamp_seek,
+            &predicate,
+            layout,
         )?;
+        if let (StartPosition::Offset(offset), None) = (&start, &predicate) {
+            // an empty page from an offset that exists may only mean the listing does not show it yet
+            let started = time_now_as_millis();
+            let mut attempt: u32 = 0;
+            let mut next_offset: Option<u64> = None;
+            while collect_outcome.records.is_empty()
+                && self.wait_for_listed_offset(
+                    keyspace,
+                    key,
+                    *offset,
+                    started,
+                    attempt,
+                    &mut next_offset,
+                )?
+            {
+                attempt += 1;
+                collect_outcome = collect_first_page(
+                    &mut stats,
+                    &self.bucket,
+                    &self.object_cache,
+                    self.config.ranged_read_size,
+                    &self.config.object_prefix,
+                    keyspace,
+                    &path_key,
+                    &data_prefix,
+                    &start,
+                    page_size,
+                    &self.key_path_parser,
+                    &direction,
+                    self.config.key_index,
+                    self.config.linear_timestamp_seek,
+                    &predicate,
+                    layout,
+                )?;
+            }
+        }
+
+        if collect_outcome.records.is_empty() && self.config.error_on_missing_key {
+            // only pay for existence checks when there is nothing to return
+            self.check_key_exists(keyspace, &data_prefix)?;
+        }
 
         if collect_outcome.requires_retry && collect_outcome.records.is_empty() {
             // failed with no results, return done so that the client doesn't end up in a continuous empty paging loop
-            return Ok(Page {
-                keyspace: keyspace.to_string(),
-                key: key.to_string(),
-                continuation: None,
-                records: collect_outcome.records,
-            });
+            return Ok((
+                Page {
+                    keyspace: keyspace.to_string(),
+                    key: key.to_string(),
+                    continuation: None,
+                    records: collect_outcome.records,
+                },
+                stats,
+            ));
         }
 
-        log::debug!("s3 read_first_page stats: {:#?}", stats);
-
         // collect worked, return results
-        return Ok(Page {
-            keyspace: keyspace.to_string(),
-            key: key.to_string(),
-            continuation: collect_outcome.continuation(),
-            records: collect_outcome.records,
-        });
+        let (records, continuation) = self.finish_page(
+            &mut stats,
+            keyspace,
+            key,
+            collect_outcome,
+            page_size,
+            &predicate,
+        )?;
+        self.log_read_stats(
+            "read_first_page",
+            keyspace,
+            key,
+            records.len() as u64,
+            &stats,
+        );
+        return Ok((
+            Page {
+                keyspace: keyspace.to_string(),
+                key: key.to_string(),
+                continuation,
+                records,
+            },
+            stats,
+        ));
     }
-
-    fn read_next_page(
+    /// read_next_page, skipping records for which the predicate returns false.
+    /// the same predicate should be given for every page of a read, continuations do not remember it.
+    pub fn read_next_page_filtered(
         &self,
         keyspace: &str,
         key: &str,
         continuation: String,
         page_size: Option<u64>,
+        predicate: Option<RecordPredicate>,
     ) -> Result<Page, StoreError> {
+        let (page, _) =
+            self.read_next_page_with_stats(keyspace, key, continuation, page_size, predicate)?;
+        Ok(page)
+    }
+    fn read_next_page_with_stats(
+        &self,
+        keyspace: &str,
+        key: &str,
+        continuation: String,
+        page_size: Option<u64>,
+        predicate: Option<RecordPredicate>,
+    ) -> Result<(Page, ReadStats), StoreError> {
         let mut stats = ReadStats {
             list_operation_count: 0,
             read_operation_count: 0,
             read_size_total: 0,
             continuation_miss_count: 0,
+            object_cache_hit_count: 0,
         };
-        let data_prefix = KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, key);
-        let page_size = match page_size {
-            None => self.config.default_page_size,
-            Some(v) => v,
-        };
+        let page_size = self.effective_page_size(page_size);
+        let collect_outcome = self.collect_next_outcome(
+            &mut stats,
+            keyspace,
+            key,
+            &continuation,
+            page_size,
+            &predicate,
+        )?;
 
-        // try collecting next page of records
-        let mut collect_outcome = collect_next_page(
+        if collect_outcome.requires_retry && collect_outcome.records.is_empty() {
+            // failed twice with no results, return done so that the client doesn't end up in a continuous empty paging loop
+            return Ok((
+                Page {
+                    keyspace: keyspace.to_string(),
+                    key: key.to_string(),
+                    continuation: None,
+                    records: collect_outcome.records,
+                },
+                stats,
+            ));
+        }
+
+        // collect worked, return results
+        let (records, continuation) = self.finish_page(
             &mut stats,
+            keyspace,
+            key,
+            collect_outcome,
+            page_size,
+            &predicate,
+        )?;
+        self.log_read_stats(
+            "read_next_page",
+            keyspace,
+            key,
+            records.len() as u64,
+            &stats,
+        );
+        return Ok((
+            Page {
+                keyspace: keyspace.to_string(),
+                key: key.to_string(),
+                continuation,
+                records,
+            },
+            stats,
+        ));
+    }
+    /// collect the records of a continuation, retrying once when the read failed with no results
+    fn collect_next_outcome(
+        &self,
+        stats: &mut ReadStats,
+        keyspace: &str,
+        key: &str,
+        continuation: &String,
+        page_size: u64,
+        predicate: &Option<RecordPredicate>,
+    ) -> Result<CollectOutcome, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let data_prefix =
+            KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key);
+        let layout = self.keyspace_layout(keyspace)?;
+
+        // try collecting next page of records
+        let collect_outcome = collect_next_page(
+            stats,
             &self.bucket,
+            &self.object_cache,
+            self.config.ranged_read_size,
             &self.config.object_prefix,
             keyspace,
-            key,
+            &path_key,
             &data_prefix,
             page_size,
             &self.key_path_parser,
-            &continuation,
+            continuation,
             &self.continuation_parser,
+            self.config.key_index,
+            predicate,
+            layout,
         )?;
+        if !(collect_outcome.requires_retry && collect_outcome.records.is_empty()) {
+            return Ok(collect_outcome);
+        }
+        // read failed with no results, likely timing of a concurrent compaction
+        // try again now that compaction would be complete (new object is created before compacted objects are deleted)
+        collect_next_page(
+            stats,
+            &self.bucket,
+            &self.object_cache,
+            self.config.ranged_read_size,
+            &self.config.object_prefix,
+            keyspace,
+            &path_key,
+            &data_prefix,
+            page_size,
+            &self.key_path_parser,
+            continuation,
+            &self.continuation_parser,
+            self.config.key_index,
+            predicate,
+            layout,
+        )
+    }
+    /// the records and continuation of a collected page.
+    /// when dedup_nonces_on_read is enabled, records whose nonce was already seen in the page are dropped,
+    /// and the page keeps collecting from its continuation until it is full again or the key was read to the end.
+    fn finish_page(
+        &self,
+        stats: &mut ReadStats,
+        keyspace: &str,
+        key: &str,
+        collect_outcome: CollectOutcome,
+        page_size: u64,
+        predicate: &Option<RecordPredicate>,
+    ) -> Result<(Vec<Record>, Option<String>), StoreError> {
+        let direction = collect_outcome.direction.clone();
+        let mut continuation = collect_outcome.continuation();
+        if !self.config.dedup_nonces_on_read {
+            return Ok((
+                self.sort_by_timestamp(collect_outcome.records, &direction),
+                continuation,
+            ));
+        }
+        // only nonces of the current page are tracked, so memory is bounded by the page size
+        let mut seen: HashSet<u128> = HashSet::new();
+        let mut records: Vec<Record> = Vec::new();
+        let mut collected = collect_outcome.records;
+        loop {
+            for record in collected {
+                let unseen = match record.nonce {
+                    None => true,
+                    Some(nonce) => seen.insert(nonce),
+                };
+                if unseen {
+                    records.push(record);
+                }
+            }
+            if records.len() as u64 >= page_size {
+                break;
+            }
+            let next = match continuation {
+                None => break,
+                Some(v) => v,
+            };
+            let remaining = page_size - records.len() as u64;
+            let collect_outcome =
+                self.collect_next_outcome(stats, keyspace, key, &next, remaining, predicate)?;
+            if collect_outcome.requires_retry && collect_outcome.records.is_empty() {
+                // return what was collected, the client continues from where it ended
+                continuation = Some(next);
+                break;
+            }
+            continuation = collect_outcome.continuation();
+            collected = collect_outcome.records;
+        }
+        Ok((self.sort_by_timestamp(records, &direction), continuation))
+    }
+}
+impl Clone for S3StoreReader {
+    fn clone(&self) -> Self {
+        let mut reader = Self::with_bucket(self.config.clone(), self.bucket.clone());
+        reader.event_listener = self.event_listener.clone();
+        reader
+    }
+}
+impl StoreReader for S3StoreReader {
+    fn read_keyspace_metadata(&self, keyspace: &str) -> Result<KeyspaceMetadata, StoreError> {
+        match self.read_keyspace_config(keyspace)? {
+            Some(metadata) => Ok(metadata),
+            None => match self.infer_keyspace_metadata(keyspace)? {
+                Some(metadata) => Ok(metadata),
+                None => Err(StoreError::KeyspaceNotFound),
+            },
+        }
+    }
+    fn read_key_metadata(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Option<KeyMetadata>, StoreError> {
+        let path_key = self.path_key(keyspace, key);
+        let watermark_path = Watermark::path(&self.config.object_prefix, keyspace, &path_key);
+        let watermark_content_opt = get_object_optional(&self.bucket, watermark_path)?;
 
-        if collect_outcome.requires_retry && collect_outcome.records.is_empty() {
-            // read failed with no results, likely timing of a concurrent compaction
-            // try again now that compaction would be complete (new object is created before compacted objects are deleted)
-            collect_outcome = collect_next_page(
-                &mut stats,
+        if let Some(watermark_content) = watermark_content_opt {
+            // use available watermark
+            let watermark = Watermark::from(&watermark_content)?;
+            // increasing details all come from last key, start from watermark
+            let list = list_exhaustive(
                 &self.bucket,
-                &self.config.object_prefix,
-                keyspace,
-                key,
-                &data_prefix,
-                page_size,
-                &self.key_path_parser,
-                &continuation,
-                &self.continuation_parser,
+                &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key),
+                Some(watermark.start_from(&self.config.object_prefix, keyspace, &path_key)),
             )?;
+            if list.is_empty() {
+                return Err(StoreError::IOError(format!(
+                    "{} is not pointing to any data",
+                    Watermark::path(&self.config.object_prefix, keyspace, &path_key),
+                )));
+            }
+            let metadata = self
+                .key_path_parser
+                .parse_or_error(&list.last().unwrap())?
+                .to_metadata();
+            self.check_compaction_manifest(keyspace, key, metadata.next_offset)?;
+            return Ok(Some(self.apply_nonce_reset(keyspace, key, metadata)?));
+        } else {
+            // no watermark, list all data files for key
+            let list = list_exhaustive(
+                &self.bucket,
+                &KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, &path_key),
+                None,
+            )?;
+            if list.is_empty() {
+                // empty, key does not exist unless its compacted objects were lost
+                self.check_compaction_manifest(keyspace, key, 0)?;
+                return Ok(None);
+            }
+            let metadata = self
+                .key_path_parser
+                .parse_or_error(&list.last().unwrap())?
+                .to_metadata();
+            self.check_compaction_manifest(keyspace, key, metadata.next_offset)?;
+            return Ok(Some(self.apply_nonce_reset(keyspace, key, metadata)?));
         }
+    }
+    fn read_first_page(
+        &self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        self.read_first_page_filtered(keyspace, key, direction, start, page_size, None)
+    }
+    fn read_next_page(
+        &self,
+        keyspace: &str,
+        key: &str,
+        continuation: String,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        self.read_next_page_filtered(keyspace, key, continuation, page_size, None)
+    }
+    fn read_first_page_from(
+        &self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        inclusive: bool,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        let (page, _) = self.read_first_page_with_stats(
+            keyspace, key, direction, start, inclusive, page_size, None,
+        )?;
+        Ok(page)
+    }
+    fn read_next_page_from(
+        &self,
+        keyspace: &str,
+        key: &str,
+        continuation: String,
+        inclusive: bool,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        let continuation = match inclusive {
+            true => continuation,
+            false => match self.continuation_parser.exclusive(&continuation)? {
+                Some(v) => v,
+                None => {
+                    return Ok(Page {
+                        keyspace: keyspace.to_string(),
+                        key: key.to_string(),
+                        continuation: None,
+                        records: Vec::new(),
+                    })
+                }
+            },
+        };
+        self.read_next_page_filtered(keyspace, key, continuation, page_size, None)
+    }
+}
 
-        if collect_outcome.requires_retry && collect_outcome.records.is_empty() {
-            // failed twice with no results, return done so that the client doesn't end up in a continuous empty paging loop
-            return Ok(Page {
-                keyspace: keyspace.to_string(),
-                key: key.to_string(),
-                continuation: None,
-                records: collect_outcome.records,
-            });
-        }
-
-        log::debug!("s3 read_next_page stats: {:#?}", stats);
+enum PageRequest {
+    First(Direction, StartPosition),
+    Next(String),
+}
 
-        // collect worked, return results
-        return Ok(Page {
-            keyspace: keyspace.to_string(),
-            key: key.to_string(),
-            continuation: collect_outcome.continuation(),
-            records: collect_outcome.records,
-        });
+/// pages of a single key read by S3StoreReader::read_pages, each with the ReadStats of reading it
+pub struct PageIterator<'a> {
+    reader: &'a S3StoreReader,
+    keyspace: String,
+    key: String,
+    page_size: Option<u64>,
+    next: Option<PageRequest>,
+}
+impl<'a> Iterator for PageIterator<'a> {
+    type Item = Result<(Page, ReadStats), StoreError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.next.take()? {
+            PageRequest::First(direction, start) => self.reader.read_first_page_with_stats(
+                &self.keyspace,
+                &self.key,
+                direction,
+                start,
+                true,
+                self.page_size,
+                None,
+            ),
+            PageRequest::Next(continuation) => self.reader.read_next_page_with_stats(
+                &self.keyspace,
+                &self.key,
+                continuation,
+                self.page_size,
+                None,
+            ),
+        };
+        if let Ok((page, _)) = &result {
+            if let Some(continuation) = &page.continuation {
+                self.next = Some(PageRequest::Next(continuation.clone()));
+            }
+        }
+        Some(result)
     }
 }
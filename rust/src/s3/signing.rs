@@ -0,0 +1,135 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+/// hex SHA-256 of a request payload, which is sent as x-amz-content-sha256 and signed
+pub fn payload_sha256(payload: &[u8]) -> String {
+    hex(&Sha256::digest(payload))
+}
+
+/// x-amz-date of a time in millis since the epoch, such as 20130524T000000Z
+pub fn amz_date(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let second_of_day = seconds % 86400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day % 3600 / 60,
+        second_of_day % 60
+    )
+}
+
+/// AWS signature version 4 of an S3 request, for requests the client does not support, such as multi-object delete.
+/// headers must have lowercase names and include host, x-amz-content-sha256 with the payload hash, and x-amz-date.
+pub fn signature(
+    method: &str,
+    uri: &str,
+    query: &str,
+    headers: &[(&str, String)],
+    secret_key: &str,
+    region: &str,
+) -> String {
+    let amz_date = header_value(headers, "x-amz-date");
+    let scope = format!("{}/{}/s3/aws4_request", &amz_date[..8], region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(
+            canonical_request(method, uri, query, headers).as_bytes()
+        ))
+    );
+    let mut key = hmac_sha256(
+        format!("AWS4{}", secret_key).as_bytes(),
+        amz_date[..8].as_bytes(),
+    );
+    for part in &[region, "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+/// Authorization header of an S3 request, see signature
+pub fn authorization(
+    method: &str,
+    uri: &str,
+    query: &str,
+    headers: &[(&str, String)],
+    access_key: &str,
+    secret_key: &str,
+    region: &str,
+) -> String {
+    let amz_date = header_value(headers, "x-amz-date");
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}/{}/s3/aws4_request,SignedHeaders={},Signature={}",
+        access_key,
+        &amz_date[..8],
+        region,
+        signed_headers(headers),
+        signature(method, uri, query, headers, secret_key, region)
+    )
+}
+
+fn canonical_request(method: &str, uri: &str, query: &str, headers: &[(&str, String)]) -> String {
+    let mut canonical_headers = String::new();
+    for (name, value) in sorted(headers) {
+        canonical_headers.push_str(&format!("{}:{}\n", name, value.trim()));
+    }
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        uri,
+        query,
+        canonical_headers,
+        signed_headers(headers),
+        header_value(headers, "x-amz-content-sha256")
+    )
+}
+
+fn signed_headers(headers: &[(&str, String)]) -> String {
+    let names: Vec<&str> = sorted(headers)
+        .into_iter()
+        .map(|(name, _)| *name)
+        .collect();
+    names.join(";")
+}
+
+fn sorted<'a>(headers: &'a [(&'a str, String)]) -> Vec<&'a (&'a str, String)> {
+    let mut sorted: Vec<&(&str, String)> = headers.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    sorted
+}
+
+fn header_value<'a>(headers: &'a [(&str, String)], name: &str) -> &'a str {
+    match headers.iter().find(|(n, _)| *n == name) {
+        Some((_, value)) => value,
+        None => panic!("signed request is missing header {}", name),
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// year, month, and day of a count of days since 1970-01-01, from Howard Hinnant's civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -1,133 +1,1339 @@
 use super::bucket::*;
 use super::cache::*;
 use crate::common::cache::*;
+use crate::common::config::keyspace_metadata_from_ini;
 use crate::common::keypath::*;
 use crate::common::records::*;
-use crate::common::time::time_now_as_millis;
-use crate::s3::S3StoreConfig;
+use crate::common::time::{
+    check_insertion_timestamp, check_timestamp_not_before, fill_timestamps, time_now_as_millis,
+};
 use crate::s3::*;
+use crate::s3::{MigrationConfig, S3StoreConfig, S3StoreReader};
 use crate::*;
-use aws_s3::bucket::Bucket;
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+
+/// max keyspace configs written at once by ensure_keyspaces
+const ENSURE_KEYSPACES_PARALLELISM: usize = 16;
+
+/// records appended to a key that are not yet written, when append_buffer_size_threshold is set
+struct AppendBuffer {
+    inserts: Vec<Insertion>,
+    size: u64,
+    /// time the first record was buffered, in millis
+    created_millis: u64,
+}
+
+/// timestamp unit and record layout of a keyspace, which never change once the keyspace is created
+#[derive(Clone, Copy)]
+struct KeyspaceFormat {
+    timestamp_unit: TimestampUnit,
+    layout: RecordLayout,
+}
 
 pub struct S3StoreWriter {
-    bucket: Bucket,
+    bucket: S3Bucket,
     config: S3StoreConfig,
     write_cache: StoreCache<CachedKey, S3CacheFetcher>,
     key_path_parser: KeyPathParser,
+    known_keyspaces: RefCell<HashSet<String>>,
+    keyspace_formats: RefCell<HashMap<String, KeyspaceFormat>>,
+    record_transform: Option<RecordTransform>,
+    append_buffers: RefCell<HashMap<(String, String), AppendBuffer>>,
+    event_listener: Option<StoreEventListener>,
 }
 impl S3StoreWriter {
     pub fn new(config: S3StoreConfig) -> Result<Self, StoreError> {
         let bucket = bucket::create(&config)?;
-        Ok(Self {
+        Ok(Self::with_bucket(config, bucket))
+    }
+    /// clones share the bucket, so they share credentials and rate limits
+    fn with_bucket(config: S3StoreConfig, bucket: S3Bucket) -> Self {
+        Self {
             bucket: bucket.clone(),
             write_cache: StoreCache::new(
                 S3CacheFetcher::new(
                     bucket,
                     config.object_prefix.clone(),
                     config.compact_records_threshold,
+                    config.key_prefix_sharding,
+                    config.nonce_resets,
                 ),
                 config.max_cached_keys,
-            ),
+            )
+            .with_max_cached_bytes(config.max_cached_bytes),
             config: config,
             key_path_parser: KeyPathParser::new(),
-        })
+            known_keyspaces: RefCell::new(HashSet::new()),
+            keyspace_formats: RefCell::new(HashMap::new()),
+            record_transform: None,
+            append_buffers: RefCell::new(HashMap::new()),
+            event_listener: None,
+        }
     }
-}
-impl Clone for S3StoreWriter {
-    fn clone(&self) -> Self {
-        Self::new(self.config.clone()).unwrap()
+    /// invoke the transform on every appended record before buffering, nonce filtering, and serialization,
+    /// so it can redact or normalize values, assign timestamps, or reject an append by returning an error.
+    /// when the writer is wrapped by a batching writer, set the transform on the batching writer instead,
+    /// so records are transformed before they are written to its write-ahead log and a rejection fails only its own append.
+    pub fn set_record_transform(mut self, v: RecordTransform) -> Self {
+        self.record_transform = Some(v);
+        self
     }
-}
-impl StoreWriter for S3StoreWriter {
-    fn create_keyspace(&self, keyspace: &str) -> Result<CreatedKeyspace, StoreError> {
-        let keyspace_config = KeyspacePath::config_path(&self.config.object_prefix, &keyspace);
-        match self.bucket.get_object(keyspace_config.clone()) {
-            Ok((_, 404)) => {}
-            Ok((_, 200)) => return Err(StoreError::KeyspaceAlreadyExists),
-            Ok((_, code)) => return Err(StoreError::IOError(format!("code {}", code))),
-            Err(err) => return Err(StoreError::IOError(err.to_string())),
-        }
-        let content = format!("[keyspace]\ncreated={}", time_now_as_millis());
-        match put_object(&self.bucket, keyspace_config, content.as_bytes()) {
-            Ok(_) => Ok(CreatedKeyspace {
-                keyspace: keyspace.to_string(),
-            }),
-            Err(err) => Err(err),
+    /// receive a StoreEvent::Appended for every data object written by an append, and a StoreEvent::Compacted for every compaction
+    pub fn set_event_listener(mut self, v: StoreEventListener) -> Self {
+        self.event_listener = Some(v);
+        self
+    }
+    /// consult the given hook before the GETs, LISTs, and unconditional PUTs of appends and compactions, to inject errors in tests.
+    /// loading a key is not affected
+    #[cfg(feature = "testing")]
+    pub fn set_fault_injector(mut self, v: FaultInjector) -> Self {
+        self.bucket.fault_injector = Some(v);
+        self
+    }
+    /// validate connectivity and credentials with a cheap LIST against the bucket and object prefix.
+    /// returns BadConfiguration when access is denied or the bucket does not exist, so startup can fail fast.
+    pub fn ping(&self) -> Result<(), StoreError> {
+        ping(&self.bucket, &self.config.object_prefix)
+    }
+    /// compute what compaction would do for the key on its next append, without writing or deleting anything.
+    /// uses the same thresholds and object selection as compaction, so the plan matches actual behavior.
+    pub fn plan_compaction(&self, keyspace: &str, key: &str) -> Result<CompactionPlan, StoreError> {
+        let kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+        plan_compaction(
+            &kinfo,
+            &self.bucket,
+            &self.config.object_prefix,
+            keyspace,
+            &self.path_key(keyspace, key),
+            &self.key_path_parser,
+            self.config.compact_records_threshold,
+            self.config.compact_size_threshold,
+            self.config.compact_objects_threshold,
+            self.config.compact_target_size,
+        )
+    }
+    /// append the first records of a key without loading it, for bulk loads of keys known to be new.
+    /// skips the watermark GET and exhaustive listing of a normal first append, starting at offset 1 and nonce 0.
+    /// a single one-object LIST verifies the key is empty, returning KeyAlreadyExists if it has data.
+    pub fn append_new(
+        &self,
+        keyspace: &str,
+        key: &str,
+        records: Vec<Insertion>,
+    ) -> Result<(), StoreError> {
+        if self.config.require_keyspace {
+            self.check_keyspace_exists(keyspace)?;
         }
+        if self.write_cache.contains_key(keyspace, key)
+            || self
+                .append_buffers
+                .borrow()
+                .contains_key(&(keyspace.to_string(), key.to_string()))
+        {
+            return Err(StoreError::KeyAlreadyExists);
+        }
+        let data_prefix = KeyPath::prefix_data_only(
+            &self.config.object_prefix,
+            keyspace,
+            &self.path_key(keyspace, key),
+        );
+        let (list, _) = list_page(&self.bucket, &data_prefix, None, None, Some(1))?;
+        if !list.is_empty() {
+            return Err(StoreError::KeyAlreadyExists);
+        }
+        let kinfo = CachedKey {
+            metadata: KeyMetadata {
+                next_nonce: 0,
+                next_offset: 1,
+            },
+            uncompacted_records: 0,
+            uncompacted_objects: 0,
+            uncompacted_size: 0,
+            prior_start_offset: 0,
+            watermark: Watermark::new(0),
+            max_timestamp: i64::MIN,
+        };
+        let records = self.transform(records)?;
+        self.append_to_key(keyspace, key, kinfo, records, true)
     }
-    fn append(&self, keyspace: &str, key: &str, records: Vec<Insertion>) -> Result<(), StoreError> {
-        let mut kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+    /// the records of an append after the record transform, which rejects the whole append with its first error
+    fn transform(&self, mut records: Vec<Insertion>) -> Result<Vec<Insertion>, StoreError> {
+        if let Some(transform) = &self.record_transform {
+            for record in records.iter_mut() {
+                transform(record)?;
+            }
+        }
+        Ok(records)
+    }
+    fn append_to_key(
+        &self,
+        keyspace: &str,
+        key: &str,
+        kinfo: CachedKey,
+        records: Vec<Insertion>,
+        dedup: bool,
+    ) -> Result<(), StoreError> {
         // determine what will be written
-        let filtered = nonce_filter(&records, kinfo.metadata.next_nonce);
+        let filtered = match dedup {
+            true => nonce_filter(&records, kinfo.metadata.next_nonce),
+            false => nonce_passthrough(&records),
+        };
 
         // nothing to insert due to nonce checking
         if filtered.records.is_empty() {
             return Ok(());
         }
 
-        // create buffer
-        let serialized = serialize_insertion(&filtered.records, kinfo.metadata.next_offset);
+        // reject the whole append before anything is written if a nonce does not fit the keyspace, a timestamp is negative,
+        // or a timestamp goes backwards under the Reject timestamp policy
+        let format = self.keyspace_format(keyspace)?;
+        let now = format.timestamp_unit.now();
+        let mut max_timestamp = kinfo.max_timestamp;
+        for insert in filtered.records.iter() {
+            format.layout.check_nonce(insert.nonce)?;
+            check_insertion_timestamp(insert)?;
+            if self.config.effective_timestamp_policy() == TimestampPolicy::Reject {
+                max_timestamp = check_timestamp_not_before(insert, now, max_timestamp)?;
+            }
+        }
 
+        // write one object per split of at most max_object_size, each linked to the object before it
+        let mut kinfo = kinfo;
+        let mut start: usize = 0;
+        while start < filtered.records.len() {
+            let mut end = start + 1;
+            let mut size = serialized_size(filtered.records[start], format.layout);
+            while end < filtered.records.len() {
+                size += serialized_size(filtered.records[end], format.layout);
+                if size > self.config.max_object_size {
+                    break;
+                }
+                end += 1;
+            }
+            let split: Vec<&Insertion> = filtered.records[start..end].to_vec();
+            start = end;
+
+            // create buffer
+            let monotonic_from = self.monotonic_from(&kinfo);
+            let serialized = serialize_insertion(
+                &split,
+                kinfo.metadata.next_offset,
+                now,
+                monotonic_from,
+                format.layout,
+            );
+            let first_nonce = match split.iter().filter_map(|e| e.nonce).min() {
+                None => kinfo.metadata.next_nonce,
+                Some(v) => v,
+            };
+            let next_nonce = match split.iter().filter_map(|e| e.nonce).max() {
+                Some(max_nonce) if max_nonce >= kinfo.metadata.next_nonce => max_nonce + 1,
+                _ => kinfo.metadata.next_nonce,
+            };
+            kinfo = self.write_serialized(
+                keyspace,
+                key,
+                kinfo,
+                serialized,
+                first_nonce,
+                next_nonce,
+                split.len() as u64,
+            )?;
+        }
+        Ok(())
+    }
+    /// append records streamed from an iterator as a single object, serializing each record as it is consumed,
+    /// so a very large append never holds every Insertion in memory at once, only the serialized object.
+    /// records are deduplicated by nonce and transformed like append, and nothing is written if the iterator yields nothing to write.
+    /// buffered appends of the key are written first to keep them in order.
+    /// an object is written each time max_object_size is reached, so a transform error may follow objects that were already written.
+    /// under the Reject timestamp policy every record is checked before the first object is written, which holds the records in memory.
+    pub fn append_iter<I: Iterator<Item = Insertion>>(
+        &self,
+        keyspace: &str,
+        key: &str,
+        inserts: I,
+    ) -> Result<(), StoreError> {
+        if self.config.require_keyspace {
+            self.check_keyspace_exists(keyspace)?;
+        }
+        self.flush_buffered(keyspace, key)?;
+        let kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+        let monotonic_from = self.monotonic_from(&kinfo);
+        let format = self.keyspace_format(keyspace)?;
+        let now = format.timestamp_unit.now();
+        let mut serializer = InsertionSerializer::new(
+            kinfo.metadata.next_offset,
+            now,
+            monotonic_from,
+            format.layout,
+        );
+        let mut kinfo = kinfo;
+        let mut first_nonce: Option<u128> = None;
+        let mut next_nonce = kinfo.metadata.next_nonce;
+        let mut checked_next_nonce = kinfo.metadata.next_nonce;
+        let mut max_timestamp = kinfo.max_timestamp;
+        let checked = inserts.map(|insert| {
+            self.check_iter_insert(
+                insert,
+                format.layout,
+                now,
+                &mut checked_next_nonce,
+                &mut max_timestamp,
+            )
+        });
+        let checked: Box<dyn Iterator<Item = Result<Option<Insertion>, StoreError>>> =
+            match self.config.effective_timestamp_policy() {
+                // reject the whole append before anything is written, as append does
+                TimestampPolicy::Reject => {
+                    let mut accepted: Vec<Result<Option<Insertion>, StoreError>> = Vec::new();
+                    for insert in checked {
+                        if let Some(insert) = insert? {
+                            accepted.push(Ok(Some(insert)));
+                        }
+                    }
+                    Box::new(accepted.into_iter())
+                }
+                _ => Box::new(checked),
+            };
+        for insert in checked {
+            let insert = match insert? {
+                // duplicate, same as nonce_filter
+                None => continue,
+                Some(v) => v,
+            };
+            if serializer.count() > 0
+                && serializer.size() + serialized_size(&insert, format.layout)
+                    > self.config.max_object_size
+            {
+                // write the split so far and start the next object after it
+                let record_count = serializer.count();
+                let first_nonce = match first_nonce.take() {
+                    None => kinfo.metadata.next_nonce,
+                    Some(v) => v,
+                };
+                kinfo = self.write_serialized(
+                    keyspace,
+                    key,
+                    kinfo,
+                    serializer.finish(),
+                    first_nonce,
+                    next_nonce,
+                    record_count,
+                )?;
+                let monotonic_from = self.monotonic_from(&kinfo);
+                serializer = InsertionSerializer::new(
+                    kinfo.metadata.next_offset,
+                    now,
+                    monotonic_from,
+                    format.layout,
+                );
+            }
+            if let Some(nonce) = insert.nonce {
+                if let None = first_nonce {
+                    first_nonce = Some(nonce);
+                }
+                next_nonce = nonce + 1;
+            }
+            serializer.push(&insert);
+        }
+
+        // nothing left to insert due to nonce checking
+        let record_count = serializer.count();
+        if record_count == 0 {
+            return Ok(());
+        }
+        let first_nonce = match first_nonce {
+            None => kinfo.metadata.next_nonce,
+            Some(v) => v,
+        };
+        self.write_serialized(
+            keyspace,
+            key,
+            kinfo,
+            serializer.finish(),
+            first_nonce,
+            next_nonce,
+            record_count,
+        )?;
+        Ok(())
+    }
+    /// transform and check a record of append_iter, returning None for a duplicate nonce.
+    /// next_nonce and max_timestamp are those of the records accepted so far, and are advanced past this one.
+    fn check_iter_insert(
+        &self,
+        mut insert: Insertion,
+        layout: RecordLayout,
+        now: i64,
+        next_nonce: &mut u128,
+        max_timestamp: &mut i64,
+    ) -> Result<Option<Insertion>, StoreError> {
+        if let Some(transform) = &self.record_transform {
+            transform(&mut insert)?;
+        }
+        if let Some(nonce) = insert.nonce {
+            if nonce < *next_nonce {
+                return Ok(None);
+            }
+        }
+        layout.check_nonce(insert.nonce)?;
+        check_insertion_timestamp(&insert)?;
+        if self.config.effective_timestamp_policy() == TimestampPolicy::Reject {
+            *max_timestamp = check_timestamp_not_before(&insert, now, *max_timestamp)?;
+        }
+        if let Some(nonce) = insert.nonce {
+            *next_nonce = nonce + 1;
+        }
+        Ok(Some(insert))
+    }
+    /// the max timestamp of the key when the Clamp timestamp policy raises lower timestamps to it
+    fn monotonic_from(&self, kinfo: &CachedKey) -> Option<i64> {
+        match self.config.effective_timestamp_policy() {
+            TimestampPolicy::Clamp => Some(kinfo.max_timestamp),
+            TimestampPolicy::Allow | TimestampPolicy::Reject => None,
+        }
+    }
+    /// write serialized records as the next object of a key, then update the cached key and check for compaction.
+    /// returns the updated key, which is also cached.
+    fn write_serialized(
+        &self,
+        keyspace: &str,
+        key: &str,
+        mut kinfo: CachedKey,
+        serialized: SerializedInsertion,
+        first_nonce: u128,
+        next_nonce: u128,
+        record_count: u64,
+    ) -> Result<CachedKey, StoreError> {
+        if serialized.clamped_count > 0 {
+            log::warn!(
+                "clamped {} timestamps of {}/{} to the max timestamp {}",
+                serialized.clamped_count,
+                keyspace,
+                key,
+                serialized.max_timestamp
+            );
+        }
+        let path_key = self.path_key(keyspace, key);
         // write buffer to bucket
         let object_key = KeyPath {
             first_offset: serialized.first_insert_offset,
             last_offset: serialized.last_insert_offset,
             min_timestamp: serialized.min_timestamp,
             max_timestamp: serialized.max_timestamp,
-            first_nonce: match filtered.first_nonce {
-                None => filtered.first_potential_nonce,
-                Some(v) => v,
-            },
-            next_nonce: filtered.next_nonce,
+            first_nonce,
+            next_nonce,
             size: serialized.buffer.len() as u64,
             prior_start_offset: kinfo.prior_start_offset,
         }
-        .to_path(&self.config.object_prefix, &keyspace, &key);
-        put_object(&self.bucket, object_key, &serialized.buffer)?;
+        .to_path(&self.config.object_prefix, &keyspace, &path_key);
+        if self.config.conditional_writes {
+            if let Err(err) = self.put_data_object_if_absent(
+                keyspace,
+                &path_key,
+                serialized.first_insert_offset,
+                object_key,
+                &serialized.buffer,
+            ) {
+                if let StoreError::Conflict(_) = err {
+                    // cached state is behind the other writer, reload it on the next append
+                    self.write_cache.remove_key(keyspace, key);
+                }
+                return Err(err);
+            }
+        } else {
+            put_object_with_storage_class(
+                &self.bucket,
+                object_key,
+                &serialized.buffer,
+                &self.config.storage_class,
+            )?;
+        }
 
-        kinfo.metadata.next_nonce = filtered.next_nonce;
+        if let Some(listener) = &self.event_listener {
+            listener(&StoreEvent::Appended {
+                keyspace: keyspace.to_string(),
+                key: key.to_string(),
+                first_offset: serialized.first_insert_offset,
+                last_offset: serialized.last_insert_offset,
+                record_count,
+                size: serialized.buffer.len() as u64,
+            });
+        }
+
+        kinfo.metadata.next_nonce = next_nonce;
         kinfo.metadata.next_offset = serialized.next_offset;
-        kinfo.uncompacted_records += filtered.records.len() as u64;
+        kinfo.uncompacted_records += record_count;
         kinfo.uncompacted_size += serialized.buffer.len() as u64;
         kinfo.uncompacted_objects += 1;
         kinfo.prior_start_offset = serialized.first_insert_offset;
+        kinfo.max_timestamp = std::cmp::max(kinfo.max_timestamp, serialized.max_timestamp);
 
         // check for compaction
+        let uncompacted_objects = kinfo.uncompacted_objects;
         let kinfo = check_compaction(
             kinfo,
             &self.bucket,
             &self.config.object_prefix,
             keyspace,
-            key,
+            &path_key,
             &self.key_path_parser,
+            self.keyspace_format(keyspace)?.layout,
             self.config.compact_records_threshold,
             self.config.compact_size_threshold,
             self.config.compact_objects_threshold,
+            self.config.compact_target_size,
+            self.config.key_index,
+            self.config.compaction_manifest,
+            self.config.conditional_writes,
+            self.config.compaction_lease_ttl_millis,
+            match &self.config.compacted_storage_class {
+                None => &self.config.storage_class,
+                Some(_) => &self.config.compacted_storage_class,
+            },
         )?;
 
+        if kinfo.uncompacted_objects < uncompacted_objects {
+            if let Some(listener) = &self.event_listener {
+                listener(&StoreEvent::Compacted {
+                    keyspace: keyspace.to_string(),
+                    key: key.to_string(),
+                    watermark_offset: kinfo.watermark.offset,
+                    uncompacted_objects: kinfo.uncompacted_objects,
+                });
+            }
+        }
+
         // update cache
-        self.write_cache.set_key(keyspace, key, kinfo);
+        self.write_cache.set_key(keyspace, key, kinfo.clone());
 
         // return result
-        return Ok(());
+        return Ok(kinfo);
+    }
+    /// copy every key of a keyspace into a new keyspace, re-encoding records in the format version of the migration config
+    /// and compacting them with the thresholds of this writer, while preserving offsets, nonces, and timestamps.
+    /// each key resumes from the next offset of its target key, so a failed migration continues where it stopped when run again.
+    /// the source must not be appended to while it is migrated.
+    pub fn migrate_keyspace(
+        &self,
+        keyspace: &str,
+        new_config: MigrationConfig,
+    ) -> Result<MigrationReport, StoreError> {
+        let target = new_config.target_keyspace.as_str();
+        if target == keyspace {
+            return Err(StoreError::BadConfiguration(
+                "migration target keyspace must differ from the source".to_string(),
+            ));
+        }
+        // records are appended as read, so nothing may change their timestamps or values
+        if self.config.effective_timestamp_policy() != TimestampPolicy::Allow
+            || self.record_transform.is_some()
+        {
+            return Err(StoreError::BadConfiguration(
+                "migrate_keyspace requires timestamp_policy Allow and no record transform"
+                    .to_string(),
+            ));
+        }
+        let timestamp_unit = self.keyspace_format(keyspace)?.timestamp_unit;
+        match self.create_keyspace_with_format(target, timestamp_unit, new_config.layout) {
+            Ok(_) => {}
+            // resuming an earlier migration
+            Err(StoreError::KeyspaceAlreadyExists) => {}
+            Err(err) => return Err(err),
+        }
+        // pages must be in offset order and contain every record to keep offsets aligned
+        let reader = S3StoreReader::new(
+            self.config
+                .clone()
+                .set_sort_pages_by_timestamp(false)
+                .set_dedup_nonces_on_read(false),
+        )?;
+        let mut report = MigrationReport {
+            keys_migrated: 0,
+            keys_skipped: 0,
+            records_copied: 0,
+        };
+        for key in reader.list_keys(keyspace)? {
+            let mut next_offset = self
+                .write_cache
+                .get_or_read_key(target, &key)?
+                .metadata
+                .next_offset;
+            let mut page = reader.read_first_page(
+                keyspace,
+                &key,
+                Direction::Forwards,
+                StartPosition::Offset(next_offset),
+                None,
+            )?;
+            let mut records_copied: u64 = 0;
+            while !page.records.is_empty() {
+                let mut inserts: Vec<Insertion> = Vec::new();
+                for record in page.records {
+                    if record.offset != next_offset {
+                        return Err(StoreError::DataLoss(format!(
+                            "{}/{} offset {} is missing, found offset {}",
+                            keyspace, key, next_offset, record.offset
+                        )));
+                    }
+                    next_offset += 1;
+                    inserts.push(Insertion {
+                        record: record.value,
+                        nonce: record.nonce,
+                        timestamp: Some(record.timestamp),
+                    });
+                }
+                records_copied += inserts.len() as u64;
+                self.append_without_dedup(target, &key, inserts)?;
+                page = match page.continuation {
+                    None => break,
+                    Some(continuation) => {
+                        reader.read_next_page(keyspace, &key, continuation, None)?
+                    }
+                };
+            }
+            // a nonce reset of the source is not in its records, so carry it over
+            if self.config.nonce_resets {
+                if let Some(metadata) = reader.read_key_metadata(keyspace, &key)? {
+                    let kinfo = self.write_cache.get_or_read_key(target, &key)?;
+                    if kinfo.metadata.next_nonce != metadata.next_nonce {
+                        self.reset_nonce(target, &key, metadata.next_nonce)?;
+                    }
+                }
+            }
+            match records_copied {
+                0 => report.keys_skipped += 1,
+                _ => report.keys_migrated += 1,
+            }
+            report.records_copied += records_copied;
+            log::info!(
+                "migrated {} records of {}/{} to {}",
+                records_copied,
+                keyspace,
+                key,
+                target
+            );
+            if let Some(listener) = &new_config.progress_listener {
+                listener(&key, &report);
+            }
+        }
+        Ok(report)
     }
-    fn flush_key(&self, _keyspace: &str, _key: &str) -> Result<(), StoreError> {
-        // no-op
+    /// rebuild the watermark of a key from its data objects, for when a crash or out-of-band tooling left it inconsistent.
+    /// returns the paths of objects whose offsets are all covered by another object, such as those left behind by an interrupted compaction,
+    /// which are only deleted when delete_redundant is true, so they can be inspected first.
+    /// the watermark moves to the first offset of the last object that reached compact_records_threshold or
+    /// compact_size_threshold, or is removed when none did. must not run while another writer appends to the key.
+    pub fn repair_key(
+        &self,
+        keyspace: &str,
+        key: &str,
+        delete_redundant: bool,
+    ) -> Result<Vec<String>, StoreError> {
+        let root_prefix = &self.config.object_prefix;
+        let path_key = self.path_key(keyspace, key);
+        let list = list_exhaustive(
+            &self.bucket,
+            &KeyPath::prefix_data_only(root_prefix, keyspace, &path_key),
+            None,
+        )?;
+        let mut objects: Vec<(String, KeyPath)> = Vec::new();
+        for path in list {
+            let key_path = self.key_path_parser.parse_or_error(&path)?;
+            objects.push((path, key_path));
+        }
+        // last offsets are not padded, so paths with the same first offset do not sort by last offset
+        objects.sort_by(|a, b| {
+            a.1.first_offset
+                .cmp(&b.1.first_offset)
+                .then(b.1.last_offset.cmp(&a.1.last_offset))
+        });
+
+        // find objects covered by a compacted object that replaced them
+        let mut kept: Vec<KeyPath> = Vec::new();
+        let mut redundant: Vec<String> = Vec::new();
+        for (path, key_path) in objects {
+            if let Some(last) = kept.last() {
+                if key_path.last_offset <= last.last_offset {
+                    redundant.push(path);
+                    continue;
+                }
+                if key_path.first_offset <= last.last_offset {
+                    return Err(StoreError::BadData(format!(
+                        "{} partially overlaps offsets {}-{}",
+                        path, last.first_offset, last.last_offset
+                    )));
+                }
+                if key_path.first_offset > last.last_offset + 1 {
+                    log::warn!(
+                        "repairing {}/{} with missing offsets {}-{}",
+                        keyspace,
+                        key,
+                        last.last_offset + 1,
+                        key_path.first_offset - 1
+                    );
+                }
+            }
+            kept.push(key_path);
+        }
+        for path in redundant.iter() {
+            match delete_redundant {
+                true => log::info!(
+                    "repair deleting {}, its offsets are in another object",
+                    path
+                ),
+                false => log::warn!("repair found {}, its offsets are in another object", path),
+            }
+        }
+        if delete_redundant {
+            delete_objects(&self.bucket, redundant.clone())?;
+        }
+
+        // the last object large enough to have been a complete compacted batch
+        let watermark = kept
+            .iter()
+            .rev()
+            .find(|key_path| {
+                key_path.last_offset - key_path.first_offset + 1
+                    >= self.config.compact_records_threshold
+                    || key_path.size >= self.config.compact_size_threshold
+            })
+            .map(|key_path| Watermark::new(key_path.first_offset));
+        let watermark_path = Watermark::path(root_prefix, keyspace, &path_key);
+        match &watermark {
+            None => delete_object(&self.bucket, watermark_path)?,
+            Some(watermark) => put_object(&self.bucket, watermark_path, &watermark.serialize())?,
+        }
+        // indexed objects may no longer be at or before the watermark
+        self.rebuild_key_index(keyspace, &path_key, &watermark)?;
+
+        // cached state was derived from the old watermark
+        self.write_cache.remove_key(keyspace, key);
+        Ok(redundant)
+    }
+    /// delete every object of a key, including its watermark, index, and markers, in batched multi-object deletes.
+    /// buffered appends of the key are discarded. must not run while another writer appends to the key.
+    pub fn delete_key(&self, keyspace: &str, key: &str) -> Result<(), StoreError> {
+        self.append_buffers
+            .borrow_mut()
+            .remove(&(keyspace.to_string(), key.to_string()));
+        let prefix = KeyPath::prefix(
+            &self.config.object_prefix,
+            keyspace,
+            &self.path_key(keyspace, key),
+        );
+        delete_objects(&self.bucket, list_exhaustive(&self.bucket, &prefix, None)?)?;
+        self.write_cache.remove_key(keyspace, key);
         Ok(())
     }
-    fn flush_all(&self) -> Result<(), StoreError> {
-        // no-op
+    /// delete every object of every key of a keyspace and then its config, in batched multi-object deletes.
+    /// the config is deleted last, so a keyspace whose deletion failed partway still exists and can be deleted again.
+    /// buffered appends of the keyspace are discarded. must not run while another writer appends to the keyspace.
+    pub fn delete_keyspace(&self, keyspace: &str) -> Result<(), StoreError> {
+        self.append_buffers
+            .borrow_mut()
+            .retain(|(buffered_keyspace, _), _| buffered_keyspace != keyspace);
+        let root_prefix = &self.config.object_prefix;
+        let prefix = KeyspacePath::prefix(root_prefix, keyspace);
+        delete_objects(&self.bucket, list_exhaustive(&self.bucket, &prefix, None)?)?;
+        delete_object(
+            &self.bucket,
+            KeyspacePath::config_path(root_prefix, keyspace),
+        )?;
+        self.write_cache.remove_keyspace(keyspace);
+        self.known_keyspaces.borrow_mut().remove(keyspace);
+        self.keyspace_formats.borrow_mut().remove(keyspace);
         Ok(())
     }
-    fn duty_cycle(&self) -> Result<(), StoreError> {
-        // no-op
+    /// delete the data objects of a key whose offsets are all before before_offset, along with their markers, for retention.
+    /// objects holding before_offset or later are kept whole, and so is the last object, so offsets and nonces carry on.
+    /// the compaction manifest and key index are trimmed to the objects left. returns the number of data objects deleted.
+    /// buffered appends of the key are written first. must not run while another writer appends to or compacts the key.
+    pub fn truncate_key(
+        &self,
+        keyspace: &str,
+        key: &str,
+        before_offset: u64,
+    ) -> Result<u64, StoreError> {
+        self.flush_buffered(keyspace, key)?;
+        let root_prefix = &self.config.object_prefix;
+        let path_key = self.path_key(keyspace, key);
+        let prefix = KeyPath::prefix(root_prefix, keyspace, &path_key);
+        let mut objects: Vec<(String, KeyPath)> = Vec::new();
+        let mut markers: Vec<(String, u64)> = Vec::new();
+        for path in list_exhaustive(&self.bucket, &prefix, None)? {
+            if let Some(key_path) = self.key_path_parser.parse(&path) {
+                objects.push((path, key_path));
+            } else if let Some(offset) = marker_offset(&prefix, &path) {
+                markers.push((path, offset));
+            }
+        }
+        // data paths sort by first offset, so every object from the first one kept is kept
+        let kept_first_offset = match objects
+            .iter()
+            .find(|(_, key_path)| key_path.last_offset >= before_offset)
+        {
+            Some((_, key_path)) => key_path.first_offset,
+            None => match objects.last() {
+                None => return Ok(0),
+                Some((_, key_path)) => key_path.first_offset,
+            },
+        };
+        let mut truncated: Vec<String> = Vec::new();
+        for (path, key_path) in objects.iter() {
+            if key_path.first_offset < kept_first_offset {
+                truncated.push(path.clone());
+            }
+        }
+        if truncated.is_empty() {
+            return Ok(0);
+        }
+        let truncated_count = truncated.len() as u64;
+        // markers of compacted objects outlive them, so every marker of an offset before the objects left goes
+        for (path, offset) in markers {
+            if offset < kept_first_offset {
+                truncated.push(path);
+            }
+        }
+
+        // trim the manifest first, so a failed delete leaves objects the manifest no longer requires
+        if self.config.compaction_manifest {
+            let manifest_path = CompactionManifest::path(root_prefix, keyspace, &path_key);
+            if let Some(contents) = get_object_optional(&self.bucket, manifest_path.clone())? {
+                let mut manifest = CompactionManifest::from(&contents)?;
+                manifest.truncate_before(kept_first_offset);
+                put_object(&self.bucket, manifest_path, &manifest.serialize())?;
+            }
+        }
+        delete_objects(&self.bucket, truncated)?;
+        // the index may list deleted objects
+        let watermark = match get_object_optional(
+            &self.bucket,
+            Watermark::path(root_prefix, keyspace, &path_key),
+        )? {
+            None => None,
+            Some(contents) => Some(Watermark::from(&contents)?),
+        };
+        self.rebuild_key_index(keyspace, &path_key, &watermark)?;
+
+        self.write_cache.remove_key(keyspace, key);
+        log::info!(
+            "truncated {} objects of {}/{} before offset {}",
+            truncated_count,
+            keyspace,
+            key,
+            kept_first_offset
+        );
+        Ok(truncated_count)
+    }
+    /// rewrite the key index from the objects at or before the watermark, when key_index is enabled
+    fn rebuild_key_index(
+        &self,
+        keyspace: &str,
+        path_key: &str,
+        watermark: &Option<Watermark>,
+    ) -> Result<(), StoreError> {
+        if !self.config.key_index {
+            return Ok(());
+        }
+        let root_prefix = &self.config.object_prefix;
+        delete_object(
+            &self.bucket,
+            KeyIndex::path(root_prefix, keyspace, path_key),
+        )?;
+        if let Some(watermark) = watermark {
+            update_key_index(
+                &self.bucket,
+                root_prefix,
+                keyspace,
+                path_key,
+                &self.key_path_parser,
+                watermark,
+            )?;
+        }
+        Ok(())
+    }
+    /// append every record regardless of nonces, for deterministic replays that reuse nonces, such as into a fresh key.
+    /// offsets advance as usual, and the next nonce of the key only moves forward, so later appends still reject nonces below the highest written.
+    /// buffered appends of the key are written first to keep them in order. nonces of the written object may overlap
+    /// those of earlier objects, so StartPosition::Nonce reads of the key are undefined afterwards.
+    pub fn append_without_dedup(
+        &self,
+        keyspace: &str,
+        key: &str,
+        records: Vec<Insertion>,
+    ) -> Result<(), StoreError> {
+        if self.config.require_keyspace {
+            self.check_keyspace_exists(keyspace)?;
+        }
+        let records = self.transform(records)?;
+        self.flush_buffered(keyspace, key)?;
+        let kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+        self.append_to_key(keyspace, key, kinfo, records, false)
+    }
+    /// add records to the append buffer of the key, writing the buffer once it reaches append_buffer_size_threshold
+    /// or its first record is older than append_buffer_max_age_millis.
+    /// timestamps are assigned when records are buffered, matching what an unbuffered append would have written.
+    fn append_buffered(
+        &self,
+        keyspace: &str,
+        key: &str,
+        mut records: Vec<Insertion>,
+    ) -> Result<(), StoreError> {
+        fill_timestamps(&mut records, || {
+            Ok(self.keyspace_format(keyspace)?.timestamp_unit.now())
+        })?;
+        let buffer_key = (keyspace.to_string(), key.to_string());
+        let size: u64 = records.iter().map(|e| e.record.len() as u64).sum();
+        let now = time_now_as_millis();
+        let full = {
+            let mut buffers = self.append_buffers.borrow_mut();
+            let buffer = buffers.entry(buffer_key).or_insert(AppendBuffer {
+                inserts: Vec::new(),
+                size: 0,
+                created_millis: now,
+            });
+            buffer.inserts.append(&mut records);
+            buffer.size += size;
+            buffer.size >= self.config.append_buffer_size_threshold
+                || self.buffer_expired(buffer, now)
+        };
+        if full {
+            self.flush_buffered(keyspace, key)?;
+        }
+        Ok(())
+    }
+    /// true when the first record of the buffer is older than append_buffer_max_age_millis
+    fn buffer_expired(&self, buffer: &AppendBuffer, now: u64) -> bool {
+        self.config.append_buffer_max_age_millis > 0
+            && now >= buffer.created_millis + self.config.append_buffer_max_age_millis
+    }
+    /// write the buffered records of a key as a single object.
+    /// the buffer is taken before writing, so records of a failed write are dropped like those of a failed unbuffered append.
+    fn flush_buffered(&self, keyspace: &str, key: &str) -> Result<(), StoreError> {
+        let buffer = self
+            .append_buffers
+            .borrow_mut()
+            .remove(&(keyspace.to_string(), key.to_string()));
+        match buffer {
+            None => Ok(()),
+            Some(buffer) => {
+                let kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+                self.append_to_key(keyspace, key, kinfo, buffer.inserts, true)
+            }
+        }
+    }
+    /// set the next nonce of a key, for a producer that restarted its nonce sequence in a new epoch.
+    /// appends are otherwise deduplicated against the highest nonce ever written, so the new sequence would be dropped.
+    /// buffered appends of the key are written first, since they were accepted under the old sequence.
+    /// the reset is stored beside the data objects and applies until the next object is written, which then carries the new sequence.
+    /// after a reset, records of the old sequence with nonces at or above new_next_nonce are no longer rejected as duplicates,
+    /// and nonces no longer increase with offset, so StartPosition::Nonce reads across the reset are undefined.
+    /// requires nonce_resets on this writer and on every reader and writer of the key, failing with BadConfiguration otherwise.
+    pub fn reset_nonce(
+        &self,
+        keyspace: &str,
+        key: &str,
+        new_next_nonce: u128,
+    ) -> Result<(), StoreError> {
+        if !self.config.nonce_resets {
+            return Err(StoreError::BadConfiguration(
+                "reset_nonce requires nonce_resets".to_string(),
+            ));
+        }
+        self.keyspace_format(keyspace)?
+            .layout
+            .check_nonce(Some(new_next_nonce))?;
+        self.flush_buffered(keyspace, key)?;
+        let mut kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+        let nonce_reset = NonceReset {
+            next_offset: kinfo.metadata.next_offset,
+            next_nonce: new_next_nonce,
+        };
+        put_object(
+            &self.bucket,
+            NonceReset::path(
+                &self.config.object_prefix,
+                keyspace,
+                &self.path_key(keyspace, key),
+            ),
+            &nonce_reset.serialize(),
+        )?;
+        kinfo.metadata.next_nonce = new_next_nonce;
+        self.write_cache.set_key(keyspace, key, kinfo);
         Ok(())
     }
+    /// write an appended data object, failing with Conflict if another writer already wrote any object at or after first_offset.
+    /// the LIST catches writers that appended before this one, but another writer may append between the LIST and the PUT.
+    /// two writers appending at the same offset are caught by first creating a marker for first_offset with If-None-Match,
+    /// since their object paths differ whenever their records do. the data object is only written by the writer that created the marker,
+    /// or by an append of the same records finding the marker of an earlier attempt. the marker is deleted when the data PUT fails.
+    /// takes the key as it appears in object paths.
+    fn put_data_object_if_absent(
+        &self,
+        keyspace: &str,
+        path_key: &str,
+        first_offset: u64,
+        object_key: String,
+        buffer: &[u8],
+    ) -> Result<(), StoreError> {
+        let data_prefix = KeyPath::prefix_data_only(&self.config.object_prefix, keyspace, path_key);
+        let start_from = KeyPath::after_offset_prefix(
+            &self.config.object_prefix,
+            keyspace,
+            path_key,
+            first_offset - 1,
+        );
+        let (list, _) = list_page(&self.bucket, &data_prefix, Some(start_from), None, Some(1))?;
+        if let Some(existing) = list.first() {
+            return Err(StoreError::Conflict(format!(
+                "{} already exists at or after offset {}",
+                existing, first_offset
+            )));
+        }
+        let lock_path =
+            KeyPath::offset_lock_path(&self.config.object_prefix, keyspace, path_key, first_offset);
+        // the marker holds the path of the object it guards, so an append of the same records recognizes it as its own
+        match put_object_if_absent(&self.bucket, lock_path.clone(), object_key.as_bytes())? {
+            ConditionalPut::Created => {}
+            ConditionalPut::AlreadyExists => {
+                match get_object_optional(&self.bucket, lock_path.clone())? {
+                    Some(ref existing) if existing.as_slice() == object_key.as_bytes() => {}
+                    _ => {
+                        return Err(StoreError::Conflict(format!(
+                            "{} already exists",
+                            lock_path
+                        )))
+                    }
+                }
+            }
+            ConditionalPut::Unsupported => {
+                return Err(StoreError::BadConfiguration(
+                    "conditional_writes is not supported by the backend".to_string(),
+                ))
+            }
+        }
+        match put_object_with_storage_class(
+            &self.bucket,
+            object_key,
+            buffer,
+            &self.config.storage_class,
+        ) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                // without the data object, the marker would fail every later append at first_offset
+                if let Err(delete_err) = delete_object(&self.bucket, lock_path.clone()) {
+                    log::warn!(
+                        "could not delete {} after a failed append: {}",
+                        lock_path,
+                        delete_err.to_string()
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+    /// the key as it appears in object paths, prefixed by its shard when key_prefix_sharding is enabled
+    fn path_key(&self, keyspace: &str, key: &str) -> String {
+        key_path_segment(keyspace, key, self.config.key_prefix_sharding)
+    }
+    /// create a new keyspace whose record timestamps are in the given unit.
+    /// the unit applies to every key in the keyspace and cannot be changed once records are written.
+    /// the config is written with If-None-Match, so only one of several concurrent creators succeeds.
+    pub fn create_keyspace_with_timestamp_unit(
+        &self,
+        keyspace: &str,
+        timestamp_unit: TimestampUnit,
+    ) -> Result<CreatedKeyspace, StoreError> {
+        self.create_keyspace_with_format(keyspace, timestamp_unit, RecordLayout::new())
+    }
+    /// create a new keyspace whose data objects are written in the given record layout, such as
+    /// RecordLayout::new().set_forward_only(true), which omits the trailing total length of every record.
+    /// backward reads of a forward-only keyspace fail with BadConfiguration.
+    pub fn create_keyspace_with_format(
+        &self,
+        keyspace: &str,
+        timestamp_unit: TimestampUnit,
+        layout: RecordLayout,
+    ) -> Result<CreatedKeyspace, StoreError> {
+        put_keyspace_config(
+            &self.bucket,
+            &self.config.object_prefix,
+            keyspace,
+            timestamp_unit,
+            layout,
+        )?;
+        self.known_keyspaces
+            .borrow_mut()
+            .insert(keyspace.to_string());
+        self.keyspace_formats.borrow_mut().insert(
+            keyspace.to_string(),
+            KeyspaceFormat {
+                timestamp_unit,
+                layout,
+            },
+        );
+        Ok(CreatedKeyspace {
+            keyspace: keyspace.to_string(),
+        })
+    }
+    /// create every keyspace that does not exist yet with millisecond timestamps, treating KeyspaceAlreadyExists as success.
+    /// configs are written by up to ENSURE_KEYSPACES_PARALLELISM threads at once, so many keyspaces are provisioned quickly.
+    /// returns the keyspaces created by this call, or the first error after every keyspace was attempted.
+    pub fn ensure_keyspaces(&self, keyspaces: &[&str]) -> Result<Vec<CreatedKeyspace>, StoreError> {
+        let mut pending: Vec<&str> = Vec::new();
+        for keyspace in keyspaces {
+            if !self.known_keyspaces.borrow().contains(*keyspace) && !pending.contains(keyspace) {
+                pending.push(keyspace);
+            }
+        }
+        let bucket = &self.bucket;
+        let object_prefix = &self.config.object_prefix;
+        let mut created: Vec<CreatedKeyspace> = Vec::new();
+        let mut result = Ok(());
+        for chunk in pending.chunks(ENSURE_KEYSPACES_PARALLELISM) {
+            let outcomes: Vec<Result<(), StoreError>> = thread::scope(|scope| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|keyspace| {
+                        scope.spawn(move || {
+                            put_keyspace_config(
+                                bucket,
+                                object_prefix,
+                                keyspace,
+                                TimestampUnit::Millis,
+                                RecordLayout::new(),
+                            )
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("keyspace creation panicked"))
+                    .collect()
+            });
+            for (keyspace, outcome) in chunk.iter().zip(outcomes) {
+                match outcome {
+                    Ok(_) => {
+                        // the format is only known for keyspaces created here
+                        self.keyspace_formats.borrow_mut().insert(
+                            keyspace.to_string(),
+                            KeyspaceFormat {
+                                timestamp_unit: TimestampUnit::Millis,
+                                layout: RecordLayout::new(),
+                            },
+                        );
+                        created.push(CreatedKeyspace {
+                            keyspace: keyspace.to_string(),
+                        });
+                    }
+                    Err(StoreError::KeyspaceAlreadyExists) => {}
+                    Err(err) => {
+                        log::error!(
+                            "could not create keyspace {}: {}",
+                            keyspace,
+                            err.to_string()
+                        );
+                        if result.is_ok() {
+                            result = Err(err);
+                        }
+                        continue;
+                    }
+                }
+                self.known_keyspaces
+                    .borrow_mut()
+                    .insert(keyspace.to_string());
+            }
+        }
+        result.map(|_| created)
+    }
+    /// timestamp unit and record layout of the keyspace, which are milliseconds and the first format for keyspaces that were never created.
+    /// remembered once read, since the format of a keyspace never changes once it was created.
+    fn keyspace_format(&self, keyspace: &str) -> Result<KeyspaceFormat, StoreError> {
+        if let Some(v) = self.keyspace_formats.borrow().get(keyspace) {
+            return Ok(*v);
+        }
+        let keyspace_config = KeyspacePath::config_path(&self.config.object_prefix, &keyspace);
+        let format = match get_object_optional(&self.bucket, keyspace_config)? {
+            None => {
+                // not remembered, since the keyspace may still be created with another format
+                return Ok(KeyspaceFormat {
+                    timestamp_unit: TimestampUnit::Millis,
+                    layout: RecordLayout::new(),
+                });
+            }
+            Some(contents) => {
+                let ini_string = match std::str::from_utf8(&contents) {
+                    Ok(v) => v,
+                    Err(err) => return Err(StoreError::IOError(err.to_string())),
+                };
+                let metadata = match ini::Ini::load_from_str(ini_string) {
+                    Ok(ini) => keyspace_metadata_from_ini(&ini)?,
+                    Err(_) => {
+                        return Err(StoreError::IOError(format!(
+                            "could not load config for keyspace {}",
+                            keyspace
+                        )))
+                    }
+                };
+                KeyspaceFormat {
+                    timestamp_unit: metadata.timestamp_unit,
+                    layout: RecordLayout::for_keyspace(&metadata)?,
+                }
+            }
+        };
+        self.keyspace_formats
+            .borrow_mut()
+            .insert(keyspace.to_string(), format);
+        Ok(format)
+    }
+    /// return KeyspaceNotFound if the keyspace config does not exist.
+    /// only existing keyspaces are remembered, so a keyspace created elsewhere is found on the next check.
+    fn check_keyspace_exists(&self, keyspace: &str) -> Result<(), StoreError> {
+        if self.known_keyspaces.borrow().contains(keyspace) {
+            return Ok(());
+        }
+        let keyspace_config = KeyspacePath::config_path(&self.config.object_prefix, &keyspace);
+        match get_object_optional(&self.bucket, keyspace_config)? {
+            None => Err(StoreError::KeyspaceNotFound),
+            Some(_) => {
+                self.known_keyspaces
+                    .borrow_mut()
+                    .insert(keyspace.to_string());
+                Ok(())
+            }
+        }
+    }
+}
+impl Clone for S3StoreWriter {
+    fn clone(&self) -> Self {
+        let mut writer = Self::with_bucket(self.config.clone(), self.bucket.clone());
+        writer.record_transform = self.record_transform.clone();
+        writer.event_listener = self.event_listener.clone();
+        writer
+    }
+}
+impl Drop for S3StoreWriter {
+    /// best-effort write of append buffers when the writer is dropped, so buffered records are not silently lost.
+    /// drop cannot return an error, so a failed write is only logged.
+    fn drop(&mut self) {
+        if self.append_buffers.borrow().is_empty() {
+            return;
+        }
+        if let Err(err) = self.flush_all() {
+            log::error!("flush on drop of writer failed: {}", err.to_string());
+        }
+    }
+}
+impl StoreWriter for S3StoreWriter {
+    fn create_keyspace(&self, keyspace: &str) -> Result<CreatedKeyspace, StoreError> {
+        self.create_keyspace_with_timestamp_unit(keyspace, TimestampUnit::Millis)
+    }
+    fn append(&self, keyspace: &str, key: &str, records: Vec<Insertion>) -> Result<(), StoreError> {
+        if self.config.require_keyspace {
+            self.check_keyspace_exists(keyspace)?;
+        }
+        // transformed before buffering, so a rejected record only fails its own append
+        let records = self.transform(records)?;
+        if self.config.append_buffer_size_threshold > 0 {
+            return self.append_buffered(keyspace, key, records);
+        }
+        let kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+        self.append_to_key(keyspace, key, kinfo, records, true)
+    }
+    fn flush_key(&self, keyspace: &str, key: &str) -> Result<(), StoreError> {
+        self.flush_buffered(keyspace, key)
+    }
+    fn flush_all(&self) -> Result<(), StoreError> {
+        let buffered: Vec<(String, String)> =
+            self.append_buffers.borrow().keys().cloned().collect();
+        let mut result = Ok(());
+        for (keyspace, key) in buffered {
+            // keep flushing other keys, returning the first failure
+            if let Err(err) = self.flush_buffered(&keyspace, &key) {
+                log::error!("flush of {}/{} failed: {}", keyspace, key, err.to_string());
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+    /// flush every buffered key like flush_all, reporting each flushed key with its next offset, or its error.
+    /// appends that were not buffered are written before append returns, so only buffered keys are reported.
+    fn flush_all_with_report(&self) -> Result<FlushReport, StoreError> {
+        let buffered: Vec<(String, String)> =
+            self.append_buffers.borrow().keys().cloned().collect();
+        let mut report = FlushReport {
+            flushed: Vec::new(),
+            failed: Vec::new(),
+        };
+        for (keyspace, key) in buffered {
+            let result = match self.flush_buffered(&keyspace, &key) {
+                Ok(_) => self.next_offset(&keyspace, &key),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(next_offset) => report.flushed.push(FlushedKey {
+                    keyspace,
+                    key,
+                    next_offset,
+                }),
+                Err(error) => {
+                    log::error!(
+                        "flush of {}/{} failed: {}",
+                        keyspace,
+                        key,
+                        error.to_string()
+                    );
+                    report.failed.push(FailedKey {
+                        keyspace,
+                        key,
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+    /// write append buffers whose first record is older than append_buffer_max_age_millis, returning the first failure
+    fn duty_cycle(&self) -> Result<(), StoreError> {
+        let now = time_now_as_millis();
+        let expired: Vec<(String, String)> = self
+            .append_buffers
+            .borrow()
+            .iter()
+            .filter(|(_, buffer)| self.buffer_expired(buffer, now))
+            .map(|(buffer_key, _)| buffer_key.clone())
+            .collect();
+        let mut result = Ok(());
+        for (keyspace, key) in expired {
+            // keep flushing other keys, returning the first failure
+            if let Err(err) = self.flush_buffered(&keyspace, &key) {
+                log::error!("flush of {}/{} failed: {}", keyspace, key, err.to_string());
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+    fn timestamp_now(&self, keyspace: &str) -> Result<i64, StoreError> {
+        Ok(self.keyspace_format(keyspace)?.timestamp_unit.now())
+    }
+    fn next_offset(&self, keyspace: &str, key: &str) -> Result<Option<u64>, StoreError> {
+        let kinfo = self.write_cache.get_or_read_key(&keyspace, &key)?;
+        Ok(Some(kinfo.metadata.next_offset))
+    }
 }
 
-pub fn check_compaction(
-    key_data: CachedKey,
-    bucket: &Bucket,
+pub fn plan_compaction(
+    key_data: &CachedKey,
+    bucket: &S3Bucket,
     root_prefix: &str,
     keyspace: &str,
     key: &str,
@@ -135,13 +1341,20 @@ pub fn check_compaction(
     compact_records_threshold: u64,
     compact_size_threshold: u64,
     compact_objects_threshold: u64,
-) -> Result<CachedKey, StoreError> {
+    compact_target_size: u64,
+) -> Result<CompactionPlan, StoreError> {
     if key_data.uncompacted_records < compact_records_threshold
         && key_data.uncompacted_objects < compact_objects_threshold
         && key_data.uncompacted_size < compact_size_threshold
     {
         // nothing to do
-        return Ok(key_data);
+        return Ok(CompactionPlan {
+            triggered: false,
+            groups: Vec::new(),
+            compacted_sizes: Vec::new(),
+            advance_watermark: false,
+            seal_watermark: false,
+        });
     }
 
     // only advance watermark if size or record count is surpassed
@@ -153,88 +1366,590 @@ pub fn check_compaction(
     let key_data_prefix = KeyPath::prefix_data_only(root_prefix, keyspace, key);
     let start_from =
         KeyPath::after_watermark_prefix(root_prefix, keyspace, key, &key_data.watermark);
-    let objects_to_merge = list_exhaustive(bucket, &key_data_prefix, Some(start_from))?;
+    let objects = list_exhaustive(bucket, &key_data_prefix, Some(start_from))?;
+
+    // split into groups that each produce an object of at most compact_target_size
+    // a single object larger than the target is kept as its own group
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut compacted_sizes: Vec<u64> = Vec::new();
+    for obj_path in objects {
+        let size = key_path_parser.parse_or_error(&obj_path)?.size;
+        let start_new_group = match compacted_sizes.last() {
+            None => true,
+            Some(group_size) => group_size.saturating_add(size) > compact_target_size,
+        };
+        if start_new_group {
+            groups.push(vec![obj_path]);
+            compacted_sizes.push(size);
+        } else {
+            groups.last_mut().unwrap().push(obj_path);
+            *compacted_sizes.last_mut().unwrap() += size;
+        }
+    }
+
+    // an object count compaction alone would re-read the growing merged object every time until the
+    // record or size threshold is reached. when compact_target_size splits the merge, all but the last
+    // group are already at the target size, so seal them behind the watermark to bound re-reads.
+    // a single object below the record and size thresholds is not a complete batch, so it is left in place
+    // without advancing the watermark, the same as a merge that does not reach those thresholds
+    let seal_watermark = !advance_watermark && groups.len() > 1;
+
+    Ok(CompactionPlan {
+        triggered: true,
+        advance_watermark,
+        seal_watermark,
+        groups,
+        compacted_sizes,
+    })
+}
+
+pub fn check_compaction(
+    key_data: CachedKey,
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    key_path_parser: &KeyPathParser,
+    layout: RecordLayout,
+    compact_records_threshold: u64,
+    compact_size_threshold: u64,
+    compact_objects_threshold: u64,
+    compact_target_size: u64,
+    write_key_index: bool,
+    write_manifest: bool,
+    delete_offset_markers: bool,
+    compaction_lease_ttl_millis: u64,
+    compacted_storage_class: &Option<String>,
+) -> Result<CachedKey, StoreError> {
+    let plan = plan_compaction(
+        &key_data,
+        bucket,
+        root_prefix,
+        keyspace,
+        key,
+        key_path_parser,
+        compact_records_threshold,
+        compact_size_threshold,
+        compact_objects_threshold,
+        compact_target_size,
+    )?;
+    if !plan.triggered {
+        // nothing to do
+        return Ok(key_data);
+    }
+    if compaction_lease_ttl_millis == 0 {
+        return apply_compaction_plan(
+            plan,
+            key_data,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            key_path_parser,
+            layout,
+            write_key_index,
+            write_manifest,
+            delete_offset_markers,
+            compacted_storage_class,
+        );
+    }
+
+    // another compactor holds the key, leave counters as they are so a later append checks again
+    let lease = match acquire_compaction_lease(
+        bucket,
+        root_prefix,
+        keyspace,
+        key,
+        compaction_lease_ttl_millis,
+    )? {
+        None => {
+            log::debug!(
+                "skipping compaction of {}/{}, another compactor holds the lease",
+                keyspace,
+                key
+            );
+            return Ok(key_data);
+        }
+        Some(v) => v,
+    };
+    // plan again while holding the lease, since another compactor may have merged objects since the first plan
+    let result = match plan_compaction(
+        &key_data,
+        bucket,
+        root_prefix,
+        keyspace,
+        key,
+        key_path_parser,
+        compact_records_threshold,
+        compact_size_threshold,
+        compact_objects_threshold,
+        compact_target_size,
+    ) {
+        Ok(plan) if plan.triggered => apply_compaction_plan(
+            plan,
+            key_data,
+            bucket,
+            root_prefix,
+            keyspace,
+            key,
+            key_path_parser,
+            layout,
+            write_key_index,
+            write_manifest,
+            delete_offset_markers,
+            compacted_storage_class,
+        ),
+        Ok(_) => Ok(key_data),
+        Err(err) => Err(err),
+    };
+    // the lease expires on its own, so failing to release it must not fail a completed compaction
+    if let Err(err) = release_compaction_lease(bucket, root_prefix, keyspace, key, &lease) {
+        log::warn!(
+            "failed to release compaction lease of {}/{}, it expires at {}: {}",
+            keyspace,
+            key,
+            lease.expires_millis,
+            err.to_string()
+        );
+    }
+    result
+}
+
+/// take the compaction lease of a key with a conditional PUT, returning None if another compactor holds it.
+/// a lease already holding our owner token is our own PUT that succeeded without a response, so it is acquired.
+/// an expired lease left by a compactor that crashed is deleted only if unchanged, so a later check can acquire it
+/// without deleting a lease another compactor took in the meantime.
+fn acquire_compaction_lease(
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    ttl_millis: u64,
+) -> Result<Option<CompactionLease>, StoreError> {
+    let path = CompactionLease::path(root_prefix, keyspace, key);
+    let now = time_now_as_millis();
+    let lease = CompactionLease {
+        expires_millis: now + ttl_millis,
+        owner: lease_owner_token(),
+    };
+    match put_object_if_absent(bucket, path.clone(), &lease.serialize())? {
+        ConditionalPut::Created => Ok(Some(lease)),
+        ConditionalPut::AlreadyExists => {
+            let (contents, etag) = match get_object_optional_with_etag(bucket, path.clone())? {
+                None => return Ok(None),
+                Some(v) => v,
+            };
+            let held = CompactionLease::from(&contents)?;
+            if held.owner == lease.owner {
+                return Ok(Some(held));
+            }
+            if held.expires_millis < now {
+                log::warn!(
+                    "deleting compaction lease of {}/{} that expired at {}",
+                    keyspace,
+                    key,
+                    held.expires_millis
+                );
+                match etag {
+                    Some(etag) => {
+                        delete_object_if_match(bucket, path, &etag)?;
+                    }
+                    None => delete_object(bucket, path)?,
+                }
+            }
+            Ok(None)
+        }
+        ConditionalPut::Unsupported => Err(StoreError::BadConfiguration(
+            "compaction_lease_ttl_millis requires a backend supporting If-None-Match".to_string(),
+        )),
+    }
+}
+
+/// a random token identifying one acquisition of a compaction lease
+fn lease_owner_token() -> u64 {
+    // every RandomState is seeded with fresh random keys
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(time_now_as_millis());
+    hasher.finish()
+}
+
+/// delete the compaction lease if it is still ours and unchanged, which it is not when compaction outlived the ttl
+fn release_compaction_lease(
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    lease: &CompactionLease,
+) -> Result<(), StoreError> {
+    let path = CompactionLease::path(root_prefix, keyspace, key);
+    let (held, etag) = match get_object_optional_with_etag(bucket, path.clone())? {
+        None => (None, None),
+        Some((contents, etag)) => (Some(CompactionLease::from(&contents)?), etag),
+    };
+    let released = match (held, etag) {
+        (Some(ref held), Some(etag)) if held.owner == lease.owner => {
+            delete_object_if_match(bucket, path, &etag)?
+        }
+        (Some(ref held), None) if held.owner == lease.owner => {
+            delete_object(bucket, path)?;
+            true
+        }
+        _ => false,
+    };
+    if !released {
+        log::warn!(
+            "compaction lease of {}/{} expired before compaction completed",
+            keyspace,
+            key
+        );
+    }
+    Ok(())
+}
 
-    let first_key = key_path_parser
-        .parse_or_error(&objects_to_merge.first().expect("objects_to_merge empty"))?;
-    let last_key = key_path_parser
-        .parse_or_error(&objects_to_merge.last().expect("objects_to_merge empty"))?;
+/// merge the objects of a triggered compaction plan and advance the watermark as planned
+fn apply_compaction_plan(
+    plan: CompactionPlan,
+    key_data: CachedKey,
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    key_path_parser: &KeyPathParser,
+    layout: RecordLayout,
+    write_key_index: bool,
+    write_manifest: bool,
+    delete_offset_markers: bool,
+    compacted_storage_class: &Option<String>,
+) -> Result<CachedKey, StoreError> {
+    let advance_watermark = plan.advance_watermark;
 
-    if objects_to_merge.len() == 1 {
+    if plan.groups.is_empty() {
+        // nothing after the watermark, such as when another compactor already merged and advanced it
+        return Ok(CachedKey {
+            uncompacted_records: 0,
+            uncompacted_objects: 0,
+            uncompacted_size: 0,
+            ..key_data
+        });
+    }
+
+    if plan.groups.len() == 1 && plan.groups[0].len() == 1 {
         // compacting one object is meaningless, return now to avoid deleting self
-        // this will only happen for cases where a watermark would be advanced
+        if !advance_watermark {
+            // below the record and size thresholds, the object keeps accumulating like a merged object
+            return Ok(CachedKey {
+                uncompacted_objects: 1,
+                ..key_data
+            });
+        }
+        // the object alone reached a threshold, so it is an entire batch
+        let first_key = key_path_parser.parse_or_error(&plan.groups[0][0])?;
         let new_watermark = Watermark::new(first_key.first_offset);
         put_object(
             bucket,
             Watermark::path(root_prefix, keyspace, key),
             &new_watermark.serialize(),
         )?;
-        // return this object as an entire batch
+        if write_key_index {
+            update_key_index(
+                bucket,
+                root_prefix,
+                keyspace,
+                key,
+                key_path_parser,
+                &new_watermark,
+            )?;
+        }
         return Ok(CachedKey {
             metadata: key_data.metadata,
             uncompacted_records: 0,
             uncompacted_objects: 0,
             uncompacted_size: 0,
             prior_start_offset: key_data.prior_start_offset,
-            watermark: Watermark::new(first_key.first_offset),
+            watermark: new_watermark,
+            max_timestamp: key_data.max_timestamp,
         });
     }
 
-    // append all buffers
-    let mut buffer: Vec<u8> = Vec::new();
-    for obj_path in &objects_to_merge {
-        let mut contents = get_object_required(bucket, obj_path.clone())?;
-        buffer.append(&mut contents);
-    }
+    // write one new object per group, each linked to the start of the object before it
+    let mut objects_to_delete: Vec<String> = Vec::new();
+    let mut manifest_segments: Vec<ManifestSegment> = Vec::new();
+    let mut prior_start_offset: Option<u64> = None;
+    let mut last_group_start_offset: u64 = 0;
+    let mut sealed_start_offset: u64 = 0;
+    let mut last_group_records: u64 = 0;
+    for group in plan.groups.iter() {
+        let first_key =
+            key_path_parser.parse_or_error(&group.first().expect("compaction group empty"))?;
+        let last_key =
+            key_path_parser.parse_or_error(&group.last().expect("compaction group empty"))?;
+        // timestamps are not required to be monotonic, so consider every object for the range
+        let mut min_timestamp = i64::MAX;
+        let mut max_timestamp = i64::MIN;
+        for obj_path in group {
+            let obj_key = key_path_parser.parse_or_error(obj_path)?;
+            min_timestamp = std::cmp::min(min_timestamp, obj_key.min_timestamp);
+            max_timestamp = std::cmp::max(max_timestamp, obj_key.max_timestamp);
+        }
+        let key_path = KeyPath {
+            first_offset: first_key.first_offset,
+            last_offset: last_key.last_offset,
+            min_timestamp,
+            max_timestamp,
+            first_nonce: first_key.first_nonce,
+            next_nonce: last_key.next_nonce,
+            size: 0,
+            prior_start_offset: match prior_start_offset {
+                None => first_key.prior_start_offset,
+                Some(v) => v,
+            },
+        };
+        prior_start_offset = Some(key_path.first_offset);
+        sealed_start_offset = last_group_start_offset;
+        last_group_start_offset = key_path.first_offset;
+        last_group_records = key_path.last_offset - key_path.first_offset + 1;
 
-    // write new object
-    let key_path = KeyPath {
-        first_offset: first_key.first_offset,
-        last_offset: last_key.last_offset,
-        min_timestamp: first_key.min_timestamp,
-        max_timestamp: last_key.max_timestamp,
-        first_nonce: first_key.first_nonce,
-        next_nonce: last_key.next_nonce,
-        size: buffer.len() as u64,
-        prior_start_offset: first_key.prior_start_offset,
-    };
-    put_object(
-        bucket,
-        key_path.to_path(&root_prefix, &keyspace, &key),
-        &buffer,
-    )?;
+        if group.len() == 1 && key_path.prior_start_offset == first_key.prior_start_offset {
+            // already a compacted object with correct linking, leave it in place
+            manifest_segments.push(ManifestSegment {
+                first_offset: first_key.first_offset,
+                last_offset: first_key.last_offset,
+                record_count: last_group_records,
+                size: first_key.size,
+            });
+            continue;
+        }
+
+        // append all buffers
+        let mut buffer: Vec<u8> = Vec::new();
+        for obj_path in group {
+            let mut contents = get_object_required(bucket, obj_path.clone())?;
+            buffer.append(&mut contents);
+        }
+
+        // every offset of the group must be present before the source objects are deleted
+        let record_count = count_records(&buffer, layout)?;
+        if record_count != last_group_records {
+            return Err(StoreError::BadData(format!(
+                "compacted object for offsets {}-{} has {} records",
+                key_path.first_offset, key_path.last_offset, record_count
+            )));
+        }
 
-    // delete old objects
-    for obj_path in objects_to_merge {
-        delete_object(bucket, obj_path)?;
+        // write new object
+        let key_path = KeyPath {
+            size: buffer.len() as u64,
+            ..key_path
+        };
+        manifest_segments.push(ManifestSegment {
+            first_offset: key_path.first_offset,
+            last_offset: key_path.last_offset,
+            record_count,
+            size: key_path.size,
+        });
+        put_object_with_storage_class(
+            bucket,
+            key_path.to_path(&root_prefix, &keyspace, &key),
+            &buffer,
+            compacted_storage_class,
+        )?;
+        objects_to_delete.extend(group.iter().cloned());
+        if delete_offset_markers {
+            // the merged object is found by the LIST of a conditional append, so the markers of its objects are no longer needed
+            for obj_path in group {
+                let obj_key = key_path_parser.parse_or_error(obj_path)?;
+                objects_to_delete.push(KeyPath::offset_lock_path(
+                    root_prefix,
+                    keyspace,
+                    key,
+                    obj_key.first_offset,
+                ));
+            }
+        }
+    }
+
+    if write_manifest {
+        // record compacted objects before deleting, so a deletion that removes too much is caught by readers
+        update_compaction_manifest(bucket, root_prefix, keyspace, key, manifest_segments)?;
     }
 
+    // delete old objects only after all new objects exist
+    delete_objects(bucket, objects_to_delete)?;
+
     if advance_watermark {
         // write new watermark
-        let new_watermark = Watermark::new(first_key.first_offset);
+        let new_watermark = Watermark::new(last_group_start_offset);
         put_object(
             bucket,
             Watermark::path(root_prefix, keyspace, key),
             &new_watermark.serialize(),
         )?;
+        if write_key_index {
+            update_key_index(
+                bucket,
+                root_prefix,
+                keyspace,
+                key,
+                key_path_parser,
+                &new_watermark,
+            )?;
+        }
         // reset cache info
         return Ok(CachedKey {
             metadata: key_data.metadata,
             uncompacted_records: 0,
             uncompacted_objects: 0,
             uncompacted_size: 0,
-            prior_start_offset: first_key.first_offset,
+            prior_start_offset: last_group_start_offset,
+            watermark: new_watermark,
+            max_timestamp: key_data.max_timestamp,
+        });
+    } else if plan.seal_watermark {
+        // object count compaction, seal every group before the last one behind the watermark
+        let new_watermark = Watermark::new(sealed_start_offset);
+        put_object(
+            bucket,
+            Watermark::path(root_prefix, keyspace, key),
+            &new_watermark.serialize(),
+        )?;
+        if write_key_index {
+            update_key_index(
+                bucket,
+                root_prefix,
+                keyspace,
+                key,
+                key_path_parser,
+                &new_watermark,
+            )?;
+        }
+        // only the last group remains uncompacted
+        return Ok(CachedKey {
+            metadata: key_data.metadata,
+            uncompacted_records: last_group_records,
+            uncompacted_objects: 1,
+            uncompacted_size: *plan.compacted_sizes.last().unwrap(),
+            prior_start_offset: last_group_start_offset,
             watermark: new_watermark,
+            max_timestamp: key_data.max_timestamp,
         });
     } else {
-        // only reset uncompacted_objects, do not advance watermark
+        // only reset uncompacted_objects, do not advance watermark.
+        // uncompacted records and size keep accumulating, so the merged object is bounded by those thresholds
         return Ok(CachedKey {
             metadata: key_data.metadata,
             uncompacted_records: key_data.uncompacted_records,
-            uncompacted_objects: 1,
+            uncompacted_objects: plan.groups.len() as u64,
             uncompacted_size: key_data.uncompacted_size,
-            prior_start_offset: first_key.first_offset,
+            prior_start_offset: last_group_start_offset,
             watermark: key_data.watermark,
+            max_timestamp: key_data.max_timestamp,
         });
     }
 }
+
+/// replace the manifest segments rewritten by a compaction
+fn update_compaction_manifest(
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    segments: Vec<ManifestSegment>,
+) -> Result<(), StoreError> {
+    let manifest_path = CompactionManifest::path(root_prefix, keyspace, key);
+    let mut manifest = match get_object_optional(bucket, manifest_path.clone())? {
+        None => CompactionManifest::new(),
+        Some(v) => CompactionManifest::from(&v)?,
+    };
+    manifest.replace_from(segments);
+    put_object(bucket, manifest_path, &manifest.serialize())
+}
+
+/// offset of a marker from KeyPath::offset_lock_path, or None for any other path of the key
+fn marker_offset(key_prefix: &str, path: &str) -> Option<u64> {
+    if !path.starts_with(key_prefix) || !path.ends_with(".lock") {
+        return None;
+    }
+    let name = &path[key_prefix.len()..path.len() - ".lock".len()];
+    if !name.starts_with('o') {
+        return None;
+    }
+    match name[1..].parse::<u64>() {
+        Ok(v) => Some(v),
+        Err(_) => None,
+    }
+}
+
+/// add every object between the last indexed object and the watermark to the key index.
+/// objects at or before the watermark are never compacted again, so existing entries never need rewriting.
+fn update_key_index(
+    bucket: &S3Bucket,
+    root_prefix: &str,
+    keyspace: &str,
+    key: &str,
+    key_path_parser: &KeyPathParser,
+    watermark: &Watermark,
+) -> Result<(), StoreError> {
+    let index_path = KeyIndex::path(root_prefix, keyspace, key);
+    let mut index = match get_object_optional(bucket, index_path.clone())? {
+        None => KeyIndex::new(),
+        Some(v) => KeyIndex::from(&v)?,
+    };
+    let start_from = match index.objects.is_empty() {
+        true => None,
+        false => Some(KeyPath::after_offset_prefix(
+            root_prefix,
+            keyspace,
+            key,
+            index.last_start_offset(),
+        )),
+    };
+    let objects = list_exhaustive(
+        bucket,
+        &KeyPath::prefix_data_only(root_prefix, keyspace, key),
+        start_from,
+    )?;
+    let mut added = false;
+    for obj_path in objects {
+        let obj_key = key_path_parser.parse_or_error(&obj_path)?;
+        if obj_key.first_offset > watermark.offset {
+            break;
+        }
+        index.objects.push(obj_key);
+        added = true;
+    }
+    if added {
+        put_object(bucket, index_path, &index.serialize())?;
+    }
+    Ok(())
+}
+
+/// write the config of a new keyspace, returning KeyspaceAlreadyExists if it is already there.
+/// the config is written with If-None-Match, so only one of several concurrent creators succeeds.
+fn put_keyspace_config(
+    bucket: &S3Bucket,
+    object_prefix: &str,
+    keyspace: &str,
+    timestamp_unit: TimestampUnit,
+    layout: RecordLayout,
+) -> Result<(), StoreError> {
+    let keyspace_config = KeyspacePath::config_path(object_prefix, keyspace);
+    let content = format!(
+        "[keyspace]\ncreated={}\ntimestamp_unit={}\nformat_version={}\nforward_only={}\nu64_nonces={}",
+        time_now_as_millis(),
+        timestamp_unit.name(),
+        RECORD_FORMAT_VERSION,
+        !layout.trailer,
+        layout.u64_nonces
+    );
+    match put_object_if_absent(bucket, keyspace_config.clone(), content.as_bytes())? {
+        ConditionalPut::Created => Ok(()),
+        ConditionalPut::AlreadyExists => Err(StoreError::KeyspaceAlreadyExists),
+        ConditionalPut::Unsupported => {
+            // backend without conditional writes, check then put, which races with concurrent creators
+            if let Some(_) = get_object_optional(bucket, keyspace_config.clone())? {
+                return Err(StoreError::KeyspaceAlreadyExists);
+            }
+            put_object(bucket, keyspace_config, content.as_bytes())
+        }
+    }
+}
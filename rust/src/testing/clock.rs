@@ -0,0 +1,30 @@
+use crate::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A Clock that only moves when told to, for deterministic tests of flush and commit intervals.
+/// Clones share the same time, so a test can keep a clone to advance the clock it gave to a writer.
+#[derive(Clone)]
+pub struct MockClock {
+    millis: Arc<AtomicU64>,
+}
+impl MockClock {
+    pub fn new(millis: u64) -> Self {
+        Self {
+            millis: Arc::new(AtomicU64::new(millis)),
+        }
+    }
+    /// move the clock forward by the given millis
+    pub fn advance(&self, millis: u64) {
+        self.millis.fetch_add(millis, Ordering::SeqCst);
+    }
+    /// set the clock to the given millis since the epoch
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+}
+impl Clock for MockClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,194 @@
+use crate::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+struct MemoryObject {
+    contents: Vec<u8>,
+    etag: String,
+}
+
+struct MemoryState {
+    objects: BTreeMap<String, MemoryObject>,
+    operation_counts: HashMap<String, u64>,
+    next_etag: u64,
+}
+
+/// An in-memory stand-in for an S3 bucket, so readers and writers can be tested without S3.
+/// Objects are listed in lexical order like S3, and conditional PUTs and GETs behave like S3 with If-None-Match support.
+/// Clones share the same objects, so a writer and a reader given the configs of clones see each other's writes.
+#[derive(Clone)]
+pub struct MemoryObjectStore {
+    state: Arc<Mutex<MemoryState>>,
+    list_page_size: usize,
+}
+impl MemoryObjectStore {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MemoryState {
+                objects: BTreeMap::new(),
+                operation_counts: HashMap::new(),
+                next_etag: 1,
+            })),
+            list_page_size: 1000,
+        }
+    }
+    /// max paths returned per LIST page, defaults to 1000 like S3
+    pub fn set_list_page_size(mut self, v: usize) -> Self {
+        self.list_page_size = v;
+        self
+    }
+    /// a config whose readers and writers use this store instead of S3, with a bucket name and static credentials
+    pub fn config(&self) -> S3StoreConfig {
+        S3StoreConfig::new()
+            .set_bucket_name("memory".to_string())
+            .set_endpoint("http://localhost".to_string())
+            .set_use_default_credentials(false)
+            .set_access_key("memory".to_string())
+            .set_secret_key("memory".to_string())
+            .set_memory_store(self.clone())
+    }
+    /// every object path, in listing order
+    pub fn paths(&self) -> Vec<String> {
+        self.state.lock().unwrap().objects.keys().cloned().collect()
+    }
+    /// contents of an object, without counting an operation
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        match self.state.lock().unwrap().objects.get(path) {
+            None => None,
+            Some(object) => Some(object.contents.clone()),
+        }
+    }
+    /// write an object, without counting an operation
+    pub fn put(&self, path: &str, contents: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let etag = format!("\"{}\"", state.next_etag);
+        state.next_etag += 1;
+        state
+            .objects
+            .insert(path.to_string(), MemoryObject { contents, etag });
+    }
+    /// remove an object, without counting an operation, as if removed by another process. returns false if it did not exist.
+    pub fn remove(&self, path: &str) -> bool {
+        self.state.lock().unwrap().objects.remove(path).is_some()
+    }
+    /// number of times the named bucket operation ran against this store, such as get_object or list_page
+    pub fn operation_count(&self, operation: &str) -> u64 {
+        match self.state.lock().unwrap().operation_counts.get(operation) {
+            None => 0,
+            Some(v) => *v,
+        }
+    }
+    /// reset every operation count to 0
+    pub fn reset_operation_counts(&self) {
+        self.state.lock().unwrap().operation_counts.clear();
+    }
+    fn count(&self, state: &mut MemoryState, operation: &str) {
+        *state
+            .operation_counts
+            .entry(operation.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// a page of paths under the prefix after start_after, continuing after the continuation token, which is the last path of the previous page
+    pub(crate) fn list_page(
+        &self,
+        prefix: &str,
+        start_after: Option<String>,
+        continuation: Option<String>,
+        max_results: Option<usize>,
+    ) -> (Vec<String>, Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        self.count(&mut state, "list_page");
+        let after = match (continuation, start_after) {
+            (Some(v), _) => Some(v),
+            (None, v) => v,
+        };
+        let limit = match max_results {
+            None => self.list_page_size,
+            Some(v) => std::cmp::min(v, self.list_page_size),
+        };
+        let mut paths: Vec<String> = Vec::new();
+        let mut truncated = false;
+        for path in state.objects.keys() {
+            if !path.starts_with(prefix) {
+                continue;
+            }
+            if let Some(after) = &after {
+                if path <= after {
+                    continue;
+                }
+            }
+            if paths.len() == limit {
+                truncated = true;
+                break;
+            }
+            paths.push(path.clone());
+        }
+        let next = match truncated {
+            true => paths.last().cloned(),
+            false => None,
+        };
+        (paths, next)
+    }
+    /// contents and ETag of an object
+    pub(crate) fn get_object(&self, operation: &str, path: &str) -> Option<(Vec<u8>, String)> {
+        let mut state = self.state.lock().unwrap();
+        self.count(&mut state, operation);
+        match state.objects.get(path) {
+            None => None,
+            Some(object) => Some((object.contents.clone(), object.etag.clone())),
+        }
+    }
+    /// write an object, or only when nothing exists at the path when if_absent is set, returning false if it was not written
+    pub(crate) fn put_object(&self, path: &str, contents: &[u8], if_absent: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.count(&mut state, "put_object");
+        if if_absent && state.objects.contains_key(path) {
+            return false;
+        }
+        let etag = format!("\"{}\"", state.next_etag);
+        state.next_etag += 1;
+        state.objects.insert(
+            path.to_string(),
+            MemoryObject {
+                contents: contents.to_vec(),
+                etag,
+            },
+        );
+        true
+    }
+    /// delete an object, or only when its ETag matches when if_match is given, returning false if it was not deleted
+    pub(crate) fn delete_object(
+        &self,
+        operation: &str,
+        path: &str,
+        if_match: Option<&str>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.count(&mut state, operation);
+        if let Some(etag) = if_match {
+            match state.objects.get(path) {
+                Some(object) if object.etag == etag => {}
+                _ => return false,
+            }
+        }
+        state.objects.remove(path).is_some()
+    }
+    /// delete every object of a multi-object delete, counted as one operation like the single S3 request
+    pub(crate) fn delete_objects(&self, paths: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        self.count(&mut state, "delete_objects");
+        for path in paths {
+            state.objects.remove(path);
+        }
+    }
+}
+impl std::fmt::Debug for MemoryObjectStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "MemoryObjectStore({} objects)",
+            self.state.lock().unwrap().objects.len()
+        )
+    }
+}
@@ -0,0 +1,8 @@
+mod clock;
+mod memory;
+mod recording;
+
+pub type RecordingStoreWriter = recording::RecordingStoreWriter;
+pub type RecordedCall = recording::RecordedCall;
+pub type MockClock = clock::MockClock;
+pub type MemoryObjectStore = memory::MemoryObjectStore;
@@ -0,0 +1,99 @@
+use crate::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A call made to a RecordingStoreWriter, in the order it was made
+#[derive(Debug)]
+pub enum RecordedCall {
+    CreateKeyspace {
+        keyspace: String,
+    },
+    Append {
+        keyspace: String,
+        key: String,
+        inserts: Vec<Insertion>,
+    },
+    FlushKey {
+        keyspace: String,
+        key: String,
+    },
+    FlushAll,
+    DutyCycle,
+}
+
+struct RecordingState {
+    calls: Vec<RecordedCall>,
+    call_count: u64,
+    injected_errors: HashMap<u64, StoreError>,
+}
+
+/// A StoreWriter that records every call in memory instead of writing anything, for testing code built on a StoreWriter.
+/// Clones share the same recorded calls and injected errors, so it can be wrapped by a BatchingStoreWriter.
+#[derive(Clone)]
+pub struct RecordingStoreWriter {
+    state: Arc<Mutex<RecordingState>>,
+}
+impl RecordingStoreWriter {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RecordingState {
+                calls: Vec::new(),
+                call_count: 0,
+                injected_errors: HashMap::new(),
+            })),
+        }
+    }
+    /// return the given error from the nth call, counting every call from 1, including calls that returned an error.
+    /// the call is still recorded, so retries show up as repeated calls.
+    pub fn set_error_on_call(self, n: u64, error: StoreError) -> Self {
+        self.state.lock().unwrap().injected_errors.insert(n, error);
+        self
+    }
+    /// number of calls made so far
+    pub fn call_count(&self) -> u64 {
+        self.state.lock().unwrap().call_count
+    }
+    /// remove and return every call recorded so far
+    pub fn take_calls(&self) -> Vec<RecordedCall> {
+        std::mem::take(&mut self.state.lock().unwrap().calls)
+    }
+    fn record(&self, call: RecordedCall) -> Result<(), StoreError> {
+        let mut state = self.state.lock().unwrap();
+        state.call_count += 1;
+        state.calls.push(call);
+        let call_number = state.call_count;
+        match state.injected_errors.remove(&call_number) {
+            None => Ok(()),
+            Some(err) => Err(err),
+        }
+    }
+}
+impl StoreWriter for RecordingStoreWriter {
+    fn create_keyspace(&self, keyspace: &str) -> Result<CreatedKeyspace, StoreError> {
+        self.record(RecordedCall::CreateKeyspace {
+            keyspace: keyspace.to_string(),
+        })?;
+        Ok(CreatedKeyspace {
+            keyspace: keyspace.to_string(),
+        })
+    }
+    fn append(&self, keyspace: &str, key: &str, inserts: Vec<Insertion>) -> Result<(), StoreError> {
+        self.record(RecordedCall::Append {
+            keyspace: keyspace.to_string(),
+            key: key.to_string(),
+            inserts,
+        })
+    }
+    fn flush_key(&self, keyspace: &str, key: &str) -> Result<(), StoreError> {
+        self.record(RecordedCall::FlushKey {
+            keyspace: keyspace.to_string(),
+            key: key.to_string(),
+        })
+    }
+    fn flush_all(&self) -> Result<(), StoreError> {
+        self.record(RecordedCall::FlushAll)
+    }
+    fn duty_cycle(&self) -> Result<(), StoreError> {
+        self.record(RecordedCall::DutyCycle)
+    }
+}
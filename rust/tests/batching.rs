@@ -0,0 +1,239 @@
+#![cfg(feature = "testing")]
+extern crate klstore;
+
+mod common;
+
+use common::*;
+use klstore::*;
+use std::sync::Arc;
+
+/// keys of the appends recorded by the writer, in order
+fn appended_keys(writer: &RecordingStoreWriter) -> Vec<String> {
+    writer
+        .take_calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            RecordedCall::Append { key, .. } => Some(key),
+            _ => None,
+        })
+        .collect()
+}
+
+fn wal_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("klstore-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn wal_keeps_entries_of_failed_appends_after_a_later_flush() {
+    let path = wal_path("failed-appends");
+    let config = BatchingStoreWriterConfig::new().set_wal_path(Some(path.clone()));
+
+    // the first batch written, of key a, fails
+    let writer = RecordingStoreWriter::new()
+        .set_error_on_call(1, StoreError::IOError("injected".to_string()));
+    let batcher = BatchingStoreWriter::new(config.clone(), writer.clone()).unwrap();
+    batcher
+        .append("ks", "a", vec![insertion("a1", 1, 1)])
+        .unwrap();
+    batcher
+        .append("ks", "b", vec![insertion("b1", 1, 1)])
+        .unwrap();
+    let report = batcher.flush_all_with_report().unwrap();
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].key, "a");
+    assert_eq!(report.flushed.len(), 1);
+    assert_eq!(report.flushed[0].key, "b");
+
+    // a later successful flush truncates the log, except for the failed append
+    batcher
+        .append("ks", "b", vec![insertion("b2", 2, 2)])
+        .unwrap();
+    batcher.flush_all().unwrap();
+    batcher.shutdown().unwrap();
+
+    let writer = RecordingStoreWriter::new();
+    let batcher = BatchingStoreWriter::new(config.clone(), writer.clone()).unwrap();
+    assert_eq!(appended_keys(&writer), vec!["a".to_string()]);
+    batcher.shutdown().unwrap();
+
+    // the replay was flushed, so nothing is replayed again
+    let writer = RecordingStoreWriter::new();
+    let batcher = BatchingStoreWriter::new(config, writer.clone()).unwrap();
+    assert_eq!(appended_keys(&writer), Vec::<String>::new());
+    batcher.shutdown().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn wal_replays_only_the_failed_append_of_a_key_appended_again() {
+    let path = wal_path("failed-then-flushed");
+    let config = BatchingStoreWriterConfig::new().set_wal_path(Some(path.clone()));
+
+    let writer = RecordingStoreWriter::new()
+        .set_error_on_call(1, StoreError::IOError("injected".to_string()));
+    let batcher = BatchingStoreWriter::new(config.clone(), writer.clone()).unwrap();
+    batcher
+        .append("ks", "a", vec![insertion("a1", 1, 1)])
+        .unwrap();
+    assert_eq!(batcher.flush_all_with_report().unwrap().failed.len(), 1);
+
+    // the next append of the key is flushed, so it is not kept with the failed append
+    batcher
+        .append("ks", "a", vec![insertion("a2", 2, 2)])
+        .unwrap();
+    batcher.flush_all().unwrap();
+    batcher.shutdown().unwrap();
+
+    let writer = RecordingStoreWriter::new();
+    let batcher = BatchingStoreWriter::new(config, writer.clone()).unwrap();
+    let replayed: Vec<Vec<u8>> = writer
+        .take_calls()
+        .into_iter()
+        .flat_map(|call| match call {
+            RecordedCall::Append { inserts, .. } => inserts,
+            _ => Vec::new(),
+        })
+        .map(|insert| insert.record)
+        .collect();
+    assert_eq!(replayed, vec![b"a1".to_vec()]);
+    batcher.shutdown().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn record_transform_runs_before_the_wal_and_rejects_only_its_own_append() {
+    let path = wal_path("transform");
+    let config = BatchingStoreWriterConfig::new().set_wal_path(Some(path.clone()));
+    let batcher = BatchingStoreWriter::new(config.clone(), RecordingStoreWriter::new())
+        .unwrap()
+        .set_record_transform(Arc::new(|insert: &mut Insertion| {
+            if insert.record == b"bad".to_vec() {
+                return Err(StoreError::BadData("rejected".to_string()));
+            }
+            insert.record = insert.record.to_ascii_uppercase();
+            Ok(())
+        }));
+    batcher
+        .append("ks", "k", vec![insertion("a", 1, 1)])
+        .unwrap();
+    match batcher.append("ks", "k", vec![insertion("bad", 2, 2)]) {
+        Err(StoreError::BadData(_)) => {}
+        other => panic!("expected BadData, got {:?}", other),
+    }
+    batcher
+        .append("ks", "k", vec![insertion("c", 3, 3)])
+        .unwrap();
+    // crash without flushing, the log holds the transformed records of the accepted appends
+    std::mem::forget(batcher);
+
+    let writer = RecordingStoreWriter::new();
+    let batcher = BatchingStoreWriter::new(config, writer.clone()).unwrap();
+    let records: Vec<Vec<u8>> = writer
+        .take_calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            RecordedCall::Append { inserts, .. } => Some(inserts),
+            _ => None,
+        })
+        .flatten()
+        .map(|insert| insert.record)
+        .collect();
+    assert_eq!(records, vec![b"A".to_vec(), b"C".to_vec()]);
+    batcher.shutdown().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn flushes_reach_the_underlying_writer_and_report_its_failures() {
+    // call 1 appends the batch, and call 2 flushes the key in the underlying writer, which fails
+    let writer = RecordingStoreWriter::new()
+        .set_error_on_call(2, StoreError::IOError("injected".to_string()));
+    let batcher =
+        BatchingStoreWriter::new(BatchingStoreWriterConfig::new(), writer.clone()).unwrap();
+    batcher
+        .append("ks", "k", vec![insertion("a", 1, 1)])
+        .unwrap();
+    match batcher.flush_key("ks", "k") {
+        Err(StoreError::IOError(_)) => {}
+        other => panic!("expected IOError, got {:?}", other),
+    }
+    batcher.flush_all().unwrap();
+    let calls: Vec<&str> = writer
+        .take_calls()
+        .iter()
+        .map(|call| match call {
+            RecordedCall::Append { .. } => "append",
+            RecordedCall::FlushKey { .. } => "flush_key",
+            RecordedCall::FlushAll => "flush_all",
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(calls, vec!["append", "flush_key", "flush_all"]);
+    batcher.shutdown().unwrap();
+}
+
+#[test]
+fn negative_timestamps_fail_only_their_own_append() {
+    let writer = RecordingStoreWriter::new();
+    let batcher =
+        BatchingStoreWriter::new(BatchingStoreWriterConfig::new(), writer.clone()).unwrap();
+    batcher
+        .append("ks", "k", vec![insertion("a", 1, 1)])
+        .unwrap();
+    match batcher.append("ks", "k", vec![insertion("b", 2, 2), insertion("c", 3, -3)]) {
+        Err(StoreError::BadData(_)) => {}
+        other => panic!("expected BadData, got {:?}", other),
+    }
+    batcher
+        .append("ks", "k", vec![insertion("d", 4, 4)])
+        .unwrap();
+    // the merged batch of the lane holds the accepted appends and is written at flush
+    batcher.flush_all().unwrap();
+    let records: Vec<Vec<u8>> = writer
+        .take_calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            RecordedCall::Append { inserts, .. } => Some(inserts),
+            _ => None,
+        })
+        .flatten()
+        .map(|insert| insert.record)
+        .collect();
+    assert_eq!(records, vec![b"a".to_vec(), b"d".to_vec()]);
+    batcher.shutdown().unwrap();
+}
+
+#[test]
+fn flush_reports_offsets_after_the_underlying_flush_and_skip_keys_flushed_alone() {
+    let store = MemoryObjectStore::new();
+    let s3 =
+        S3StoreWriter::new(uncompacted_config(&store).set_append_buffer_size_threshold(1 << 20))
+            .unwrap();
+    s3.create_keyspace("ks").unwrap();
+    let batcher = BatchingStoreWriter::new(BatchingStoreWriterConfig::new(), s3).unwrap();
+    batcher
+        .append(
+            "ks",
+            "a",
+            vec![insertion("a1", 1, 1), insertion("a2", 2, 2)],
+        )
+        .unwrap();
+    batcher
+        .append("ks", "b", vec![insertion("b1", 1, 1)])
+        .unwrap();
+    batcher.flush_key("ks", "b").unwrap();
+
+    // b was flushed on its own, and the offset of a includes the records held in the append buffer of the underlying writer
+    let report = batcher.flush_all_with_report().unwrap();
+    let flushed: Vec<(String, Option<u64>)> = report
+        .flushed
+        .iter()
+        .map(|f| (f.key.clone(), f.next_offset))
+        .collect();
+    assert_eq!(flushed, vec![("a".to_string(), Some(3))]);
+    assert!(report.failed.is_empty());
+    assert!(batcher.flush_all_with_report().unwrap().flushed.is_empty());
+    batcher.shutdown().unwrap();
+}
@@ -0,0 +1,56 @@
+#![allow(dead_code)]
+use klstore::*;
+
+pub fn insertion(value: &str, nonce: u128, timestamp: i64) -> Insertion {
+    Insertion {
+        record: value.as_bytes().to_vec(),
+        nonce: Some(nonce),
+        timestamp: Some(timestamp),
+    }
+}
+
+/// append one object per entry of counts to ks/k, each holding that many equally sized records with increasing nonces and timestamps
+pub fn append_objects(writer: &S3StoreWriter, counts: &[u64]) {
+    let mut next: u64 = writer.next_offset("ks", "k").unwrap().unwrap();
+    for count in counts {
+        let mut records = Vec::new();
+        for _ in 0..*count {
+            records.push(insertion(
+                &format!("r{:04}", next),
+                next as u128,
+                next as i64,
+            ));
+            next += 1;
+        }
+        writer.append("ks", "k", records).unwrap();
+    }
+}
+
+/// offsets of every record of ks/k read from start in pages of page_size, following continuations
+pub fn read_offsets(
+    reader: &S3StoreReader,
+    direction: Direction,
+    start: StartPosition,
+    page_size: u64,
+) -> Vec<u64> {
+    let mut page = reader
+        .read_first_page("ks", "k", direction, start, Some(page_size))
+        .unwrap();
+    let mut offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    while let Some(continuation) = page.continuation {
+        page = reader
+            .read_next_page("ks", "k", continuation, Some(page_size))
+            .unwrap();
+        offsets.extend(page.records.iter().map(|r| r.offset));
+    }
+    offsets
+}
+
+/// a writer that never compacts, to lay out objects exactly as appended
+pub fn uncompacted_config(store: &MemoryObjectStore) -> S3StoreConfig {
+    store
+        .config()
+        .set_compact_records_threshold(u64::MAX)
+        .set_compact_objects_threshold(u64::MAX)
+        .set_compact_size_threshold(u64::MAX)
+}
@@ -0,0 +1,239 @@
+#![cfg(feature = "testing")]
+extern crate klstore;
+
+mod common;
+
+use common::*;
+use klstore::*;
+
+/// (first_offset, last_offset, sealed) of every object of ks/k
+fn layout(reader: &S3StoreReader) -> Vec<(u64, u64, bool)> {
+    reader
+        .describe_key("ks", "k")
+        .unwrap()
+        .objects
+        .iter()
+        .map(|o| (o.first_offset, o.last_offset, o.sealed))
+        .collect()
+}
+
+/// size of each object appended by append_objects(&[2])
+fn two_record_object_size() -> u64 {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2]);
+    S3StoreReader::new(store.config())
+        .unwrap()
+        .describe_key("ks", "k")
+        .unwrap()
+        .objects[0]
+        .size
+}
+
+fn object_count_config(store: &MemoryObjectStore, target_size: u64) -> S3StoreConfig {
+    uncompacted_config(store)
+        .set_compact_objects_threshold(4)
+        .set_compact_target_size(target_size)
+}
+
+#[test]
+fn object_count_compaction_seals_groups_at_the_target_size() {
+    let store = MemoryObjectStore::new();
+    let size = two_record_object_size();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2, 2]);
+
+    let writer = S3StoreWriter::new(object_count_config(&store, 2 * size)).unwrap();
+    let plan = writer.plan_compaction("ks", "k").unwrap();
+    assert!(plan.triggered);
+    assert!(!plan.advance_watermark);
+    assert!(plan.seal_watermark);
+    assert_eq!(plan.groups.len(), 2);
+
+    append_objects(&writer, &[2]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    assert_eq!(
+        layout(&reader),
+        vec![(1, 4, true), (5, 8, true), (9, 10, false)]
+    );
+    assert_eq!(
+        read_offsets(&reader, Direction::Forwards, StartPosition::First, 100),
+        (1..11).collect::<Vec<u64>>()
+    );
+
+    // sealed objects are never read again by later object count compactions
+    store.reset_operation_counts();
+    append_objects(&writer, &[2, 2, 2]);
+    assert_eq!(store.operation_count("get_object"), 4);
+    assert_eq!(
+        layout(&reader),
+        vec![(1, 4, true), (5, 8, true), (9, 12, true), (13, 16, false)]
+    );
+}
+
+#[test]
+fn object_count_compaction_within_the_target_size_does_not_seal() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2]);
+
+    let writer = S3StoreWriter::new(object_count_config(&store, u64::MAX)).unwrap();
+    append_objects(&writer, &[2]);
+    let plan = writer.plan_compaction("ks", "k").unwrap();
+    assert!(!plan.triggered);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    assert_eq!(layout(&reader), vec![(1, 8, false)]);
+    assert_eq!(
+        reader.describe_key("ks", "k").unwrap().watermark_offset,
+        None
+    );
+}
+
+#[test]
+fn oversized_object_is_sealed_as_its_own_group() {
+    let store = MemoryObjectStore::new();
+    let size = two_record_object_size();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[6, 2, 2]);
+
+    // the 6 record object exceeds the target size alone, so it is left in place and sealed
+    let writer = S3StoreWriter::new(object_count_config(&store, 2 * size)).unwrap();
+    append_objects(&writer, &[2]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    assert_eq!(
+        layout(&reader),
+        vec![(1, 6, true), (7, 10, true), (11, 12, false)]
+    );
+}
+
+#[test]
+fn record_threshold_advances_the_watermark_instead_of_sealing() {
+    let store = MemoryObjectStore::new();
+    let size = two_record_object_size();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2, 2]);
+
+    let writer =
+        S3StoreWriter::new(object_count_config(&store, 2 * size).set_compact_records_threshold(8))
+            .unwrap();
+    let plan = writer.plan_compaction("ks", "k").unwrap();
+    assert!(plan.advance_watermark);
+    assert!(!plan.seal_watermark);
+}
+
+#[test]
+fn repair_deletes_objects_left_by_an_interrupted_compaction_only_when_asked() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let left = reader.describe_key("ks", "k").unwrap().objects[0]
+        .path
+        .clone();
+    let contents = store.get(&left).unwrap();
+
+    // compaction merged both objects, but crashed before deleting the first
+    let compacting =
+        S3StoreWriter::new(uncompacted_config(&store).set_compact_records_threshold(6)).unwrap();
+    append_objects(&compacting, &[2]);
+    store.put(&left, contents);
+    assert_eq!(layout(&reader), vec![(1, 2, true), (1, 6, true)]);
+
+    assert_eq!(
+        compacting.repair_key("ks", "k", false).unwrap(),
+        vec![left.clone()]
+    );
+    assert!(store.get(&left).is_some());
+    assert_eq!(
+        compacting.repair_key("ks", "k", true).unwrap(),
+        vec![left.clone()]
+    );
+    assert!(store.get(&left).is_none());
+    assert_eq!(layout(&reader), vec![(1, 6, true)]);
+    assert_eq!(
+        reader.describe_key("ks", "k").unwrap().watermark_offset,
+        Some(1)
+    );
+}
+
+fn lease(expires_millis: u64, owner: u64) -> Vec<u8> {
+    let mut lease = expires_millis.to_le_bytes().to_vec();
+    lease.extend_from_slice(&owner.to_le_bytes());
+    lease
+}
+
+#[test]
+fn compaction_waits_for_a_held_lease_and_replaces_an_expired_one() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(
+        uncompacted_config(&store)
+            .set_compact_records_threshold(4)
+            .set_compaction_lease_ttl_millis(60000),
+    )
+    .unwrap();
+    writer.create_keyspace("ks").unwrap();
+    let reader = S3StoreReader::new(store.config()).unwrap();
+
+    // another compactor holds the lease, so the triggered compaction is skipped
+    store.put("ks/k/compacting", lease(u64::MAX, 7));
+    append_objects(&writer, &[2, 2]);
+    assert_eq!(layout(&reader).len(), 2);
+    assert_eq!(store.get("ks/k/compacting"), Some(lease(u64::MAX, 7)));
+
+    // an expired lease, written before leases had an owner, is deleted by the next check and acquired by the one after
+    store.put("ks/k/compacting", 1u64.to_le_bytes().to_vec());
+    append_objects(&writer, &[1]);
+    assert_eq!(layout(&reader).len(), 3);
+    assert!(store.get("ks/k/compacting").is_none());
+    append_objects(&writer, &[1]);
+    assert_eq!(layout(&reader), vec![(1, 6, true)]);
+    assert!(store.get("ks/k/compacting").is_none());
+}
+
+#[test]
+fn object_count_compaction_just_over_the_threshold_keeps_counting_from_the_merged_object() {
+    use std::sync::{Arc, Mutex};
+
+    let store = MemoryObjectStore::new();
+    let compactions: Arc<Mutex<Vec<(u64, u64)>>> = Arc::new(Mutex::new(Vec::new()));
+    let listener_compactions = compactions.clone();
+    let writer = S3StoreWriter::new(uncompacted_config(&store).set_compact_objects_threshold(4))
+        .unwrap()
+        .set_event_listener(Arc::new(move |event| {
+            if let StoreEvent::Compacted {
+                watermark_offset,
+                uncompacted_objects,
+                ..
+            } = event
+            {
+                listener_compactions
+                    .lock()
+                    .unwrap()
+                    .push((*watermark_offset, *uncompacted_objects));
+            }
+        }));
+    writer.create_keyspace("ks").unwrap();
+    let reader = S3StoreReader::new(store.config()).unwrap();
+
+    // the fourth object triggers a merge that reaches no record or size threshold, so the watermark stays
+    append_objects(&writer, &[1, 1, 1, 1, 1]);
+    assert_eq!(*compactions.lock().unwrap(), vec![(0, 1)]);
+    assert_eq!(layout(&reader), vec![(1, 4, false), (5, 5, false)]);
+    assert_eq!(
+        reader.describe_key("ks", "k").unwrap().watermark_offset,
+        None
+    );
+    // the merged object and the one after it count as two objects, so nothing is triggered yet
+    assert!(!writer.plan_compaction("ks", "k").unwrap().triggered);
+
+    append_objects(&writer, &[1, 1]);
+    assert_eq!(*compactions.lock().unwrap(), vec![(0, 1), (0, 1)]);
+    assert_eq!(layout(&reader), vec![(1, 7, false)]);
+    assert!(!writer.plan_compaction("ks", "k").unwrap().triggered);
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "testing")]
+extern crate klstore;
+
+mod common;
+
+use common::*;
+use klstore::*;
+
+#[test]
+fn layout_flags_are_stored_in_the_keyspace_config() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    let layout = format::RecordLayout::new()
+        .set_forward_only(true)
+        .set_u64_nonces(true);
+    writer
+        .create_keyspace_with_format("ks", TimestampUnit::Millis, layout)
+        .unwrap();
+    let config = String::from_utf8(store.get("ks_config.ini").unwrap()).unwrap();
+    assert!(config.contains("format_version=1"), "{}", config);
+    assert!(config.contains("forward_only=true"), "{}", config);
+    assert!(config.contains("u64_nonces=true"), "{}", config);
+
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let metadata = reader.read_keyspace_metadata("ks").unwrap();
+    assert_eq!(metadata.format_version, format::FORMAT_VERSION);
+    assert_eq!(
+        format::RecordLayout::for_keyspace(&metadata).unwrap(),
+        layout
+    );
+
+    // configs written before the flags existed have the default layout
+    store.put(
+        "old_config.ini",
+        b"[keyspace]\ncreated=1\nformat_version=1".to_vec(),
+    );
+    let metadata = reader.read_keyspace_metadata("old").unwrap();
+    assert!(!metadata.forward_only);
+    assert!(!metadata.u64_nonces);
+}
+
+#[test]
+fn serialized_records_round_trip_and_reject_nonces_beyond_the_layout() {
+    let layout = format::RecordLayout::new().set_u64_nonces(true);
+    let inserts = vec![insertion("a", 7, 1), insertion("b", 8, 2)];
+    let buffer = format::serialize_records(layout, &inserts, 10, 0).unwrap();
+    let records = format::deserialize_records(
+        layout,
+        &buffer,
+        Direction::Backwards,
+        &StartPosition::First,
+        &None,
+    )
+    .unwrap();
+    let offsets: Vec<(u64, Option<u128>)> = records.iter().map(|r| (r.offset, r.nonce)).collect();
+    assert_eq!(offsets, vec![(11, Some(8)), (10, Some(7))]);
+
+    let inserts = vec![insertion("a", u64::MAX as u128, 1)];
+    match format::serialize_records(layout, &inserts, 1, 0) {
+        Err(StoreError::BadData(_)) => {}
+        other => panic!("expected BadData, got {:?}", other),
+    }
+    assert!(format::serialize_records(format::RecordLayout::new(), &inserts, 1, 0).is_ok());
+}
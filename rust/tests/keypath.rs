@@ -0,0 +1,37 @@
+#![cfg(feature = "testing")]
+extern crate klstore;
+
+use klstore::*;
+
+fn path(first_offset: u64, last_offset: u64) -> String {
+    KeyPath {
+        first_offset,
+        last_offset,
+        min_timestamp: 0,
+        max_timestamp: 0,
+        first_nonce: 0,
+        next_nonce: 0,
+        size: 0,
+        prior_start_offset: 0,
+    }
+    .to_path("", "ks", "k")
+}
+
+#[test]
+fn after_offset_prefix_sorts_between_adjacent_objects() {
+    let offsets: Vec<u64> = vec![1, 9, 10, 99, 100, 12345678901, u64::MAX / 2, u64::MAX - 2];
+    for offset in offsets {
+        let prefix = KeyPath::after_offset_prefix("", "ks", "k", offset);
+        // listings start after the prefix, so they skip the object at offset and include the one after it
+        assert!(path(offset, offset) < prefix, "offset {}", offset);
+        assert!(path(offset + 1, offset + 1) > prefix, "offset {}", offset);
+        assert!(path(offset + 1, u64::MAX) > prefix, "offset {}", offset);
+        // paths sort by first offset regardless of how many digits it has
+        assert!(
+            path(offset, u64::MAX) < path(offset + 1, offset + 1),
+            "offset {}",
+            offset
+        );
+    }
+    assert!(KeyPath::after_offset_prefix("", "ks", "k", 0) < path(1, 1));
+}
@@ -0,0 +1,1124 @@
+#![cfg(feature = "testing")]
+extern crate klstore;
+
+mod common;
+
+use common::*;
+use klstore::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn smoke() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append("ks", "k", vec![insertion("a", 1, 1), insertion("b", 2, 2)])
+        .unwrap();
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let page = reader
+        .read_first_page("ks", "k", Direction::Forwards, StartPosition::First, None)
+        .unwrap();
+    assert_eq!(page.records.len(), 2);
+}
+
+#[test]
+fn backward_pages_ending_mid_object_do_not_repeat_records() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[4, 7]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    for page_size in 1..12 {
+        let offsets = read_offsets(
+            &reader,
+            Direction::Backwards,
+            StartPosition::Offset(11),
+            page_size,
+        );
+        assert_eq!(
+            offsets,
+            (1..12).rev().collect::<Vec<u64>>(),
+            "page_size {}",
+            page_size
+        );
+    }
+}
+
+#[test]
+fn continuations_round_trip_in_both_directions() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 3]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+
+    let page = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Forwards,
+            StartPosition::First,
+            Some(2),
+        )
+        .unwrap();
+    let continuation = page.continuation.unwrap();
+    assert!(continuation.starts_with("f:"), "{}", continuation);
+    let page = reader
+        .read_next_page("ks", "k", continuation, Some(2))
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![3, 4]);
+
+    let page = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Backwards,
+            StartPosition::Offset(5),
+            Some(2),
+        )
+        .unwrap();
+    let continuation = page.continuation.unwrap();
+    assert!(continuation.starts_with("b:"), "{}", continuation);
+    let page = reader
+        .read_next_page("ks", "k", continuation, Some(2))
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![3, 2]);
+
+    match reader.read_next_page("ks", "k", "3:1".to_string(), None) {
+        Err(StoreError::InvalidContinuation(_)) => {}
+        other => panic!("expected InvalidContinuation, got {:?}", other),
+    }
+}
+
+#[test]
+fn continuations_for_offsets_seek_in_both_directions_without_a_miss() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2]);
+    let misses = Arc::new(AtomicU64::new(0));
+    let listener_misses = misses.clone();
+    let reader = S3StoreReader::new(store.config())
+        .unwrap()
+        .set_event_listener(Arc::new(move |event| {
+            if let StoreEvent::Read {
+                continuation_miss_count,
+                ..
+            } = event
+            {
+                listener_misses.fetch_add(*continuation_miss_count, Ordering::SeqCst);
+            }
+        }));
+
+    let page = reader
+        .read_next_page(
+            "ks",
+            "k",
+            continuation_for_offset(3, Direction::Forwards),
+            Some(3),
+        )
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![3, 4, 5]);
+    let page = reader
+        .read_next_page("ks", "k", page.continuation.unwrap(), Some(3))
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![6]);
+
+    let page = reader
+        .read_next_page(
+            "ks",
+            "k",
+            continuation_for_offset(4, Direction::Backwards),
+            Some(3),
+        )
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![4, 3, 2]);
+    let page = reader
+        .read_next_page("ks", "k", page.continuation.unwrap(), Some(3))
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![1]);
+
+    // past the end of the key there is nothing to seek to
+    let page = reader
+        .read_next_page(
+            "ks",
+            "k",
+            continuation_for_offset(7, Direction::Forwards),
+            None,
+        )
+        .unwrap();
+    assert!(page.records.is_empty());
+    assert_eq!(page.continuation, None);
+    assert_eq!(misses.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn continuations_from_the_end_of_a_key_are_not_misses() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2]);
+    let misses = Arc::new(AtomicU64::new(0));
+    let listener_misses = misses.clone();
+    let reader = S3StoreReader::new(store.config())
+        .unwrap()
+        .set_event_listener(Arc::new(move |event| {
+            if let StoreEvent::Read {
+                continuation_miss_count,
+                ..
+            } = event
+            {
+                listener_misses.fetch_add(*continuation_miss_count, Ordering::SeqCst);
+            }
+        }));
+
+    // a full page ending at the last object is anchored after it
+    let page = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Forwards,
+            StartPosition::First,
+            Some(4),
+        )
+        .unwrap();
+    assert_eq!(page.records.len(), 4);
+    store.reset_operation_counts();
+    let page = reader
+        .read_next_page("ks", "k", page.continuation.unwrap(), Some(4))
+        .unwrap();
+    assert!(page.records.is_empty());
+    // the listing after the anchor and a single listing from the watermark, without a search
+    assert_eq!(store.operation_count("list_page"), 2);
+    assert_eq!(misses.load(Ordering::SeqCst), 0);
+}
+
+fn page_offsets(page: Result<Page, StoreError>) -> Vec<u64> {
+    page.unwrap().records.iter().map(|r| r.offset).collect()
+}
+
+#[test]
+fn first_page_boundaries_are_inclusive_unless_excluded() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 3]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let read = |direction: Direction, start: StartPosition, inclusive: bool| {
+        page_offsets(reader.read_first_page_from("ks", "k", direction, start, inclusive, None))
+    };
+
+    assert_eq!(
+        read(Direction::Forwards, StartPosition::Offset(3), true),
+        vec![3, 4, 5]
+    );
+    assert_eq!(
+        read(Direction::Forwards, StartPosition::Offset(3), false),
+        vec![4, 5]
+    );
+    assert_eq!(
+        read(Direction::Backwards, StartPosition::Offset(3), true),
+        vec![3, 2, 1]
+    );
+    assert_eq!(
+        read(Direction::Backwards, StartPosition::Offset(3), false),
+        vec![2, 1]
+    );
+    assert_eq!(
+        read(Direction::Forwards, StartPosition::Timestamp(2), false),
+        vec![3, 4, 5]
+    );
+    assert_eq!(
+        read(Direction::Backwards, StartPosition::Nonce(4), false),
+        vec![3, 2, 1]
+    );
+    // nothing is beyond the boundary
+    assert_eq!(
+        read(Direction::Forwards, StartPosition::Offset(5), false),
+        Vec::<u64>::new()
+    );
+    assert_eq!(
+        read(Direction::Backwards, StartPosition::Offset(0), false),
+        Vec::<u64>::new()
+    );
+    // First has no boundary to exclude
+    assert_eq!(
+        read(Direction::Forwards, StartPosition::First, false),
+        vec![1, 2, 3, 4, 5]
+    );
+}
+
+#[test]
+fn next_page_boundaries_are_inclusive_unless_excluded() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 3]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let read = |continuation: String, inclusive: bool| {
+        page_offsets(reader.read_next_page_from("ks", "k", continuation, inclusive, None))
+    };
+
+    // continuations built from an offset
+    assert_eq!(
+        read(continuation_for_offset(2, Direction::Forwards), true),
+        vec![2, 3, 4, 5]
+    );
+    assert_eq!(
+        read(continuation_for_offset(2, Direction::Forwards), false),
+        vec![3, 4, 5]
+    );
+    assert_eq!(
+        read(continuation_for_offset(4, Direction::Backwards), true),
+        vec![4, 3, 2, 1]
+    );
+    assert_eq!(
+        read(continuation_for_offset(4, Direction::Backwards), false),
+        vec![3, 2, 1]
+    );
+    assert_eq!(
+        read(continuation_for_offset(1, Direction::Backwards), false),
+        Vec::<u64>::new()
+    );
+
+    // anchored continuations, the backward one steps out of its anchor object
+    let forward = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Forwards,
+            StartPosition::First,
+            Some(1),
+        )
+        .unwrap()
+        .continuation
+        .unwrap();
+    assert_eq!(read(forward.clone(), true), vec![2, 3, 4, 5]);
+    assert_eq!(read(forward, false), vec![3, 4, 5]);
+    let backward = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Backwards,
+            StartPosition::Offset(5),
+            Some(2),
+        )
+        .unwrap()
+        .continuation
+        .unwrap();
+    assert_eq!(read(backward.clone(), true), vec![3, 2, 1]);
+    assert_eq!(read(backward, false), vec![2, 1]);
+}
+
+/// a reader implementing only the required methods, delegating them to an S3StoreReader
+struct RequiredOnlyReader(S3StoreReader);
+impl StoreReader for RequiredOnlyReader {
+    fn read_keyspace_metadata(&self, keyspace: &str) -> Result<KeyspaceMetadata, StoreError> {
+        self.0.read_keyspace_metadata(keyspace)
+    }
+    fn read_key_metadata(
+        &self,
+        keyspace: &str,
+        key: &str,
+    ) -> Result<Option<KeyMetadata>, StoreError> {
+        self.0.read_key_metadata(keyspace, key)
+    }
+    fn read_first_page(
+        &self,
+        keyspace: &str,
+        key: &str,
+        direction: Direction,
+        start: StartPosition,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        self.0
+            .read_first_page(keyspace, key, direction, start, page_size)
+    }
+    fn read_next_page(
+        &self,
+        keyspace: &str,
+        key: &str,
+        continuation: String,
+        page_size: Option<u64>,
+    ) -> Result<Page, StoreError> {
+        self.0
+            .read_next_page(keyspace, key, continuation, page_size)
+    }
+}
+
+#[test]
+fn default_boundary_reads_match_the_s3_reader() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 3]);
+    let s3_reader = S3StoreReader::new(store.config()).unwrap();
+    let reader = RequiredOnlyReader(S3StoreReader::new(store.config()).unwrap());
+
+    for inclusive in vec![true, false] {
+        for (direction, start) in vec![
+            (Direction::Forwards, StartPosition::Offset(3)),
+            (Direction::Backwards, StartPosition::Nonce(4)),
+            (Direction::Forwards, StartPosition::Offset(5)),
+            (Direction::Backwards, StartPosition::Offset(0)),
+        ] {
+            assert_eq!(
+                page_offsets(reader.read_first_page_from(
+                    "ks",
+                    "k",
+                    direction.clone(),
+                    start.clone(),
+                    inclusive,
+                    None
+                )),
+                page_offsets(
+                    s3_reader.read_first_page_from("ks", "k", direction, start, inclusive, None)
+                )
+            );
+        }
+        for continuation in vec![
+            continuation_for_offset(2, Direction::Forwards),
+            continuation_for_offset(4, Direction::Backwards),
+            continuation_for_offset(1, Direction::Backwards),
+        ] {
+            assert_eq!(
+                page_offsets(reader.read_next_page_from(
+                    "ks",
+                    "k",
+                    continuation.clone(),
+                    inclusive,
+                    None
+                )),
+                page_offsets(s3_reader.read_next_page_from(
+                    "ks",
+                    "k",
+                    continuation,
+                    inclusive,
+                    None
+                ))
+            );
+        }
+    }
+    assert_eq!(
+        exclusive_continuation(&continuation_for_offset(1, Direction::Backwards)).unwrap(),
+        None
+    );
+    match reader.read_next_page_from("ks", "k", "x".to_string(), false, None) {
+        Err(StoreError::InvalidContinuation(_)) => {}
+        other => panic!("expected InvalidContinuation, got {:?}", other),
+    }
+}
+
+/// a writer that compacts every object of ks/k once the key reaches the given record count
+fn compacting_writer(store: &MemoryObjectStore, records: u64) -> S3StoreWriter {
+    S3StoreWriter::new(uncompacted_config(store).set_compact_records_threshold(records)).unwrap()
+}
+
+#[test]
+fn forward_page_after_compaction_between_pages_is_full() {
+    // a page of 3 ends mid-object and a page of 2 ends at an object boundary
+    for page_size in 2..4 {
+        let store = MemoryObjectStore::new();
+        let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+        writer.create_keyspace("ks").unwrap();
+        append_objects(&writer, &[2, 2, 2, 2]);
+        let reader = S3StoreReader::new(store.config()).unwrap();
+        let page = reader
+            .read_first_page(
+                "ks",
+                "k",
+                Direction::Forwards,
+                StartPosition::First,
+                Some(page_size),
+            )
+            .unwrap();
+        let mut offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+
+        // merge every object into one, removing the object the continuation is anchored to
+        append_objects(&compacting_writer(&store, 10), &[2]);
+        assert_eq!(reader.describe_key("ks", "k").unwrap().objects.len(), 1);
+
+        let mut page = reader
+            .read_next_page("ks", "k", page.continuation.unwrap(), Some(page_size))
+            .unwrap();
+        assert_eq!(page.records.len() as u64, page_size);
+        offsets.extend(page.records.iter().map(|r| r.offset));
+        while let Some(continuation) = page.continuation {
+            page = reader
+                .read_next_page("ks", "k", continuation, Some(page_size))
+                .unwrap();
+            offsets.extend(page.records.iter().map(|r| r.offset));
+        }
+        assert_eq!(
+            offsets,
+            (1..11).collect::<Vec<u64>>(),
+            "page_size {}",
+            page_size
+        );
+    }
+}
+
+#[test]
+fn listings_fail_without_listing_past_max_list_pages() {
+    // a single path per LIST page, so each object of the key takes a page
+    let store = MemoryObjectStore::new().set_list_page_size(1);
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[1, 1, 1]);
+    let reader = S3StoreReader::new(store.config().set_max_list_pages(2)).unwrap();
+    store.reset_operation_counts();
+    match reader.list_keys("ks") {
+        Err(StoreError::IOError(_)) => {}
+        other => panic!("expected IOError, got {:?}", other),
+    }
+    assert_eq!(store.operation_count("list_page"), 2);
+    match reader.read_first_page("ks", "k", Direction::Forwards, StartPosition::First, None) {
+        Err(StoreError::IOError(_)) => {}
+        other => panic!("expected IOError, got {:?}", other),
+    }
+
+    let reader = S3StoreReader::new(store.config().set_max_list_pages(3)).unwrap();
+    assert_eq!(reader.list_keys("ks").unwrap(), vec!["k".to_string()]);
+    assert_eq!(
+        read_offsets(&reader, Direction::Forwards, StartPosition::First, 10),
+        vec![1, 2, 3]
+    );
+}
+
+#[test]
+fn linear_timestamp_seek_finds_interleaved_timestamps() {
+    // a single path per LIST page, so a binary search would page through the key
+    let store = MemoryObjectStore::new().set_list_page_size(1);
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    let mut nonce: u128 = 0;
+    for timestamps in &[[1, 2], [10, 11], [3, 4], [20, 21]] {
+        let records = timestamps
+            .iter()
+            .map(|t| {
+                nonce += 1;
+                insertion("v", nonce, *t)
+            })
+            .collect();
+        writer.append("ks", "k", records).unwrap();
+    }
+    let reader = S3StoreReader::new(store.config().set_linear_timestamp_seek(true)).unwrap();
+    assert_eq!(
+        read_offsets(
+            &reader,
+            Direction::Forwards,
+            StartPosition::Timestamp(10),
+            100
+        ),
+        vec![3, 4, 7, 8]
+    );
+    assert_eq!(
+        read_offsets(
+            &reader,
+            Direction::Forwards,
+            StartPosition::Timestamp(3),
+            100
+        ),
+        vec![3, 4, 5, 6, 7, 8]
+    );
+    assert_eq!(
+        read_offsets(
+            &reader,
+            Direction::Backwards,
+            StartPosition::Timestamp(4),
+            100
+        ),
+        vec![6, 5, 2, 1]
+    );
+}
+
+#[test]
+fn backward_page_after_compaction_between_pages_is_full() {
+    for page_size in 2..4 {
+        let store = MemoryObjectStore::new();
+        let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+        writer.create_keyspace("ks").unwrap();
+        append_objects(&writer, &[2, 2, 2, 2]);
+        let reader = S3StoreReader::new(store.config()).unwrap();
+        let page = reader
+            .read_first_page(
+                "ks",
+                "k",
+                Direction::Backwards,
+                StartPosition::Offset(8),
+                Some(page_size),
+            )
+            .unwrap();
+        let mut offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+
+        // merge every object into one starting before the anchor of the continuation
+        append_objects(&compacting_writer(&store, 10), &[2]);
+        assert_eq!(reader.describe_key("ks", "k").unwrap().objects.len(), 1);
+
+        let mut page = reader
+            .read_next_page("ks", "k", page.continuation.unwrap(), Some(page_size))
+            .unwrap();
+        assert_eq!(page.records.len() as u64, page_size);
+        offsets.extend(page.records.iter().map(|r| r.offset));
+        while let Some(continuation) = page.continuation {
+            page = reader
+                .read_next_page("ks", "k", continuation, Some(page_size))
+                .unwrap();
+            offsets.extend(page.records.iter().map(|r| r.offset));
+        }
+        assert_eq!(
+            offsets,
+            (1..9).rev().collect::<Vec<u64>>(),
+            "page_size {}",
+            page_size
+        );
+    }
+}
+
+#[test]
+fn backward_read_recovers_from_object_removed_after_listing() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let page = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Backwards,
+            StartPosition::Offset(6),
+            Some(2),
+        )
+        .unwrap();
+    let removed = reader.describe_key("ks", "k").unwrap().objects[1]
+        .path
+        .clone();
+
+    // the next GET of the middle object finds nothing, as if compaction removed it after the LIST,
+    // and compaction then writes the merged object the search finds instead
+    let missed = Arc::new(AtomicBool::new(false));
+    let injector_missed = missed.clone();
+    let injector_store = store.clone();
+    let reader = reader.set_fault_injector(Arc::new(move |operation, path| {
+        if operation == "get_object"
+            && path == removed
+            && !injector_missed.swap(true, Ordering::SeqCst)
+        {
+            append_objects(&compacting_writer(&injector_store, 8), &[2]);
+            return Some(InjectedFault::Missing);
+        }
+        None
+    }));
+    let mut offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    let mut continuation = page.continuation;
+    while let Some(c) = continuation {
+        let page = reader.read_next_page("ks", "k", c, Some(2)).unwrap();
+        offsets.extend(page.records.iter().map(|r| r.offset));
+        continuation = page.continuation;
+    }
+    assert!(missed.load(Ordering::SeqCst));
+    assert_eq!(offsets, vec![6, 5, 4, 3, 2, 1]);
+}
+
+#[test]
+fn cached_objects_are_read_and_revalidated_without_a_head() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2]);
+    let reader = S3StoreReader::new(
+        store
+            .config()
+            .set_object_cache_capacity(10)
+            .set_object_cache_ttl_millis(0),
+    )
+    .unwrap();
+    store.reset_operation_counts();
+    for _ in 0..2 {
+        assert_eq!(
+            read_offsets(&reader, Direction::Forwards, StartPosition::First, 100),
+            vec![1, 2]
+        );
+    }
+    assert_eq!(store.operation_count("head_object"), 0);
+}
+
+/// (key, offset) of every record of a merged read of keys, following continuations
+fn read_merged_records(
+    reader: &S3StoreReader,
+    keys: &[&str],
+    order: MergeOrder,
+    direction: Direction,
+    page_size: u64,
+) -> Vec<(String, u64)> {
+    let mut page = reader
+        .read_merged(
+            "ks",
+            keys,
+            order,
+            direction,
+            StartPosition::First,
+            Some(page_size),
+        )
+        .unwrap();
+    let mut records: Vec<(String, u64)> = Vec::new();
+    loop {
+        records.extend(
+            page.records
+                .iter()
+                .map(|r| (r.key.clone(), r.record.offset)),
+        );
+        match page.continuation {
+            None => return records,
+            Some(continuation) => {
+                page = reader
+                    .read_merged_next("ks", keys, continuation, Some(page_size))
+                    .unwrap()
+            }
+        }
+    }
+}
+
+#[test]
+fn merged_reads_follow_the_order_and_treat_missing_keys_as_empty() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append(
+            "ks",
+            "a",
+            vec![insertion("a1", 1, 1), insertion("a2", 2, 2)],
+        )
+        .unwrap();
+    writer
+        .append("ks", "a", vec![insertion("a3", 3, 30)])
+        .unwrap();
+    writer
+        .append(
+            "ks",
+            "b",
+            vec![insertion("b1", 1, 10), insertion("b2", 2, 20)],
+        )
+        .unwrap();
+    let reader = S3StoreReader::new(store.config().set_error_on_missing_key(true)).unwrap();
+    let keys = ["a", "missing", "b"];
+    let record = |key: &str, offset: u64| (key.to_string(), offset);
+
+    for page_size in 1..6 {
+        assert_eq!(
+            read_merged_records(
+                &reader,
+                &keys,
+                MergeOrder::Timestamp,
+                Direction::Forwards,
+                page_size
+            ),
+            vec![
+                record("a", 1),
+                record("a", 2),
+                record("b", 1),
+                record("b", 2),
+                record("a", 3)
+            ],
+            "page_size {}",
+            page_size
+        );
+        assert_eq!(
+            read_merged_records(
+                &reader,
+                &keys,
+                MergeOrder::Offset,
+                Direction::Forwards,
+                page_size
+            ),
+            vec![
+                record("a", 1),
+                record("b", 1),
+                record("a", 2),
+                record("b", 2),
+                record("a", 3)
+            ],
+            "page_size {}",
+            page_size
+        );
+    }
+    let page = reader
+        .read_merged(
+            "ks",
+            &keys,
+            MergeOrder::Offset,
+            Direction::Forwards,
+            StartPosition::First,
+            Some(2),
+        )
+        .unwrap();
+    assert!(page.continuation.unwrap().starts_with("f:o:"));
+}
+
+#[test]
+fn deduplicated_pages_keep_reading_until_full() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append_without_dedup(
+            "ks",
+            "k",
+            vec![
+                insertion("a", 1, 1),
+                insertion("a", 1, 1),
+                insertion("a", 1, 1),
+                insertion("b", 2, 2),
+                insertion("c", 3, 3),
+                insertion("d", 4, 4),
+            ],
+        )
+        .unwrap();
+    let reader = S3StoreReader::new(store.config().set_dedup_nonces_on_read(true)).unwrap();
+    let page = reader
+        .read_first_page(
+            "ks",
+            "k",
+            Direction::Forwards,
+            StartPosition::First,
+            Some(3),
+        )
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![1, 4, 5]);
+    let page = reader
+        .read_next_page("ks", "k", page.continuation.unwrap(), Some(3))
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![6]);
+}
+
+/// a reader of ks/k whose first GET of the middle of three objects of 2 records finds it missing,
+/// after compacting every object of the key into one as a concurrent compaction would
+fn reader_racing_compaction(store: &MemoryObjectStore) -> (S3StoreReader, Arc<AtomicBool>) {
+    let writer = S3StoreWriter::new(uncompacted_config(store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let removed = reader.describe_key("ks", "k").unwrap().objects[1]
+        .path
+        .clone();
+    let missed = Arc::new(AtomicBool::new(false));
+    let injector_missed = missed.clone();
+    let injector_store = store.clone();
+    let reader = reader.set_fault_injector(Arc::new(move |operation, path| {
+        if operation == "get_object"
+            && path == removed
+            && !injector_missed.swap(true, Ordering::SeqCst)
+        {
+            append_objects(&compacting_writer(&injector_store, 8), &[2]);
+            return Some(InjectedFault::Missing);
+        }
+        None
+    }));
+    (reader, missed)
+}
+
+#[test]
+fn raw_objects_and_visited_records_survive_a_concurrent_compaction() {
+    let store = MemoryObjectStore::new();
+    let (reader, missed) = reader_racing_compaction(&store);
+    let objects = reader.read_raw_objects("ks", "k", 1, 6).unwrap();
+    assert!(missed.load(Ordering::SeqCst));
+    // the compacted object replaces the first object that was collected before it
+    let paths: Vec<String> = objects.into_iter().map(|(path, _)| path).collect();
+    let compacted: Vec<String> = reader
+        .describe_key("ks", "k")
+        .unwrap()
+        .objects
+        .into_iter()
+        .map(|o| o.path)
+        .collect();
+    assert_eq!(paths, compacted);
+
+    let store = MemoryObjectStore::new();
+    let (reader, missed) = reader_racing_compaction(&store);
+    let mut offsets: Vec<u64> = Vec::new();
+    reader
+        .for_each_record("ks", "k", 1, 6, |record| offsets.push(record.offset))
+        .unwrap();
+    assert!(missed.load(Ordering::SeqCst));
+    assert_eq!(offsets, (1..7).collect::<Vec<u64>>());
+}
+
+#[test]
+fn read_your_writes_reads_key_metadata_once_per_read() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2]);
+
+    // the listing of the read, and of its first retry, lag behind the listing of key metadata
+    let lists = Arc::new(AtomicU64::new(0));
+    let watermark_gets = Arc::new(AtomicU64::new(0));
+    let injector_lists = lists.clone();
+    let injector_watermark_gets = watermark_gets.clone();
+    let reader = S3StoreReader::new(store.config().set_read_your_writes_timeout_millis(5000))
+        .unwrap()
+        .set_fault_injector(Arc::new(move |operation, path| {
+            if operation == "get_object" && path.ends_with("/watermark") {
+                injector_watermark_gets.fetch_add(1, Ordering::SeqCst);
+            }
+            if operation == "list_page" {
+                let list = injector_lists.fetch_add(1, Ordering::SeqCst) + 1;
+                if list == 1 || list == 3 {
+                    return Some(InjectedFault::Missing);
+                }
+            }
+            None
+        }));
+    assert_eq!(
+        read_offsets(&reader, Direction::Forwards, StartPosition::Offset(1), 100),
+        vec![1, 2]
+    );
+    assert!(lists.load(Ordering::SeqCst) > 3);
+    assert_eq!(watermark_gets.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn read_object_of_a_missing_path_is_not_found() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append("ks", "k", vec![insertion("a", 1, 1)])
+        .unwrap();
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let path = reader.describe_key("ks", "k").unwrap().objects[0]
+        .path
+        .clone();
+    assert_eq!(reader.read_object(&path).unwrap().len(), 1);
+    store.remove(&path);
+    match reader.read_object(&path) {
+        Err(StoreError::ObjectNotFound(missing)) => assert_eq!(missing, path),
+        other => panic!("expected ObjectNotFound, got {:?}", other),
+    }
+}
+
+#[test]
+fn metadata_of_a_keyspace_without_config_is_inferred_from_every_object() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    writer
+        .append("ks", "a", vec![insertion("a", 1, 50)])
+        .unwrap();
+    writer
+        .append("ks", "b", vec![insertion("b", 1, 10)])
+        .unwrap();
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let metadata = reader.read_keyspace_metadata("ks").unwrap();
+    assert_eq!(metadata.created_timestamp, 10);
+    assert_eq!(metadata.format_version, 1);
+    // a keyspace with neither config nor objects is not found, which retrying will not change
+    match reader.read_keyspace_metadata("missing") {
+        Err(err @ StoreError::KeyspaceNotFound) => assert!(!err.is_retryable()),
+        other => panic!("expected KeyspaceNotFound, got {:?}", other),
+    }
+
+    writer
+        .create_keyspace_with_format(
+            "forward",
+            TimestampUnit::Millis,
+            format::RecordLayout::new()
+                .set_forward_only(true)
+                .set_u64_nonces(true),
+        )
+        .unwrap();
+    writer
+        .append(
+            "forward",
+            "k",
+            vec![insertion("a", 1, 1), insertion("b", 2, 2)],
+        )
+        .unwrap();
+    assert!(store.remove("forward_config.ini"));
+    let metadata = reader.read_keyspace_metadata("forward").unwrap();
+    assert_eq!(metadata.format_version, format::FORMAT_VERSION);
+    assert!(metadata.forward_only);
+    assert!(metadata.u64_nonces);
+}
+
+#[test]
+fn layout_of_a_keyspace_without_config_is_remembered_once_it_has_objects() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    writer
+        .append("ks", "k", vec![insertion("a", 1, 1)])
+        .unwrap();
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    assert_eq!(
+        read_offsets(&reader, Direction::Forwards, StartPosition::First, 10),
+        vec![1]
+    );
+    // a remembered layout does not read the config again
+    store.put("ks_config.ini", b"not a config".to_vec());
+    assert_eq!(
+        read_offsets(&reader, Direction::Forwards, StartPosition::First, 10),
+        vec![1]
+    );
+
+    // a keyspace without objects may still be created with another format
+    let page = reader
+        .read_first_page(
+            "later",
+            "k",
+            Direction::Forwards,
+            StartPosition::First,
+            None,
+        )
+        .unwrap();
+    assert!(page.records.is_empty());
+    writer
+        .create_keyspace_with_format(
+            "later",
+            TimestampUnit::Millis,
+            format::RecordLayout::new().set_forward_only(true),
+        )
+        .unwrap();
+    writer
+        .append(
+            "later",
+            "k",
+            vec![insertion("a", 1, 1), insertion("b", 2, 2)],
+        )
+        .unwrap();
+    let page = reader
+        .read_first_page(
+            "later",
+            "k",
+            Direction::Forwards,
+            StartPosition::First,
+            None,
+        )
+        .unwrap();
+    assert_eq!(page.records.len(), 2);
+}
+
+#[test]
+fn default_starts_keep_the_direction_of_the_read() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2]);
+    writer.create_keyspace("other").unwrap();
+    writer
+        .append(
+            "other",
+            "k",
+            vec![
+                insertion("a", 1, 1),
+                insertion("b", 2, 2),
+                insertion("c", 3, 3),
+            ],
+        )
+        .unwrap();
+    let reader = S3StoreReader::new(
+        store
+            .config()
+            .set_default_start(StartPosition::Offset(2))
+            .set_keyspace_default_start("ks".to_string(), StartPosition::Offset(5)),
+    )
+    .unwrap();
+
+    let read = |keyspace: &str, direction: Direction| {
+        page_offsets(reader.read_first_page(
+            keyspace,
+            "k",
+            direction,
+            StartPosition::Default,
+            Some(2),
+        ))
+    };
+    assert_eq!(read("ks", Direction::Forwards), vec![5, 6]);
+    assert_eq!(read("ks", Direction::Backwards), vec![5, 4]);
+    assert_eq!(read("other", Direction::Forwards), vec![2, 3]);
+    assert_eq!(read("other", Direction::Backwards), vec![2, 1]);
+    assert_eq!(
+        page_offsets(reader.read_first_page_from(
+            "ks",
+            "k",
+            Direction::Backwards,
+            StartPosition::Default,
+            false,
+            Some(2)
+        )),
+        vec![4, 3]
+    );
+    // explicit starts are read as given, and a backwards read from First starts at the newest record
+    assert_eq!(
+        page_offsets(reader.read_first_page(
+            "ks",
+            "k",
+            Direction::Backwards,
+            StartPosition::First,
+            Some(2)
+        )),
+        vec![6, 5]
+    );
+}
+
+#[test]
+fn estimates_list_as_the_read_lists_without_getting_data_objects() {
+    // a single path per LIST page, so the listing after the seek pages through the key
+    let store = MemoryObjectStore::new().set_list_page_size(1);
+    let writer = S3StoreWriter::new(uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    append_objects(&writer, &[2, 2, 2]);
+    let data_gets = Arc::new(AtomicU64::new(0));
+    let injector_data_gets = data_gets.clone();
+    let reader = S3StoreReader::new(store.config())
+        .unwrap()
+        .set_fault_injector(Arc::new(move |operation, path| {
+            if operation == "get_object" && path.contains("/data_") {
+                injector_data_gets.fetch_add(1, Ordering::SeqCst);
+            }
+            None
+        }));
+
+    let estimate = reader
+        .estimate_read(
+            "ks",
+            "k",
+            Direction::Forwards,
+            StartPosition::Offset(2),
+            Some(3),
+        )
+        .unwrap();
+    assert_eq!(data_gets.load(Ordering::SeqCst), 0);
+    assert_eq!(estimate.object_count, 2);
+    assert_eq!(estimate.record_count, 3);
+
+    let (page, stats) = reader
+        .read_pages(
+            "ks",
+            "k",
+            Direction::Forwards,
+            StartPosition::Offset(2),
+            Some(3),
+        )
+        .next()
+        .unwrap()
+        .unwrap();
+    let offsets: Vec<u64> = page.records.iter().map(|r| r.offset).collect();
+    assert_eq!(offsets, vec![2, 3, 4]);
+    assert_eq!(data_gets.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        estimate.stats.list_operation_count,
+        stats.list_operation_count
+    );
+}
@@ -0,0 +1,423 @@
+#![cfg(feature = "testing")]
+extern crate klstore;
+
+mod common;
+
+use klstore::*;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn format_of_a_keyspace_created_after_first_use_is_read_again() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    let millis = writer.timestamp_now("ks").unwrap();
+    S3StoreWriter::new(store.config())
+        .unwrap()
+        .create_keyspace_with_timestamp_unit("ks", TimestampUnit::Nanos)
+        .unwrap();
+    assert!(writer.timestamp_now("ks").unwrap() > millis * 1000);
+}
+
+#[test]
+fn conditional_append_at_an_offset_locked_by_another_writer_conflicts() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config().set_conditional_writes(true)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append("ks", "k", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+    assert!(store.get("ks/k/o00000000000000000001.lock").is_some());
+    assert_eq!(
+        S3StoreReader::new(store.config())
+            .unwrap()
+            .describe_key("ks", "k")
+            .unwrap()
+            .objects
+            .len(),
+        1
+    );
+
+    // another writer created the marker of the next offset, but its object is not listed yet
+    store.put("ks/k/o00000000000000000002.lock", b"other".to_vec());
+    match writer.append("ks", "k", vec![common::insertion("b", 2, 2)]) {
+        Err(StoreError::Conflict(_)) => {}
+        other => panic!("expected Conflict, got {:?}", other),
+    }
+    assert_eq!(
+        S3StoreReader::new(store.config())
+            .unwrap()
+            .describe_key("ks", "k")
+            .unwrap()
+            .objects
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn conditional_append_after_a_failed_data_put_is_not_locked_out() {
+    let store = MemoryObjectStore::new();
+    let failed: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let injector_failed = failed.clone();
+    let writer = S3StoreWriter::new(
+        store
+            .config()
+            .set_conditional_writes(true)
+            .set_compact_records_threshold(3),
+    )
+    .unwrap()
+    .set_fault_injector(Arc::new(move |operation, path| {
+        let mut failed = injector_failed.lock().unwrap();
+        if operation == "put_object" && path.contains("/data_") && failed.is_empty() {
+            failed.push(path.to_string());
+            return Some(InjectedFault::Error(StoreError::Unavailable(
+                "injected".to_string(),
+            )));
+        }
+        None
+    }));
+    writer.create_keyspace("ks").unwrap();
+    assert!(writer
+        .append("ks", "k", vec![common::insertion("a", 1, 1)])
+        .is_err());
+    assert_eq!(failed.lock().unwrap().len(), 1);
+    assert!(store.get("ks/k/o00000000000000000001.lock").is_none());
+
+    // a marker left by an attempt whose delete failed holds the same object path, so the same records carry on
+    let failed_path = failed.lock().unwrap()[0].clone();
+    store.put(
+        "ks/k/o00000000000000000001.lock",
+        failed_path.as_bytes().to_vec(),
+    );
+    writer
+        .append("ks", "k", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+    assert!(store.get(&failed_path).is_some());
+
+    // compaction deletes the markers of the objects it merges
+    common::append_objects(&writer, &[1, 1]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    assert_eq!(reader.describe_key("ks", "k").unwrap().objects.len(), 1);
+    assert!(store.paths().iter().all(|path| !path.ends_with(".lock")));
+}
+
+fn buffered_config(store: &MemoryObjectStore) -> S3StoreConfig {
+    common::uncompacted_config(store).set_append_buffer_size_threshold(1 << 20)
+}
+
+fn object_count(store: &MemoryObjectStore) -> usize {
+    S3StoreReader::new(store.config())
+        .unwrap()
+        .describe_key("ks", "k")
+        .unwrap()
+        .objects
+        .len()
+}
+
+#[test]
+fn append_buffers_are_written_once_old_enough_and_on_drop() {
+    let store = MemoryObjectStore::new();
+    let writer =
+        S3StoreWriter::new(buffered_config(&store).set_append_buffer_max_age_millis(200)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append("ks", "k", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+    writer.duty_cycle().unwrap();
+    assert_eq!(object_count(&store), 0);
+    std::thread::sleep(std::time::Duration::from_millis(250));
+    writer.duty_cycle().unwrap();
+    assert_eq!(object_count(&store), 1);
+
+    let writer = S3StoreWriter::new(buffered_config(&store)).unwrap();
+    writer
+        .append("ks", "k", vec![common::insertion("b", 2, 2)])
+        .unwrap();
+    assert_eq!(object_count(&store), 1);
+    drop(writer);
+    assert_eq!(object_count(&store), 2);
+}
+
+#[test]
+fn nonce_resets_are_only_read_and_written_when_enabled() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append("ks", "k", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+    match writer.reset_nonce("ks", "k", 100) {
+        Err(StoreError::BadConfiguration(_)) => {}
+        other => panic!("expected BadConfiguration, got {:?}", other),
+    }
+
+    let resetting = S3StoreWriter::new(store.config().set_nonce_resets(true)).unwrap();
+    resetting.reset_nonce("ks", "k", 100).unwrap();
+
+    // a reader without nonce_resets never GETs the reset object and keeps the nonce from the object paths
+    let reset_gets = Arc::new(AtomicU64::new(0));
+    let injector_reset_gets = reset_gets.clone();
+    let reader = S3StoreReader::new(store.config())
+        .unwrap()
+        .set_fault_injector(Arc::new(move |operation, path| {
+            if operation == "get_object" && path.ends_with("/nonce_reset") {
+                injector_reset_gets.fetch_add(1, Ordering::SeqCst);
+            }
+            None
+        }));
+    let metadata = reader.read_key_metadata("ks", "k").unwrap().unwrap();
+    assert_eq!(metadata.next_nonce, 2);
+    assert_eq!(reset_gets.load(Ordering::SeqCst), 0);
+
+    let reader = S3StoreReader::new(store.config().set_nonce_resets(true)).unwrap();
+    let metadata = reader.read_key_metadata("ks", "k").unwrap().unwrap();
+    assert_eq!(metadata.next_nonce, 100);
+}
+
+#[test]
+fn deleting_keys_and_keyspaces_removes_every_object_in_batched_requests() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(
+        common::uncompacted_config(&store)
+            .set_conditional_writes(true)
+            .set_compaction_manifest(true),
+    )
+    .unwrap();
+    writer.create_keyspace("ks").unwrap();
+    common::append_objects(&writer, &[1, 1, 1]);
+    writer
+        .append("ks", "other", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+    writer.create_keyspace("ks2").unwrap();
+    writer
+        .append("ks2", "k", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+
+    store.reset_operation_counts();
+    writer.delete_key("ks", "k").unwrap();
+    assert_eq!(store.operation_count("delete_objects"), 1);
+    assert!(store.paths().iter().all(|path| !path.starts_with("ks/k/")));
+    assert!(store
+        .paths()
+        .iter()
+        .any(|path| path.starts_with("ks/other/")));
+    // the key starts over, since the writer forgot it
+    writer
+        .append("ks", "k", vec![common::insertion("b", 1, 1)])
+        .unwrap();
+    assert_eq!(writer.next_offset("ks", "k").unwrap(), Some(2));
+
+    writer.delete_keyspace("ks").unwrap();
+    assert!(store.paths().iter().all(|path| path.starts_with("ks2")));
+    assert_eq!(store.paths().len(), 3);
+    // the config is gone, so the keyspace can be created again
+    writer.create_keyspace("ks").unwrap();
+}
+
+#[test]
+fn truncating_a_key_deletes_whole_objects_before_the_offset_and_keeps_the_last() {
+    let store = MemoryObjectStore::new();
+    let writer =
+        S3StoreWriter::new(common::uncompacted_config(&store).set_conditional_writes(true))
+            .unwrap();
+    writer.create_keyspace("ks").unwrap();
+    common::append_objects(&writer, &[2, 2, 2]);
+
+    // offset 4 is in the second object, which is kept whole
+    assert_eq!(writer.truncate_key("ks", "k", 4).unwrap(), 1);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    assert_eq!(
+        common::read_offsets(&reader, Direction::Forwards, StartPosition::First, 10),
+        vec![3, 4, 5, 6]
+    );
+    assert!(store.get("ks/k/o00000000000000000001.lock").is_none());
+    assert!(store.get("ks/k/o00000000000000000003.lock").is_some());
+
+    // the last object is kept even when every offset is before the truncation
+    assert_eq!(writer.truncate_key("ks", "k", 100).unwrap(), 1);
+    assert_eq!(
+        common::read_offsets(&reader, Direction::Forwards, StartPosition::First, 10),
+        vec![5, 6]
+    );
+    common::append_objects(&writer, &[1]);
+    assert_eq!(
+        common::read_offsets(&reader, Direction::Forwards, StartPosition::First, 10),
+        vec![5, 6, 7]
+    );
+    assert_eq!(writer.truncate_key("ks", "k", 5).unwrap(), 0);
+}
+
+#[test]
+fn negative_timestamps_are_rejected_before_anything_is_written() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config()).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    let inserts = || vec![common::insertion("a", 1, 1), common::insertion("b", 2, -1)];
+    match writer.append("ks", "k", inserts()) {
+        Err(StoreError::BadData(_)) => {}
+        other => panic!("expected BadData, got {:?}", other),
+    }
+    match writer.append_iter("ks", "k", inserts().into_iter()) {
+        Err(StoreError::BadData(_)) => {}
+        other => panic!("expected BadData, got {:?}", other),
+    }
+    assert_eq!(writer.next_offset("ks", "k").unwrap(), Some(1));
+
+    // the epoch itself is a valid timestamp
+    writer
+        .append("ks", "k", vec![common::insertion("a", 1, 0)])
+        .unwrap();
+    assert_eq!(writer.next_offset("ks", "k").unwrap(), Some(2));
+}
+
+#[test]
+fn reject_checks_every_split_of_append_iter_before_writing() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(
+        common::uncompacted_config(&store)
+            .set_timestamp_policy(TimestampPolicy::Reject)
+            .set_max_object_size(1),
+    )
+    .unwrap();
+    writer.create_keyspace("ks").unwrap();
+    let inserts = || {
+        vec![
+            common::insertion("a", 1, 5),
+            common::insertion("b", 2, 6),
+            common::insertion("c", 3, 4),
+        ]
+    };
+    match writer.append_iter("ks", "k", inserts().into_iter()) {
+        Err(StoreError::BadData(_)) => {}
+        other => panic!("expected BadData, got {:?}", other),
+    }
+    assert_eq!(writer.next_offset("ks", "k").unwrap(), Some(1));
+
+    // every record in order, one object per record
+    let mut inserts = inserts();
+    inserts[2].timestamp = Some(6);
+    writer.append_iter("ks", "k", inserts.into_iter()).unwrap();
+    assert_eq!(object_count(&store), 3);
+}
+
+#[test]
+fn deprecated_monotonic_timestamps_field_clamps_timestamps() {
+    let store = MemoryObjectStore::new();
+    let mut config = common::uncompacted_config(&store);
+    #[allow(deprecated)]
+    {
+        config.monotonic_timestamps = true;
+    }
+    assert_eq!(config.effective_timestamp_policy(), TimestampPolicy::Clamp);
+    let writer = S3StoreWriter::new(config).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append(
+            "ks",
+            "k",
+            vec![common::insertion("a", 1, 5), common::insertion("b", 2, 3)],
+        )
+        .unwrap();
+    let page = S3StoreReader::new(store.config())
+        .unwrap()
+        .read_first_page("ks", "k", Direction::Forwards, StartPosition::First, None)
+        .unwrap();
+    let timestamps: Vec<i64> = page.records.iter().map(|r| r.timestamp).collect();
+    assert_eq!(timestamps, vec![5, 5]);
+}
+
+/// offset, value, nonce, and timestamp of every record of a key, read in one page
+fn migrated_records(
+    reader: &S3StoreReader,
+    keyspace: &str,
+    key: &str,
+) -> Vec<(u64, Vec<u8>, Option<u128>, i64)> {
+    reader
+        .read_first_page(
+            keyspace,
+            key,
+            Direction::Forwards,
+            StartPosition::First,
+            Some(100),
+        )
+        .unwrap()
+        .records
+        .into_iter()
+        .map(|r| (r.offset, r.value, r.nonce, r.timestamp))
+        .collect()
+}
+
+#[test]
+fn migration_resumes_each_key_from_the_next_offset_of_its_copy() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(common::uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    common::append_objects(&writer, &[2, 1]);
+    writer
+        .append("ks", "other", vec![common::insertion("o", 7, 7)])
+        .unwrap();
+    let report = writer
+        .migrate_keyspace("ks", MigrationConfig::new("ks2".to_string()))
+        .unwrap();
+    assert_eq!(report.keys_migrated, 2);
+    assert_eq!(report.records_copied, 4);
+
+    // records appended after the first run are all that is left to copy
+    common::append_objects(&writer, &[2]);
+    let report = writer
+        .migrate_keyspace("ks", MigrationConfig::new("ks2".to_string()))
+        .unwrap();
+    assert_eq!(report.keys_migrated, 1);
+    assert_eq!(report.keys_skipped, 1);
+    assert_eq!(report.records_copied, 2);
+
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    for key in &["k", "other"] {
+        assert_eq!(
+            migrated_records(&reader, "ks2", key),
+            migrated_records(&reader, "ks", key)
+        );
+    }
+    assert_eq!(migrated_records(&reader, "ks2", "k").len(), 5);
+}
+
+#[test]
+fn migration_carries_nonce_resets_over_when_enabled() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(store.config().set_nonce_resets(true)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    writer
+        .append("ks", "k", vec![common::insertion("a", 1, 1)])
+        .unwrap();
+    writer.reset_nonce("ks", "k", 100).unwrap();
+    writer
+        .migrate_keyspace("ks", MigrationConfig::new("ks2".to_string()))
+        .unwrap();
+    let reader = S3StoreReader::new(store.config().set_nonce_resets(true)).unwrap();
+    let metadata = reader.read_key_metadata("ks2", "k").unwrap().unwrap();
+    assert_eq!(metadata.next_offset, 2);
+    assert_eq!(metadata.next_nonce, 100);
+}
+
+#[test]
+fn migration_fails_with_data_loss_on_a_gap_in_the_source() {
+    let store = MemoryObjectStore::new();
+    let writer = S3StoreWriter::new(common::uncompacted_config(&store)).unwrap();
+    writer.create_keyspace("ks").unwrap();
+    common::append_objects(&writer, &[2, 2, 2]);
+    let reader = S3StoreReader::new(store.config()).unwrap();
+    let removed = reader.describe_key("ks", "k").unwrap().objects[1]
+        .path
+        .clone();
+    assert!(store.remove(&removed));
+    match writer.migrate_keyspace("ks", MigrationConfig::new("ks2".to_string())) {
+        Err(StoreError::DataLoss(_)) => {}
+        other => panic!("expected DataLoss, got {:?}", other),
+    }
+    // pages are checked before they are appended, so no record at or after the gap is copied
+    let next_offset = writer.next_offset("ks2", "k").unwrap().unwrap();
+    assert!(next_offset <= 3);
+}